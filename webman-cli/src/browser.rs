@@ -1,5 +1,6 @@
 use anyhow::Result;
 
+use crate::spool::Spool;
 use serde::{Deserialize, Serialize};
 use webman_core::{browser::*, node, Client, ToOk};
 
@@ -7,19 +8,48 @@ use webman_core::{browser::*, node, Client, ToOk};
 #[serde(transparent)]
 pub struct Browsers(std::collections::HashMap<String, BrowserSetting>);
 
+/// Mine the urls visited since the last import and spool them, then send the spooled batches
+/// to target. The batches which could not be sent are retried on the next run.
 pub async fn provide(
-    client: Client,
+    client: &Client,
     provider_name: String,
     target: &node::Name,
     browsers: Browsers,
+    rules: &UrlRules,
+    spool: &Spool,
 ) -> Result<()> {
-    let since = client.last_import_time(target, &provider_name).await?;
+    // the spooled urls are not known to the target yet, so mining continues after them
+    let watermark = spool.watermark(&provider_name)?;
+    let since = match client.last_import_time(target, &provider_name).await {
+        Ok(t) => watermark.map_or(t, |w| w.max(t)),
+        Err(e) => match watermark {
+            Some(w) => {
+                log::warn!(
+                    "could not reach {}, mining since the spooled urls: {:?}",
+                    target,
+                    e
+                );
+                w
+            }
+            None => return Err(e),
+        },
+    };
     log::debug!("browser settings: {:?}", browsers);
-    let tasks = browsers
-        .0
-        .into_values()
-        .flat_map(|b| b.url_insert(provider_name.clone(), since).to_ok().flatten())
-        .map(|insert| client.insert_urls(target, insert));
-    futures::future::try_join_all(tasks).await?;
+    for insert in browsers.0.into_values().flat_map(|b| {
+        b.url_insert(provider_name.clone(), since, rules)
+            .to_ok()
+            .flatten()
+    }) {
+        spool.push(&insert)?;
+    }
+    let sent = spool.flush(client, target, false).await?;
+    let status = spool.status()?;
+    log::info!(
+        "{} batches are sent to {}, {} batches ({} bytes) are left in the spool",
+        sent,
+        target,
+        status.batches,
+        status.bytes
+    );
     Ok(())
 }
@@ -7,7 +7,7 @@ use figment::{
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use std::str::FromStr;
-use webman_core::{config, url};
+use webman_core::{browser::UrlRules, config, url};
 
 pub fn target(fig: &Figment) -> String {
     fig.extract_inner("target").unwrap_or_else(|_| {
@@ -48,6 +48,29 @@ pub fn browsers(fig: &Figment) -> Browsers {
         .expect("could not construct Browsers in [cli]")
 }
 
+/// the global include/exclude rules for all browsers
+pub fn url_rules(fig: &Figment) -> UrlRules {
+    fig.extract_inner("provider")
+        .expect("could not construct include/exclude rules in [cli.provider]")
+}
+
+/// where the batches which are not sent yet are kept, $XDG_DATA_HOME/webman/spool by default
+pub fn spool_dir(fig: &Figment) -> PathBuf {
+    fig.extract_inner("spool_dir").unwrap_or_else(|_| {
+        let mut p = match std::env::var("XDG_DATA_HOME") {
+            Ok(d) => PathBuf::from(d),
+            Err(_) => {
+                let home =
+                    std::env::var("HOME").expect("neither env xdg_data_home nor home is set");
+                PathBuf::from(home).join(".local").join("share")
+            }
+        };
+        p.push("webman");
+        p.push("spool");
+        p
+    })
+}
+
 #[derive(Default, Debug, Deserialize, Serialize)]
 pub struct TagConfig {
     pub location: PathBuf,
@@ -0,0 +1,88 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use webman_core::{node, stats::Period, url::Filter, Client};
+
+fn filter(limit: i64, days: Option<i64>) -> Filter {
+    Filter {
+        limit,
+        time_from: days.map(|d| (Utc::now() - Duration::days(d)).naive_utc()),
+        ..Default::default()
+    }
+}
+
+pub async fn top(
+    client: &Client,
+    target: &node::Name,
+    limit: i64,
+    days: Option<i64>,
+) -> Result<()> {
+    let hosts = client.top_hosts(target, &filter(limit, days)).await?;
+    println!(
+        "{:<40} {:>8} {:>6} {:<20} {:<20}",
+        "host", "visits", "urls", "first visit", "last visit"
+    );
+    for h in hosts {
+        println!(
+            "{:<40} {:>8} {:>6} {:<20} {:<20}",
+            h.host,
+            h.visit_count,
+            h.url_count,
+            h.first_visit_time.format("%Y-%m-%d %H:%M"),
+            h.last_visit_time.format("%Y-%m-%d %H:%M")
+        );
+    }
+    Ok(())
+}
+
+pub async fn stats(
+    client: &Client,
+    target: &node::Name,
+    host: &str,
+    period: Period,
+    days: Option<i64>,
+) -> Result<()> {
+    let stats = match client
+        .host_stats(target, host, period, &filter(0, days))
+        .await?
+    {
+        Some(s) => s,
+        None => {
+            println!("no visits of {} found", host);
+            return Ok(());
+        }
+    };
+    let s = stats.summary;
+    println!(
+        "{}: {} visits of {} urls, first visit {}, last visit {}",
+        s.host,
+        s.visit_count,
+        s.url_count,
+        s.first_visit_time.format("%Y-%m-%d %H:%M"),
+        s.last_visit_time.format("%Y-%m-%d %H:%M")
+    );
+    println!();
+    println!(
+        "{:<16} {:<10} {:>8} {:>6}",
+        "provider", "browser", "visits", "urls"
+    );
+    for b in stats.browsers {
+        println!(
+            "{:<16} {:<10} {:>8} {:>6}",
+            b.provider_name,
+            format!("{:?}", b.browser),
+            b.visit_count,
+            b.url_count
+        );
+    }
+    println!();
+    println!("{:<12} {:>8} {:>6}", period.to_string(), "visits", "urls");
+    for t in stats.trend {
+        println!(
+            "{:<12} {:>8} {:>6}",
+            t.time.format("%Y-%m-%d"),
+            t.visit_count,
+            t.url_count
+        );
+    }
+    Ok(())
+}
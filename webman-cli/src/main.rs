@@ -1,5 +1,11 @@
 mod browser;
 mod config;
+mod host;
+mod nodes;
+mod query;
+mod session;
+mod spool;
+mod sync;
 mod tag;
 
 use clap::{Parser, Subcommand};
@@ -26,12 +32,85 @@ struct Cli {
 enum Commands {
     #[clap(subcommand)]
     Tag(Tag),
+    #[clap(subcommand)]
+    Privacy(Privacy),
 
-    Provide,
+    /// mine the urls of the browsers and send them to target, keeping them in the spool until sent
+    Provide {
+        /// keep running and provide every given minutes
+        #[clap(long, value_parser)]
+        every: Option<u64>,
+    },
+    #[clap(subcommand)]
+    Spool(SpoolCommands),
+    /// remove tracking parameters and secrets from the urls stored in target
+    Sanitize,
+    /// merge the urls stored in target which only differ in e.g. scheme, `www.` or fragment
+    MergeDuplicates,
+    /// show the sites with the most visits, or the statistics of one site
+    Hosts {
+        #[clap(value_parser)]
+        host: Option<String>,
+        #[clap(short, long, value_parser, default_value_t = 20)]
+        limit: i64,
+        /// only count the urls visited in the last days
+        #[clap(short, long, value_parser)]
+        days: Option<i64>,
+        /// day, week or month
+        #[clap(short, long, value_parser, default_value = "week")]
+        period: String,
+    },
+    #[clap(subcommand)]
+    Sessions(Sessions),
+    #[clap(subcommand)]
+    Queries(Queries),
+    /// show how the url was reached and which pages were opened from it
+    Navigation {
+        #[clap(value_parser)]
+        url: String,
+        /// the maximal number of pages to go back
+        #[clap(short, long, value_parser, default_value_t = 10)]
+        depth: i32,
+    },
     SyncServer {
         host: String,
         remote: String,
     },
+    /// show which endpoint of every node answers, and the nodes discovered in the LAN
+    Nodes,
+    #[clap(subcommand)]
+    Sync(SyncCommands),
+}
+
+#[derive(Subcommand)]
+enum SyncCommands {
+    /// show when target last synced with each of its peers, and which of them are stale
+    Status,
+    /// show the latest sync runs of target
+    History {
+        /// only show the runs with this peer
+        #[clap(value_parser)]
+        peer: Option<String>,
+        #[clap(short, long, value_parser, default_value_t = 20)]
+        limit: i64,
+    },
+    /// compare the visits of target and remote, and show the buckets in which they differ
+    Check {
+        remote: String,
+        /// reconcile the differing buckets on both nodes
+        #[clap(long, action)]
+        repair: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SpoolCommands {
+    /// show the batches which are not sent to target yet
+    Status,
+    /// send the spooled batches to target now, ignoring their backoff
+    Retry,
+    /// move the batches which could not be sent back into the spool and send them to target
+    RetryDead,
 }
 
 #[derive(Subcommand)]
@@ -48,6 +127,66 @@ enum Tag {
     Write,
 }
 
+#[derive(Subcommand)]
+enum Privacy {
+    /// set the privacy (normal or private) of urls manually
+    Set {
+        #[clap(value_parser, required = true)]
+        privacy: String,
+        #[clap(value_parser, required = true)]
+        urls: Vec<String>,
+    },
+    /// apply the privacy rules of the target to its existing urls
+    Backfill,
+}
+
+#[derive(Subcommand)]
+enum Queries {
+    /// show the search queries matching the query and the pages opened from their results
+    Search {
+        #[clap(value_parser, default_value = "")]
+        query: String,
+        #[clap(short, long, value_parser, default_value_t = 20)]
+        limit: i64,
+    },
+    /// show what was searched for before the url was found
+    Of {
+        #[clap(value_parser)]
+        url: String,
+    },
+    /// extract the search queries of the urls already stored in target
+    Backfill,
+}
+
+#[derive(Subcommand)]
+enum Sessions {
+    /// rebuild the browsing sessions of target from its visits
+    Rebuild {
+        /// minutes without visits which end a session
+        #[clap(short, long, value_parser)]
+        idle: Option<i64>,
+    },
+    /// show the sessions containing a page which matches the query
+    Search {
+        #[clap(value_parser, default_value = "")]
+        query: String,
+        #[clap(short, long, value_parser, default_value_t = 10)]
+        limit: i64,
+    },
+    /// print the urls of a session, one per line, to reopen them
+    Urls {
+        #[clap(value_parser)]
+        id: i32,
+    },
+    /// tag all urls of a session
+    Tag {
+        #[clap(value_parser)]
+        id: i32,
+        #[clap(value_parser)]
+        tag: String,
+    },
+}
+
 #[tokio::main]
 async fn main() {
     let fig = init_fig().select("cli");
@@ -85,6 +224,21 @@ async fn main() {
     .unwrap();
 
     let client = Client::with_apikey(&config().api_key);
+    // the nodes which are not configured may be found in the LAN
+    let mut names = vec![target.clone()];
+    match &cli.command {
+        Commands::SyncServer { host, remote } => {
+            names.push(node::Name::new(host));
+            names.push(node::Name::new(remote));
+        }
+        Commands::Sync(SyncCommands::Check { remote, .. }) => names.push(node::Name::new(remote)),
+        _ => {}
+    }
+    if !matches!(cli.command, Commands::Nodes) {
+        nodes::resolve(&names.iter().collect::<Vec<_>>())
+            .await
+            .unwrap();
+    }
 
     match cli.command {
         Commands::Tag(tag) => {
@@ -103,17 +257,177 @@ async fn main() {
                 Tag::Write => tag::write(&client, &target, &tag_config).await.unwrap(),
             }
         }
-        Commands::Provide => browser::provide(
-            client,
-            config::provider_name(&fig),
-            &target,
-            config::browsers(&fig),
-        )
-        .await
-        .unwrap(),
-        Commands::SyncServer { host, remote } => client
-            .sync_urls(&node::Name::from(host), &node::Name::from(remote))
+        Commands::Privacy(privacy) => match privacy {
+            Privacy::Set { privacy, urls } => client
+                .set_privacy(&target, privacy.as_str().try_into().unwrap(), urls)
+                .await
+                .unwrap(),
+            Privacy::Backfill => {
+                let n = client.backfill_privacy(&target).await.unwrap();
+                log::info!("{} urls are marked as private", n);
+            }
+        },
+        Commands::Provide { every } => {
+            let spool = spool::Spool::open(&config::spool_dir(&fig), &target).unwrap();
+            let provider_name = config::provider_name(&fig);
+            let browsers = config::browsers(&fig);
+            let rules = config::url_rules(&fig);
+            match every {
+                Some(minutes) => {
+                    let mut interval =
+                        tokio::time::interval(std::time::Duration::from_secs(minutes * 60));
+                    loop {
+                        interval.tick().await;
+                        browser::provide(
+                            &client,
+                            provider_name.clone(),
+                            &target,
+                            browsers.clone(),
+                            &rules,
+                            &spool,
+                        )
+                        .await
+                        .to_ok();
+                    }
+                }
+                None => browser::provide(&client, provider_name, &target, browsers, &rules, &spool)
+                    .await
+                    .unwrap(),
+            }
+        }
+        Commands::Spool(cmd) => {
+            let spool = spool::Spool::open(&config::spool_dir(&fig), &target).unwrap();
+            match cmd {
+                SpoolCommands::Status => {
+                    let status = spool.status().unwrap();
+                    println!("{} batches ({} bytes)", status.batches, status.bytes);
+                    if status.dead > 0 {
+                        println!(
+                            "{} batches could not be sent and are kept apart",
+                            status.dead
+                        );
+                    }
+                    if let Some(t) = status.next_attempt {
+                        println!("next attempt at {}", t.format("%Y-%m-%d %H:%M:%S"));
+                    }
+                }
+                SpoolCommands::Retry => {
+                    let n = spool.flush(&client, &target, true).await.unwrap();
+                    log::info!("{} spooled batches are sent to {}", n, target);
+                }
+                SpoolCommands::RetryDead => {
+                    let revived = spool.revive().unwrap();
+                    let n = spool.flush(&client, &target, true).await.unwrap();
+                    log::info!(
+                        "{} batches are moved back into the spool, {} spooled batches are sent to {}",
+                        revived,
+                        n,
+                        target
+                    );
+                }
+            }
+        }
+        Commands::Sanitize => {
+            let n = client.sanitize_urls(&target).await.unwrap();
+            log::info!("{} urls are sanitized", n);
+        }
+        Commands::MergeDuplicates => {
+            let n = client.merge_duplicate_urls(&target).await.unwrap();
+            log::info!("{} duplicate urls are merged", n);
+        }
+        Commands::Hosts {
+            host,
+            limit,
+            days,
+            period,
+        } => match host {
+            Some(host) => host::stats(
+                &client,
+                &target,
+                &host,
+                period.parse().expect("invalid period"),
+                days,
+            )
             .await
             .unwrap(),
+            None => host::top(&client, &target, limit, days).await.unwrap(),
+        },
+        Commands::Sessions(sessions) => match sessions {
+            Sessions::Rebuild { idle } => {
+                let n = client.rebuild_sessions(&target, idle).await.unwrap();
+                log::info!("{} browsing sessions are built", n);
+            }
+            Sessions::Search { query, limit } => session::search(&client, &target, &query, limit)
+                .await
+                .unwrap(),
+            Sessions::Urls { id } => {
+                for url in client.session_urls(&target, id).await.unwrap() {
+                    println!("{}", url);
+                }
+            }
+            Sessions::Tag { id, tag } => {
+                let n = client
+                    .tag_session(&target, id, tag.as_str().try_into().unwrap())
+                    .await
+                    .unwrap();
+                log::info!("{} urls of session {} are tagged as {}", n, id, tag);
+            }
+        },
+        Commands::Queries(queries) => match queries {
+            Queries::Search { query, limit } => {
+                query::print(client.search_queries(&target, &query, limit).await.unwrap())
+            }
+            Queries::Of { url } => {
+                query::print(client.queries_of_url(&target, &url).await.unwrap())
+            }
+            Queries::Backfill => {
+                let n = client.backfill_search_queries(&target).await.unwrap();
+                log::info!("{} search queries are extracted", n);
+            }
+        },
+        Commands::Navigation { url, depth } => {
+            match client.navigation(&target, &url, depth).await.unwrap() {
+                Some(nav) => {
+                    for step in nav.referrers {
+                        println!("{} {}", step.visit_time.format("%Y-%m-%d %H:%M"), step.url);
+                        println!("  ↓");
+                    }
+                    println!("{}", nav.url);
+                    for step in nav.opened {
+                        println!(
+                            "  → {} {}",
+                            step.visit_time.format("%Y-%m-%d %H:%M"),
+                            step.url
+                        );
+                    }
+                }
+                None => println!("{} is not found", url),
+            }
+        }
+        Commands::SyncServer { host, remote } => {
+            let run = client
+                .sync_all(&node::Name::from(host), &node::Name::from(remote))
+                .await
+                .unwrap();
+            sync::print_run(&run);
+        }
+        Commands::Nodes => nodes::list(&client).await.unwrap(),
+        Commands::Sync(cmd) => match cmd {
+            SyncCommands::Status => sync::status(&client, &target).await.unwrap(),
+            SyncCommands::History { peer, limit } => {
+                for run in client
+                    .sync_history(&target, peer.as_deref(), limit)
+                    .await
+                    .unwrap()
+                {
+                    sync::print_run(&run);
+                }
+            }
+            SyncCommands::Check { remote, repair } => {
+                sync::check(&client, &target, &node::Name::from(remote), repair)
+                    .await
+                    .unwrap()
+            }
+        },
     }
 }
@@ -0,0 +1,55 @@
+use anyhow::Result;
+use webman_core::{config, discovery, node, Client};
+
+/// show which endpoint of every configured node answers, and the peers discovered in the LAN
+pub async fn list(client: &Client) -> Result<()> {
+    for (name, _) in config().nodes.iter() {
+        match client.probe(&node::Name::new(name)).await {
+            Ok(endpoint) => println!("{} {}", name, endpoint),
+            Err(_) => println!("{} unreachable", name),
+        }
+    }
+    let d = match &config().discovery {
+        Some(d) => d,
+        None => return Ok(()),
+    };
+    let peers = discovery::discover(d, &config().api_key).await?;
+    discovery::register(&peers);
+    if !peers.is_empty() {
+        println!("discovered:");
+    }
+    for p in peers {
+        println!(
+            "{} {}{}{}",
+            p.name,
+            p.endpoint,
+            if p.verified { "" } else { " (unverified)" },
+            if config().nodes.configured(&p.name).is_none() {
+                " (new)"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(())
+}
+
+/// search the LAN for the nodes which are not configured, if discovery is enabled
+pub async fn resolve(names: &[&node::Name]) -> Result<()> {
+    let unknown: Vec<_> = names
+        .iter()
+        .filter(|n| config().nodes.find(n).is_none())
+        .collect();
+    if unknown.is_empty() {
+        return Ok(());
+    }
+    if let Some(d) = &config().discovery {
+        discovery::register(&discovery::discover(d, &config().api_key).await?);
+    }
+    for n in unknown {
+        if config().nodes.find(n).is_none() {
+            anyhow::bail!("{} is neither configured nor discovered in the LAN", n);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,15 @@
+use webman_core::url::QueryLogEntry;
+
+pub fn print(entries: Vec<QueryLogEntry>) {
+    for e in entries {
+        println!(
+            "{} [{}] {}",
+            e.last_visit_time.format("%Y-%m-%d %H:%M"),
+            e.engine,
+            e.query
+        );
+        for r in e.results {
+            println!("    {} {}", r.title, r.url);
+        }
+    }
+}
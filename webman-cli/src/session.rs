@@ -0,0 +1,20 @@
+use anyhow::Result;
+use webman_core::{node, Client};
+
+pub async fn search(client: &Client, target: &node::Name, query: &str, limit: i64) -> Result<()> {
+    let sessions = client.search_sessions(target, query, limit).await?;
+    for s in sessions {
+        println!(
+            "#{} {} {:?} {} - {}",
+            s.id,
+            s.provider_name,
+            s.browser,
+            s.start_time.format("%Y-%m-%d %H:%M"),
+            s.end_time.format("%H:%M")
+        );
+        for u in s.urls {
+            println!("    {} {}", u.title, u.url);
+        }
+    }
+    Ok(())
+}
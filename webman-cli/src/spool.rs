@@ -0,0 +1,458 @@
+//! The mined batches are written to a spool directory before they are sent, so that no batch is
+//! lost while the target is unreachable. Sending a batch is retried on later runs with an
+//! exponential backoff, and it is only removed from the spool once the target stored it. A batch
+//! which the target rejects or which cannot be decoded is moved to the dead-letter directory
+//! instead, so that it does not hold back the batches after it. The watermark has already moved
+//! past the urls of a dead batch, so `spool retry-dead` moves the dead batches back into the spool
+//! once the cause is fixed, where they are sent again in the order they were mined.
+use anyhow::{Context, Result};
+use chrono::{Duration, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+};
+use webman_core::{node, resp::UrlInsert, Client, WebmanError};
+
+/// the delay before the first retry of a batch, it doubles with every failed attempt
+const FIRST_BACKOFF_SECS: i64 = 60;
+const MAX_BACKOFF_SECS: i64 = 6 * 60 * 60;
+const STATE_FILE: &str = "state.json";
+const LOCK_FILE: &str = "spool.lock";
+const DEAD_DIR: &str = "dead";
+const BATCH_EXTENSION: &str = "msgpack";
+
+/// The spooled batches of a target.
+pub struct Spool {
+    dir: PathBuf,
+    /// locked while the spool is open, so that two runs for the same target neither overwrite
+    /// the state of each other nor send the same batch
+    _lock: fs::File,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct State {
+    /// the last visit time of the spooled urls of every provider, the next import starts there
+    #[serde(default)]
+    watermarks: HashMap<String, NaiveDateTime>,
+    /// the failed attempts of the batches by file name
+    #[serde(default)]
+    retries: HashMap<String, Retry>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Retry {
+    attempts: u32,
+    next_attempt: NaiveDateTime,
+}
+
+#[derive(Debug, Default)]
+pub struct SpoolStatus {
+    pub batches: usize,
+    pub bytes: u64,
+    /// the batches which were moved to the dead-letter directory
+    pub dead: usize,
+    /// when the next batch is retried, None if the spool is empty
+    pub next_attempt: Option<NaiveDateTime>,
+}
+
+/// the delay after the `attempts`th failed attempt
+pub fn backoff(attempts: u32) -> Duration {
+    let secs = FIRST_BACKOFF_SECS.saturating_mul(1 << attempts.saturating_sub(1).min(20));
+    Duration::seconds(secs.min(MAX_BACKOFF_SECS))
+}
+
+impl Spool {
+    /// the spool of `target` in `dir`, which is created if it does not exist
+    pub fn open(dir: &Path, target: &node::Name) -> Result<Spool> {
+        let dir = dir.join(target.as_str());
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("could not create spool directory {:?}", dir))?;
+        let path = dir.join(LOCK_FILE);
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("could not open {:?}", path))?;
+        if let Err(fs::TryLockError::WouldBlock) = lock.try_lock() {
+            log::info!("waiting for another run to release the spool {:?}", dir);
+            lock.lock()
+                .with_context(|| format!("could not lock {:?}", path))?;
+        }
+        Ok(Spool { dir, _lock: lock })
+    }
+
+    fn state(&self) -> Result<State> {
+        let path = self.dir.join(STATE_FILE);
+        if !path.exists() {
+            return Ok(State::default());
+        }
+        let f = fs::File::open(&path).with_context(|| format!("could not open {:?}", path))?;
+        serde_json::from_reader(f).with_context(|| format!("could not read {:?}", path))
+    }
+
+    fn save_state(&self, state: &State) -> Result<()> {
+        write_atomic(&self.dir.join(STATE_FILE), &serde_json::to_vec(state)?)
+    }
+
+    /// where the next import of the provider starts, None if nothing was spooled for it yet
+    pub fn watermark(&self, provider: &str) -> Result<Option<NaiveDateTime>> {
+        Ok(self.state()?.watermarks.get(provider).copied())
+    }
+
+    /// write the batch to the spool and move the watermark of its provider past its urls
+    pub fn push(&self, insert: &UrlInsert) -> Result<()> {
+        let name = format!(
+            "{}-{:?}.{}",
+            Utc::now().format("%Y%m%dT%H%M%S%.9f"),
+            insert.browser,
+            BATCH_EXTENSION
+        );
+        write_atomic(&self.dir.join(name), &rmp_serde::to_vec(insert)?)?;
+        let last_visit = insert
+            .urls
+            .iter()
+            .map(|u| u.last_visit_time)
+            .chain(insert.last_import_time)
+            .max();
+        if let Some(t) = last_visit {
+            let mut state = self.state()?;
+            let w = state.watermarks.entry(insert.name.clone()).or_insert(t);
+            *w = (*w).max(t);
+            self.save_state(&state)?;
+        }
+        Ok(())
+    }
+
+    /// the spooled batches in `dir`, the oldest first
+    fn batches_in(dir: &Path) -> Result<Vec<PathBuf>> {
+        let mut res = vec![];
+        if !dir.exists() {
+            return Ok(res);
+        }
+        for entry in fs::read_dir(dir)
+            .with_context(|| format!("could not read spool directory {:?}", dir))?
+        {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == BATCH_EXTENSION) {
+                res.push(path);
+            }
+        }
+        res.sort();
+        Ok(res)
+    }
+
+    fn batches(&self) -> Result<Vec<PathBuf>> {
+        Self::batches_in(&self.dir)
+    }
+
+    pub fn status(&self) -> Result<SpoolStatus> {
+        let state = self.state()?;
+        let mut status = SpoolStatus::default();
+        let now = Utc::now().naive_utc();
+        for path in self.batches()? {
+            status.batches += 1;
+            status.bytes += fs::metadata(&path)?.len();
+            let next = state
+                .retries
+                .get(&file_name(&path))
+                .map_or(now, |r| r.next_attempt);
+            status.next_attempt = Some(status.next_attempt.map_or(next, |t| t.min(next)));
+        }
+        status.dead = Self::batches_in(&self.dir.join(DEAD_DIR))?.len();
+        Ok(status)
+    }
+
+    /// Send the batches in the order they were mined, unless the backoff of the oldest one has not
+    /// passed yet and `force` is not set. Sending stops at the first failure, since the target is
+    /// most likely unreachable. Returns the number of sent batches.
+    pub async fn flush(&self, client: &Client, target: &node::Name, force: bool) -> Result<usize> {
+        self.send_batches(target, force, |insert, upload_id| async move {
+            client.insert_urls(target, insert, &upload_id).await
+        })
+        .await
+    }
+
+    /// see [`Spool::flush`], a batch is uploaded with the id derived from its file name, so that
+    /// a retry resumes the upload at the chunks the target has not stored yet
+    async fn send_batches<F, Fut>(
+        &self,
+        target: &node::Name,
+        force: bool,
+        mut send: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(UrlInsert, String) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut state = self.state()?;
+        let now = Utc::now().naive_utc();
+        let mut sent = 0;
+        for path in self.batches()? {
+            let name = file_name(&path);
+            if !force
+                && state
+                    .retries
+                    .get(&name)
+                    .is_some_and(|r| r.next_attempt > now)
+            {
+                break;
+            }
+            let bytes = fs::read(&path).with_context(|| format!("could not read {:?}", path))?;
+            let insert: UrlInsert = match rmp_serde::from_slice(&bytes) {
+                Ok(insert) => insert,
+                Err(e) => {
+                    self.bury(&path, &mut state, &anyhow::Error::new(e))?;
+                    continue;
+                }
+            };
+            let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+            let upload_id = format!("{}-{}", insert.name, stem);
+            match send(insert, upload_id).await {
+                Ok(()) => {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("could not remove {:?}", path))?;
+                    state.retries.remove(&name);
+                    sent += 1;
+                }
+                Err(e) if is_rejected(&e) => self.bury(&path, &mut state, &e)?,
+                Err(e) => {
+                    let retry = state.retries.entry(name).or_insert(Retry {
+                        attempts: 0,
+                        next_attempt: now,
+                    });
+                    retry.attempts += 1;
+                    retry.next_attempt = now + backoff(retry.attempts);
+                    log::warn!(
+                        "could not send {:?} to {}, attempt {} failed, retrying after {}: {:?}",
+                        path,
+                        target,
+                        retry.attempts,
+                        retry.next_attempt,
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+        self.save_state(&state)?;
+        Ok(sent)
+    }
+
+    /// Move the batches in the dead-letter directory back into the spool, e.g. after the target
+    /// was upgraded to accept them. Their file names keep the time they were mined, so they are
+    /// sent before the batches mined after them. Returns the number of moved batches.
+    pub fn revive(&self) -> Result<usize> {
+        let mut state = self.state()?;
+        let dead = Self::batches_in(&self.dir.join(DEAD_DIR))?;
+        for path in &dead {
+            let name = file_name(path);
+            fs::rename(path, self.dir.join(&name))
+                .with_context(|| format!("could not move {:?} to {:?}", path, self.dir))?;
+            state.retries.remove(&name);
+        }
+        self.save_state(&state)?;
+        Ok(dead.len())
+    }
+
+    /// move the batch to the dead-letter directory, where it is kept for inspection
+    fn bury(&self, path: &Path, state: &mut State, e: &anyhow::Error) -> Result<()> {
+        let name = file_name(path);
+        let dead = self.dir.join(DEAD_DIR);
+        fs::create_dir_all(&dead)
+            .with_context(|| format!("could not create dead-letter directory {:?}", dead))?;
+        fs::rename(path, dead.join(&name))
+            .with_context(|| format!("could not move {:?} to {:?}", path, dead))?;
+        state.retries.remove(&name);
+        log::error!(
+            "{:?} is moved to {:?}, it cannot be sent: {:?}",
+            path,
+            dead,
+            e
+        );
+        Ok(())
+    }
+}
+
+/// Whether the target refused the batch itself, so that sending it again cannot succeed. A wrong
+/// api key concerns every batch and a conflict may be caused by a concurrent write, so both are
+/// retried.
+fn is_rejected(e: &anyhow::Error) -> bool {
+    matches!(
+        WebmanError::of(e),
+        WebmanError::InvalidInput(_) | WebmanError::NotFound(_)
+    )
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// write to a temporary file first, so that a crash never leaves a truncated file behind
+fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, bytes).with_context(|| format!("could not write {:?}", tmp))?;
+    fs::rename(&tmp, path).with_context(|| format!("could not move {:?} to {:?}", tmp, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use std::cell::RefCell;
+    use webman_core::{browser::Browser, url::Url};
+
+    fn insert(urls: &[(&str, u32)]) -> UrlInsert {
+        let day = |d| {
+            NaiveDate::from_ymd_opt(2026, 10, d)
+                .unwrap()
+                .and_hms_opt(12, 0, 0)
+                .unwrap()
+        };
+        UrlInsert {
+            name: "laptop".to_owned(),
+            browser: Browser::Firefox,
+            urls: urls
+                .iter()
+                .map(|(url, d)| Url {
+                    url: url.to_string(),
+                    last_visit_time: day(*d),
+                    ..Default::default()
+                })
+                .collect(),
+            last_import_time: None,
+            edges: vec![],
+            search_terms: vec![],
+            redirects: vec![],
+            durations: vec![],
+            favicons: vec![],
+            downloads: vec![],
+            chunk: None,
+        }
+    }
+
+    fn spool(dir: &tempfile::TempDir) -> Spool {
+        Spool::open(dir.path(), &node::Name::new("pi")).unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        assert_eq!(backoff(1), Duration::seconds(60));
+        assert_eq!(backoff(2), Duration::seconds(120));
+        assert_eq!(backoff(4), Duration::seconds(480));
+        assert_eq!(backoff(30), Duration::hours(6));
+    }
+
+    #[test]
+    fn push_moves_watermark() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(&dir);
+        assert_eq!(spool.watermark("laptop").unwrap(), None);
+        spool.push(&insert(&[("u1", 3), ("u2", 5)])).unwrap();
+        spool.push(&insert(&[("u3", 4)])).unwrap();
+        let w = spool.watermark("laptop").unwrap().unwrap();
+        assert_eq!(w.to_string(), "2026-10-05 12:00:00");
+        assert_eq!(spool.watermark("desktop").unwrap(), None);
+        assert_eq!(spool.status().unwrap().batches, 2);
+    }
+
+    #[tokio::test]
+    async fn flush_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(&dir);
+        let target = node::Name::new("pi");
+        for url in ["u1", "u2", "u3", "u4"] {
+            spool.push(&insert(&[(url, 1)])).unwrap();
+        }
+        fs::write(spool.dir.join("0-garbage.msgpack"), b"not msgpack").unwrap();
+        let sent = RefCell::new(vec![]);
+        let send = |fail: &'static str| {
+            let sent = &sent;
+            move |insert: UrlInsert, upload_id: String| async move {
+                let url = insert.urls[0].url.clone();
+                sent.borrow_mut().push((url.clone(), upload_id));
+                match url.as_str() {
+                    "u2" => Err(WebmanError::InvalidInput("bad batch".to_owned()).into()),
+                    u if u == fail => Err(WebmanError::Unavailable("down".to_owned()).into()),
+                    _ => Ok(()),
+                }
+            }
+        };
+
+        // the rejected batch is buried and the next one is sent, the unreachable target stops
+        assert_eq!(
+            spool
+                .send_batches(&target, false, send("u3"))
+                .await
+                .unwrap(),
+            1
+        );
+        let urls: Vec<_> = sent.borrow().iter().map(|(u, _)| u.clone()).collect();
+        assert_eq!(urls, vec!["u1", "u2", "u3"]);
+        let status = spool.status().unwrap();
+        assert_eq!((status.batches, status.dead), (2, 2));
+        let retries = spool.state().unwrap().retries;
+        assert_eq!(
+            retries.values().map(|r| r.attempts).collect::<Vec<_>>(),
+            vec![1]
+        );
+        assert!(retries
+            .values()
+            .all(|r| r.next_attempt > Utc::now().naive_utc()));
+
+        // the backoff of the oldest batch holds back all of them
+        assert_eq!(
+            spool.send_batches(&target, false, send("")).await.unwrap(),
+            0
+        );
+        assert_eq!(sent.borrow().len(), 3);
+
+        // forced, the batches are sent in order and the retry keeps its upload id
+        assert_eq!(
+            spool.send_batches(&target, true, send("")).await.unwrap(),
+            2
+        );
+        let sent = sent.into_inner();
+        assert_eq!(sent[3].0, "u3");
+        assert_eq!(sent[3].1, sent[2].1);
+        assert!(sent[3].1.starts_with("laptop-"));
+        assert_eq!(sent[4].0, "u4");
+        assert_eq!(spool.status().unwrap().batches, 0);
+        assert!(spool.state().unwrap().retries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn revive_dead_batches() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = spool(&dir);
+        let target = node::Name::new("pi");
+        spool.push(&insert(&[("u1", 1)])).unwrap();
+        let reject = |_: UrlInsert, _: String| async {
+            Err(WebmanError::InvalidInput("bad batch".to_owned()).into())
+        };
+        assert_eq!(spool.send_batches(&target, false, reject).await.unwrap(), 0);
+        spool.push(&insert(&[("u2", 2)])).unwrap();
+        let status = spool.status().unwrap();
+        assert_eq!((status.batches, status.dead), (1, 1));
+        // the watermark is past the dead batch, so it is only sent again when revived
+        let w = spool.watermark("laptop").unwrap().unwrap();
+        assert_eq!(w.to_string(), "2026-10-02 12:00:00");
+
+        assert_eq!(spool.revive().unwrap(), 1);
+        let status = spool.status().unwrap();
+        assert_eq!((status.batches, status.dead), (2, 0));
+        let sent = RefCell::new(vec![]);
+        let accept = |insert: UrlInsert, _: String| {
+            sent.borrow_mut().push(insert.urls[0].url.clone());
+            async { Ok(()) }
+        };
+        assert_eq!(spool.send_batches(&target, false, accept).await.unwrap(), 2);
+        assert_eq!(sent.into_inner(), vec!["u1", "u2"]);
+        assert_eq!(spool.revive().unwrap(), 0);
+    }
+}
@@ -0,0 +1,57 @@
+use anyhow::Result;
+use webman_core::{node, sync::SyncRun, Client};
+
+pub async fn status(client: &Client, target: &node::Name) -> Result<()> {
+    for s in client.sync_status(target).await? {
+        let last_success = s
+            .last_success
+            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_else(|| "never".to_owned());
+        println!(
+            "{} last synced {}{}",
+            s.peer,
+            last_success,
+            if s.stale { " (stale)" } else { "" }
+        );
+        if let Some(run) = s.last_run.filter(|r| r.error.is_some()) {
+            print_run(&run);
+        }
+    }
+    Ok(())
+}
+
+pub fn print_run(run: &SyncRun) {
+    println!(
+        "  {} - {} {} {}",
+        run.started_at.format("%Y-%m-%d %H:%M:%S"),
+        run.finished_at.format("%H:%M:%S"),
+        run.peer,
+        run.error.as_deref().unwrap_or("ok")
+    );
+    for r in &run.rows {
+        println!(
+            "    {} {}: {} pulled, {} pushed",
+            r.provider_name, r.browser, r.pulled, r.pushed
+        );
+    }
+}
+
+pub async fn check(
+    client: &Client,
+    target: &node::Name,
+    remote: &node::Name,
+    repair: bool,
+) -> Result<()> {
+    let divergences = client.check_sync(target, remote).await?;
+    if divergences.is_empty() {
+        println!("{} and {} are in sync", target, remote);
+    }
+    for d in &divergences {
+        println!("{}", d);
+    }
+    if repair && !divergences.is_empty() {
+        let n = client.repair_sync(target, remote, &divergences).await?;
+        log::info!("{} rows are repaired", n);
+    }
+    Ok(())
+}
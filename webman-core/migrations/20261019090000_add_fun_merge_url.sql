@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE OR REPLACE FUNCTION merge_url (from_id integer, to_id integer)
+  RETURNS integer AS $$
+    BEGIN
+      INSERT INTO visits
+      SELECT to_id, provider_id, browser_type, visit_count, last_visit_time
+      FROM visits WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visits_pkey DO
+       UPDATE SET visit_count = visits.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(visits.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM visits WHERE url_id = from_id;
+      UPDATE urls SET tag = GREATEST(urls.tag, f.tag), privacy = GREATEST(urls.privacy, f.privacy)
+      FROM urls f WHERE urls.id = to_id AND f.id = from_id;
+      DELETE FROM urls WHERE id = from_id;
+      RETURN to_id;
+    END; $$ LANGUAGE plpgsql;
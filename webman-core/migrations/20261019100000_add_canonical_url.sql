@@ -0,0 +1,3 @@
+-- Add migration script here
+ALTER TABLE urls ADD COLUMN canonical TEXT;
+CREATE INDEX urls_canonical_idx ON urls (canonical);
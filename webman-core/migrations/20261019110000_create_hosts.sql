@@ -0,0 +1,21 @@
+-- Add migration script here
+CREATE TABLE hosts
+  (
+    id SERIAL NOT NULL PRIMARY KEY,
+    host TEXT NOT NULL UNIQUE
+  );
+
+ALTER TABLE urls ADD COLUMN host_id INTEGER REFERENCES hosts(id);
+CREATE INDEX urls_host_id_idx ON urls (host_id);
+
+-- the same as webman_core::url::normalized_host
+WITH url_hosts AS (
+SELECT id, regexp_replace(lower(substring(url from '^[^:/?#]+://(?:[^/?#]*@)?(\[[^\]/?#]*\]|[^/?#:]+)')), '^www\.', '') as host
+FROM urls
+), new_hosts AS (
+INSERT INTO hosts(host)
+SELECT DISTINCT host FROM url_hosts WHERE host IS NOT NULL
+RETURNING id, host
+) UPDATE urls SET host_id = new_hosts.id
+FROM url_hosts INNER JOIN new_hosts ON url_hosts.host = new_hosts.host
+WHERE urls.id = url_hosts.id;
@@ -0,0 +1,38 @@
+-- Add migration script here
+CREATE TABLE sessions
+  (
+    id SERIAL NOT NULL PRIMARY KEY,
+    provider_id SMALLINT NOT NULL REFERENCES providers(id),
+    browser_type browser NOT NULL,
+    start_time timestamp NOT NULL,
+    end_time timestamp NOT NULL
+  );
+
+CREATE TABLE session_urls
+  (
+    session_id INTEGER NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    url_id INTEGER NOT NULL REFERENCES urls(id),
+    PRIMARY KEY(session_id, url_id)
+  );
+CREATE INDEX session_urls_url_id_idx ON session_urls (url_id);
+
+-- the merged url takes over the sessions of the removed one
+CREATE OR REPLACE FUNCTION merge_url (from_id integer, to_id integer)
+  RETURNS integer AS $$
+    BEGIN
+      INSERT INTO visits
+      SELECT to_id, provider_id, browser_type, visit_count, last_visit_time
+      FROM visits WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visits_pkey DO
+       UPDATE SET visit_count = visits.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(visits.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM visits WHERE url_id = from_id;
+      INSERT INTO session_urls
+      SELECT session_id, to_id FROM session_urls WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM session_urls WHERE url_id = from_id;
+      UPDATE urls SET tag = GREATEST(urls.tag, f.tag), privacy = GREATEST(urls.privacy, f.privacy)
+      FROM urls f WHERE urls.id = to_id AND f.id = from_id;
+      DELETE FROM urls WHERE id = from_id;
+      RETURN to_id;
+    END; $$ LANGUAGE plpgsql;
@@ -0,0 +1,44 @@
+-- Add migration script here
+CREATE TABLE navigations
+  (
+    from_url_id INTEGER NOT NULL REFERENCES urls(id),
+    to_url_id INTEGER NOT NULL REFERENCES urls(id),
+    provider_id SMALLINT NOT NULL REFERENCES providers(id),
+    browser_type browser NOT NULL,
+    visit_count INTEGER NOT NULL,
+    last_visit_time timestamp NOT NULL,
+    PRIMARY KEY(to_url_id, from_url_id, provider_id, browser_type)
+  );
+CREATE INDEX navigations_from_url_id_idx ON navigations (from_url_id);
+
+-- the merged url takes over the sessions and navigations of the removed one
+CREATE OR REPLACE FUNCTION merge_url (from_id integer, to_id integer)
+  RETURNS integer AS $$
+    BEGIN
+      INSERT INTO visits
+      SELECT to_id, provider_id, browser_type, visit_count, last_visit_time
+      FROM visits WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visits_pkey DO
+       UPDATE SET visit_count = visits.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(visits.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM visits WHERE url_id = from_id;
+      INSERT INTO session_urls
+      SELECT session_id, to_id FROM session_urls WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM session_urls WHERE url_id = from_id;
+      INSERT INTO navigations
+      SELECT CASE WHEN from_url_id = from_id THEN to_id ELSE from_url_id END,
+             CASE WHEN to_url_id = from_id THEN to_id ELSE to_url_id END,
+             provider_id, browser_type, visit_count, last_visit_time
+      FROM navigations
+      WHERE (from_url_id = from_id AND to_url_id != to_id)
+         OR (to_url_id = from_id AND from_url_id != to_id)
+       ON CONFLICT ON CONSTRAINT navigations_pkey DO
+       UPDATE SET visit_count = navigations.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(navigations.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM navigations WHERE from_url_id = from_id OR to_url_id = from_id;
+      UPDATE urls SET tag = GREATEST(urls.tag, f.tag), privacy = GREATEST(urls.privacy, f.privacy)
+      FROM urls f WHERE urls.id = to_id AND f.id = from_id;
+      DELETE FROM urls WHERE id = from_id;
+      RETURN to_id;
+    END; $$ LANGUAGE plpgsql;
@@ -0,0 +1,42 @@
+-- Add migration script here
+-- the final page which a transient page (e.g. a link shortener or a login page) redirected to
+ALTER TABLE urls ADD COLUMN redirect_to INTEGER REFERENCES urls(id);
+CREATE INDEX urls_redirect_to_idx ON urls (redirect_to);
+
+-- urls redirecting to the removed url redirect to the merged one
+CREATE OR REPLACE FUNCTION merge_url (from_id integer, to_id integer)
+  RETURNS integer AS $$
+    BEGIN
+      INSERT INTO visits
+      SELECT to_id, provider_id, browser_type, visit_count, last_visit_time
+      FROM visits WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visits_pkey DO
+       UPDATE SET visit_count = visits.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(visits.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM visits WHERE url_id = from_id;
+      INSERT INTO session_urls
+      SELECT session_id, to_id FROM session_urls WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM session_urls WHERE url_id = from_id;
+      INSERT INTO navigations
+      SELECT CASE WHEN from_url_id = from_id THEN to_id ELSE from_url_id END,
+             CASE WHEN to_url_id = from_id THEN to_id ELSE to_url_id END,
+             provider_id, browser_type, visit_count, last_visit_time
+      FROM navigations
+      WHERE (from_url_id = from_id AND to_url_id != to_id)
+         OR (to_url_id = from_id AND from_url_id != to_id)
+       ON CONFLICT ON CONSTRAINT navigations_pkey DO
+       UPDATE SET visit_count = navigations.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(navigations.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM navigations WHERE from_url_id = from_id OR to_url_id = from_id;
+      INSERT INTO search_queries
+      SELECT to_id, query, engine FROM search_queries WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM search_queries WHERE url_id = from_id;
+      UPDATE urls SET redirect_to = to_id WHERE redirect_to = from_id;
+      UPDATE urls SET redirect_to = NULL WHERE id = to_id AND redirect_to = to_id;
+      UPDATE urls SET tag = GREATEST(urls.tag, f.tag), privacy = GREATEST(urls.privacy, f.privacy)
+      FROM urls f WHERE urls.id = to_id AND f.id = from_id;
+      DELETE FROM urls WHERE id = from_id;
+      RETURN to_id;
+    END; $$ LANGUAGE plpgsql;
@@ -0,0 +1,45 @@
+-- Add migration script here
+-- the time spent on a url, estimated from the gaps between visits if the browser does not record it
+ALTER TABLE visits
+  ADD COLUMN duration_secs BIGINT NOT NULL DEFAULT 0,
+  ADD COLUMN duration_estimated BOOLEAN NOT NULL DEFAULT false;
+
+-- the time spent on the removed url is added to the merged one
+CREATE OR REPLACE FUNCTION merge_url (from_id integer, to_id integer)
+  RETURNS integer AS $$
+    BEGIN
+      INSERT INTO visits
+      SELECT to_id, provider_id, browser_type, visit_count, last_visit_time, duration_secs, duration_estimated
+      FROM visits WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visits_pkey DO
+       UPDATE SET visit_count = visits.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(visits.last_visit_time, EXCLUDED.last_visit_time),
+                  duration_secs = visits.duration_secs + EXCLUDED.duration_secs,
+                  duration_estimated = visits.duration_estimated OR EXCLUDED.duration_estimated;
+      DELETE FROM visits WHERE url_id = from_id;
+      INSERT INTO session_urls
+      SELECT session_id, to_id FROM session_urls WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM session_urls WHERE url_id = from_id;
+      INSERT INTO navigations
+      SELECT CASE WHEN from_url_id = from_id THEN to_id ELSE from_url_id END,
+             CASE WHEN to_url_id = from_id THEN to_id ELSE to_url_id END,
+             provider_id, browser_type, visit_count, last_visit_time
+      FROM navigations
+      WHERE (from_url_id = from_id AND to_url_id != to_id)
+         OR (to_url_id = from_id AND from_url_id != to_id)
+       ON CONFLICT ON CONSTRAINT navigations_pkey DO
+       UPDATE SET visit_count = navigations.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(navigations.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM navigations WHERE from_url_id = from_id OR to_url_id = from_id;
+      INSERT INTO search_queries
+      SELECT to_id, query, engine FROM search_queries WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM search_queries WHERE url_id = from_id;
+      UPDATE urls SET redirect_to = to_id WHERE redirect_to = from_id;
+      UPDATE urls SET redirect_to = NULL WHERE id = to_id AND redirect_to = to_id;
+      UPDATE urls SET tag = GREATEST(urls.tag, f.tag), privacy = GREATEST(urls.privacy, f.privacy)
+      FROM urls f WHERE urls.id = to_id AND f.id = from_id;
+      DELETE FROM urls WHERE id = from_id;
+      RETURN to_id;
+    END; $$ LANGUAGE plpgsql;
@@ -0,0 +1,10 @@
+-- Add migration script here
+-- the best icon of every host among the ones mined from all providers
+CREATE TABLE favicons
+  (
+    host_id INTEGER NOT NULL PRIMARY KEY REFERENCES hosts(id) ON DELETE CASCADE,
+    mime TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    data BYTEA NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+  );
@@ -0,0 +1,61 @@
+-- Add migration script here
+CREATE TABLE downloads
+  (
+    url_id INTEGER NOT NULL REFERENCES urls(id),
+    provider_id SMALLINT NOT NULL REFERENCES providers(id),
+    browser_type browser NOT NULL,
+    file_name TEXT NOT NULL,
+    size BIGINT,
+    mime TEXT,
+    -- the page the download was started on
+    referrer_id INTEGER REFERENCES urls(id) ON DELETE SET NULL,
+    start_time TIMESTAMP NOT NULL,
+    PRIMARY KEY (url_id, provider_id, browser_type, start_time)
+  );
+CREATE INDEX downloads_referrer_id_idx ON downloads (referrer_id);
+
+-- downloads of the removed url are moved to the merged one
+CREATE OR REPLACE FUNCTION merge_url (from_id integer, to_id integer)
+  RETURNS integer AS $$
+    BEGIN
+      INSERT INTO visits
+      SELECT to_id, provider_id, browser_type, visit_count, last_visit_time, duration_secs, duration_estimated
+      FROM visits WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visits_pkey DO
+       UPDATE SET visit_count = visits.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(visits.last_visit_time, EXCLUDED.last_visit_time),
+                  duration_secs = visits.duration_secs + EXCLUDED.duration_secs,
+                  duration_estimated = visits.duration_estimated OR EXCLUDED.duration_estimated;
+      DELETE FROM visits WHERE url_id = from_id;
+      INSERT INTO session_urls
+      SELECT session_id, to_id FROM session_urls WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM session_urls WHERE url_id = from_id;
+      INSERT INTO navigations
+      SELECT CASE WHEN from_url_id = from_id THEN to_id ELSE from_url_id END,
+             CASE WHEN to_url_id = from_id THEN to_id ELSE to_url_id END,
+             provider_id, browser_type, visit_count, last_visit_time
+      FROM navigations
+      WHERE (from_url_id = from_id AND to_url_id != to_id)
+         OR (to_url_id = from_id AND from_url_id != to_id)
+       ON CONFLICT ON CONSTRAINT navigations_pkey DO
+       UPDATE SET visit_count = navigations.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(navigations.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM navigations WHERE from_url_id = from_id OR to_url_id = from_id;
+      INSERT INTO search_queries
+      SELECT to_id, query, engine FROM search_queries WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM search_queries WHERE url_id = from_id;
+      INSERT INTO downloads
+      SELECT to_id, provider_id, browser_type, file_name, size, mime, referrer_id, start_time
+      FROM downloads WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM downloads WHERE url_id = from_id;
+      UPDATE downloads SET referrer_id = to_id WHERE referrer_id = from_id;
+      UPDATE urls SET redirect_to = to_id WHERE redirect_to = from_id;
+      UPDATE urls SET redirect_to = NULL WHERE id = to_id AND redirect_to = to_id;
+      UPDATE urls SET tag = GREATEST(urls.tag, f.tag), privacy = GREATEST(urls.privacy, f.privacy)
+      FROM urls f WHERE urls.id = to_id AND f.id = from_id;
+      DELETE FROM urls WHERE id = from_id;
+      RETURN to_id;
+    END; $$ LANGUAGE plpgsql;
@@ -0,0 +1,44 @@
+-- Add migration script here
+-- every change of urls and visits gets the next number of this sequence, so that peers can pull
+-- the changes since the last number they have seen
+CREATE SEQUENCE change_seq;
+ALTER TABLE urls ADD COLUMN seq BIGINT NOT NULL DEFAULT nextval('change_seq');
+ALTER TABLE visits ADD COLUMN seq BIGINT NOT NULL DEFAULT nextval('change_seq');
+CREATE INDEX urls_seq_idx ON urls (seq);
+CREATE INDEX visits_seq_idx ON visits (seq);
+
+CREATE OR REPLACE FUNCTION bump_seq()
+  RETURNS trigger AS $$
+    BEGIN
+      NEW.seq := nextval('change_seq');
+      RETURN NEW;
+    END; $$ LANGUAGE plpgsql;
+
+-- updates which do not change anything are not logged, otherwise peers would echo them forever
+CREATE TRIGGER urls_bump_seq BEFORE UPDATE ON urls FOR EACH ROW
+  WHEN (OLD.title IS DISTINCT FROM NEW.title
+     OR OLD.tag IS DISTINCT FROM NEW.tag
+     OR OLD.privacy IS DISTINCT FROM NEW.privacy)
+  EXECUTE FUNCTION bump_seq();
+
+CREATE TRIGGER visits_bump_seq BEFORE UPDATE ON visits FOR EACH ROW
+  WHEN (OLD.visit_count IS DISTINCT FROM NEW.visit_count
+     OR OLD.last_visit_time IS DISTINCT FROM NEW.last_visit_time
+     OR OLD.duration_secs IS DISTINCT FROM NEW.duration_secs
+     OR OLD.duration_estimated IS DISTINCT FROM NEW.duration_estimated)
+  EXECUTE FUNCTION bump_seq();
+
+-- the random id of this node, sequence numbers of different ids are not comparable
+CREATE TABLE node_identity
+  (
+    node_id TEXT NOT NULL PRIMARY KEY
+  );
+INSERT INTO node_identity VALUES (gen_random_uuid()::text);
+
+-- the last sequence number of every peer whose changes are applied
+CREATE TABLE sync_peers
+  (
+    node_id TEXT NOT NULL PRIMARY KEY,
+    acked_seq BIGINT NOT NULL,
+    synced_at TIMESTAMP NOT NULL DEFAULT now()
+  );
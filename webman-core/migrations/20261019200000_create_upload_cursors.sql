@@ -0,0 +1,8 @@
+-- Add migration script here
+-- the next chunk expected of every chunked upload of urls, so that an interrupted upload is
+-- resumed without storing a chunk twice
+CREATE TABLE upload_cursors (
+  upload_id TEXT PRIMARY KEY,
+  next_chunk INTEGER NOT NULL,
+  updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
@@ -0,0 +1,23 @@
+-- Add migration script here
+-- the history of the sync runs of this node with its peers
+CREATE TABLE sync_runs
+  (
+     id          SERIAL PRIMARY KEY,
+     peer        TEXT NOT NULL,
+     started_at  TIMESTAMP NOT NULL,
+     finished_at TIMESTAMP NOT NULL,
+     -- NULL if the run succeeded
+     error       TEXT
+  );
+CREATE INDEX sync_runs_peer_idx ON sync_runs (peer, finished_at DESC);
+
+-- the visits moved by a run for every provider and browser
+CREATE TABLE sync_run_rows
+  (
+     run_id        INTEGER NOT NULL REFERENCES sync_runs(id) ON DELETE CASCADE,
+     provider_name TEXT NOT NULL,
+     browser_type  BROWSER NOT NULL,
+     pulled        BIGINT NOT NULL,
+     pushed        BIGINT NOT NULL,
+     PRIMARY KEY (run_id, provider_name, browser_type)
+  );
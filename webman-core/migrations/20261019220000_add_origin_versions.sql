@@ -0,0 +1,50 @@
+-- Add migration script here
+-- the node which made the latest change of a row, and the sequence number of the change there.
+-- origin_node is NULL for the changes of this node, whose origin_seq is the seq of the row
+ALTER TABLE urls ADD COLUMN origin_node TEXT, ADD COLUMN origin_seq BIGINT;
+ALTER TABLE visits ADD COLUMN origin_node TEXT, ADD COLUMN origin_seq BIGINT;
+UPDATE urls SET origin_seq = seq;
+UPDATE visits SET origin_seq = seq;
+ALTER TABLE urls ALTER COLUMN origin_seq SET NOT NULL;
+ALTER TABLE visits ALTER COLUMN origin_seq SET NOT NULL;
+
+-- every change is a change of this node, merged rows get the origin of the peer afterwards if
+-- they equal the rows of the peer
+CREATE OR REPLACE FUNCTION bump_seq()
+  RETURNS trigger AS $$
+    BEGIN
+      NEW.seq := nextval('change_seq');
+      NEW.origin_node := NULL;
+      NEW.origin_seq := NEW.seq;
+      RETURN NEW;
+    END; $$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION local_origin()
+  RETURNS trigger AS $$
+    BEGIN
+      IF NEW.origin_node IS NULL THEN
+        NEW.origin_seq := NEW.seq;
+      END IF;
+      RETURN NEW;
+    END; $$ LANGUAGE plpgsql;
+
+CREATE TRIGGER urls_local_origin BEFORE INSERT ON urls FOR EACH ROW
+  EXECUTE FUNCTION local_origin();
+CREATE TRIGGER visits_local_origin BEFORE INSERT ON visits FOR EACH ROW
+  EXECUTE FUNCTION local_origin();
+
+-- the highest sequence number of every other node up to which all its changes are applied
+CREATE TABLE origin_versions
+  (
+    node_id TEXT NOT NULL PRIMARY KEY,
+    seq BIGINT NOT NULL
+  );
+
+-- whether a peer with the given versions already has the change
+CREATE OR REPLACE FUNCTION is_known (origin_node text, origin_seq bigint, nodes text[], seqs bigint[])
+  RETURNS boolean AS $$
+    SELECT EXISTS (
+      SELECT 1 FROM UNNEST(nodes, seqs) AS known(node_id, seq)
+      WHERE known.node_id = COALESCE(origin_node, (SELECT node_id FROM node_identity))
+        AND origin_seq <= known.seq)
+  $$ LANGUAGE sql STABLE;
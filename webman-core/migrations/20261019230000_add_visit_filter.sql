@@ -0,0 +1,89 @@
+-- Add migration script here
+-- the time a url was added to the history of a provider and browser. Browsers only keep the last
+-- visit of a url, so it is the last visit time when the url was imported first
+ALTER TABLE visits ADD COLUMN added_time timestamp;
+UPDATE visits SET added_time = last_visit_time;
+ALTER TABLE visits ALTER COLUMN added_time SET NOT NULL;
+
+CREATE OR REPLACE FUNCTION first_added_time()
+  RETURNS trigger AS $$
+    BEGIN
+      NEW.added_time := COALESCE(NEW.added_time, NEW.last_visit_time);
+      RETURN NEW;
+    END; $$ LANGUAGE plpgsql;
+
+CREATE TRIGGER visits_added_time BEFORE INSERT ON visits FOR EACH ROW
+  EXECUTE FUNCTION first_added_time();
+
+-- whether the url matches the fields of a `Filter` which belong to urls
+CREATE OR REPLACE FUNCTION url_matches (u urls, f_privacy privacy, url_segs text[], title_segs text[],
+  f_tag tag, unlocked boolean, f_host text)
+  RETURNS boolean AS $$
+    SELECT (f_privacy IS NULL OR u.privacy = f_privacy)
+      AND u.url ILIKE ALL (url_segs)
+      AND u.title ILIKE ALL (title_segs)
+      AND (f_tag IS NULL OR u.tag = f_tag)
+      AND (unlocked OR u.privacy = 'normal')
+      AND (f_host IS NULL OR u.host_id = (SELECT id FROM hosts WHERE host = f_host))
+  $$ LANGUAGE sql STABLE;
+
+-- whether the visit of the url matches all fields of a `Filter`
+CREATE OR REPLACE FUNCTION visit_matches (u urls, v visits, p_id smallint, b browser,
+  f_privacy privacy, time_from timestamp, time_to timestamp, url_segs text[], title_segs text[],
+  f_tag tag, unlocked boolean, f_host text)
+  RETURNS boolean AS $$
+    SELECT (p_id IS NULL OR v.provider_id = p_id)
+      AND (b IS NULL OR v.browser_type = b)
+      AND (time_from IS NULL OR v.last_visit_time >= time_from)
+      AND (time_to IS NULL OR v.last_visit_time <= time_to)
+      AND url_matches(u, f_privacy, url_segs, title_segs, f_tag, unlocked, f_host)
+  $$ LANGUAGE sql STABLE;
+
+-- the merged url keeps the earliest added time
+CREATE OR REPLACE FUNCTION merge_url (from_id integer, to_id integer)
+  RETURNS integer AS $$
+    BEGIN
+      INSERT INTO visits(url_id, provider_id, browser_type, visit_count, last_visit_time,
+        duration_secs, duration_estimated, added_time)
+      SELECT to_id, provider_id, browser_type, visit_count, last_visit_time, duration_secs,
+        duration_estimated, added_time
+      FROM visits WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visits_pkey DO
+       UPDATE SET visit_count = visits.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(visits.last_visit_time, EXCLUDED.last_visit_time),
+                  duration_secs = visits.duration_secs + EXCLUDED.duration_secs,
+                  duration_estimated = visits.duration_estimated OR EXCLUDED.duration_estimated,
+                  added_time = LEAST(visits.added_time, EXCLUDED.added_time);
+      DELETE FROM visits WHERE url_id = from_id;
+      INSERT INTO session_urls
+      SELECT session_id, to_id FROM session_urls WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM session_urls WHERE url_id = from_id;
+      INSERT INTO navigations
+      SELECT CASE WHEN from_url_id = from_id THEN to_id ELSE from_url_id END,
+             CASE WHEN to_url_id = from_id THEN to_id ELSE to_url_id END,
+             provider_id, browser_type, visit_count, last_visit_time
+      FROM navigations
+      WHERE (from_url_id = from_id AND to_url_id != to_id)
+         OR (to_url_id = from_id AND from_url_id != to_id)
+       ON CONFLICT ON CONSTRAINT navigations_pkey DO
+       UPDATE SET visit_count = navigations.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(navigations.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM navigations WHERE from_url_id = from_id OR to_url_id = from_id;
+      INSERT INTO search_queries
+      SELECT to_id, query, engine FROM search_queries WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM search_queries WHERE url_id = from_id;
+      INSERT INTO downloads
+      SELECT to_id, provider_id, browser_type, file_name, size, mime, referrer_id, start_time
+      FROM downloads WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM downloads WHERE url_id = from_id;
+      UPDATE downloads SET referrer_id = to_id WHERE referrer_id = from_id;
+      UPDATE urls SET redirect_to = to_id WHERE redirect_to = from_id;
+      UPDATE urls SET redirect_to = NULL WHERE id = to_id AND redirect_to = to_id;
+      UPDATE urls SET tag = GREATEST(urls.tag, f.tag), privacy = GREATEST(urls.privacy, f.privacy)
+      FROM urls f WHERE urls.id = to_id AND f.id = from_id;
+      DELETE FROM urls WHERE id = from_id;
+      RETURN to_id;
+    END; $$ LANGUAGE plpgsql;
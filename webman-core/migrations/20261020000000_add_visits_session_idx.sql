@@ -0,0 +1,3 @@
+-- Add migration script here
+-- the order in which the visits are read to rebuild the sessions
+CREATE INDEX visits_session_idx ON visits (provider_id, browser_type, last_visit_time, url_id);
@@ -0,0 +1,118 @@
+-- Add migration script here
+-- Sequence numbers are taken in the order of nextval, not in the order the transactions commit, so
+-- a peer which pulled up to the highest committed number could miss a lower number committed
+-- later. Every transaction which takes a number holds a shared lock until it ends, and the
+-- watermark is only read once the lock can be taken exclusively: all numbers up to it are then
+-- committed or rolled back.
+CREATE OR REPLACE FUNCTION next_change_seq()
+  RETURNS bigint AS $$
+    SELECT pg_advisory_xact_lock_shared(hashtext('change_seq'));
+    SELECT nextval('change_seq');
+  $$ LANGUAGE sql VOLATILE;
+
+-- the highest sequence number below which no change can commit anymore
+CREATE OR REPLACE FUNCTION stable_seq()
+  RETURNS bigint AS $$
+    SELECT pg_advisory_xact_lock(hashtext('change_seq'));
+    SELECT CASE WHEN is_called THEN last_value ELSE 0 END FROM change_seq;
+  $$ LANGUAGE sql VOLATILE;
+
+ALTER TABLE urls ALTER COLUMN seq SET DEFAULT next_change_seq();
+ALTER TABLE visits ALTER COLUMN seq SET DEFAULT next_change_seq();
+
+CREATE OR REPLACE FUNCTION bump_seq()
+  RETURNS trigger AS $$
+    BEGIN
+      NEW.seq := next_change_seq();
+      NEW.origin_node := NULL;
+      NEW.origin_seq := NEW.seq;
+      RETURN NEW;
+    END; $$ LANGUAGE plpgsql;
+
+-- the urls which were merged into another url, so that peers merge them too instead of keeping
+-- or resurrecting them
+CREATE TABLE url_tombstones
+  (
+    url TEXT NOT NULL PRIMARY KEY,
+    merged_into TEXT NOT NULL,
+    seq BIGINT NOT NULL DEFAULT next_change_seq(),
+    origin_node TEXT,
+    origin_seq BIGINT NOT NULL DEFAULT 0
+  );
+CREATE INDEX url_tombstones_seq_idx ON url_tombstones (seq);
+
+CREATE TRIGGER url_tombstones_local_origin BEFORE INSERT ON url_tombstones FOR EACH ROW
+  EXECUTE FUNCTION local_origin();
+CREATE TRIGGER url_tombstones_bump_seq BEFORE UPDATE ON url_tombstones FOR EACH ROW
+  WHEN (OLD.merged_into IS DISTINCT FROM NEW.merged_into)
+  EXECUTE FUNCTION bump_seq();
+
+-- a url which is inserted again is alive
+CREATE OR REPLACE FUNCTION drop_tombstone()
+  RETURNS trigger AS $$
+    BEGIN
+      DELETE FROM url_tombstones WHERE url = NEW.url;
+      RETURN NEW;
+    END; $$ LANGUAGE plpgsql;
+
+CREATE TRIGGER urls_drop_tombstone AFTER INSERT ON urls FOR EACH ROW
+  EXECUTE FUNCTION drop_tombstone();
+
+-- the removed url leaves a tombstone
+CREATE OR REPLACE FUNCTION merge_url (from_id integer, to_id integer)
+  RETURNS integer AS $$
+    BEGIN
+      INSERT INTO visits(url_id, provider_id, browser_type, visit_count, last_visit_time,
+        duration_secs, duration_estimated, added_time)
+      SELECT to_id, provider_id, browser_type, visit_count, last_visit_time, duration_secs,
+        duration_estimated, added_time
+      FROM visits WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visits_pkey DO
+       UPDATE SET visit_count = visits.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(visits.last_visit_time, EXCLUDED.last_visit_time),
+                  duration_secs = visits.duration_secs + EXCLUDED.duration_secs,
+                  duration_estimated = visits.duration_estimated OR EXCLUDED.duration_estimated,
+                  added_time = LEAST(visits.added_time, EXCLUDED.added_time);
+      DELETE FROM visits WHERE url_id = from_id;
+      INSERT INTO visit_durations
+      SELECT to_id, provider_id, browser_type, visit_id, secs, estimated
+      FROM visit_durations WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visit_durations_pkey DO
+       UPDATE SET secs = visit_durations.secs + EXCLUDED.secs,
+                  estimated = visit_durations.estimated OR EXCLUDED.estimated;
+      DELETE FROM visit_durations WHERE url_id = from_id;
+      INSERT INTO session_urls
+      SELECT session_id, to_id FROM session_urls WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM session_urls WHERE url_id = from_id;
+      INSERT INTO navigations
+      SELECT CASE WHEN from_url_id = from_id THEN to_id ELSE from_url_id END,
+             CASE WHEN to_url_id = from_id THEN to_id ELSE to_url_id END,
+             provider_id, browser_type, visit_count, last_visit_time
+      FROM navigations
+      WHERE (from_url_id = from_id AND to_url_id != to_id)
+         OR (to_url_id = from_id AND from_url_id != to_id)
+       ON CONFLICT ON CONSTRAINT navigations_pkey DO
+       UPDATE SET visit_count = navigations.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(navigations.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM navigations WHERE from_url_id = from_id OR to_url_id = from_id;
+      INSERT INTO search_queries
+      SELECT to_id, query, engine FROM search_queries WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM search_queries WHERE url_id = from_id;
+      INSERT INTO downloads
+      SELECT to_id, provider_id, browser_type, file_name, size, mime, referrer_id, start_time
+      FROM downloads WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM downloads WHERE url_id = from_id;
+      UPDATE downloads SET referrer_id = to_id WHERE referrer_id = from_id;
+      UPDATE urls SET redirect_to = to_id WHERE redirect_to = from_id;
+      UPDATE urls SET redirect_to = NULL WHERE id = to_id AND redirect_to = to_id;
+      UPDATE urls SET tag = GREATEST(urls.tag, f.tag), privacy = GREATEST(urls.privacy, f.privacy)
+      FROM urls f WHERE urls.id = to_id AND f.id = from_id;
+      INSERT INTO url_tombstones(url, merged_into)
+      SELECT f.url, t.url FROM urls f, urls t WHERE f.id = from_id AND t.id = to_id
+       ON CONFLICT (url) DO UPDATE SET merged_into = EXCLUDED.merged_into;
+      DELETE FROM urls WHERE id = from_id;
+      RETURN to_id;
+    END; $$ LANGUAGE plpgsql;
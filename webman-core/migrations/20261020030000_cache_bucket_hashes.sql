@@ -0,0 +1,83 @@
+-- Add migration script here
+-- the bucket of a url in the hash tree of sync, the first byte of the md5 hash of the url
+ALTER TABLE urls ADD COLUMN bucket integer
+  GENERATED ALWAYS AS (get_byte(decode(md5(url), 'hex'), 0)) STORED;
+
+-- a visit row as text for hashing, timestamps are formatted explicitly since the text of a
+-- timestamp depends on the DateStyle of the session
+CREATE OR REPLACE FUNCTION visit_row (u urls, v visits)
+  RETURNS text AS $$
+    SELECT concat_ws('|', u.url, u.title, u.tag, u.privacy, v.visit_count,
+      to_char(v.last_visit_time, 'YYYY-MM-DD"T"HH24:MI:SS.US'), v.duration_secs,
+      v.duration_estimated)
+  $$ LANGUAGE sql STABLE;
+
+-- the hashes of the buckets of every provider and browser, they are computed again when the
+-- tree is read if their buckets are marked dirty
+CREATE TABLE bucket_hashes
+  (
+    provider_id SMALLINT NOT NULL REFERENCES providers(id),
+    browser_type browser NOT NULL,
+    bucket INTEGER NOT NULL,
+    hash TEXT NOT NULL,
+    PRIMARY KEY (provider_id, browser_type, bucket)
+  );
+
+CREATE TABLE dirty_buckets
+  (
+    provider_id SMALLINT NOT NULL,
+    browser_type browser NOT NULL,
+    bucket INTEGER NOT NULL,
+    PRIMARY KEY (provider_id, browser_type, bucket)
+  );
+INSERT INTO dirty_buckets
+SELECT DISTINCT provider_id, browser_type, bucket
+FROM visits INNER JOIN urls ON urls.id = visits.url_id;
+
+CREATE OR REPLACE FUNCTION mark_new_visit_buckets()
+  RETURNS trigger AS $$
+    BEGIN
+      INSERT INTO dirty_buckets
+      SELECT DISTINCT provider_id, browser_type, bucket
+      FROM new_rows INNER JOIN urls ON urls.id = new_rows.url_id
+       ON CONFLICT DO NOTHING;
+      RETURN NULL;
+    END; $$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION mark_old_visit_buckets()
+  RETURNS trigger AS $$
+    BEGIN
+      INSERT INTO dirty_buckets
+      SELECT DISTINCT provider_id, browser_type, bucket
+      FROM old_rows INNER JOIN urls ON urls.id = old_rows.url_id
+       ON CONFLICT DO NOTHING;
+      RETURN NULL;
+    END; $$ LANGUAGE plpgsql;
+
+-- only the hashed columns of a url change its buckets
+CREATE OR REPLACE FUNCTION mark_url_buckets()
+  RETURNS trigger AS $$
+    BEGIN
+      INSERT INTO dirty_buckets
+      SELECT DISTINCT provider_id, browser_type, new_rows.bucket
+      FROM new_rows
+        INNER JOIN old_rows ON old_rows.id = new_rows.id
+        INNER JOIN visits ON visits.url_id = new_rows.id
+      WHERE (old_rows.title, old_rows.tag, old_rows.privacy)
+        IS DISTINCT FROM (new_rows.title, new_rows.tag, new_rows.privacy)
+       ON CONFLICT DO NOTHING;
+      RETURN NULL;
+    END; $$ LANGUAGE plpgsql;
+
+CREATE TRIGGER visits_insert_buckets AFTER INSERT ON visits
+  REFERENCING NEW TABLE AS new_rows FOR EACH STATEMENT
+  EXECUTE FUNCTION mark_new_visit_buckets();
+CREATE TRIGGER visits_update_buckets AFTER UPDATE ON visits
+  REFERENCING NEW TABLE AS new_rows FOR EACH STATEMENT
+  EXECUTE FUNCTION mark_new_visit_buckets();
+CREATE TRIGGER visits_delete_buckets AFTER DELETE ON visits
+  REFERENCING OLD TABLE AS old_rows FOR EACH STATEMENT
+  EXECUTE FUNCTION mark_old_visit_buckets();
+CREATE TRIGGER urls_update_buckets AFTER UPDATE ON urls
+  REFERENCING OLD TABLE AS old_rows NEW TABLE AS new_rows FOR EACH STATEMENT
+  EXECUTE FUNCTION mark_url_buckets();
@@ -0,0 +1,51 @@
+-- Add migration script here
+-- navigations, search queries, downloads and favicons are replicated like urls and visits, see
+-- 20261019190000_create_change_log.sql and 20261019220000_add_origin_versions.sql
+ALTER TABLE navigations ADD COLUMN seq BIGINT NOT NULL DEFAULT next_change_seq(),
+  ADD COLUMN origin_node TEXT, ADD COLUMN origin_seq BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE search_queries ADD COLUMN seq BIGINT NOT NULL DEFAULT next_change_seq(),
+  ADD COLUMN origin_node TEXT, ADD COLUMN origin_seq BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE downloads ADD COLUMN seq BIGINT NOT NULL DEFAULT next_change_seq(),
+  ADD COLUMN origin_node TEXT, ADD COLUMN origin_seq BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE favicons ADD COLUMN seq BIGINT NOT NULL DEFAULT next_change_seq(),
+  ADD COLUMN origin_node TEXT, ADD COLUMN origin_seq BIGINT NOT NULL DEFAULT 0;
+UPDATE navigations SET origin_seq = seq;
+UPDATE search_queries SET origin_seq = seq;
+UPDATE downloads SET origin_seq = seq;
+UPDATE favicons SET origin_seq = seq;
+CREATE INDEX navigations_seq_idx ON navigations (seq);
+CREATE INDEX search_queries_seq_idx ON search_queries (seq);
+CREATE INDEX downloads_seq_idx ON downloads (seq);
+CREATE INDEX favicons_seq_idx ON favicons (seq);
+
+CREATE TRIGGER navigations_local_origin BEFORE INSERT ON navigations FOR EACH ROW
+  EXECUTE FUNCTION local_origin();
+CREATE TRIGGER search_queries_local_origin BEFORE INSERT ON search_queries FOR EACH ROW
+  EXECUTE FUNCTION local_origin();
+CREATE TRIGGER downloads_local_origin BEFORE INSERT ON downloads FOR EACH ROW
+  EXECUTE FUNCTION local_origin();
+CREATE TRIGGER favicons_local_origin BEFORE INSERT ON favicons FOR EACH ROW
+  EXECUTE FUNCTION local_origin();
+
+CREATE TRIGGER navigations_bump_seq BEFORE UPDATE ON navigations FOR EACH ROW
+  WHEN (OLD.from_url_id IS DISTINCT FROM NEW.from_url_id
+     OR OLD.to_url_id IS DISTINCT FROM NEW.to_url_id
+     OR OLD.visit_count IS DISTINCT FROM NEW.visit_count
+     OR OLD.last_visit_time IS DISTINCT FROM NEW.last_visit_time)
+  EXECUTE FUNCTION bump_seq();
+CREATE TRIGGER search_queries_bump_seq BEFORE UPDATE ON search_queries FOR EACH ROW
+  WHEN (OLD.query IS DISTINCT FROM NEW.query
+     OR OLD.engine IS DISTINCT FROM NEW.engine)
+  EXECUTE FUNCTION bump_seq();
+CREATE TRIGGER downloads_bump_seq BEFORE UPDATE ON downloads FOR EACH ROW
+  WHEN (OLD.url_id IS DISTINCT FROM NEW.url_id
+     OR OLD.file_name IS DISTINCT FROM NEW.file_name
+     OR OLD.size IS DISTINCT FROM NEW.size
+     OR OLD.mime IS DISTINCT FROM NEW.mime
+     OR OLD.referrer_id IS DISTINCT FROM NEW.referrer_id)
+  EXECUTE FUNCTION bump_seq();
+CREATE TRIGGER favicons_bump_seq BEFORE UPDATE ON favicons FOR EACH ROW
+  WHEN (OLD.mime IS DISTINCT FROM NEW.mime
+     OR OLD.width IS DISTINCT FROM NEW.width
+     OR OLD.data IS DISTINCT FROM NEW.data)
+  EXECUTE FUNCTION bump_seq();
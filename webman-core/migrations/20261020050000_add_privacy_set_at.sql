@@ -0,0 +1,79 @@
+-- Add migration script here
+-- when a user set the privacy of a url, NULL if it was derived from the privacy rules. The
+-- privacy a user set last wins, a privacy set by a user wins over a derived one, and derived
+-- privacy only becomes stricter. Of two privacies set at the same time the stricter one wins.
+ALTER TABLE urls ADD COLUMN privacy_set_at TIMESTAMP;
+
+DROP TRIGGER urls_bump_seq ON urls;
+CREATE TRIGGER urls_bump_seq BEFORE UPDATE ON urls FOR EACH ROW
+  WHEN (OLD.title IS DISTINCT FROM NEW.title
+     OR OLD.tag IS DISTINCT FROM NEW.tag
+     OR OLD.privacy IS DISTINCT FROM NEW.privacy
+     OR OLD.privacy_set_at IS DISTINCT FROM NEW.privacy_set_at)
+  EXECUTE FUNCTION bump_seq();
+
+-- the merged url keeps the privacy which wins
+CREATE OR REPLACE FUNCTION merge_url (from_id integer, to_id integer)
+  RETURNS integer AS $$
+    BEGIN
+      INSERT INTO visits(url_id, provider_id, browser_type, visit_count, last_visit_time,
+        duration_secs, duration_estimated, added_time)
+      SELECT to_id, provider_id, browser_type, visit_count, last_visit_time, duration_secs,
+        duration_estimated, added_time
+      FROM visits WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visits_pkey DO
+       UPDATE SET visit_count = visits.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(visits.last_visit_time, EXCLUDED.last_visit_time),
+                  duration_secs = visits.duration_secs + EXCLUDED.duration_secs,
+                  duration_estimated = visits.duration_estimated OR EXCLUDED.duration_estimated,
+                  added_time = LEAST(visits.added_time, EXCLUDED.added_time);
+      DELETE FROM visits WHERE url_id = from_id;
+      INSERT INTO visit_durations
+      SELECT to_id, provider_id, browser_type, visit_id, secs, estimated
+      FROM visit_durations WHERE url_id = from_id
+       ON CONFLICT ON CONSTRAINT visit_durations_pkey DO
+       UPDATE SET secs = visit_durations.secs + EXCLUDED.secs,
+                  estimated = visit_durations.estimated OR EXCLUDED.estimated;
+      DELETE FROM visit_durations WHERE url_id = from_id;
+      INSERT INTO session_urls
+      SELECT session_id, to_id FROM session_urls WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM session_urls WHERE url_id = from_id;
+      INSERT INTO navigations
+      SELECT CASE WHEN from_url_id = from_id THEN to_id ELSE from_url_id END,
+             CASE WHEN to_url_id = from_id THEN to_id ELSE to_url_id END,
+             provider_id, browser_type, visit_count, last_visit_time
+      FROM navigations
+      WHERE (from_url_id = from_id AND to_url_id != to_id)
+         OR (to_url_id = from_id AND from_url_id != to_id)
+       ON CONFLICT ON CONSTRAINT navigations_pkey DO
+       UPDATE SET visit_count = navigations.visit_count + EXCLUDED.visit_count,
+                  last_visit_time = GREATEST(navigations.last_visit_time, EXCLUDED.last_visit_time);
+      DELETE FROM navigations WHERE from_url_id = from_id OR to_url_id = from_id;
+      INSERT INTO search_queries
+      SELECT to_id, query, engine FROM search_queries WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM search_queries WHERE url_id = from_id;
+      INSERT INTO downloads
+      SELECT to_id, provider_id, browser_type, file_name, size, mime, referrer_id, start_time
+      FROM downloads WHERE url_id = from_id
+       ON CONFLICT DO NOTHING;
+      DELETE FROM downloads WHERE url_id = from_id;
+      UPDATE downloads SET referrer_id = to_id WHERE referrer_id = from_id;
+      UPDATE urls SET redirect_to = to_id WHERE redirect_to = from_id;
+      UPDATE urls SET redirect_to = NULL WHERE id = to_id AND redirect_to = to_id;
+      UPDATE urls SET tag = GREATEST(urls.tag, f.tag),
+        privacy = CASE
+          WHEN COALESCE(f.privacy_set_at > urls.privacy_set_at, f.privacy_set_at IS NOT NULL)
+            THEN f.privacy
+          WHEN f.privacy_set_at IS NOT DISTINCT FROM urls.privacy_set_at
+            THEN GREATEST(urls.privacy, f.privacy)
+          ELSE urls.privacy END,
+        privacy_set_at = GREATEST(urls.privacy_set_at, f.privacy_set_at)
+      FROM urls f WHERE urls.id = to_id AND f.id = from_id;
+      INSERT INTO url_tombstones(url, merged_into)
+      SELECT f.url, t.url FROM urls f, urls t WHERE f.id = from_id AND t.id = to_id
+       ON CONFLICT (url) DO UPDATE SET merged_into = EXCLUDED.merged_into;
+      DELETE FROM urls WHERE id = from_id;
+      RETURN to_id;
+    END; $$ LANGUAGE plpgsql;
@@ -6,15 +6,24 @@ mod vivaldi;
 
 use self::{firefox::Firefox, vivaldi::Vivaldi};
 
-use super::Browser;
-use crate::{url::Url, web::resp::UrlInsert, ToOk};
+use super::{Browser, UrlRules};
+use crate::{
+    config,
+    download::Download,
+    error::WebmanError,
+    favicon::Favicon,
+    navigation::{self, NavigationEdge},
+    url::{duration, SearchTerm, Url, UrlDuration},
+    web::resp::UrlInsert,
+    ToOk,
+};
 use anyhow::{Context, Result};
 use chrono::NaiveDateTime;
 use floorp::Floorp;
-use log::info;
+use log::{info, warn};
 use rusqlite::{types::FromSql, Connection, OpenFlags, ToSql};
 use serde::{Deserialize, Serialize};
-use std::{borrow::Cow, fmt::Debug};
+use std::{borrow::Cow, collections::HashSet, fmt::Debug, path::Path};
 const TEMP_DIR: &str = "/tmp";
 use chromium::Chromium;
 use safari::Safari;
@@ -23,6 +32,8 @@ use safari::Safari;
 pub struct BrowserSetting {
     pub browser: Browser,
     pub location: Option<String>,
+    #[serde(flatten)]
+    pub rules: UrlRules,
 }
 
 impl BrowserSetting {
@@ -33,14 +44,33 @@ impl BrowserSetting {
         }
     }
 
-    pub fn url_insert(&self, provider: String, since: NaiveDateTime) -> Result<Option<UrlInsert>> {
+    /// mine the urls since `since`, urls which are not allowed by the `global_rules` or the rules of
+    /// this browser are dropped, the rest are sanitized by the rules in config.
+    pub fn url_insert(
+        &self,
+        provider: String,
+        since: NaiveDateTime,
+        global_rules: &UrlRules,
+    ) -> Result<Option<UrlInsert>> {
         let loc = self.location();
         let mut last_import_time = None;
-        let urls = match self.browser {
+        let mut edges = vec![];
+        let mut search_terms = vec![];
+        let mut redirects = vec![];
+        let mut durations = vec![];
+        let mut favicons = vec![];
+        let mut downloads = vec![];
+        let mut urls = match self.browser {
             Browser::Chromium => {
                 let conn = Chromium.establish_connection(&loc).with_context(|| {
                     format!("could not connect to {:?} browser db", self.browser)
                 })?;
+                edges = Chromium.mine_edges(&conn, since);
+                redirects = Chromium.mine_redirects(&conn, since);
+                search_terms = Chromium.mine_search_terms(&conn, since);
+                durations = Chromium.mine_durations(&conn, since);
+                favicons = Chromium.mine_favicons(&loc);
+                downloads = Chromium.mine_downloads(&conn, since);
                 Chromium.mine_urls(&conn, since)
             }
             Browser::Vivaldi => {
@@ -48,6 +78,12 @@ impl BrowserSetting {
                 let conn = vi.establish_connection(&loc).with_context(|| {
                     format!("could not connect to {:?} browser db", self.browser)
                 })?;
+                edges = vi.mine_edges(&conn, since);
+                redirects = vi.mine_redirects(&conn, since);
+                search_terms = vi.mine_search_terms(&conn, since);
+                durations = vi.mine_durations(&conn, since);
+                favicons = vi.mine_favicons(&loc);
+                downloads = vi.mine_downloads(&conn, since);
                 vi.mine_urls(&conn, since)
             }
             Browser::Safari => {
@@ -58,13 +94,24 @@ impl BrowserSetting {
                     )
                 })?;
                 last_import_time = Some(Safari.last_import_time(&conn)?);
+                durations = Safari.mine_durations(&conn, since);
+                favicons = Safari.mine_favicons(&loc);
+                downloads = Safari.mine_downloads(&conn, since);
                 Safari.mine_urls(&conn, since)
             }
-            Browser::Chrome => todo!(),
+            Browser::Chrome => Err(WebmanError::Miner(
+                "mining the history of chrome is not supported yet".to_owned(),
+            )
+            .into()),
             Browser::Firefox => {
                 let conn = Firefox.establish_connection(&loc).with_context(|| {
                     format!("could not connect to {:?} browser db", self.browser)
                 })?;
+                edges = Firefox.mine_edges(&conn, since);
+                redirects = Firefox.mine_redirects(&conn, since);
+                durations = Firefox.mine_durations(&conn, since);
+                favicons = Firefox.mine_favicons(&loc);
+                downloads = Firefox.mine_downloads(&conn, since);
                 Firefox.mine_urls(&conn, since)
             }
             Browser::Floorp => {
@@ -72,10 +119,55 @@ impl BrowserSetting {
                 let conn = fl.establish_connection(&loc).with_context(|| {
                     format!("could not connect to {:?} browser db", self.browser)
                 })?;
+                edges = fl.mine_edges(&conn, since);
+                redirects = fl.mine_redirects(&conn, since);
+                durations = fl.mine_durations(&conn, since);
+                favicons = fl.mine_favicons(&loc);
+                downloads = fl.mine_downloads(&conn, since);
                 fl.mine_urls(&conn, since)
             }
         }?;
-        if urls.is_empty() {
+        let total = urls.len();
+        let rules = global_rules.merge(&self.rules);
+        urls.retain(|u| rules.allows(&u.url));
+        if urls.len() < total {
+            info!(
+                "excluded {} of {} urls from {:?}",
+                total - urls.len(),
+                total,
+                self.browser
+            );
+        }
+        edges.retain(|e| rules.allows(&e.from_url) && rules.allows(&e.to_url));
+        redirects.retain(|e| rules.allows(&e.from_url) && rules.allows(&e.to_url));
+        search_terms.retain(|t| rules.allows(&t.url));
+        for t in search_terms.iter_mut() {
+            t.url = config().sanitize.sanitize(&t.url).into_owned();
+        }
+        downloads.retain(|d| rules.allows(&d.url));
+        for d in downloads.iter_mut() {
+            d.url = config().sanitize.sanitize(&d.url).into_owned();
+            d.referrer = d
+                .referrer
+                .take()
+                .filter(|r| rules.allows(r))
+                .map(|r| config().sanitize.sanitize(&r).into_owned());
+        }
+        let urls = config().sanitize.apply(urls);
+        let edges = navigation::sanitize_edges(&config().sanitize, edges);
+        let redirects = navigation::sanitize_edges(&config().sanitize, redirects);
+        durations.retain(|d| rules.allows(&d.url));
+        for d in durations.iter_mut() {
+            d.url = config().sanitize.sanitize(&d.url).into_owned();
+        }
+        let durations = UrlDuration::dedup(durations);
+        // only the icons of the hosts visited since the last import are uploaded
+        let hosts: HashSet<_> = urls
+            .iter()
+            .flat_map(|u| crate::url::normalized_host(&u.url))
+            .collect();
+        let favicons = Favicon::dedup(favicons.into_iter().filter(|f| hosts.contains(&f.host)));
+        if urls.is_empty() && downloads.is_empty() {
             info!("no new urls found");
             Ok(None)
         } else {
@@ -85,6 +177,13 @@ impl BrowserSetting {
                 browser: self.browser,
                 urls,
                 last_import_time,
+                edges,
+                search_terms,
+                redirects,
+                durations,
+                favicons,
+                downloads,
+                chunk: None,
             }))
         }
     }
@@ -100,8 +199,12 @@ pub fn establish_connection_in_place(location: &str) -> Result<Connection> {
 pub fn establish_connection_copy(location: &str, db_name: &str) -> Result<Connection> {
     let copied_path = format!("{}/{}", TEMP_DIR, db_name);
     let uri = &copied_path;
-    std::fs::copy(location, &copied_path)?;
-    std::fs::copy(
+    let copy = |from: String, to: String| {
+        std::fs::copy(&from, &to)
+            .map_err(|e| WebmanError::Miner(format!("could not copy {} to {}: {}", from, to, e)))
+    };
+    copy(location.to_owned(), copied_path.clone())?;
+    copy(
         format!("{}-wal", location),
         format!("{}/{}-wal", TEMP_DIR, db_name),
     )?;
@@ -122,6 +225,7 @@ impl Url {
             title: title.unwrap_or_default(),
             visit_count: visit_count.unwrap_or_default(),
             last_visit_time,
+            ..Default::default()
         }
     }
 }
@@ -130,11 +234,37 @@ pub trait BrowserMiner {
     type Timestamp: Sized + Debug + ToSql + FromSql;
     const BROWSER_TYPE: Browser;
     const QUERY: &'static str;
+    /// the query of the links followed since a time, which returns the url of the source page,
+    /// the url of the target page and the visit time. None if the browser does not record them.
+    const EDGE_QUERY: Option<&'static str> = None;
+    /// the same as `EDGE_QUERY`, but only returns the redirects from a transient page to the
+    /// page it redirected to
+    const REDIRECT_QUERY: Option<&'static str> = None;
+    /// the query of the time spent on the visits since a time, which returns the id of the visit,
+    /// the url and the duration in microseconds
+    const DURATION_QUERY: Option<&'static str> = None;
+    /// the query of all visits since a time sorted by time, which returns the id of the visit, the
+    /// url and the visit time. It is used to estimate the durations if `DURATION_QUERY` is None.
+    const VISIT_QUERY: Option<&'static str> = None;
+    /// the file name of the favicon database, which is in the same directory as the history
+    const FAVICON_DB: Option<&'static str> = None;
+    /// the query of the favicons, which returns the page url, the image data and its width
+    const FAVICON_QUERY: Option<&'static str> = None;
+    /// the query of the finished downloads started since a time, which returns the source url,
+    /// the path of the file, its size, its mime type, the referrer page and the start time
+    const DOWNLOAD_QUERY: Option<&'static str> = None;
+    /// the query of the search terms recorded for the result pages visited since a time, which
+    /// returns the url and the term
+    const SEARCH_TERM_QUERY: Option<&'static str> = None;
 
     fn ts_to_datetime(&self, ts: Self::Timestamp) -> NaiveDateTime;
     fn datetime_to_ts(&self, dt: NaiveDateTime) -> Self::Timestamp;
     fn establish_connection(&self, location: &str) -> Result<Connection>;
 
+    fn establish_favicon_connection(&self, location: &str) -> Result<Connection> {
+        establish_connection_in_place(location)
+    }
+
     fn mine_urls(
         &self,
         conn: &Connection,
@@ -160,4 +290,196 @@ pub trait BrowserMiner {
             .context("could not query browser db")?;
         Ok(rows.flat_map(|r| r.to_ok()).collect())
     }
+
+    fn query_edges(
+        &self,
+        conn: &Connection,
+        query: Option<&str>,
+        since: NaiveDateTime,
+    ) -> Result<Vec<NavigationEdge>> {
+        let query = match query {
+            Some(q) => q,
+            None => return Ok(vec![]),
+        };
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt
+            .query_map([self.datetime_to_ts(since)], |row| {
+                Ok(NavigationEdge::new(
+                    row.get(0)?,
+                    row.get(1)?,
+                    self.ts_to_datetime(row.get(2)?),
+                ))
+            })
+            .context("could not query navigations in browser db")?;
+        Ok(rows.flat_map(|r| r.to_ok()).collect())
+    }
+
+    /// the navigations are only extra information, so the urls are imported without them if they
+    /// cannot be mined
+    fn mine_edges(&self, conn: &Connection, since: NaiveDateTime) -> Vec<NavigationEdge> {
+        self.query_edges(conn, Self::EDGE_QUERY, since)
+            .unwrap_or_else(|e| {
+                warn!(
+                    "could not mine navigations of {:?}: {:?}",
+                    Self::BROWSER_TYPE,
+                    e
+                );
+                vec![]
+            })
+    }
+
+    /// the redirects only hide transient pages, the urls are imported without them if they cannot
+    /// be mined
+    fn mine_redirects(&self, conn: &Connection, since: NaiveDateTime) -> Vec<NavigationEdge> {
+        self.query_edges(conn, Self::REDIRECT_QUERY, since)
+            .unwrap_or_else(|e| {
+                warn!(
+                    "could not mine redirects of {:?}: {:?}",
+                    Self::BROWSER_TYPE,
+                    e
+                );
+                vec![]
+            })
+    }
+
+    fn query_search_terms(
+        &self,
+        conn: &Connection,
+        since: NaiveDateTime,
+    ) -> Result<Vec<SearchTerm>> {
+        let query = match Self::SEARCH_TERM_QUERY {
+            Some(q) => q,
+            None => return Ok(vec![]),
+        };
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt
+            .query_map([self.datetime_to_ts(since)], |row| {
+                Ok(SearchTerm {
+                    url: row.get(0)?,
+                    term: row.get(1)?,
+                })
+            })
+            .context("could not query search terms in browser db")?;
+        Ok(rows.flat_map(|r| r.to_ok()).collect())
+    }
+
+    /// the search terms are only extra information, an import does not fail because of them
+    fn mine_search_terms(&self, conn: &Connection, since: NaiveDateTime) -> Vec<SearchTerm> {
+        self.query_search_terms(conn, since).unwrap_or_else(|e| {
+            warn!(
+                "could not mine search terms of {:?}: {:?}",
+                Self::BROWSER_TYPE,
+                e
+            );
+            vec![]
+        })
+    }
+
+    fn query_durations(&self, conn: &Connection, since: NaiveDateTime) -> Result<Vec<UrlDuration>> {
+        let since = self.datetime_to_ts(since);
+        if let Some(query) = Self::DURATION_QUERY {
+            let mut stmt = conn.prepare(query)?;
+            let rows = stmt
+                .query_map([since], |row| {
+                    Ok(UrlDuration {
+                        visit_id: row.get(0)?,
+                        url: row.get(1)?,
+                        secs: row.get::<_, i64>(2)? / 1_000_000,
+                        estimated: false,
+                    })
+                })
+                .context("could not query visit durations in browser db")?;
+            Ok(rows
+                .flat_map(|r| r.to_ok())
+                .filter(|d| d.secs > 0)
+                .collect())
+        } else if let Some(query) = Self::VISIT_QUERY {
+            let mut stmt = conn.prepare(query)?;
+            let rows = stmt
+                .query_map([since], |row| {
+                    Ok((row.get(0)?, row.get(1)?, self.ts_to_datetime(row.get(2)?)))
+                })
+                .context("could not query visits in browser db")?;
+            Ok(duration::estimate(rows.flat_map(|r| r.to_ok())))
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    /// the time spent on pages is only extra information, an import does not fail because of it
+    fn mine_durations(&self, conn: &Connection, since: NaiveDateTime) -> Vec<UrlDuration> {
+        self.query_durations(conn, since).unwrap_or_else(|e| {
+            warn!(
+                "could not mine durations of {:?}: {:?}",
+                Self::BROWSER_TYPE,
+                e
+            );
+            vec![]
+        })
+    }
+
+    fn query_downloads(&self, conn: &Connection, since: NaiveDateTime) -> Result<Vec<Download>> {
+        let query = match Self::DOWNLOAD_QUERY {
+            Some(q) => q,
+            None => return Ok(vec![]),
+        };
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt
+            .query_map([self.datetime_to_ts(since)], |row| {
+                Ok(Download::from_path(
+                    row.get(0)?,
+                    &row.get::<_, String>(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    self.ts_to_datetime(row.get(5)?),
+                ))
+            })
+            .context("could not query downloads in browser db")?;
+        Ok(rows.flat_map(|r| r.to_ok()).flatten().collect())
+    }
+
+    /// the downloads are only extra information, an import does not fail because of them
+    fn mine_downloads(&self, conn: &Connection, since: NaiveDateTime) -> Vec<Download> {
+        self.query_downloads(conn, since).unwrap_or_else(|e| {
+            warn!(
+                "could not mine downloads of {:?}: {:?}",
+                Self::BROWSER_TYPE,
+                e
+            );
+            vec![]
+        })
+    }
+
+    fn query_favicons(&self, history_location: &str) -> Result<Vec<Favicon>> {
+        let (db, query) = match (Self::FAVICON_DB, Self::FAVICON_QUERY) {
+            (Some(db), Some(query)) => (db, query),
+            _ => return Ok(vec![]),
+        };
+        let location = Path::new(history_location).with_file_name(db);
+        let conn = self.establish_favicon_connection(&location.to_string_lossy())?;
+        let mut stmt = conn.prepare(query)?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok(Favicon::from_page(
+                    &row.get::<_, String>(0)?,
+                    row.get(1)?,
+                    row.get::<_, Option<i32>>(2)?.unwrap_or_default(),
+                ))
+            })
+            .context("could not query favicons in browser db")?;
+        Ok(Favicon::dedup(rows.flat_map(|r| r.to_ok()).flatten()))
+    }
+
+    /// the best favicon of every host, an import does not fail because of missing favicons
+    fn mine_favicons(&self, history_location: &str) -> Vec<Favicon> {
+        self.query_favicons(history_location).unwrap_or_else(|e| {
+            warn!(
+                "could not mine favicons of {:?}: {:?}",
+                Self::BROWSER_TYPE,
+                e
+            );
+            vec![]
+        })
+    }
 }
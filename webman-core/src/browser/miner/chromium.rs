@@ -20,6 +20,70 @@ FROM urls
 WHERE last_visit_time > ? AND length(url) < 2500
 "#;
 
+    const EDGE_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT from_urls.url, to_urls.url, visits.visit_time
+FROM visits
+  INNER JOIN visits AS from_visits ON from_visits.id = visits.from_visit
+  INNER JOIN urls AS to_urls ON to_urls.id = visits.url
+  INNER JOIN urls AS from_urls ON from_urls.id = from_visits.url
+WHERE visits.visit_time > ? AND from_urls.id != to_urls.id
+  AND length(from_urls.url) < 2500 AND length(to_urls.url) < 2500
+"#,
+    );
+
+    // 3221225472 is the mask of the qualifiers CLIENT_REDIRECT and SERVER_REDIRECT
+    const REDIRECT_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT from_urls.url, to_urls.url, visits.visit_time
+FROM visits
+  INNER JOIN visits AS from_visits ON from_visits.id = visits.from_visit
+  INNER JOIN urls AS to_urls ON to_urls.id = visits.url
+  INNER JOIN urls AS from_urls ON from_urls.id = from_visits.url
+WHERE visits.visit_time > ? AND from_urls.id != to_urls.id
+  AND (visits.transition & 3221225472) != 0
+  AND length(from_urls.url) < 2500 AND length(to_urls.url) < 2500
+"#,
+    );
+
+    const DURATION_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT visits.id, urls.url, visits.visit_duration
+FROM visits INNER JOIN urls ON urls.id = visits.url
+WHERE visits.visit_time > ? AND length(urls.url) < 2500 AND visits.visit_duration > 0
+"#,
+    );
+
+    const SEARCH_TERM_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT urls.url, keyword_search_terms.term
+FROM keyword_search_terms INNER JOIN urls ON urls.id = keyword_search_terms.url_id
+WHERE urls.last_visit_time > ? AND length(urls.url) < 2500
+"#,
+    );
+
+    // state 1 is COMPLETE, the first url of the chain is the link which started the download
+    const DOWNLOAD_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT downloads_url_chains.url, downloads.target_path, downloads.total_bytes,
+  downloads.mime_type, COALESCE(NULLIF(downloads.tab_url, ''), downloads.referrer),
+  downloads.start_time
+FROM downloads INNER JOIN downloads_url_chains ON downloads_url_chains.id = downloads.id
+WHERE downloads.start_time > ? AND downloads.state = 1
+  AND downloads_url_chains.chain_index = 0 AND length(downloads_url_chains.url) < 2500
+"#,
+    );
+
+    const FAVICON_DB: Option<&'static str> = Some("Favicons");
+
+    const FAVICON_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT icon_mapping.page_url, favicon_bitmaps.image_data, favicon_bitmaps.width
+FROM icon_mapping INNER JOIN favicon_bitmaps ON favicon_bitmaps.icon_id = icon_mapping.icon_id
+WHERE favicon_bitmaps.image_data IS NOT NULL AND length(icon_mapping.page_url) < 2500
+"#,
+    );
+
     fn ts_to_datetime(&self, dt: Self::Timestamp) -> NaiveDateTime {
         let sec = dt / 1000000 - CHROME_UNIX_DIFF;
         let nano_sec = (dt % 1000000) * 1000;
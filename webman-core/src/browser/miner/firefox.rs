@@ -18,6 +18,70 @@ FROM moz_places
 WHERE last_visit_date > ? AND length(url) < 2500
 "#;
 
+    const EDGE_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT from_places.url, to_places.url, moz_historyvisits.visit_date
+FROM moz_historyvisits
+  INNER JOIN moz_historyvisits AS from_visits ON from_visits.id = moz_historyvisits.from_visit
+  INNER JOIN moz_places AS to_places ON to_places.id = moz_historyvisits.place_id
+  INNER JOIN moz_places AS from_places ON from_places.id = from_visits.place_id
+WHERE moz_historyvisits.visit_date > ? AND from_places.id != to_places.id
+  AND length(from_places.url) < 2500 AND length(to_places.url) < 2500
+"#,
+    );
+
+    // 5 and 6 are the visit types TRANSITION_REDIRECT_PERMANENT and TRANSITION_REDIRECT_TEMPORARY
+    const REDIRECT_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT from_places.url, to_places.url, moz_historyvisits.visit_date
+FROM moz_historyvisits
+  INNER JOIN moz_historyvisits AS from_visits ON from_visits.id = moz_historyvisits.from_visit
+  INNER JOIN moz_places AS to_places ON to_places.id = moz_historyvisits.place_id
+  INNER JOIN moz_places AS from_places ON from_places.id = from_visits.place_id
+WHERE moz_historyvisits.visit_date > ? AND from_places.id != to_places.id
+  AND moz_historyvisits.visit_type IN (5, 6)
+  AND length(from_places.url) < 2500 AND length(to_places.url) < 2500
+"#,
+    );
+
+    const VISIT_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT moz_historyvisits.id, moz_places.url, moz_historyvisits.visit_date
+FROM moz_historyvisits INNER JOIN moz_places ON moz_places.id = moz_historyvisits.place_id
+WHERE moz_historyvisits.visit_date > ? AND length(moz_places.url) < 2500
+ORDER BY moz_historyvisits.visit_date
+"#,
+    );
+
+    // the downloads are stored as annotations of the source url, state 1 is finished
+    const DOWNLOAD_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT moz_places.url, dest.content, json_extract(meta.content, '$.fileSize'), NULL, NULL,
+  dest.dateAdded
+FROM moz_annos AS dest
+  INNER JOIN moz_anno_attributes AS dest_attr ON dest_attr.id = dest.anno_attribute_id
+  INNER JOIN moz_places ON moz_places.id = dest.place_id
+  LEFT JOIN moz_anno_attributes AS meta_attr ON meta_attr.name = 'downloads/metaData'
+  LEFT JOIN moz_annos AS meta
+    ON meta.place_id = dest.place_id AND meta.anno_attribute_id = meta_attr.id
+WHERE dest.dateAdded > ? AND dest_attr.name = 'downloads/destinationFileURI'
+  AND COALESCE(json_extract(meta.content, '$.state'), 1) = 1
+  AND length(moz_places.url) < 2500
+"#,
+    );
+
+    const FAVICON_DB: Option<&'static str> = Some("favicons.sqlite");
+
+    const FAVICON_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT moz_pages_w_icons.page_url, moz_icons.data, moz_icons.width
+FROM moz_icons_to_pages
+  INNER JOIN moz_pages_w_icons ON moz_pages_w_icons.id = moz_icons_to_pages.page_id
+  INNER JOIN moz_icons ON moz_icons.id = moz_icons_to_pages.icon_id
+WHERE moz_icons.data IS NOT NULL AND length(moz_pages_w_icons.page_url) < 2500
+"#,
+    );
+
     fn ts_to_datetime(&self, dt: Self::Timestamp) -> NaiveDateTime {
         let sec = dt / 1000000;
         NaiveDateTime::from_timestamp(sec, 0)
@@ -30,11 +94,15 @@ WHERE last_visit_date > ? AND length(url) < 2500
     fn establish_connection(&self, location: &str) -> Result<Connection> {
         establish_connection_copy(location, "firefox.db")
     }
+
+    fn establish_favicon_connection(&self, location: &str) -> Result<Connection> {
+        establish_connection_copy(location, "firefox_favicons.db")
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::browser::BrowserSetting;
+    use crate::browser::{BrowserSetting, UrlRules};
 
     use super::*;
     #[test]
@@ -42,8 +110,25 @@ mod tests {
         BrowserSetting {
             browser: Browser::Firefox,
             location: None,
+            rules: UrlRules::default(),
         }
-        .url_insert("firefox".to_string(), NaiveDateTime::from_timestamp(0, 0))
+        .url_insert(
+            "firefox".to_string(),
+            NaiveDateTime::from_timestamp(0, 0),
+            &UrlRules::default(),
+        )
         .unwrap();
     }
+
+    #[test]
+    fn mine_without_tables() {
+        // a profile without the tables of the extra information still imports its urls
+        let conn = Connection::open_in_memory().unwrap();
+        let since = NaiveDateTime::from_timestamp(0, 0);
+        assert!(Firefox.mine_edges(&conn, since).is_empty());
+        assert!(Firefox.mine_redirects(&conn, since).is_empty());
+        assert!(Firefox.mine_search_terms(&conn, since).is_empty());
+        assert!(Firefox.mine_durations(&conn, since).is_empty());
+        assert!(Firefox.mine_downloads(&conn, since).is_empty());
+    }
 }
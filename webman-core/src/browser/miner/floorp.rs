@@ -14,6 +14,18 @@ impl BrowserMiner for Floorp {
 
     const QUERY: &'static str = <Firefox as BrowserMiner>::QUERY;
 
+    const EDGE_QUERY: Option<&'static str> = <Firefox as BrowserMiner>::EDGE_QUERY;
+
+    const REDIRECT_QUERY: Option<&'static str> = <Firefox as BrowserMiner>::REDIRECT_QUERY;
+
+    const VISIT_QUERY: Option<&'static str> = <Firefox as BrowserMiner>::VISIT_QUERY;
+
+    const DOWNLOAD_QUERY: Option<&'static str> = <Firefox as BrowserMiner>::DOWNLOAD_QUERY;
+
+    const FAVICON_DB: Option<&'static str> = <Firefox as BrowserMiner>::FAVICON_DB;
+
+    const FAVICON_QUERY: Option<&'static str> = <Firefox as BrowserMiner>::FAVICON_QUERY;
+
     fn ts_to_datetime(&self, dt: Self::Timestamp) -> NaiveDateTime {
         self.0.ts_to_datetime(dt)
     }
@@ -25,4 +37,8 @@ impl BrowserMiner for Floorp {
     fn establish_connection(&self, location: &str) -> Result<Connection> {
         self.0.establish_connection(location)
     }
+
+    fn establish_favicon_connection(&self, location: &str) -> Result<Connection> {
+        self.0.establish_favicon_connection(location)
+    }
 }
@@ -42,6 +42,15 @@ FROM history_items HI INNER JOIN last_visits LV ON HI.id = LV.history_item
 WHERE length(url) < 2500
 "#;
 
+    const VISIT_QUERY: Option<&'static str> = Some(
+        r#"
+SELECT history_visits.id, history_items.url, history_visits.visit_time
+FROM history_visits INNER JOIN history_items ON history_items.id = history_visits.history_item
+WHERE history_visits.visit_time > ? AND length(history_items.url) < 2500
+ORDER BY history_visits.visit_time
+"#,
+    );
+
     fn ts_to_datetime(&self, dt: Self::Timestamp) -> NaiveDateTime {
         let integer = dt as i64;
         let sec = integer + SAFARI_UNIX_DIFF;
@@ -80,6 +89,33 @@ mod tests {
         assert_eq!(Safari.datetime_to_ts(datetime), ts);
     }
 
+    #[test]
+    fn safari_durations() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            r#"
+CREATE TABLE history_items (id INTEGER PRIMARY KEY, url TEXT);
+CREATE TABLE history_visits (id INTEGER PRIMARY KEY, history_item INTEGER, visit_time REAL);
+INSERT INTO history_items VALUES (1, 'https://a.com'), (2, 'https://b.com');
+INSERT INTO history_visits VALUES (10, 1, 100.5), (11, 2, 130.5), (12, 1, 200.5);
+"#,
+        )
+        .unwrap();
+        let since = Safari.ts_to_datetime(0.);
+        let durations: Vec<_> = Safari
+            .mine_durations(&conn, since)
+            .into_iter()
+            .map(|d| (d.visit_id, d.url, d.secs, d.estimated))
+            .collect();
+        assert_eq!(
+            durations,
+            vec![
+                (10, "https://a.com".to_owned(), 30, true),
+                (11, "https://b.com".to_owned(), 70, true),
+            ]
+        );
+    }
+
     #[ignore]
     #[test]
     fn safari_last_import_time() {
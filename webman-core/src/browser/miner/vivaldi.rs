@@ -14,6 +14,20 @@ impl BrowserMiner for Vivaldi {
 
     const QUERY: &'static str = <Chromium as BrowserMiner>::QUERY;
 
+    const EDGE_QUERY: Option<&'static str> = <Chromium as BrowserMiner>::EDGE_QUERY;
+
+    const REDIRECT_QUERY: Option<&'static str> = <Chromium as BrowserMiner>::REDIRECT_QUERY;
+
+    const DURATION_QUERY: Option<&'static str> = <Chromium as BrowserMiner>::DURATION_QUERY;
+
+    const DOWNLOAD_QUERY: Option<&'static str> = <Chromium as BrowserMiner>::DOWNLOAD_QUERY;
+
+    const FAVICON_DB: Option<&'static str> = <Chromium as BrowserMiner>::FAVICON_DB;
+
+    const FAVICON_QUERY: Option<&'static str> = <Chromium as BrowserMiner>::FAVICON_QUERY;
+
+    const SEARCH_TERM_QUERY: Option<&'static str> = <Chromium as BrowserMiner>::SEARCH_TERM_QUERY;
+
     fn ts_to_datetime(&self, dt: Self::Timestamp) -> NaiveDateTime {
         self.0.ts_to_datetime(dt)
     }
@@ -25,4 +39,8 @@ impl BrowserMiner for Vivaldi {
     fn establish_connection(&self, location: &str) -> Result<Connection> {
         self.0.establish_connection(location)
     }
+
+    fn establish_favicon_connection(&self, location: &str) -> Result<Connection> {
+        self.0.establish_favicon_connection(location)
+    }
 }
@@ -1,11 +1,16 @@
 #[cfg(feature = "browser")]
 mod miner;
 #[cfg(feature = "browser")]
+mod rules;
+#[cfg(feature = "browser")]
 pub use miner::BrowserSetting;
+#[cfg(feature = "browser")]
+pub use rules::UrlRules;
 
 use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
 
-#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, EnumString, Display)]
 #[cfg_attr(feature = "server", derive(sqlx::Type))]
 #[cfg_attr(
     feature = "server",
@@ -21,6 +26,13 @@ pub enum Browser {
     Floorp,
 }
 
+#[cfg(feature = "server")]
+impl sqlx::postgres::PgHasArrayType for Browser {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_browser")
+    }
+}
+
 #[cfg(feature = "browser")]
 impl Browser {
     pub fn default_location(&self) -> String {
@@ -0,0 +1,49 @@
+use crate::url::UrlMatcher;
+use serde::{Deserialize, Serialize};
+
+/// Decides which mined urls are uploaded. A url is uploaded if it matches any of `include` (or
+/// `include` is empty) and none of `exclude`.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Default)]
+pub struct UrlRules {
+    #[serde(default)]
+    pub include: Vec<UrlMatcher>,
+    #[serde(default)]
+    pub exclude: Vec<UrlMatcher>,
+}
+
+impl UrlRules {
+    pub fn allows(&self, url: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|m| m.is_match(url)))
+            && !self.exclude.iter().any(|m| m.is_match(url))
+    }
+
+    /// combine the rules of both sides
+    pub fn merge(&self, other: &Self) -> Self {
+        Self {
+            include: [&self.include[..], &other.include[..]].concat(),
+            exclude: [&self.exclude[..], &other.exclude[..]].concat(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows() {
+        let global: UrlRules =
+            serde_json::from_str(r#"{"exclude": [{"scheme": "file"}, {"domain": "localhost"}]}"#)
+                .unwrap();
+        assert!(global.allows("https://docs.rs"));
+        assert!(!global.allows("file:///home/weiss/a.pdf"));
+        assert!(!global.allows("http://localhost:3000/"));
+
+        let browser: UrlRules =
+            serde_json::from_str(r#"{"include": [{"regex": "^https://"}]}"#).unwrap();
+        let rules = global.merge(&browser);
+        assert!(rules.allows("https://docs.rs"));
+        assert!(!rules.allows("http://docs.rs"));
+        assert!(!rules.allows("https://localhost/"));
+    }
+}
@@ -1,6 +1,6 @@
 use std::{env, path::PathBuf};
 
-use crate::node::Nodes;
+use crate::{discovery::DiscoveryConfig, node::Nodes, url::SanitizeRules};
 use figment::{
     providers::{Format, Toml},
     Figment,
@@ -38,6 +38,12 @@ pub struct Config {
     pub name: Option<String>,
     pub api_key: String,
     pub nodes: Nodes,
+    /// rules to remove tracking parameters and secrets from urls before they are inserted
+    #[serde(default)]
+    pub sanitize: SanitizeRules,
+    /// find the nodes in the LAN, disabled if not set
+    #[serde(default)]
+    pub discovery: Option<DiscoveryConfig>,
 }
 
 pub fn config() -> &'static Config {
@@ -0,0 +1,91 @@
+use super::{provider, url, Conn, Pool};
+use crate::{browser::Browser, download::Download, url::PrivacyRules};
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+
+/// Store the downloads of the provider and browser. The source urls which are not stored yet are
+/// inserted without a visit, titled by the file name and classified by the privacy rules.
+pub async fn insert_downloads(
+    pool: &mut Conn,
+    name: &str,
+    browser: Browser,
+    downloads: Vec<Download>,
+    rules: &PrivacyRules,
+) -> Result<u64> {
+    if downloads.is_empty() {
+        return Ok(0);
+    }
+    let p_id = provider::find_or_insert(&mut *pool, name).await?;
+    url::insert_missing(
+        &mut *pool,
+        downloads.iter().map(|d| d.url.clone()).collect(),
+        downloads.iter().map(|d| d.file_name.clone()).collect(),
+        downloads
+            .iter()
+            .map(|d| rules.classify(&d.url, name, browser))
+            .collect(),
+    )
+    .await?;
+    let n = downloads.len();
+    let (mut urls, mut file_names, mut sizes, mut mimes, mut referrers, mut times) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    for d in downloads {
+        urls.push(d.url);
+        file_names.push(d.file_name);
+        sizes.push(d.size);
+        mimes.push(d.mime);
+        referrers.push(d.referrer);
+        times.push(d.start_time);
+    }
+    sqlx::query!(
+        r#"
+INSERT INTO downloads(url_id, provider_id, browser_type, file_name, size, mime, referrer_id, start_time)
+SELECT urls.id, $1, $2, file_name, size, mime, referrers.id, start_time
+FROM UNNEST($3::text[], $4::text[], $5::bigint[], $6::text[], $7::text[], $8::timestamp[])
+    AS data(url, file_name, size, mime, referrer, start_time)
+  INNER JOIN urls ON urls.url = data.url
+  LEFT JOIN urls AS referrers ON referrers.url = data.referrer
+ON CONFLICT DO NOTHING
+"#,
+        p_id,
+        browser as Browser,
+        &urls,
+        &file_names,
+        &sizes as &[Option<i64>],
+        &mimes as &[Option<String>],
+        &referrers as &[Option<String>],
+        &times as &[NaiveDateTime],
+    )
+    .execute(pool)
+    .await
+    .context("could not insert downloads")
+    .map(|r| r.rows_affected())
+}
+
+/// the files downloaded from `url`, the latest first
+pub async fn downloads_of_url(pool: &mut Pool, url: &str, unlocked: bool) -> Result<Vec<Download>> {
+    sqlx::query_as!(
+        Download,
+        r#"
+SELECT DISTINCT ON (start_time, file_name) urls.url, file_name, size, mime,
+  referrers.url as "referrer?", start_time
+FROM downloads
+  INNER JOIN urls ON urls.id = downloads.url_id
+  LEFT JOIN urls AS referrers ON referrers.id = downloads.referrer_id
+    AND ($2 OR referrers.privacy = 'normal')
+WHERE urls.url = $1 AND ($2 OR urls.privacy = 'normal')
+ORDER BY start_time DESC, file_name
+"#,
+        url,
+        unlocked,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get downloads of {}", url))
+}
@@ -0,0 +1,63 @@
+use super::{host, Conn, Pool};
+use crate::favicon::{Favicon, PREFERRED_WIDTH};
+use anyhow::{Context, Result};
+
+/// store the icons, an icon replaces the stored one of its host unless the stored one fits
+/// better, see [`Favicon::is_better_than`]
+pub async fn upsert_favicons(pool: &mut Conn, favicons: Vec<Favicon>) -> Result<u64> {
+    if favicons.is_empty() {
+        return Ok(0);
+    }
+    let hosts: Vec<Option<String>> = favicons.iter().map(|f| Some(f.host.clone())).collect();
+    host::upsert_hosts(&mut *pool, &hosts).await?;
+    let n = favicons.len();
+    let (mut mimes, mut widths, mut data) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    for f in favicons {
+        mimes.push(f.mime);
+        widths.push(f.width);
+        data.push(f.data);
+    }
+    sqlx::query!(
+        r#"
+INSERT INTO favicons(host_id, mime, width, data)
+SELECT DISTINCT ON (hosts.id) hosts.id, mime, width, data
+FROM UNNEST($1::text[], $2::text[], $3::int[], $4::bytea[]) AS icons(host, mime, width, data)
+  INNER JOIN hosts ON hosts.host = icons.host
+ORDER BY hosts.id
+ON CONFLICT (host_id) DO UPDATE
+SET mime = EXCLUDED.mime, width = EXCLUDED.width, data = EXCLUDED.data, updated_at = now()
+WHERE (abs(EXCLUDED.width - $5), -EXCLUDED.width) <= (abs(favicons.width - $5), -favicons.width)
+"#,
+        &hosts as &[Option<String>],
+        &mimes,
+        &widths,
+        &data,
+        PREFERRED_WIDTH,
+    )
+    .execute(pool)
+    .await
+    .context("could not upsert favicons")
+    .map(|r| r.rows_affected())
+}
+
+/// the icon of the host, the hosts only visited by private urls are hidden unless `unlocked`
+pub async fn favicon(pool: &mut Pool, host: &str, unlocked: bool) -> Result<Option<Favicon>> {
+    sqlx::query_as!(
+        Favicon,
+        r#"
+SELECT hosts.host, mime, width, data
+FROM favicons INNER JOIN hosts ON hosts.id = favicons.host_id
+WHERE hosts.host = $1
+  AND ($2 OR EXISTS (SELECT 1 FROM urls WHERE urls.host_id = hosts.id AND privacy = 'normal'))
+"#,
+        host,
+        unlocked,
+    )
+    .fetch_optional(pool)
+    .await
+    .with_context(|| format!("could not get favicon of {}", host))
+}
@@ -0,0 +1,148 @@
+use super::{Conn, Pool};
+use crate::{
+    browser::Browser,
+    stats::{BrowserUsage, HostStats, HostSummary, Period, TrendPoint},
+    url::{normalized_host, Filter, UrlPrivacy, UrlTag},
+};
+use anyhow::{Context, Result};
+
+pub async fn upsert_hosts(pool: &mut Conn, hosts: &[Option<String>]) -> Result<u64> {
+    sqlx::query!(
+        r#"
+INSERT INTO hosts(host)
+SELECT DISTINCT host FROM UNNEST($1::text[]) AS data(host)
+WHERE host IS NOT NULL
+ON CONFLICT (host) DO NOTHING
+"#,
+        hosts as &[Option<String>]
+    )
+    .execute(pool)
+    .await
+    .context("could not insert hosts")
+    .map(|r| r.rows_affected())
+}
+
+/// the host as stored, e.g. `docs.rs` for `www.Docs.rs`
+fn normalize(host: &str) -> String {
+    normalized_host(&format!("http://{}", host)).unwrap_or_else(|| host.to_owned())
+}
+
+/// the hosts with the most visits of the urls matching `f`
+pub async fn top_hosts(pool: &mut Pool, mut f: Filter) -> Result<Vec<HostSummary>> {
+    f.init();
+    f.check_pid(&mut *pool).await?;
+    f.host = f.host.as_deref().map(normalize);
+
+    sqlx::query_as!(
+        HostSummary,
+        r#"
+SELECT host as "host!",
+  SUM(visit_count)::bigint as "visit_count!",
+  COUNT(DISTINCT urls.id) as "url_count!",
+  MIN(last_visit_time) as "first_visit_time!",
+  MAX(last_visit_time) as "last_visit_time!"
+FROM hosts
+  INNER JOIN urls ON urls.host_id = hosts.id
+  INNER JOIN visits ON visits.url_id = urls.id
+WHERE visit_matches(urls, visits, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+GROUP BY host
+ORDER BY 2 DESC, 5 DESC
+LIMIT $11
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.privacy as Option<UrlPrivacy>,
+        f.time_from,
+        f.time_to,
+        f.url_segs.as_slice(),
+        f.title_segs.as_slice(),
+        f.tag as Option<UrlTag>,
+        f.unlocked,
+        f.host,
+        f.limit
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get top hosts by {:?}", f))
+}
+
+/// the summary, browser breakdown and trend of one host, None if the host has no visits matching `f`
+pub async fn host_stats(
+    pool: &mut Pool,
+    host: &str,
+    period: Period,
+    mut f: Filter,
+) -> Result<Option<HostStats>> {
+    f.init();
+    f.check_pid(&mut *pool).await?;
+    let host = &normalize(host);
+    f.host = Some(host.to_owned());
+    f.limit = 1;
+    let summary = match top_hosts(&mut *pool, f.clone()).await?.pop() {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+
+    let browsers = sqlx::query_as!(
+        BrowserUsage,
+        r#"
+SELECT provider_name,
+  browser_type as "browser!: Browser",
+  SUM(visit_count)::bigint as "visit_count!",
+  COUNT(*) as "url_count!"
+FROM urls
+  INNER JOIN visits ON visits.url_id = urls.id
+  INNER JOIN providers ON providers.id = visits.provider_id
+WHERE visit_matches(urls, visits, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+GROUP BY provider_name, browser_type
+ORDER BY 3 DESC
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.privacy as Option<UrlPrivacy>,
+        f.time_from,
+        f.time_to,
+        f.url_segs.as_slice(),
+        f.title_segs.as_slice(),
+        f.tag as Option<UrlTag>,
+        f.unlocked,
+        f.host,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .with_context(|| format!("could not get browser usage of host {}", host))?;
+
+    let trend = sqlx::query_as!(
+        TrendPoint,
+        r#"
+SELECT date_trunc($11, last_visit_time) as "time!",
+  SUM(visit_count)::bigint as "visit_count!",
+  COUNT(DISTINCT urls.id) as "url_count!"
+FROM urls
+  INNER JOIN visits ON visits.url_id = urls.id
+WHERE visit_matches(urls, visits, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+GROUP BY 1
+ORDER BY 1
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.privacy as Option<UrlPrivacy>,
+        f.time_from,
+        f.time_to,
+        f.url_segs.as_slice(),
+        f.title_segs.as_slice(),
+        f.tag as Option<UrlTag>,
+        f.unlocked,
+        f.host,
+        period.to_string(),
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get trend of host {}", host))?;
+
+    Ok(Some(HostStats {
+        summary,
+        browsers,
+        trend,
+    }))
+}
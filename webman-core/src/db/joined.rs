@@ -1,7 +1,9 @@
-use super::{provider, url, visit, Pool};
+use super::{provider, url, visit, Conn, Pool};
 use crate::{
     browser::Browser,
-    url::{Filter, Url, UrlPrivacy, UrlResult, UrlTag},
+    error::WebmanError,
+    sync::PAGE_SIZE,
+    url::{Filter, PrivacyRules, Url, UrlCursor, UrlPage, UrlPrivacy, UrlResult, UrlTag},
 };
 use anyhow::{Context, Result};
 use chrono::NaiveDateTime;
@@ -31,7 +33,7 @@ pub async fn get_urls(pool: &mut Pool, mut filter: Filter) -> Result<Vec<Url>> {
         sqlx::query_as!(
             Url,
             r#"
-SELECT url, title, visit_count, last_visit_time
+SELECT url, title, visit_count, last_visit_time, privacy as "privacy: UrlPrivacy"
 FROM urls INNER JOIN visits ON urls.id = visits.url_id  
 WHERE provider_id = $1 AND last_visit_time > $2 AND browser_type = $3
 "#,
@@ -43,13 +45,84 @@ WHERE provider_id = $1 AND last_visit_time > $2 AND browser_type = $3
         .await
         .with_context(|| format!("could not get urls by p_id {:?} since {:?}", id, since))
     } else {
-        Err(anyhow::anyhow!(
+        Err(WebmanError::InvalidInput(format!(
             "the required values in filter are missing: {:?}",
             filter
         ))
+        .into())
     }
 }
 
+/// a page of the urls of [`get_urls`] in the order of last visit time and url, starting after
+/// `filter.after`. A page has at most [`PAGE_SIZE`] urls, or `filter.limit` if it is smaller.
+pub async fn get_url_page(pool: &mut Pool, mut filter: Filter) -> Result<UrlPage> {
+    filter.ready()?;
+    filter.check_pid(&mut *pool).await?;
+
+    let (id, since, browser) = match filter {
+        Filter {
+            p_id: Some(id),
+            time_from: Some(since),
+            browser: Some(browser),
+            ..
+        } => (id, since, browser),
+        _ => {
+            return Err(WebmanError::InvalidInput(format!(
+                "the required values in filter are missing: {:?}",
+                filter
+            ))
+            .into())
+        }
+    };
+    let limit = match filter.limit {
+        l if l > 0 => l.min(PAGE_SIZE),
+        _ => PAGE_SIZE,
+    };
+    let (after_time, after_url) = match filter.after {
+        Some(UrlCursor {
+            last_visit_time,
+            url,
+        }) => (Some(last_visit_time), Some(url)),
+        None => (None, None),
+    };
+    let urls = sqlx::query_as!(
+        Url,
+        r#"
+SELECT url, title, visit_count, last_visit_time, privacy as "privacy: UrlPrivacy"
+FROM urls INNER JOIN visits ON urls.id = visits.url_id
+WHERE provider_id = $1 AND last_visit_time > $2 AND browser_type = $3
+  AND ($4::timestamp is null OR (last_visit_time, url COLLATE "C") > ($4, $5::text COLLATE "C"))
+  AND ($7 OR privacy = 'normal')
+ORDER BY last_visit_time, url COLLATE "C"
+LIMIT $6
+"#,
+        id,
+        since,
+        browser as Browser,
+        after_time,
+        after_url,
+        limit,
+        filter.unlocked,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| {
+        format!(
+            "could not get a page of urls by p_id {:?} since {:?}",
+            id, since
+        )
+    })?;
+    let next = if urls.len() as i64 == limit {
+        urls.last().map(|u| UrlCursor {
+            last_visit_time: u.last_visit_time,
+            url: u.url.clone(),
+        })
+    } else {
+        None
+    };
+    Ok(UrlPage { urls, next })
+}
+
 pub async fn fuzzy_search(pool: &mut Pool, mut f: Filter) -> Result<Vec<UrlResult>> {
     f.init();
     f.check_pid(&mut *pool).await?;
@@ -58,19 +131,40 @@ pub async fn fuzzy_search(pool: &mut Pool, mut f: Filter) -> Result<Vec<UrlResul
         UrlResult,
         r#"
 WITH grouped_visits AS (
-SELECT url_id, SUM(visit_count) as visit_count, MAX(last_visit_time) as last_visit_time
-FROM visits
+-- the visits of transient pages are counted for the pages they redirected to
+SELECT CASE WHEN $12 THEN url_id ELSE COALESCE(redirect_to, url_id) END as url_id,
+  SUM(visit_count) as visit_count, MAX(last_visit_time) as last_visit_time,
+  SUM(duration_secs) as duration_secs,
+  bool_or(duration_estimated) FILTER (WHERE duration_secs > 0) as duration_estimated
+FROM (
+  SELECT url_id, provider_id, browser_type, visit_count, last_visit_time, duration_secs,
+    duration_estimated
+  FROM visits
+  UNION ALL
+  -- the urls of downloads may have no visit, they count as visited when the download started
+  SELECT url_id, provider_id, browser_type, 0, start_time, 0, false
+  FROM downloads WHERE $13
+) AS visits INNER JOIN urls ON urls.id = visits.url_id
 WHERE ($1::smallint is null OR provider_id = $1)
   AND ($2::browser is null OR browser_type = $2) 
-GROUP BY url_id
+GROUP BY 1
 HAVING ($4::timestamp is null OR MAX(last_visit_time) >= $4) 
   AND ($5::timestamp is null OR MAX(last_visit_time) <= $5) 
-) SELECT url as "url!", title as "title!", tag as "tag!:_", privacy as "privacy!:_"
+), matched_urls AS (
+-- only the best url of urls with the same canonical key is kept
+SELECT DISTINCT ON (COALESCE(canonical, url)) url, title, tag, privacy, last_visit_time, visit_count,
+  duration_secs, duration_estimated
 FROM urls INNER JOIN grouped_visits ON urls.id = grouped_visits.url_id 
-WHERE ($3::privacy is null OR privacy = $3)
-  AND url ILIKE ALL ($6::text[]) 
-  AND title ILIKE ALL ($7::text[]) 
-  AND ($8::tag is null OR tag = $8)
+WHERE url_matches(urls, $3, $6, '{}', $8, $10, $11)
+  -- the title, or the file name of a download of the url, matches
+  AND (title ILIKE ALL ($7::text[]) OR $13 AND EXISTS (
+    SELECT 1 FROM downloads WHERE downloads.url_id = urls.id AND file_name ILIKE ALL ($7::text[])))
+  AND (NOT $13 OR EXISTS (SELECT 1 FROM downloads WHERE downloads.url_id = urls.id))
+ORDER BY COALESCE(canonical, url), tag DESC, last_visit_time DESC, visit_count DESC
+) SELECT url as "url!", title as "title!", tag as "tag!:_", privacy as "privacy!:_",
+  NULLIF(duration_secs, 0)::bigint as time_spent,
+  COALESCE(duration_estimated, false) as "time_estimated!"
+FROM matched_urls
 ORDER BY tag DESC, last_visit_time DESC, visit_count DESC
 limit $9
 "#,
@@ -83,13 +177,25 @@ limit $9
         f.title_segs.as_slice(),
         f.tag as Option<UrlTag>,
         f.limit,
+        f.unlocked,
+        f.host,
+        f.transient,
+        f.download,
     )
     .fetch_all(pool)
     .await
     .with_context(|| format!("could not fuzzy search by {:?}", f))
 }
 
-pub fn unpack_urls(urls: Vec<Url>) -> (Vec<String>, Vec<String>, Vec<i32>, Vec<NaiveDateTime>) {
+type UnpackedUrls = (
+    Vec<String>,
+    Vec<String>,
+    Vec<i32>,
+    Vec<NaiveDateTime>,
+    Vec<UrlPrivacy>,
+);
+
+pub fn unpack_urls(urls: Vec<Url>) -> UnpackedUrls {
     let n = urls.len();
     urls.into_iter().fold(
         (
@@ -97,19 +203,21 @@ pub fn unpack_urls(urls: Vec<Url>) -> (Vec<String>, Vec<String>, Vec<i32>, Vec<N
             Vec::with_capacity(n),
             Vec::with_capacity(n),
             Vec::with_capacity(n),
+            Vec::with_capacity(n),
         ),
-        |(mut url, mut title, mut count, mut time), u| {
+        |(mut url, mut title, mut count, mut time, mut privacy), u| {
             url.push(u.url);
             title.push(u.title);
             count.push(u.visit_count);
             time.push(u.last_visit_time);
-            (url, title, count, time)
+            privacy.push(u.privacy);
+            (url, title, count, time, privacy)
         },
     )
 }
 
 pub async fn insert_urls(
-    pool: &mut Pool,
+    pool: &mut Conn,
     name: &str,
     browser: Browser,
     urls: Vec<Url>,
@@ -120,14 +228,14 @@ pub async fn insert_urls(
 }
 
 pub async fn insert_urls_by_id(
-    pool: &mut Pool,
+    pool: &mut Conn,
     p_id: i16,
     browser: Browser,
     urls: Vec<Url>,
     last_import_time: Option<NaiveDateTime>,
 ) -> Result<i32> {
-    let (urls, titles, counts, times) = unpack_urls(urls);
-    let url_ids = url::upsert_urls(&mut *pool, urls, titles).await?;
+    let (urls, titles, counts, times, privacies) = unpack_urls(urls);
+    let url_ids = url::upsert_urls(&mut *pool, urls, titles, privacies).await?;
     assert_eq!(
         url_ids.len(),
         times.len(),
@@ -137,3 +245,32 @@ pub async fn insert_urls_by_id(
     provider::update_last_import_time(pool, p_id, last_import_time).await?;
     Ok(num)
 }
+
+/// apply the privacy rules to all stored urls which are not private yet and whose privacy no user
+/// set, returns the number of urls which became private.
+pub async fn backfill_privacy(pool: &mut Pool, rules: &PrivacyRules) -> Result<u64> {
+    if rules.is_empty() {
+        return Ok(0);
+    }
+    let rows = sqlx::query!(
+        r#"
+SELECT DISTINCT url, provider_name, browser_type as "browser: Browser"
+FROM urls
+  INNER JOIN visits ON urls.id = visits.url_id
+  INNER JOIN providers ON providers.id = visits.provider_id
+WHERE privacy = 'normal' AND privacy_set_at IS NULL
+ORDER BY url
+"#
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get normal urls")?;
+
+    let mut private_urls: Vec<String> = rows
+        .into_iter()
+        .filter(|r| rules.classify(&r.url, &r.provider_name, r.browser) == UrlPrivacy::Private)
+        .map(|r| r.url)
+        .collect();
+    private_urls.dedup();
+    url::raise_privacy(pool, private_urls, UrlPrivacy::Private).await
+}
@@ -1,6 +1,16 @@
+mod download;
+mod favicon;
+mod host;
 mod joined;
+mod navigation;
 pub mod prelude;
 mod provider;
+mod search_query;
+pub mod session;
+pub mod stats;
+pub mod sync;
+mod timeline;
+mod upload;
 mod url;
 mod visit;
 
@@ -11,6 +21,8 @@ use anyhow::{Context, Result};
 use sqlx::migrate::Migrator;
 
 type Pool = sqlx::pool::PoolConnection<sqlx::Postgres>;
+/// a connection or a transaction, for the functions which are also run inside a transaction
+type Conn = sqlx::PgConnection;
 
 static MIGRATOR: Migrator = sqlx::migrate!();
 pub async fn migrate(pool: &sqlx::PgPool) -> Result<()> {
@@ -0,0 +1,131 @@
+use super::{provider, Conn, Pool};
+use crate::{
+    browser::Browser,
+    navigation::{Navigation, NavigationEdge, NavigationStep},
+};
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+
+/// store the edges between stored urls, edges with unknown urls are dropped. Returns the number of
+/// stored edges.
+pub async fn insert_navigations(
+    pool: &mut Conn,
+    name: &str,
+    browser: Browser,
+    edges: Vec<NavigationEdge>,
+) -> Result<u64> {
+    if edges.is_empty() {
+        return Ok(0);
+    }
+    let p_id = provider::find_or_insert(&mut *pool, name).await?;
+    let n = edges.len();
+    let (mut from_urls, mut to_urls, mut times) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    for e in edges {
+        from_urls.push(e.from_url);
+        to_urls.push(e.to_url);
+        times.push(e.visit_time);
+    }
+    sqlx::query!(
+        r#"
+INSERT INTO navigations(from_url_id, to_url_id, provider_id, browser_type, visit_count, last_visit_time)
+SELECT from_urls.id, to_urls.id, $1, $2, COUNT(*), MAX(visit_time)
+FROM UNNEST($3::text[], $4::text[], $5::timestamp[]) AS data(from_url, to_url, visit_time)
+  INNER JOIN urls AS from_urls ON from_urls.url = data.from_url
+  INNER JOIN urls AS to_urls ON to_urls.url = data.to_url
+WHERE from_urls.id != to_urls.id
+GROUP BY from_urls.id, to_urls.id
+ON CONFLICT ON CONSTRAINT navigations_pkey DO
+UPDATE SET visit_count = navigations.visit_count + EXCLUDED.visit_count,
+           last_visit_time = GREATEST(navigations.last_visit_time, EXCLUDED.last_visit_time)
+"#,
+        p_id,
+        browser as Browser,
+        &from_urls,
+        &to_urls,
+        &times as &[NaiveDateTime],
+    )
+    .execute(pool)
+    .await
+    .with_context(|| format!("could not insert navigations of {} {:?}", name, browser))
+    .map(|r| r.rows_affected())
+}
+
+/// The referrers of `url` up to `depth` steps back and the pages opened from it. Private pages are
+/// left out unless `unlocked`. None if the url is not stored.
+pub async fn navigation(
+    pool: &mut Pool,
+    url: &str,
+    depth: i32,
+    unlocked: bool,
+) -> Result<Option<Navigation>> {
+    let id = sqlx::query_scalar!(
+        r#"SELECT id FROM urls WHERE url = $1 AND ($2 OR privacy = 'normal')"#,
+        url,
+        unlocked
+    )
+    .fetch_optional(&mut *pool)
+    .await
+    .with_context(|| format!("could not find url {}", url))?;
+    let id = match id {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+
+    // follow the latest link to every page, the path prevents loops
+    let referrers = sqlx::query_as!(
+        NavigationStep,
+        r#"
+WITH RECURSIVE chain(url_id, depth, path, visit_time, visit_count) AS (
+  SELECT $1::integer, 0, ARRAY[$1::integer], NULL::timestamp, NULL::integer
+  UNION ALL
+  SELECT n.from_url_id, chain.depth + 1, chain.path || n.from_url_id, n.last_visit_time, n.visit_count
+  FROM chain CROSS JOIN LATERAL (
+    SELECT from_url_id, SUM(visit_count)::integer as visit_count, MAX(last_visit_time) as last_visit_time
+    FROM navigations
+    WHERE to_url_id = chain.url_id AND from_url_id != ALL(chain.path)
+    GROUP BY from_url_id
+    ORDER BY 3 DESC
+    LIMIT 1
+  ) n
+  WHERE chain.depth < $2
+) SELECT url as "url!", title as "title!", visit_time as "visit_time!", visit_count as "visit_count!"
+FROM chain INNER JOIN urls ON urls.id = chain.url_id
+WHERE depth > 0 AND ($3 OR privacy = 'normal')
+ORDER BY depth DESC
+"#,
+        id,
+        depth,
+        unlocked
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .with_context(|| format!("could not get referrers of {}", url))?;
+
+    let opened = sqlx::query_as!(
+        NavigationStep,
+        r#"
+SELECT url, title,
+  MAX(last_visit_time) as "visit_time!",
+  SUM(navigations.visit_count)::integer as "visit_count!"
+FROM navigations INNER JOIN urls ON urls.id = navigations.to_url_id
+WHERE from_url_id = $1 AND ($2 OR privacy = 'normal')
+GROUP BY url, title
+ORDER BY 3 DESC
+"#,
+        id,
+        unlocked
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get pages opened from {}", url))?;
+
+    Ok(Some(Navigation {
+        url: url.to_owned(),
+        referrers,
+        opened,
+    }))
+}
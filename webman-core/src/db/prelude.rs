@@ -1,7 +1,20 @@
 pub use super::{
-    joined::{fuzzy_search, get_urls, insert_urls},
+    download::{downloads_of_url, insert_downloads},
+    favicon::{favicon, upsert_favicons},
+    host::{host_stats, top_hosts},
+    joined::{backfill_privacy, fuzzy_search, get_url_page, get_urls, insert_urls},
     migrate,
+    navigation::{insert_navigations, navigation},
     provider::{all as all_providers, last_import_time},
-    url::{get_all_tags, set_tag},
-    visit::all_browsers,
+    search_query::{
+        backfill_search_queries, insert_search_queries, queries_of_url, search_queries,
+    },
+    session, stats, sync,
+    timeline::timeline,
+    upload::{advance_upload, lock_upload, upload_cursor},
+    url::{
+        backfill_canonical, get_all_tags, merge_duplicates as merge_duplicate_urls,
+        sanitize_all as sanitize_urls, set_privacy, set_redirects, set_tag,
+    },
+    visit::{add_durations, all_browsers},
 };
@@ -1,9 +1,9 @@
-use super::Pool;
-use crate::node::Provider;
+use super::Conn;
+use crate::{error::WebmanError, node::Provider};
 use anyhow::{Context, Result};
 use chrono::NaiveDateTime;
 
-pub async fn last_import_time(pool: &mut Pool, name: &str) -> Result<NaiveDateTime> {
+pub async fn last_import_time(pool: &mut Conn, name: &str) -> Result<NaiveDateTime> {
     match sqlx::query!(
         r#"
 SELECT last_import_time
@@ -22,7 +22,7 @@ WHERE provider_name = $1
     }
 }
 
-pub async fn all(pool: &mut Pool) -> Result<Vec<Provider>> {
+pub async fn all(pool: &mut Conn) -> Result<Vec<Provider>> {
     sqlx::query_as!(
         Provider,
         r#"
@@ -35,7 +35,7 @@ FROM providers
     .context("could not find all providers")
 }
 
-pub async fn try_find(pool: &mut Pool, name: &str) -> Result<Option<i16>> {
+pub async fn try_find(pool: &mut Conn, name: &str) -> Result<Option<i16>> {
     let res = sqlx::query!(
         r#"
 SELECT id
@@ -50,15 +50,15 @@ WHERE provider_name = $1
     Ok(res.map(|x| x.id))
 }
 
-pub async fn find(pool: &mut Pool, name: &str) -> Result<i16> {
+pub async fn find(pool: &mut Conn, name: &str) -> Result<i16> {
     match try_find(pool, name).await {
         Ok(Some(id)) => Ok(id),
-        Ok(None) => Err(anyhow::anyhow!("could not find provider by name: {}", name)),
+        Ok(None) => Err(WebmanError::NotFound(format!("no provider named {}", name)).into()),
         Err(e) => Err(e),
     }
 }
 
-pub async fn find_or_insert(pool: &mut Pool, name: &str) -> Result<i16> {
+pub async fn find_or_insert(pool: &mut Conn, name: &str) -> Result<i16> {
     match try_find(&mut *pool, name).await {
         Ok(Some(id)) => Ok(id),
         Err(e) => Err(e),
@@ -76,8 +76,10 @@ RETURNING id
     }
 }
 
+/// the last import time never moves backwards, concurrent imports and syncs may finish in any
+/// order
 pub async fn update_last_import_time(
-    pool: &mut Pool,
+    pool: &mut Conn,
     id: i16,
     last_import_time: Option<NaiveDateTime>,
 ) -> Result<i16> {
@@ -88,7 +90,7 @@ pub async fn update_last_import_time(
 
     sqlx::query!(
         r#"
-UPDATE providers SET last_import_time = $1 WHERE id = $2
+UPDATE providers SET last_import_time = GREATEST(last_import_time, $1) WHERE id = $2
 RETURNING id
 "#,
         last_import_time,
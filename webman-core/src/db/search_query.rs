@@ -0,0 +1,190 @@
+use super::{Conn, Pool};
+use crate::{
+    browser::Browser,
+    url::{Filter, QueryLogEntry, SearchEngines, SearchQuery, UrlPrivacy, UrlResult, UrlTag},
+};
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
+
+/// store the queries of stored urls, a new query of a url replaces the old one
+pub async fn insert_search_queries(pool: &mut Conn, queries: Vec<SearchQuery>) -> Result<u64> {
+    if queries.is_empty() {
+        return Ok(0);
+    }
+    let n = queries.len();
+    let (mut urls, mut texts, mut engines) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    for q in queries {
+        urls.push(q.url);
+        texts.push(q.query);
+        engines.push(q.engine);
+    }
+    sqlx::query!(
+        r#"
+INSERT INTO search_queries(url_id, query, engine)
+SELECT DISTINCT ON (urls.id) urls.id, query, engine
+FROM UNNEST($1::text[], $2::text[], $3::text[]) AS data(url, query, engine)
+  INNER JOIN urls ON urls.url = data.url
+ON CONFLICT (url_id) DO
+UPDATE SET query = EXCLUDED.query, engine = EXCLUDED.engine
+"#,
+        &urls,
+        &texts,
+        &engines,
+    )
+    .execute(pool)
+    .await
+    .context("could not insert search queries")
+    .map(|r| r.rows_affected())
+}
+
+/// extract the queries of all stored urls by `engines`, returns the number of stored queries
+pub async fn backfill_search_queries(pool: &mut Pool, engines: &SearchEngines) -> Result<u64> {
+    let urls = sqlx::query_scalar!(
+        r#"
+SELECT url FROM urls
+WHERE NOT EXISTS (SELECT 1 FROM search_queries WHERE url_id = urls.id)
+"#
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get urls without search queries")?;
+    let queries = engines.extract_all(urls.iter().map(String::as_str), vec![]);
+    insert_search_queries(pool, queries).await
+}
+
+struct QueryRow {
+    url_id: i32,
+    query: String,
+    engine: String,
+    url: String,
+    last_visit_time: NaiveDateTime,
+}
+
+/// attach the pages opened from the result pages, private pages are left out unless `unlocked`
+async fn with_results(
+    pool: &mut Pool,
+    rows: Vec<QueryRow>,
+    unlocked: bool,
+) -> Result<Vec<QueryLogEntry>> {
+    let ids: Vec<i32> = rows.iter().map(|r| r.url_id).collect();
+    let results = sqlx::query!(
+        r#"
+SELECT from_url_id, url, title, tag as "tag: UrlTag", privacy as "privacy: UrlPrivacy"
+FROM navigations INNER JOIN urls ON urls.id = navigations.to_url_id
+WHERE from_url_id = ANY($1::integer[]) AND ($2 OR privacy = 'normal')
+GROUP BY from_url_id, url, title, tag, privacy
+ORDER BY MAX(last_visit_time)
+"#,
+        &ids,
+        unlocked,
+    )
+    .fetch_all(pool)
+    .await
+    .context("could not get results of search queries")?;
+
+    let mut by_query: HashMap<i32, Vec<UrlResult>> = HashMap::with_capacity(ids.len());
+    for r in results {
+        by_query.entry(r.from_url_id).or_default().push(UrlResult {
+            url: r.url,
+            title: r.title,
+            tag: r.tag,
+            privacy: r.privacy,
+            ..Default::default()
+        });
+    }
+    Ok(rows
+        .into_iter()
+        .map(|r| QueryLogEntry {
+            results: by_query.remove(&r.url_id).unwrap_or_default(),
+            query: r.query,
+            engine: r.engine,
+            url: r.url,
+            last_visit_time: r.last_visit_time,
+        })
+        .collect())
+}
+
+/// The latest search queries whose text matches the title segments of `f`, and their results.
+pub async fn search_queries(pool: &mut Pool, mut f: Filter) -> Result<Vec<QueryLogEntry>> {
+    f.init();
+    f.check_pid(&mut *pool).await?;
+    let rows = sqlx::query_as!(
+        QueryRow,
+        r#"
+SELECT url_id as "url_id!", query as "query!", engine as "engine!", url as "url!",
+  last_visit_time as "last_visit_time!"
+FROM (
+  SELECT search_queries.url_id, query, engine, url, MAX(last_visit_time) as last_visit_time
+  FROM search_queries
+    INNER JOIN urls ON urls.id = search_queries.url_id
+    INNER JOIN visits ON visits.url_id = urls.id
+  WHERE ($1::smallint is null OR provider_id = $1)
+    AND ($2::browser is null OR browser_type = $2)
+    AND query ILIKE ALL ($3::text[])
+    AND ($4 OR privacy = 'normal')
+  GROUP BY search_queries.url_id, query, engine, url
+  HAVING ($5::timestamp is null OR MAX(last_visit_time) >= $5)
+    AND ($6::timestamp is null OR MAX(last_visit_time) <= $6)
+) q
+ORDER BY last_visit_time DESC
+LIMIT $7
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.title_segs.as_slice(),
+        f.unlocked,
+        f.time_from,
+        f.time_to,
+        f.limit,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .with_context(|| format!("could not search queries by {:?}", f))?;
+    with_results(pool, rows, f.unlocked).await
+}
+
+/// the search queries which led to `url`, directly or over at most `depth` pages
+pub async fn queries_of_url(
+    pool: &mut Pool,
+    url: &str,
+    depth: i32,
+    unlocked: bool,
+) -> Result<Vec<QueryLogEntry>> {
+    let rows = sqlx::query_as!(
+        QueryRow,
+        r#"
+WITH RECURSIVE referrers(url_id, depth) AS (
+  SELECT id, 0 FROM urls WHERE url = $1 AND ($3 OR privacy = 'normal')
+  UNION
+  SELECT from_url_id, depth + 1
+  FROM referrers INNER JOIN navigations ON navigations.to_url_id = referrers.url_id
+  WHERE depth < $2
+)
+SELECT url_id as "url_id!", query as "query!", engine as "engine!", url as "url!",
+  last_visit_time as "last_visit_time!"
+FROM (
+  SELECT search_queries.url_id, query, engine, url, MAX(last_visit_time) as last_visit_time,
+    MIN(referrers.depth) as depth
+  FROM referrers
+    INNER JOIN search_queries ON search_queries.url_id = referrers.url_id
+    INNER JOIN urls ON urls.id = search_queries.url_id
+    INNER JOIN visits ON visits.url_id = urls.id
+  WHERE referrers.depth > 0 AND ($3 OR privacy = 'normal')
+  GROUP BY search_queries.url_id, query, engine, url
+) q
+ORDER BY depth, last_visit_time DESC
+"#,
+        url,
+        depth,
+        unlocked,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .with_context(|| format!("could not get search queries of {}", url))?;
+    with_results(pool, rows, unlocked).await
+}
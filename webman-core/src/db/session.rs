@@ -0,0 +1,228 @@
+use super::{Conn, Pool};
+use crate::{
+    browser::Browser,
+    session::{BrowsingSession, SessionBuilder, SessionVisit},
+    url::{Filter, UrlPrivacy, UrlResult, UrlTag},
+};
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use sqlx::Connection;
+use std::collections::HashMap;
+
+/// the number of visits read at once to build sessions
+const BATCH: i64 = 10_000;
+
+/// Replace all stored sessions by the sessions built from the current visits in one transaction,
+/// returns the number of sessions. The visits are read in batches, so only the sessions of a
+/// batch are kept in memory.
+pub async fn rebuild(pool: &mut Pool, builder: &SessionBuilder) -> Result<usize> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM sessions")
+        .execute(&mut tx)
+        .await
+        .context("could not delete old sessions")?;
+
+    let mut n = 0;
+    let mut open: Option<((i16, Browser), Vec<SessionVisit>)> = None;
+    let mut after: Option<(i16, Browser, NaiveDateTime, i32)> = None;
+    loop {
+        let rows = sqlx::query!(
+            r#"
+SELECT provider_id, browser_type as "browser: Browser", url_id, host_id, last_visit_time
+FROM visits INNER JOIN urls ON urls.id = visits.url_id
+WHERE $1::smallint is null
+  OR (provider_id, browser_type, last_visit_time, url_id) > ($1, $2::browser, $3::timestamp, $4::integer)
+ORDER BY provider_id, browser_type, last_visit_time, url_id
+LIMIT $5
+"#,
+            after.map(|a| a.0),
+            after.map(|a| a.1) as Option<Browser>,
+            after.map(|a| a.2),
+            after.map(|a| a.3),
+            BATCH,
+        )
+        .fetch_all(&mut tx)
+        .await
+        .context("could not get visits to build sessions")?;
+        let last_batch = (rows.len() as i64) < BATCH;
+
+        let mut closed = vec![];
+        for r in rows {
+            let key = (r.provider_id, r.browser);
+            after = Some((r.provider_id, r.browser, r.last_visit_time, r.url_id));
+            let visit = SessionVisit {
+                url_id: r.url_id,
+                host_id: r.host_id,
+                time: r.last_visit_time,
+            };
+            match &mut open {
+                Some((k, visits)) if *k == key => {
+                    closed.extend(builder.push(visits, visit).map(|s| (key, s)));
+                }
+                _ => {
+                    if let Some((k, visits)) = open.replace((key, vec![visit])) {
+                        closed.extend(builder.finish(visits).map(|s| (k, s)));
+                    }
+                }
+            }
+        }
+        if last_batch {
+            if let Some((k, visits)) = open.take() {
+                closed.extend(builder.finish(visits).map(|s| (k, s)));
+            }
+        }
+        n += insert(&mut tx, closed).await?;
+        if last_batch {
+            break;
+        }
+    }
+    tx.commit().await.context("could not commit sessions")?;
+    Ok(n)
+}
+
+async fn insert(
+    pool: &mut Conn,
+    sessions: Vec<((i16, Browser), Vec<SessionVisit>)>,
+) -> Result<usize> {
+    if sessions.is_empty() {
+        return Ok(0);
+    }
+    let (mut p_ids, mut browsers, mut starts, mut ends, mut members) =
+        (vec![], vec![], vec![], vec![], vec![]);
+    for ((p_id, browser), s) in sessions {
+        p_ids.push(p_id);
+        browsers.push(browser);
+        starts.push(s.first().unwrap().time);
+        ends.push(s.last().unwrap().time);
+        members.push(s.into_iter().map(|v| v.url_id).collect::<Vec<_>>());
+    }
+
+    let ids = sqlx::query!(
+        r#"
+INSERT INTO sessions(provider_id, browser_type, start_time, end_time)
+SELECT * FROM UNNEST($1::smallint[], $2::browser[], $3::timestamp[], $4::timestamp[])
+RETURNING id
+"#,
+        &p_ids,
+        &browsers as &[Browser],
+        &starts as &[NaiveDateTime],
+        &ends as &[NaiveDateTime],
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not insert sessions")?;
+
+    let (session_ids, url_ids): (Vec<i32>, Vec<i32>) = ids
+        .iter()
+        .zip(&members)
+        .flat_map(|(r, urls)| urls.iter().map(move |u| (r.id, *u)))
+        .unzip();
+    sqlx::query!(
+        r#"
+INSERT INTO session_urls(session_id, url_id)
+SELECT * FROM UNNEST($1::integer[], $2::integer[])
+"#,
+        &session_ids,
+        &url_ids,
+    )
+    .execute(pool)
+    .await
+    .context("could not insert urls of sessions")?;
+    Ok(ids.len())
+}
+
+/// The latest sessions containing a page which matches `f`. Private pages are left out of the
+/// sessions unless `f` is unlocked.
+pub async fn search(pool: &mut Pool, mut f: Filter) -> Result<Vec<BrowsingSession>> {
+    f.init();
+    f.check_pid(&mut *pool).await?;
+
+    let sessions = sqlx::query!(
+        r#"
+SELECT sessions.id, provider_name, browser_type as "browser: Browser", start_time, end_time
+FROM sessions INNER JOIN providers ON providers.id = sessions.provider_id
+WHERE ($1::smallint is null OR provider_id = $1)
+  AND ($2::browser is null OR browser_type = $2)
+  AND ($4::timestamp is null OR end_time >= $4)
+  AND ($5::timestamp is null OR start_time <= $5)
+  AND EXISTS (
+    SELECT 1 FROM session_urls INNER JOIN urls ON urls.id = session_urls.url_id
+    WHERE session_id = sessions.id
+      AND url_matches(urls, $3, $6, $7, $8, $9, $10))
+ORDER BY end_time DESC
+LIMIT $11
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.privacy as Option<UrlPrivacy>,
+        f.time_from,
+        f.time_to,
+        f.url_segs.as_slice(),
+        f.title_segs.as_slice(),
+        f.tag as Option<UrlTag>,
+        f.unlocked,
+        f.host,
+        f.limit,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .with_context(|| format!("could not search sessions by {:?}", f))?;
+
+    let ids: Vec<i32> = sessions.iter().map(|s| s.id).collect();
+    let members = sqlx::query!(
+        r#"
+SELECT session_id, url, title, tag as "tag: UrlTag", privacy as "privacy: UrlPrivacy"
+FROM session_urls
+  INNER JOIN sessions ON sessions.id = session_urls.session_id
+  INNER JOIN urls ON urls.id = session_urls.url_id
+  INNER JOIN visits ON visits.url_id = urls.id
+    AND visits.provider_id = sessions.provider_id
+    AND visits.browser_type = sessions.browser_type
+WHERE session_id = ANY($1::integer[])
+  AND ($2 OR privacy = 'normal')
+ORDER BY last_visit_time
+"#,
+        &ids,
+        f.unlocked,
+    )
+    .fetch_all(pool)
+    .await
+    .context("could not get urls of sessions")?;
+
+    let mut urls: HashMap<i32, Vec<UrlResult>> = HashMap::with_capacity(ids.len());
+    for m in members {
+        urls.entry(m.session_id).or_default().push(UrlResult {
+            url: m.url,
+            title: m.title,
+            tag: m.tag,
+            privacy: m.privacy,
+            ..Default::default()
+        });
+    }
+    Ok(sessions
+        .into_iter()
+        .map(|s| BrowsingSession {
+            urls: urls.remove(&s.id).unwrap_or_default(),
+            id: s.id,
+            provider_name: s.provider_name,
+            browser: s.browser,
+            start_time: s.start_time,
+            end_time: s.end_time,
+        })
+        .collect())
+}
+
+/// the urls of the session, private urls are left out unless `unlocked`
+pub async fn urls(pool: &mut Pool, id: i32, unlocked: bool) -> Result<Vec<String>> {
+    sqlx::query_scalar!(
+        r#"
+SELECT url FROM session_urls INNER JOIN urls ON urls.id = session_urls.url_id
+WHERE session_id = $1 AND ($2 OR privacy = 'normal')
+"#,
+        id,
+        unlocked
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get urls of session {}", id))
+}
@@ -0,0 +1,205 @@
+//! Statistics for the charts of the web ui. All of them accept the same [`Filter`] as search,
+//! `limit` is ignored except by [`time_spent`]. Time buckets are computed in the time zone `tz`, which is a name of the
+//! IANA time zone database like `Europe/Berlin`.
+use super::Pool;
+use crate::{
+    browser::Browser,
+    error::WebmanError,
+    stats::{HourCount, Period, TagCount, TimeSpent, TrendPoint, UrlsAdded},
+    url::{Filter, UrlPrivacy, UrlTag},
+};
+use anyhow::{Context, Result};
+
+async fn prepare(pool: &mut Pool, f: &mut Filter, tz: &str) -> Result<()> {
+    f.init();
+    f.check_pid(&mut *pool).await?;
+    let known = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM pg_timezone_names WHERE name = $1) as "known!""#,
+        tz
+    )
+    .fetch_one(pool)
+    .await
+    .context("could not check time zone")?;
+    if !known {
+        return Err(WebmanError::InvalidInput(format!("unknown time zone {}", tz)).into());
+    }
+    Ok(())
+}
+
+/// the visits grouped by the last visit time of urls
+pub async fn visits(
+    pool: &mut Pool,
+    period: Period,
+    tz: &str,
+    mut f: Filter,
+) -> Result<Vec<TrendPoint>> {
+    prepare(&mut *pool, &mut f, tz).await?;
+    sqlx::query_as!(
+        TrendPoint,
+        r#"
+SELECT date_trunc($11, last_visit_time AT TIME ZONE 'UTC' AT TIME ZONE $12) as "time!",
+  SUM(visit_count)::bigint as "visit_count!",
+  COUNT(DISTINCT url_id) as "url_count!"
+FROM urls INNER JOIN visits ON urls.id = visits.url_id
+WHERE visit_matches(urls, visits, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+GROUP BY 1
+ORDER BY 1
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.privacy as Option<UrlPrivacy>,
+        f.time_from,
+        f.time_to,
+        f.url_segs.as_slice(),
+        f.title_segs.as_slice(),
+        f.tag as Option<UrlTag>,
+        f.unlocked,
+        f.host,
+        period.to_string(),
+        tz,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get visits per {} by {:?}", period, f))
+}
+
+/// the urls added to the history of every provider and browser, grouped by the time they were
+/// added. The time range of `f` is the range of the added time.
+pub async fn urls(
+    pool: &mut Pool,
+    period: Period,
+    tz: &str,
+    mut f: Filter,
+) -> Result<Vec<UrlsAdded>> {
+    prepare(&mut *pool, &mut f, tz).await?;
+    sqlx::query_as!(
+        UrlsAdded,
+        r#"
+SELECT provider_name,
+  browser_type as "browser!: Browser",
+  date_trunc($11, added_time AT TIME ZONE 'UTC' AT TIME ZONE $12) as "time!",
+  COUNT(*) as "url_count!"
+FROM urls
+  INNER JOIN visits ON urls.id = visits.url_id
+  INNER JOIN providers ON providers.id = visits.provider_id
+WHERE visit_matches(urls, visits, $1, $2, $3, NULL, NULL, $6, $7, $8, $9, $10)
+  AND ($4::timestamp is null OR added_time >= $4)
+  AND ($5::timestamp is null OR added_time <= $5)
+GROUP BY 1, 2, 3
+ORDER BY 3, 1, 2
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.privacy as Option<UrlPrivacy>,
+        f.time_from,
+        f.time_to,
+        f.url_segs.as_slice(),
+        f.title_segs.as_slice(),
+        f.tag as Option<UrlTag>,
+        f.unlocked,
+        f.host,
+        period.to_string(),
+        tz,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get urls added per {} by {:?}", period, f))
+}
+
+/// the urls of every tag
+pub async fn tags(pool: &mut Pool, mut f: Filter) -> Result<Vec<TagCount>> {
+    prepare(&mut *pool, &mut f, "UTC").await?;
+    sqlx::query_as!(
+        TagCount,
+        r#"
+SELECT tag as "tag!: UrlTag",
+  SUM(visit_count)::bigint as "visit_count!",
+  COUNT(DISTINCT url_id) as "url_count!"
+FROM urls INNER JOIN visits ON urls.id = visits.url_id
+WHERE visit_matches(urls, visits, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+GROUP BY tag
+ORDER BY tag
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.privacy as Option<UrlPrivacy>,
+        f.time_from,
+        f.time_to,
+        f.url_segs.as_slice(),
+        f.title_segs.as_slice(),
+        f.tag as Option<UrlTag>,
+        f.unlocked,
+        f.host,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get tag distribution by {:?}", f))
+}
+
+/// the visits grouped by the hour of the last visit time, hours without visits are omitted
+pub async fn hours(pool: &mut Pool, tz: &str, mut f: Filter) -> Result<Vec<HourCount>> {
+    prepare(&mut *pool, &mut f, tz).await?;
+    sqlx::query_as!(
+        HourCount,
+        r#"
+SELECT EXTRACT(HOUR FROM last_visit_time AT TIME ZONE 'UTC' AT TIME ZONE $11)::integer as "hour!",
+  SUM(visit_count)::bigint as "visit_count!",
+  COUNT(DISTINCT url_id) as "url_count!"
+FROM urls INNER JOIN visits ON urls.id = visits.url_id
+WHERE visit_matches(urls, visits, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+GROUP BY 1
+ORDER BY 1
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.privacy as Option<UrlPrivacy>,
+        f.time_from,
+        f.time_to,
+        f.url_segs.as_slice(),
+        f.title_segs.as_slice(),
+        f.tag as Option<UrlTag>,
+        f.unlocked,
+        f.host,
+        tz,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get busiest hours by {:?}", f))
+}
+
+/// the urls or hosts the most time was spent on, at most `limit` of them
+pub async fn time_spent(pool: &mut Pool, by_host: bool, mut f: Filter) -> Result<Vec<TimeSpent>> {
+    prepare(&mut *pool, &mut f, "UTC").await?;
+    sqlx::query_as!(
+        TimeSpent,
+        r#"
+SELECT CASE WHEN $11 THEN hosts.host ELSE url END as "key!",
+  SUM(duration_secs)::bigint as "secs!",
+  bool_or(duration_estimated) as "estimated!"
+FROM urls
+  INNER JOIN visits ON urls.id = visits.url_id
+  LEFT JOIN hosts ON hosts.id = urls.host_id
+WHERE duration_secs > 0
+  AND visit_matches(urls, visits, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+  AND ($11 = false OR hosts.host is not null)
+GROUP BY 1
+ORDER BY 2 DESC, 1
+LIMIT $12
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.privacy as Option<UrlPrivacy>,
+        f.time_from,
+        f.time_to,
+        f.url_segs.as_slice(),
+        f.title_segs.as_slice(),
+        f.tag as Option<UrlTag>,
+        f.unlocked,
+        f.host,
+        by_host,
+        f.limit,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get time spent by {:?}", f))
+}
@@ -0,0 +1,1378 @@
+//! The change log of this node and the acknowledged changes of its peers, see [`crate::sync`].
+use super::{favicon, provider, search_query, url, Conn, Pool};
+use crate::{
+    browser::Browser,
+    favicon::Favicon,
+    sync::{
+        tree::{BucketHash, TreeNode},
+        ChangeSet, DownloadChange, FaviconChange, NavigationChange, OriginVersion,
+        SearchQueryChange, SyncRows, SyncRun, UrlChange, UrlDeletion, VisitChange, FAVICON_WEIGHT,
+    },
+    url::{SearchQuery, UrlPrivacy, UrlTag},
+};
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use sqlx::Connection;
+use std::collections::{HashMap, HashSet};
+
+/// the number of runs kept in the history of every peer
+pub const MAX_RUNS: i64 = 100;
+/// the longest chain of urls merged into each other which is followed
+const MAX_MERGE_CHAIN: i32 = 10;
+
+/// the random id of this node
+pub async fn node_id(pool: &mut Pool) -> Result<String> {
+    sqlx::query_scalar!("SELECT node_id FROM node_identity")
+        .fetch_one(pool)
+        .await
+        .context("could not get node id")
+}
+
+/// the last sequence number of the peer whose changes are applied, 0 if there are none
+pub async fn acked_seq(pool: &mut Pool, node_id: &str) -> Result<i64> {
+    sqlx::query_scalar!(
+        "SELECT acked_seq FROM sync_peers WHERE node_id = $1",
+        node_id
+    )
+    .fetch_optional(pool)
+    .await
+    .with_context(|| format!("could not get acked seq of {}", node_id))
+    .map(Option::unwrap_or_default)
+}
+
+/// The versions of the changes of every origin this node has, including its own. The own version
+/// is the commit-safe watermark, since a change below the highest stored one may still commit,
+/// see `stable_seq`.
+pub async fn versions(pool: &mut Pool) -> Result<Vec<OriginVersion>> {
+    sqlx::query_as!(
+        OriginVersion,
+        r#"
+SELECT node_id as "node_id!", seq as "seq!" FROM origin_versions
+UNION ALL
+SELECT node_id, stable_seq() FROM node_identity
+ORDER BY 1
+"#
+    )
+    .fetch_all(pool)
+    .await
+    .context("could not get origin versions")
+}
+
+/// remember that all changes up to the versions are applied, the versions of this node itself
+/// are ignored
+pub async fn record_versions(pool: &mut Pool, versions: Vec<OriginVersion>) -> Result<()> {
+    let (nodes, seqs): (Vec<_>, Vec<_>) = versions.into_iter().map(|v| (v.node_id, v.seq)).unzip();
+    sqlx::query!(
+        r#"
+INSERT INTO origin_versions(node_id, seq)
+SELECT * FROM UNNEST($1::text[], $2::bigint[]) AS data(node_id, seq)
+WHERE data.node_id <> (SELECT node_id FROM node_identity)
+ON CONFLICT (node_id) DO UPDATE
+SET seq = GREATEST(origin_versions.seq, EXCLUDED.seq)
+"#,
+        &nodes,
+        &seqs,
+    )
+    .execute(pool)
+    .await
+    .context("could not record origin versions")?;
+    Ok(())
+}
+
+/// the changes after `since` which are not `known` by the peer, at most `limit` rows are changed
+/// in the set, where a favicon counts as [`FAVICON_WEIGHT`] rows
+pub async fn changes(
+    pool: &mut Pool,
+    since: i64,
+    limit: i64,
+    known: &[OriginVersion],
+) -> Result<ChangeSet> {
+    // a change above the stable number may still be joined by a lower one, see `stable_seq`
+    let stable = sqlx::query_scalar!(r#"SELECT stable_seq() as "seq!""#)
+        .fetch_one(&mut *pool)
+        .await
+        .context("could not get the stable sequence number")?;
+    // the page ends at the sequence number of the change which reaches `limit`
+    let bound = sqlx::query_scalar!(
+        r#"
+SELECT seq as "seq!" FROM (
+  SELECT seq, SUM(weight) OVER (ORDER BY seq) as total FROM (
+    SELECT seq, 1::bigint as weight FROM urls WHERE seq > $1 AND seq <= $3
+    UNION ALL
+    SELECT seq, 1 FROM visits WHERE seq > $1 AND seq <= $3
+    UNION ALL
+    SELECT seq, 1 FROM url_tombstones WHERE seq > $1 AND seq <= $3
+    UNION ALL
+    SELECT seq, 1 FROM navigations WHERE seq > $1 AND seq <= $3
+    UNION ALL
+    SELECT seq, 1 FROM search_queries WHERE seq > $1 AND seq <= $3
+    UNION ALL
+    SELECT seq, 1 FROM downloads WHERE seq > $1 AND seq <= $3
+    UNION ALL
+    SELECT seq, $4::bigint FROM favicons WHERE seq > $1 AND seq <= $3
+  ) changes
+) totals
+WHERE total >= $2::bigint
+ORDER BY seq
+LIMIT 1
+"#,
+        since,
+        limit.max(1),
+        stable,
+        FAVICON_WEIGHT,
+    )
+    .fetch_optional(&mut *pool)
+    .await
+    .context("could not find the end of changes")?;
+    let more = bound.is_some();
+    let last_seq = bound.unwrap_or_else(|| since.max(stable));
+
+    let (nodes, seqs): (Vec<_>, Vec<_>) = known.iter().map(|v| (v.node_id.clone(), v.seq)).unzip();
+    let urls = sqlx::query_as!(
+        UrlChange,
+        r#"
+SELECT url, title, tag as "tag: UrlTag", privacy as "privacy: UrlPrivacy", privacy_set_at, seq,
+  COALESCE(origin_node, (SELECT node_id FROM node_identity)) as "origin!", origin_seq
+FROM urls
+WHERE (seq > $1 AND seq <= $2 AND NOT is_known(origin_node, origin_seq, $3, $4))
+  OR id IN (
+    SELECT url_id FROM visits
+    WHERE seq > $1 AND seq <= $2 AND NOT is_known(origin_node, origin_seq, $3, $4)
+    UNION
+    SELECT UNNEST(ARRAY[from_url_id, to_url_id]) FROM navigations
+    WHERE seq > $1 AND seq <= $2 AND NOT is_known(origin_node, origin_seq, $3, $4)
+    UNION
+    SELECT url_id FROM search_queries
+    WHERE seq > $1 AND seq <= $2 AND NOT is_known(origin_node, origin_seq, $3, $4)
+    UNION
+    SELECT UNNEST(ARRAY[url_id, referrer_id]) FROM downloads
+    WHERE seq > $1 AND seq <= $2 AND NOT is_known(origin_node, origin_seq, $3, $4))
+ORDER BY seq
+"#,
+        since,
+        last_seq,
+        &nodes,
+        &seqs,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get changed urls")?;
+
+    let visits = sqlx::query_as!(
+        VisitChange,
+        r#"
+SELECT url, provider_name, browser_type as "browser: Browser", visit_count, last_visit_time,
+  duration_secs, duration_estimated, visits.seq,
+  COALESCE(visits.origin_node, (SELECT node_id FROM node_identity)) as "origin!",
+  visits.origin_seq
+FROM visits
+  INNER JOIN urls ON urls.id = visits.url_id
+  INNER JOIN providers ON providers.id = visits.provider_id
+WHERE visits.seq > $1 AND visits.seq <= $2
+  AND NOT is_known(visits.origin_node, visits.origin_seq, $3, $4)
+ORDER BY visits.seq
+"#,
+        since,
+        last_seq,
+        &nodes,
+        &seqs,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get changed visits")?;
+
+    let deleted = sqlx::query_as!(
+        UrlDeletion,
+        r#"
+SELECT url, merged_into, seq,
+  COALESCE(origin_node, (SELECT node_id FROM node_identity)) as "origin!", origin_seq
+FROM url_tombstones
+WHERE seq > $1 AND seq <= $2 AND NOT is_known(origin_node, origin_seq, $3, $4)
+ORDER BY seq
+"#,
+        since,
+        last_seq,
+        &nodes,
+        &seqs,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get removed urls")?;
+
+    let navigations = sqlx::query_as!(
+        NavigationChange,
+        r#"
+SELECT from_urls.url as from_url, to_urls.url as to_url, provider_name,
+  browser_type as "browser: Browser", visit_count, last_visit_time, navigations.seq,
+  COALESCE(navigations.origin_node, (SELECT node_id FROM node_identity)) as "origin!",
+  navigations.origin_seq
+FROM navigations
+  INNER JOIN urls AS from_urls ON from_urls.id = navigations.from_url_id
+  INNER JOIN urls AS to_urls ON to_urls.id = navigations.to_url_id
+  INNER JOIN providers ON providers.id = navigations.provider_id
+WHERE navigations.seq > $1 AND navigations.seq <= $2
+  AND NOT is_known(navigations.origin_node, navigations.origin_seq, $3, $4)
+ORDER BY navigations.seq
+"#,
+        since,
+        last_seq,
+        &nodes,
+        &seqs,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get changed navigations")?;
+
+    let search_queries = sqlx::query_as!(
+        SearchQueryChange,
+        r#"
+SELECT url, query, engine, search_queries.seq,
+  COALESCE(search_queries.origin_node, (SELECT node_id FROM node_identity)) as "origin!",
+  search_queries.origin_seq
+FROM search_queries INNER JOIN urls ON urls.id = search_queries.url_id
+WHERE search_queries.seq > $1 AND search_queries.seq <= $2
+  AND NOT is_known(search_queries.origin_node, search_queries.origin_seq, $3, $4)
+ORDER BY search_queries.seq
+"#,
+        since,
+        last_seq,
+        &nodes,
+        &seqs,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get changed search queries")?;
+
+    let downloads = sqlx::query_as!(
+        DownloadChange,
+        r#"
+SELECT urls.url, provider_name, browser_type as "browser: Browser", file_name, size, mime,
+  referrers.url as "referrer?", start_time, downloads.seq,
+  COALESCE(downloads.origin_node, (SELECT node_id FROM node_identity)) as "origin!",
+  downloads.origin_seq
+FROM downloads
+  INNER JOIN urls ON urls.id = downloads.url_id
+  INNER JOIN providers ON providers.id = downloads.provider_id
+  LEFT JOIN urls AS referrers ON referrers.id = downloads.referrer_id
+WHERE downloads.seq > $1 AND downloads.seq <= $2
+  AND NOT is_known(downloads.origin_node, downloads.origin_seq, $3, $4)
+ORDER BY downloads.seq
+"#,
+        since,
+        last_seq,
+        &nodes,
+        &seqs,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get changed downloads")?;
+
+    let favicons = sqlx::query_as!(
+        FaviconChange,
+        r#"
+SELECT host, mime, width, data, seq,
+  COALESCE(origin_node, (SELECT node_id FROM node_identity)) as "origin!", origin_seq
+FROM favicons INNER JOIN hosts ON hosts.id = favicons.host_id
+WHERE seq > $1 AND seq <= $2 AND NOT is_known(origin_node, origin_seq, $3, $4)
+ORDER BY seq
+"#,
+        since,
+        last_seq,
+        &nodes,
+        &seqs,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get changed favicons")?;
+
+    Ok(ChangeSet {
+        node_id: node_id(&mut *pool).await?,
+        since,
+        last_seq,
+        more,
+        providers: provider::all(&mut *pool).await?,
+        urls,
+        visits,
+        deleted,
+        navigations,
+        search_queries,
+        downloads,
+        favicons,
+        versions: versions(&mut *pool).await?,
+    })
+}
+
+/// The urls which were merged here, and the urls they were finally merged into. The removals
+/// which the peer with the versions already knew are skipped: a url it still sends was inserted
+/// again after it.
+async fn merge_targets(
+    pool: &mut Conn,
+    urls: &[String],
+    nodes: &[String],
+    seqs: &[i64],
+) -> Result<HashMap<String, String>> {
+    let rows = sqlx::query!(
+        r#"
+WITH RECURSIVE chains AS (
+  SELECT url as start, merged_into, 1 as depth
+  FROM url_tombstones
+  WHERE url = ANY ($1::text[]) AND NOT is_known(origin_node, origin_seq, $2, $3)
+  UNION ALL
+  SELECT chains.start, t.merged_into, depth + 1
+  FROM chains INNER JOIN url_tombstones t ON t.url = chains.merged_into
+  WHERE depth < $4
+)
+SELECT DISTINCT ON (start) start as "start!", merged_into as "merged_into!"
+FROM chains
+ORDER BY start, depth DESC
+"#,
+        urls,
+        nodes,
+        seqs,
+        MAX_MERGE_CHAIN,
+    )
+    .fetch_all(pool)
+    .await
+    .context("could not get merged urls")?;
+    Ok(rows.into_iter().map(|r| (r.start, r.merged_into)).collect())
+}
+
+/// merge the removed urls of a peer into the urls they were merged into there, and keep their
+/// tombstones to forward them
+async fn merge_deletions(
+    pool: &mut Conn,
+    deleted: Vec<UrlDeletion>,
+    nodes: &[String],
+    seqs: &[i64],
+) -> Result<()> {
+    if deleted.is_empty() {
+        return Ok(());
+    }
+    let into: Vec<String> = deleted.iter().map(|d| d.merged_into.clone()).collect();
+    let targets = merge_targets(&mut *pool, &into, nodes, seqs).await?;
+    for d in &deleted {
+        let from = sqlx::query!(
+            r#"
+SELECT id, title, privacy as "privacy: UrlPrivacy" FROM urls WHERE url = $1
+"#,
+            d.url
+        )
+        .fetch_optional(&mut *pool)
+        .await
+        .with_context(|| format!("could not find removed url {}", d.url))?;
+        let from = match from {
+            Some(from) => from,
+            None => continue,
+        };
+        let into = targets.get(&d.merged_into).unwrap_or(&d.merged_into);
+        let to_id = match url::try_find(&mut *pool, into).await? {
+            Some(id) => id,
+            None => url::upsert_urls(
+                &mut *pool,
+                vec![into.clone()],
+                vec![from.title],
+                vec![from.privacy],
+            )
+            .await?
+            .remove(0),
+        };
+        if to_id != from.id {
+            url::merge(&mut *pool, from.id, to_id).await?;
+        }
+    }
+    let (mut urls, mut into, mut origins, mut origin_seqs) = (vec![], vec![], vec![], vec![]);
+    for d in deleted {
+        urls.push(d.url);
+        into.push(d.merged_into);
+        origins.push(d.origin);
+        origin_seqs.push(d.origin_seq);
+    }
+    sqlx::query!(
+        r#"
+INSERT INTO url_tombstones(url, merged_into)
+SELECT * FROM UNNEST($1::text[], $2::text[])
+ON CONFLICT (url) DO UPDATE SET merged_into = EXCLUDED.merged_into
+"#,
+        &urls,
+        &into,
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not record removed urls")?;
+    sqlx::query!(
+        r#"
+UPDATE url_tombstones SET origin_node = data.origin, origin_seq = data.origin_seq
+FROM UNNEST($1::text[], $2::text[], $3::text[], $4::bigint[])
+    AS data(url, merged_into, origin, origin_seq)
+WHERE url_tombstones.url = data.url AND url_tombstones.merged_into = data.merged_into
+  AND data.origin <> '' AND data.origin <> (SELECT node_id FROM node_identity)
+"#,
+        &urls,
+        &into,
+        &origins,
+        &origin_seqs,
+    )
+    .execute(pool)
+    .await
+    .context("could not keep origins of removed urls")?;
+    Ok(())
+}
+
+/// the visits of merged urls as visits of the urls they were merged into, the visits which then
+/// meet keep the larger values. They are changes of this node.
+fn redirect_visits(
+    visits: Vec<VisitChange>,
+    targets: &HashMap<String, String>,
+) -> Vec<VisitChange> {
+    if targets.is_empty() {
+        return visits;
+    }
+    let mut res: Vec<VisitChange> = Vec::with_capacity(visits.len());
+    let mut index = HashMap::<(String, String, String), usize>::new();
+    for mut v in visits {
+        if let Some(target) = targets.get(&v.url) {
+            v.url = target.clone();
+            v.origin = String::new();
+        }
+        let key = (
+            v.url.clone(),
+            v.provider_name.clone(),
+            v.browser.to_string(),
+        );
+        match index.get(&key) {
+            Some(&i) => {
+                let old = &mut res[i];
+                if v.duration_secs > old.duration_secs {
+                    old.duration_estimated = v.duration_estimated;
+                } else if v.duration_secs == old.duration_secs {
+                    old.duration_estimated |= v.duration_estimated;
+                }
+                old.duration_secs = old.duration_secs.max(v.duration_secs);
+                old.visit_count = old.visit_count.max(v.visit_count);
+                old.last_visit_time = old.last_visit_time.max(v.last_visit_time);
+                old.origin = String::new();
+            }
+            None => {
+                index.insert(key, res.len());
+                res.push(v);
+            }
+        }
+    }
+    res
+}
+
+/// the url a merged url was merged into, a redirected row is a change of this node
+fn redirect(url: &mut String, origin: &mut String, targets: &HashMap<String, String>) {
+    if let Some(target) = targets.get(url) {
+        *url = target.clone();
+        origin.clear();
+    }
+}
+
+/// merge the navigations of a peer, the navigations which meet keep the larger values
+async fn merge_navigations(
+    pool: &mut Conn,
+    navigations: Vec<NavigationChange>,
+    targets: &HashMap<String, String>,
+) -> Result<()> {
+    if navigations.is_empty() {
+        return Ok(());
+    }
+    let n = navigations.len();
+    let (mut from_urls, mut to_urls, mut providers, mut browsers, mut counts, mut times) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    let (mut origins, mut origin_seqs) = (Vec::with_capacity(n), Vec::with_capacity(n));
+    for mut nav in navigations {
+        redirect(&mut nav.from_url, &mut nav.origin, targets);
+        redirect(&mut nav.to_url, &mut nav.origin, targets);
+        from_urls.push(nav.from_url);
+        to_urls.push(nav.to_url);
+        providers.push(nav.provider_name);
+        browsers.push(nav.browser);
+        counts.push(nav.visit_count);
+        times.push(nav.last_visit_time);
+        origins.push(nav.origin);
+        origin_seqs.push(nav.origin_seq);
+    }
+    sqlx::query!(
+        r#"
+INSERT INTO navigations(from_url_id, to_url_id, provider_id, browser_type, visit_count,
+  last_visit_time)
+SELECT from_urls.id, to_urls.id, providers.id, browser, MAX(visit_count), MAX(last_visit_time)
+FROM UNNEST($1::text[], $2::text[], $3::text[], $4::browser[], $5::integer[], $6::timestamp[])
+    AS data(from_url, to_url, provider, browser, visit_count, last_visit_time)
+  INNER JOIN urls AS from_urls ON from_urls.url = data.from_url
+  INNER JOIN urls AS to_urls ON to_urls.url = data.to_url
+  INNER JOIN providers ON providers.provider_name = data.provider
+WHERE from_urls.id <> to_urls.id
+GROUP BY 1, 2, 3, 4
+ON CONFLICT ON CONSTRAINT navigations_pkey DO UPDATE
+SET visit_count = GREATEST(navigations.visit_count, EXCLUDED.visit_count),
+    last_visit_time = GREATEST(navigations.last_visit_time, EXCLUDED.last_visit_time)
+"#,
+        &from_urls,
+        &to_urls,
+        &providers,
+        &browsers as &[Browser],
+        &counts,
+        &times as &[NaiveDateTime],
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not apply navigations")?;
+    sqlx::query!(
+        r#"
+UPDATE navigations SET origin_node = data.origin, origin_seq = data.origin_seq
+FROM UNNEST($1::text[], $2::text[], $3::text[], $4::browser[], $5::integer[], $6::timestamp[],
+    $7::text[], $8::bigint[])
+    AS data(from_url, to_url, provider, browser, visit_count, last_visit_time, origin,
+      origin_seq),
+  urls AS from_urls, urls AS to_urls, providers
+WHERE from_urls.url = data.from_url AND to_urls.url = data.to_url
+  AND providers.provider_name = data.provider
+  AND navigations.from_url_id = from_urls.id AND navigations.to_url_id = to_urls.id
+  AND navigations.provider_id = providers.id AND navigations.browser_type = data.browser
+  AND navigations.visit_count = data.visit_count
+  AND navigations.last_visit_time = data.last_visit_time
+  AND data.origin <> '' AND data.origin <> (SELECT node_id FROM node_identity)
+"#,
+        &from_urls,
+        &to_urls,
+        &providers,
+        &browsers as &[Browser],
+        &counts,
+        &times as &[NaiveDateTime],
+        &origins,
+        &origin_seqs,
+    )
+    .execute(pool)
+    .await
+    .context("could not keep origins of navigations")?;
+    Ok(())
+}
+
+/// merge the search queries of a peer, the query of a url is overwritten
+async fn merge_search_queries(
+    pool: &mut Conn,
+    queries: Vec<SearchQueryChange>,
+    targets: &HashMap<String, String>,
+) -> Result<()> {
+    if queries.is_empty() {
+        return Ok(());
+    }
+    let n = queries.len();
+    let (mut urls, mut texts, mut engines, mut origins, mut origin_seqs) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    for mut q in queries {
+        redirect(&mut q.url, &mut q.origin, targets);
+        urls.push(q.url);
+        texts.push(q.query);
+        engines.push(q.engine);
+        origins.push(q.origin);
+        origin_seqs.push(q.origin_seq);
+    }
+    let queries = urls
+        .iter()
+        .zip(&texts)
+        .zip(&engines)
+        .map(|((url, query), engine)| SearchQuery {
+            url: url.clone(),
+            query: query.clone(),
+            engine: engine.clone(),
+        })
+        .collect();
+    search_query::insert_search_queries(&mut *pool, queries).await?;
+    sqlx::query!(
+        r#"
+UPDATE search_queries SET origin_node = data.origin, origin_seq = data.origin_seq
+FROM UNNEST($1::text[], $2::text[], $3::text[], $4::text[], $5::bigint[])
+    AS data(url, query, engine, origin, origin_seq),
+  urls
+WHERE urls.url = data.url AND search_queries.url_id = urls.id
+  AND search_queries.query = data.query AND search_queries.engine = data.engine
+  AND data.origin <> '' AND data.origin <> (SELECT node_id FROM node_identity)
+"#,
+        &urls,
+        &texts,
+        &engines,
+        &origins,
+        &origin_seqs,
+    )
+    .execute(pool)
+    .await
+    .context("could not keep origins of search queries")?;
+    Ok(())
+}
+
+/// merge the downloads of a peer, the file name is overwritten and unknown sizes, mime types and
+/// referrers are filled in
+async fn merge_downloads(
+    pool: &mut Conn,
+    downloads: Vec<DownloadChange>,
+    targets: &HashMap<String, String>,
+) -> Result<()> {
+    if downloads.is_empty() {
+        return Ok(());
+    }
+    let n = downloads.len();
+    let (mut urls, mut providers, mut browsers, mut file_names, mut sizes) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    let (mut mimes, mut referrers, mut times, mut origins, mut origin_seqs) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    for mut d in downloads {
+        redirect(&mut d.url, &mut d.origin, targets);
+        if let Some(referrer) = d.referrer.as_mut() {
+            redirect(referrer, &mut d.origin, targets);
+        }
+        urls.push(d.url);
+        providers.push(d.provider_name);
+        browsers.push(d.browser);
+        file_names.push(d.file_name);
+        sizes.push(d.size);
+        mimes.push(d.mime);
+        referrers.push(d.referrer);
+        times.push(d.start_time);
+        origins.push(d.origin);
+        origin_seqs.push(d.origin_seq);
+    }
+    sqlx::query!(
+        r#"
+INSERT INTO downloads(url_id, provider_id, browser_type, file_name, size, mime, referrer_id,
+  start_time)
+SELECT DISTINCT ON (urls.id, providers.id, browser, start_time)
+  urls.id, providers.id, browser, file_name, size, mime, referrers.id, start_time
+FROM UNNEST($1::text[], $2::text[], $3::browser[], $4::text[], $5::bigint[], $6::text[],
+    $7::text[], $8::timestamp[])
+    AS data(url, provider, browser, file_name, size, mime, referrer, start_time)
+  INNER JOIN urls ON urls.url = data.url
+  INNER JOIN providers ON providers.provider_name = data.provider
+  LEFT JOIN urls AS referrers ON referrers.url = data.referrer
+ORDER BY urls.id, providers.id, browser, start_time
+ON CONFLICT ON CONSTRAINT downloads_pkey DO UPDATE
+SET file_name = EXCLUDED.file_name,
+    size = COALESCE(EXCLUDED.size, downloads.size),
+    mime = COALESCE(EXCLUDED.mime, downloads.mime),
+    referrer_id = COALESCE(EXCLUDED.referrer_id, downloads.referrer_id)
+"#,
+        &urls,
+        &providers,
+        &browsers as &[Browser],
+        &file_names,
+        &sizes as &[Option<i64>],
+        &mimes as &[Option<String>],
+        &referrers as &[Option<String>],
+        &times as &[NaiveDateTime],
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not apply downloads")?;
+    sqlx::query!(
+        r#"
+UPDATE downloads SET origin_node = data.origin, origin_seq = data.origin_seq
+FROM UNNEST($1::text[], $2::text[], $3::browser[], $4::text[], $5::bigint[], $6::text[],
+    $7::text[], $8::timestamp[], $9::text[], $10::bigint[])
+    AS data(url, provider, browser, file_name, size, mime, referrer, start_time, origin,
+      origin_seq)
+  INNER JOIN urls ON urls.url = data.url
+  INNER JOIN providers ON providers.provider_name = data.provider
+  LEFT JOIN urls AS referrers ON referrers.url = data.referrer
+WHERE downloads.url_id = urls.id AND downloads.provider_id = providers.id
+  AND downloads.browser_type = data.browser AND downloads.start_time = data.start_time
+  AND downloads.file_name = data.file_name AND downloads.size IS NOT DISTINCT FROM data.size
+  AND downloads.mime IS NOT DISTINCT FROM data.mime
+  AND downloads.referrer_id IS NOT DISTINCT FROM referrers.id
+  AND data.origin <> '' AND data.origin <> (SELECT node_id FROM node_identity)
+"#,
+        &urls,
+        &providers,
+        &browsers as &[Browser],
+        &file_names,
+        &sizes as &[Option<i64>],
+        &mimes as &[Option<String>],
+        &referrers as &[Option<String>],
+        &times as &[NaiveDateTime],
+        &origins,
+        &origin_seqs,
+    )
+    .execute(pool)
+    .await
+    .context("could not keep origins of downloads")?;
+    Ok(())
+}
+
+/// merge the favicons of a peer, the icon which fits better is kept, see
+/// [`favicon::upsert_favicons`]
+async fn merge_favicons(pool: &mut Conn, favicons: Vec<FaviconChange>) -> Result<()> {
+    if favicons.is_empty() {
+        return Ok(());
+    }
+    let n = favicons.len();
+    let (mut icons, mut hosts, mut mimes, mut widths, mut data) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    let (mut origins, mut origin_seqs) = (Vec::with_capacity(n), Vec::with_capacity(n));
+    for f in favicons {
+        hosts.push(f.host.clone());
+        mimes.push(f.mime.clone());
+        widths.push(f.width);
+        data.push(f.data.clone());
+        origins.push(f.origin);
+        origin_seqs.push(f.origin_seq);
+        icons.push(Favicon {
+            host: f.host,
+            mime: f.mime,
+            width: f.width,
+            data: f.data,
+        });
+    }
+    favicon::upsert_favicons(&mut *pool, icons).await?;
+    sqlx::query!(
+        r#"
+UPDATE favicons SET origin_node = icons.origin, origin_seq = icons.origin_seq
+FROM UNNEST($1::text[], $2::text[], $3::int[], $4::bytea[], $5::text[], $6::bigint[])
+    AS icons(host, mime, width, data, origin, origin_seq),
+  hosts
+WHERE hosts.host = icons.host AND favicons.host_id = hosts.id
+  AND favicons.mime = icons.mime AND favicons.width = icons.width
+  AND favicons.data = icons.data
+  AND icons.origin <> '' AND icons.origin <> (SELECT node_id FROM node_identity)
+"#,
+        &hosts,
+        &mimes,
+        &widths,
+        &data,
+        &origins,
+        &origin_seqs,
+    )
+    .execute(pool)
+    .await
+    .context("could not keep origins of favicons")?;
+    Ok(())
+}
+
+/// Merge the rows of a peer. Merging the same rows twice does not change anything: titles and
+/// tags are overwritten, and the visits and navigations keep the larger values. The privacy of a
+/// url is overwritten if the peer knew its version here, otherwise both changed it and the
+/// privacy which wins is kept, see [`UrlChange::winning_privacy`]. Search queries and downloads
+/// are overwritten, and favicons keep the icon which fits better. Removed urls are merged into
+/// the urls they were merged into on the peer, and the rows of urls merged here are applied to
+/// the urls they were merged into.
+async fn merge(pool: &mut Conn, changes: ChangeSet) -> Result<u64> {
+    let ChangeSet {
+        providers,
+        urls,
+        visits,
+        deleted,
+        navigations,
+        search_queries,
+        downloads,
+        favicons,
+        versions,
+        ..
+    } = changes;
+    let (names, import_times): (Vec<_>, Vec<_>) = providers
+        .into_iter()
+        .map(|p| (p.name, p.last_import_time))
+        .unzip();
+    sqlx::query!(
+        r#"
+INSERT INTO providers(provider_name, last_import_time)
+SELECT * FROM UNNEST($1::text[], $2::timestamp[])
+ON CONFLICT (provider_name) DO UPDATE
+SET last_import_time = GREATEST(providers.last_import_time, EXCLUDED.last_import_time)
+"#,
+        &names,
+        &import_times as &[NaiveDateTime],
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not apply providers")?;
+
+    let n = (urls.len()
+        + visits.len()
+        + deleted.len()
+        + navigations.len()
+        + search_queries.len()
+        + downloads.len()
+        + favicons.len()) as u64;
+    let (nodes, seqs): (Vec<_>, Vec<_>) = versions.into_iter().map(|v| (v.node_id, v.seq)).unzip();
+    merge_deletions(&mut *pool, deleted, &nodes, &seqs).await?;
+    let mut changed: Vec<String> = urls.iter().map(|u| u.url.clone()).collect();
+    changed.extend(visits.iter().map(|v| v.url.clone()));
+    for nav in &navigations {
+        changed.extend([nav.from_url.clone(), nav.to_url.clone()]);
+    }
+    changed.extend(search_queries.iter().map(|q| q.url.clone()));
+    for d in &downloads {
+        changed.push(d.url.clone());
+        changed.extend(d.referrer.clone());
+    }
+    changed.sort_unstable();
+    changed.dedup();
+    let targets = merge_targets(&mut *pool, &changed, &nodes, &seqs).await?;
+    // the urls whose version here the peer knew, the change of the peer is newer
+    let outdated: HashSet<String> = sqlx::query_scalar!(
+        r#"
+SELECT url FROM urls WHERE url = ANY ($1::text[]) AND is_known(origin_node, origin_seq, $2, $3)
+"#,
+        &changed,
+        &nodes,
+        &seqs,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get versions of urls")?
+    .into_iter()
+    .collect();
+
+    let urls: Vec<_> = urls
+        .into_iter()
+        .filter(|u| !targets.contains_key(&u.url))
+        .collect();
+    let mut url_strings = Vec::with_capacity(urls.len());
+    let mut titles = Vec::with_capacity(urls.len());
+    let mut privacies = Vec::with_capacity(urls.len());
+    let mut set_ats = Vec::with_capacity(urls.len());
+    let mut tags = Vec::with_capacity(urls.len());
+    let mut newer = Vec::with_capacity(urls.len());
+    let mut origins = Vec::with_capacity(urls.len());
+    let mut origin_seqs = Vec::with_capacity(urls.len());
+    for u in urls {
+        newer.push(outdated.contains(&u.url));
+        url_strings.push(u.url);
+        titles.push(u.title);
+        privacies.push(u.privacy);
+        set_ats.push(u.privacy_set_at);
+        tags.push(u.tag);
+        origins.push(u.origin);
+        origin_seqs.push(u.origin_seq);
+    }
+    let ids = url::upsert_urls(
+        &mut *pool,
+        url_strings.clone(),
+        titles.clone(),
+        privacies.clone(),
+    )
+    .await?;
+    // the privacy of the peer wins if it is newer, otherwise as in `UrlChange::winning_privacy`
+    sqlx::query!(
+        r#"
+UPDATE urls SET tag = data.tag,
+  privacy = CASE
+    WHEN data.newer OR COALESCE(data.set_at > urls.privacy_set_at, data.set_at IS NOT NULL)
+      THEN data.privacy
+    WHEN data.set_at IS NOT DISTINCT FROM urls.privacy_set_at
+      THEN GREATEST(urls.privacy, data.privacy)
+    ELSE urls.privacy END,
+  privacy_set_at = CASE
+    WHEN data.newer OR COALESCE(data.set_at > urls.privacy_set_at, data.set_at IS NOT NULL)
+      THEN data.set_at
+    ELSE urls.privacy_set_at END
+FROM UNNEST($1::integer[], $2::tag[], $3::privacy[], $4::timestamp[], $5::bool[])
+    AS data(id, tag, privacy, set_at, newer)
+WHERE urls.id = data.id
+"#,
+        &ids,
+        &tags as &[UrlTag],
+        &privacies as &[UrlPrivacy],
+        &set_ats as &[Option<NaiveDateTime>],
+        &newer,
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not apply tags and privacy")?;
+    // the urls which equal the changes of the peer keep their origin, so that they are not sent
+    // back to it. The others are new changes of this node.
+    sqlx::query!(
+        r#"
+UPDATE urls SET origin_node = data.origin, origin_seq = data.origin_seq
+FROM UNNEST($1::text[], $2::text[], $3::tag[], $4::privacy[], $5::timestamp[], $6::text[],
+    $7::bigint[])
+    AS data(url, title, tag, privacy, set_at, origin, origin_seq)
+WHERE urls.url = data.url AND urls.title = data.title AND urls.tag = data.tag
+  AND urls.privacy = data.privacy AND urls.privacy_set_at IS NOT DISTINCT FROM data.set_at
+  AND data.origin <> '' AND data.origin <> (SELECT node_id FROM node_identity)
+"#,
+        &url_strings,
+        &titles,
+        &tags as &[UrlTag],
+        &privacies as &[UrlPrivacy],
+        &set_ats as &[Option<NaiveDateTime>],
+        &origins,
+        &origin_seqs,
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not keep origins of urls")?;
+
+    let visits = redirect_visits(visits, &targets);
+    let n_visits = visits.len();
+    let (mut v_urls, mut v_providers, mut browsers, mut counts, mut times, mut secs, mut estimated) = (
+        Vec::with_capacity(n_visits),
+        Vec::with_capacity(n_visits),
+        Vec::with_capacity(n_visits),
+        Vec::with_capacity(n_visits),
+        Vec::with_capacity(n_visits),
+        Vec::with_capacity(n_visits),
+        Vec::with_capacity(n_visits),
+    );
+    let (mut origins, mut origin_seqs) =
+        (Vec::with_capacity(n_visits), Vec::with_capacity(n_visits));
+    for v in visits {
+        v_urls.push(v.url);
+        v_providers.push(v.provider_name);
+        browsers.push(v.browser);
+        counts.push(v.visit_count);
+        times.push(v.last_visit_time);
+        secs.push(v.duration_secs);
+        estimated.push(v.duration_estimated);
+        origins.push(v.origin);
+        origin_seqs.push(v.origin_seq);
+    }
+    sqlx::query!(
+        r#"
+INSERT INTO visits(url_id, provider_id, browser_type, visit_count, last_visit_time,
+  duration_secs, duration_estimated)
+SELECT urls.id, providers.id, browser, visit_count, last_visit_time, secs, estimated
+FROM UNNEST($1::text[], $2::text[], $3::browser[], $4::integer[], $5::timestamp[],
+    $6::bigint[], $7::bool[])
+    AS data(url, provider, browser, visit_count, last_visit_time, secs, estimated)
+  INNER JOIN urls ON urls.url = data.url
+  INNER JOIN providers ON providers.provider_name = data.provider
+ON CONFLICT ON CONSTRAINT visits_pkey DO UPDATE
+SET visit_count = GREATEST(visits.visit_count, EXCLUDED.visit_count),
+    last_visit_time = GREATEST(visits.last_visit_time, EXCLUDED.last_visit_time),
+    duration_secs = GREATEST(visits.duration_secs, EXCLUDED.duration_secs),
+    duration_estimated = CASE
+      WHEN EXCLUDED.duration_secs > visits.duration_secs THEN EXCLUDED.duration_estimated
+      WHEN EXCLUDED.duration_secs = visits.duration_secs
+        THEN visits.duration_estimated OR EXCLUDED.duration_estimated
+      ELSE visits.duration_estimated END
+"#,
+        &v_urls,
+        &v_providers,
+        &browsers as &[Browser],
+        &counts,
+        &times as &[NaiveDateTime],
+        &secs,
+        &estimated,
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not apply visits")?;
+    sqlx::query!(
+        r#"
+UPDATE visits SET origin_node = data.origin, origin_seq = data.origin_seq
+FROM UNNEST($1::text[], $2::text[], $3::browser[], $4::integer[], $5::timestamp[],
+    $6::bigint[], $7::bool[], $8::text[], $9::bigint[])
+    AS data(url, provider, browser, visit_count, last_visit_time, secs, estimated, origin,
+      origin_seq),
+  urls, providers
+WHERE urls.url = data.url AND providers.provider_name = data.provider
+  AND visits.url_id = urls.id AND visits.provider_id = providers.id
+  AND visits.browser_type = data.browser
+  AND visits.visit_count = data.visit_count AND visits.last_visit_time = data.last_visit_time
+  AND visits.duration_secs = data.secs AND visits.duration_estimated = data.estimated
+  AND data.origin <> '' AND data.origin <> (SELECT node_id FROM node_identity)
+"#,
+        &v_urls,
+        &v_providers,
+        &browsers as &[Browser],
+        &counts,
+        &times as &[NaiveDateTime],
+        &secs,
+        &estimated,
+        &origins,
+        &origin_seqs,
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not keep origins of visits")?;
+
+    merge_navigations(&mut *pool, navigations, &targets).await?;
+    merge_search_queries(&mut *pool, search_queries, &targets).await?;
+    merge_downloads(&mut *pool, downloads, &targets).await?;
+    merge_favicons(pool, favicons).await?;
+    Ok(n)
+}
+
+/// merge the changes of a peer in one transaction, see [`merge`], and remember `last_seq` of it
+pub async fn apply(pool: &mut Pool, changes: ChangeSet) -> Result<u64> {
+    let (node_id, last_seq) = (changes.node_id.clone(), changes.last_seq);
+    let mut tx = pool.begin().await?;
+    let n = merge(&mut tx, changes).await?;
+    sqlx::query!(
+        r#"
+INSERT INTO sync_peers(node_id, acked_seq) VALUES ($1, $2)
+ON CONFLICT (node_id) DO UPDATE
+SET acked_seq = GREATEST(sync_peers.acked_seq, EXCLUDED.acked_seq), synced_at = now()
+"#,
+        node_id,
+        last_seq,
+    )
+    .execute(&mut tx)
+    .await
+    .with_context(|| format!("could not ack changes of {}", node_id))?;
+    tx.commit()
+        .await
+        .with_context(|| format!("could not commit changes of {}", node_id))?;
+    Ok(n)
+}
+
+/// merge the reconciled rows of differing buckets in one transaction, see [`merge`]
+pub async fn repair(pool: &mut Pool, rows: ChangeSet) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+    let n = merge(&mut tx, rows).await?;
+    tx.commit()
+        .await
+        .context("could not commit repaired rows")?;
+    Ok(n)
+}
+
+/// hash the buckets again whose visits changed since they were hashed last
+async fn refresh_buckets(pool: &mut Conn) -> Result<()> {
+    let dirty = sqlx::query!(
+        r#"
+DELETE FROM dirty_buckets
+RETURNING provider_id, browser_type as "browser: Browser", bucket
+"#
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get dirty buckets")?;
+    if dirty.is_empty() {
+        return Ok(());
+    }
+    let n = dirty.len();
+    let (mut providers, mut browsers, mut buckets) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    for d in dirty {
+        providers.push(d.provider_id);
+        browsers.push(d.browser);
+        buckets.push(d.bucket);
+    }
+    sqlx::query!(
+        r#"
+DELETE FROM bucket_hashes
+USING UNNEST($1::smallint[], $2::browser[], $3::integer[]) AS dirty(provider_id, browser, bucket)
+WHERE bucket_hashes.provider_id = dirty.provider_id
+  AND bucket_hashes.browser_type = dirty.browser AND bucket_hashes.bucket = dirty.bucket
+"#,
+        &providers,
+        &browsers as &[Browser],
+        &buckets,
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not drop dirty bucket hashes")?;
+    // rows are sorted in the "C" collation, so that the hashes do not depend on the locale of
+    // the database
+    sqlx::query!(
+        r#"
+INSERT INTO bucket_hashes
+SELECT visits.provider_id, visits.browser_type, urls.bucket,
+  md5(string_agg(visit_row(urls, visits), E'\n' ORDER BY url COLLATE "C"))
+FROM visits
+  INNER JOIN urls ON urls.id = visits.url_id
+  INNER JOIN UNNEST($1::smallint[], $2::browser[], $3::integer[])
+    AS dirty(provider_id, browser, bucket)
+    ON visits.provider_id = dirty.provider_id AND visits.browser_type = dirty.browser
+      AND urls.bucket = dirty.bucket
+GROUP BY 1, 2, 3
+"#,
+        &providers,
+        &browsers as &[Browser],
+        &buckets,
+    )
+    .execute(pool)
+    .await
+    .context("could not hash dirty buckets")?;
+    Ok(())
+}
+
+/// the hash of every provider and browser
+pub async fn tree(pool: &mut Pool) -> Result<Vec<TreeNode>> {
+    let mut tx = pool.begin().await?;
+    refresh_buckets(&mut tx).await?;
+    let res = sqlx::query_as!(
+        TreeNode,
+        r#"
+SELECT provider_name, browser_type as "browser!: Browser",
+  md5(string_agg(bucket || ':' || hash, ',' ORDER BY bucket)) as "hash!"
+FROM bucket_hashes
+  INNER JOIN providers ON providers.id = bucket_hashes.provider_id
+GROUP BY 1, 2
+ORDER BY 1, 2
+"#,
+    )
+    .fetch_all(&mut tx)
+    .await
+    .context("could not get hash tree")?;
+    tx.commit()
+        .await
+        .context("could not commit bucket hashes")?;
+    Ok(res)
+}
+
+/// the hashes of the url buckets of a provider and browser
+pub async fn buckets(pool: &mut Pool, name: &str, browser: Browser) -> Result<Vec<BucketHash>> {
+    let mut tx = pool.begin().await?;
+    refresh_buckets(&mut tx).await?;
+    let res = sqlx::query_as!(
+        BucketHash,
+        r#"
+SELECT bucket, hash
+FROM bucket_hashes
+  INNER JOIN providers ON providers.id = bucket_hashes.provider_id
+WHERE provider_name = $1 AND browser_type = $2
+ORDER BY 1
+"#,
+        name,
+        browser as Browser,
+    )
+    .fetch_all(&mut tx)
+    .await
+    .with_context(|| format!("could not get buckets of {} {:?}", name, browser))?;
+    tx.commit()
+        .await
+        .context("could not commit bucket hashes")?;
+    Ok(res)
+}
+
+/// the visits in the buckets of a provider and browser, and their urls
+pub async fn bucket_rows(
+    pool: &mut Pool,
+    name: &str,
+    browser: Browser,
+    buckets: &[i32],
+) -> Result<ChangeSet> {
+    let visits = sqlx::query_as!(
+        VisitChange,
+        r#"
+SELECT url, provider_name, browser_type as "browser: Browser", visit_count, last_visit_time,
+  duration_secs, duration_estimated, visits.seq,
+  COALESCE(visits.origin_node, (SELECT node_id FROM node_identity)) as "origin!",
+  visits.origin_seq
+FROM visits
+  INNER JOIN urls ON urls.id = visits.url_id
+  INNER JOIN providers ON providers.id = visits.provider_id
+WHERE provider_name = $1 AND browser_type = $2 AND bucket = ANY ($3::integer[])
+"#,
+        name,
+        browser as Browser,
+        buckets,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .with_context(|| format!("could not get visits of buckets of {} {:?}", name, browser))?;
+    let url_strings: Vec<String> = visits.iter().map(|v| v.url.clone()).collect();
+    let urls = sqlx::query_as!(
+        UrlChange,
+        r#"
+SELECT url, title, tag as "tag: UrlTag", privacy as "privacy: UrlPrivacy", privacy_set_at, seq,
+  COALESCE(origin_node, (SELECT node_id FROM node_identity)) as "origin!", origin_seq
+FROM urls
+WHERE url = ANY ($1::text[])
+"#,
+        &url_strings,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get urls of buckets")?;
+    // the removed urls of the buckets, so that a repair does not insert them again
+    let deleted = sqlx::query_as!(
+        UrlDeletion,
+        r#"
+SELECT url, merged_into, seq,
+  COALESCE(origin_node, (SELECT node_id FROM node_identity)) as "origin!", origin_seq
+FROM url_tombstones
+WHERE get_byte(decode(md5(url), 'hex'), 0) = ANY ($1::integer[])
+"#,
+        buckets,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get removed urls of buckets")?;
+    Ok(ChangeSet {
+        node_id: node_id(&mut *pool).await?,
+        providers: provider::all(&mut *pool).await?,
+        urls,
+        visits,
+        deleted,
+        ..Default::default()
+    })
+}
+
+/// store a sync run in the history, only the latest [`MAX_RUNS`] runs of the peer are kept
+pub async fn record_run(pool: &mut Pool, run: SyncRun) -> Result<i32> {
+    let id = sqlx::query_scalar!(
+        r#"
+INSERT INTO sync_runs(peer, started_at, finished_at, error) VALUES ($1, $2, $3, $4)
+RETURNING id
+"#,
+        run.peer,
+        run.started_at,
+        run.finished_at,
+        run.error,
+    )
+    .fetch_one(&mut *pool)
+    .await
+    .with_context(|| format!("could not record sync run with {}", run.peer))?;
+
+    let n = run.rows.len();
+    let (mut names, mut browsers, mut pulled, mut pushed) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    for r in run.rows {
+        names.push(r.provider_name);
+        browsers.push(r.browser);
+        pulled.push(r.pulled);
+        pushed.push(r.pushed);
+    }
+    sqlx::query!(
+        r#"
+INSERT INTO sync_run_rows(run_id, provider_name, browser_type, pulled, pushed)
+SELECT $1, * FROM UNNEST($2::text[], $3::browser[], $4::bigint[], $5::bigint[])
+ON CONFLICT DO NOTHING
+"#,
+        id,
+        &names,
+        &browsers as &[Browser],
+        &pulled,
+        &pushed,
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not record rows of sync run")?;
+
+    sqlx::query!(
+        r#"
+DELETE FROM sync_runs
+WHERE peer = $1 AND id NOT IN (
+  SELECT id FROM sync_runs WHERE peer = $1 ORDER BY finished_at DESC LIMIT $2)
+"#,
+        run.peer,
+        MAX_RUNS,
+    )
+    .execute(pool)
+    .await
+    .context("could not drop old sync runs")?;
+    Ok(id)
+}
+
+/// the latest runs, of all peers if `peer` is None, the latest first
+pub async fn runs(pool: &mut Pool, peer: Option<&str>, limit: i64) -> Result<Vec<SyncRun>> {
+    let runs = sqlx::query!(
+        r#"
+SELECT id, peer, started_at, finished_at, error
+FROM sync_runs
+WHERE $1::text is null OR peer = $1
+ORDER BY finished_at DESC
+LIMIT $2
+"#,
+        peer,
+        limit,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get sync runs")?;
+    let ids: Vec<i32> = runs.iter().map(|r| r.id).collect();
+    let mut rows = run_rows(pool, &ids).await?;
+    Ok(runs
+        .into_iter()
+        .map(|r| SyncRun {
+            peer: r.peer,
+            started_at: r.started_at,
+            finished_at: r.finished_at,
+            error: r.error,
+            rows: rows.remove(&r.id).unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// the latest run of every peer, and when its last successful run finished
+pub async fn last_runs(pool: &mut Pool) -> Result<Vec<(SyncRun, Option<NaiveDateTime>)>> {
+    let runs = sqlx::query!(
+        r#"
+SELECT DISTINCT ON (peer) id, peer, started_at, finished_at, error,
+  (SELECT MAX(finished_at) FROM sync_runs s WHERE s.peer = sync_runs.peer AND s.error IS NULL)
+    as last_success
+FROM sync_runs
+ORDER BY peer, finished_at DESC
+"#,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get the last sync runs")?;
+    let ids: Vec<i32> = runs.iter().map(|r| r.id).collect();
+    let mut rows = run_rows(pool, &ids).await?;
+    Ok(runs
+        .into_iter()
+        .map(|r| {
+            let run = SyncRun {
+                peer: r.peer,
+                started_at: r.started_at,
+                finished_at: r.finished_at,
+                error: r.error,
+                rows: rows.remove(&r.id).unwrap_or_default(),
+            };
+            (run, r.last_success)
+        })
+        .collect())
+}
+
+async fn run_rows(pool: &mut Pool, ids: &[i32]) -> Result<HashMap<i32, Vec<SyncRows>>> {
+    let rows = sqlx::query!(
+        r#"
+SELECT run_id, provider_name, browser_type as "browser: Browser", pulled, pushed
+FROM sync_run_rows
+WHERE run_id = ANY($1)
+ORDER BY provider_name, browser_type
+"#,
+        ids,
+    )
+    .fetch_all(pool)
+    .await
+    .context("could not get rows of sync runs")?;
+    let mut res = HashMap::<i32, Vec<SyncRows>>::new();
+    for r in rows {
+        res.entry(r.run_id).or_default().push(SyncRows {
+            provider_name: r.provider_name,
+            browser: r.browser,
+            pulled: r.pulled,
+            pushed: r.pushed,
+        });
+    }
+    Ok(res)
+}
@@ -1,9 +1,22 @@
 use super::*;
 use crate::{
     browser::Browser,
+    download::Download,
+    favicon::Favicon,
+    navigation::NavigationEdge,
     node,
     node::Provider,
-    url::{Filter, Url, UrlPrivacy},
+    session::SessionBuilder,
+    stats::Period,
+    sync::{
+        tree::{self, BucketHash},
+        ChangeSet, OriginVersion, SyncRows, SyncRun, UrlChange, UrlDeletion, FAVICON_WEIGHT,
+    },
+    timeline::TimelinePage,
+    url::{
+        Filter, PrivacyRule, PrivacyRules, SanitizeRules, SearchEngines, SearchQuery, SearchTerm,
+        Url, UrlDuration, UrlPrivacy, UrlTag,
+    },
 };
 use chrono::{NaiveDateTime, TimeZone, Utc};
 
@@ -26,18 +39,21 @@ fn mock_urls(n: i32) -> Vec<Url> {
             title: "t1".to_owned(),
             visit_count: 1 + n,
             last_visit_time: mock_time(1 + n),
+            privacy: UrlPrivacy::Normal,
         },
         Url {
             url: "u2".to_owned(),
             title: "t2".to_owned(),
             visit_count: 2 + n,
             last_visit_time: mock_time(2 + n),
+            privacy: UrlPrivacy::Normal,
         },
         Url {
             url: "u3".to_owned(),
             title: "t3".to_owned(),
             visit_count: 3 + n,
             last_visit_time: mock_time(3 + n),
+            privacy: UrlPrivacy::Normal,
         },
     ]
 }
@@ -202,15 +218,1418 @@ async fn insert_joined_urls() {
     )
 }
 
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn privacy() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    let mut urls = mock_urls(0);
+    urls[0].privacy = UrlPrivacy::Private;
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), urls, None)
+        .await
+        .unwrap();
+
+    let private = || Filter {
+        privacy: Some(UrlPrivacy::Private),
+        unlocked: true,
+        ..Default::default()
+    };
+    let res = joined::fuzzy_search(&mut conn, private()).await.unwrap();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].url, "u1");
+
+    // private urls are still synced
+    let f = Filter::bulk_urls(mock_node().to_string(), mock_browser(), mock_time(-1));
+    let got = joined::get_urls(&mut conn, f).await.unwrap();
+    assert_eq!(got.len(), 3);
+    assert!(got
+        .iter()
+        .any(|u| u.url == "u1" && u.privacy == UrlPrivacy::Private));
+
+    // private urls are hidden unless unlocked
+    let res = joined::fuzzy_search(&mut conn, Filter::default())
+        .await
+        .unwrap();
+    assert_eq!(res.len(), 2);
+    let locked = Filter {
+        privacy: Some(UrlPrivacy::Private),
+        ..Default::default()
+    };
+    let res = joined::fuzzy_search(&mut conn, locked).await.unwrap();
+    assert_eq!(res.len(), 0);
+    url::set_tag(&mut conn, ["u1".to_owned()], UrlTag::Saved)
+        .await
+        .unwrap();
+    assert!(url::get_all_tags(&mut conn, false)
+        .await
+        .unwrap()
+        .is_empty());
+    assert_eq!(url::get_all_tags(&mut conn, true).await.unwrap().len(), 1);
+
+    // inserting the url again as normal should not make it public
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(1), None)
+        .await
+        .unwrap();
+    let res = joined::fuzzy_search(&mut conn, private()).await.unwrap();
+    assert_eq!(res.len(), 1);
+
+    url::set_privacy(&mut conn, ["u1".to_owned()], UrlPrivacy::Normal)
+        .await
+        .unwrap();
+    let res = joined::fuzzy_search(&mut conn, private()).await.unwrap();
+    assert_eq!(res.len(), 0);
+
+    // the privacy a user set is kept by the next import and the privacy rules
+    let mut urls = mock_urls(2);
+    urls[0].privacy = UrlPrivacy::Private;
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), urls, None)
+        .await
+        .unwrap();
+    let res = joined::fuzzy_search(&mut conn, private()).await.unwrap();
+    assert_eq!(res.len(), 0);
+    let rules = PrivacyRules::new(vec![PrivacyRule::Profile {
+        provider: mock_node().to_string(),
+        browser: Some(mock_browser()),
+    }]);
+    assert_eq!(
+        joined::backfill_privacy(&mut conn, &rules).await.unwrap(),
+        2
+    );
+    let res = joined::fuzzy_search(&mut conn, private()).await.unwrap();
+    assert_eq!(res.len(), 2);
+    assert!(res.iter().all(|u| u.url != "u1"));
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn sanitize_urls() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    let mut urls = mock_urls(0);
+    urls[0].url = "u1?utm_source=x".to_owned();
+    urls[2].url = "u3?token=secret".to_owned();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), urls, None)
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(
+        &mut conn,
+        p_id,
+        mock_browser(),
+        mock_urls(0)[..1].to_vec(),
+        None,
+    )
+    .await
+    .unwrap();
+    url::set_tag(&mut conn, ["u1?utm_source=x".to_owned()], UrlTag::Saved)
+        .await
+        .unwrap();
+
+    let n = url::sanitize_all(&mut conn, &SanitizeRules::default())
+        .await
+        .unwrap();
+    assert_eq!(n, 2);
+
+    let f = Filter::bulk_urls(mock_node().to_string(), mock_browser(), mock_time(-1));
+    let mut got = joined::get_urls(&mut conn, f).await.unwrap();
+    got.sort_by(|a, b| a.url.cmp(&b.url));
+    let urls: Vec<_> = got.iter().map(|u| u.url.as_str()).collect();
+    assert_eq!(urls, vec!["u1", "u2", "u3?token=redacted"]);
+    assert_eq!(got[0].visit_count, 2);
+
+    let tags = url::get_all_tags(&mut conn, true).await.unwrap();
+    assert_eq!(tags[&UrlTag::Saved], vec!["u1".to_owned()]);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn duplicate_urls() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    let mut urls = mock_urls(0);
+    urls[0].url = "http://x.com".to_owned();
+    urls[1].url = "https://www.x.com/#frag".to_owned();
+    urls[2].url = "https://x.com/?utm_source=hn".to_owned();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), urls, None)
+        .await
+        .unwrap();
+    url::set_tag(&mut conn, ["http://x.com".to_owned()], UrlTag::Favorite)
+        .await
+        .unwrap();
+
+    // search results are grouped by the canonical url
+    let res = joined::fuzzy_search(&mut conn, Filter::default())
+        .await
+        .unwrap();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].url, "http://x.com");
+
+    // urls stored before the canonical urls were introduced get them on start
+    sqlx::query("UPDATE urls SET canonical = NULL WHERE url = 'http://x.com'")
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(url::backfill_canonical(&mut conn).await.unwrap(), 1);
+
+    // the url visited last is kept, unless it has tracking parameters
+    assert_eq!(url::merge_duplicates(&mut conn).await.unwrap(), 2);
+    let f = Filter::bulk_urls(mock_node().to_string(), mock_browser(), mock_time(-1));
+    let got = joined::get_urls(&mut conn, f).await.unwrap();
+    assert_eq!(got.len(), 1);
+    assert_eq!(got[0].url, "https://www.x.com/#frag");
+    assert_eq!(got[0].visit_count, 6);
+    assert_eq!(got[0].last_visit_time, mock_time(3));
+    let tags = url::get_all_tags(&mut conn, true).await.unwrap();
+    assert_eq!(tags[&UrlTag::Favorite], vec![got[0].url.clone()]);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn hosts() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    let mut urls = mock_urls(0);
+    urls[0].url = "https://www.docs.rs/a".to_owned();
+    urls[1].url = "https://docs.rs/b".to_owned();
+    urls[2].url = "https://crates.io".to_owned();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), urls, None)
+        .await
+        .unwrap();
+
+    let top = host::top_hosts(&mut conn, Filter::default()).await.unwrap();
+    let got: Vec<_> = top
+        .iter()
+        .map(|h| (h.host.as_str(), h.visit_count, h.url_count))
+        .collect();
+    assert_eq!(got, vec![("crates.io", 3, 1), ("docs.rs", 3, 2)]);
+
+    let stats = host::host_stats(&mut conn, "docs.rs", Period::Day, Filter::default())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(stats.summary.first_visit_time, mock_time(1));
+    assert_eq!(stats.browsers.len(), 1);
+    assert_eq!(stats.browsers[0].browser, mock_browser());
+    assert_eq!(stats.trend.len(), 1);
+    assert_eq!(stats.trend[0].visit_count, 3);
+    let www = host::host_stats(&mut conn, "www.Docs.rs", Period::Day, Filter::default())
+        .await
+        .unwrap();
+    assert_eq!(www, Some(stats));
+    assert!(
+        host::host_stats(&mut conn, "none.rs", Period::Day, Filter::default())
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let res = joined::fuzzy_search(&mut conn, Filter::parse("@docs.rs".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(res.len(), 2);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn stats() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+    url::set_tag(&mut conn, ["u1".to_owned()], UrlTag::Saved)
+        .await
+        .unwrap();
+    let tz = "America/New_York";
+
+    let visits = stats::visits(&mut conn, Period::Day, tz, Filter::default())
+        .await
+        .unwrap();
+    assert_eq!(visits.len(), 1);
+    assert_eq!(
+        visits[0].time,
+        NaiveDateTime::parse_from_str("1969-12-31 00:00", "%Y-%m-%d %H:%M").unwrap()
+    );
+    assert_eq!(visits[0].visit_count, 6);
+    assert!(
+        stats::visits(&mut conn, Period::Day, "Nowhere", Filter::default())
+            .await
+            .is_err()
+    );
+
+    let tags = stats::tags(&mut conn, Filter::parse("t".to_owned()))
+        .await
+        .unwrap();
+    let got: Vec<_> = tags.iter().map(|t| (t.tag, t.url_count)).collect();
+    assert_eq!(got, vec![(UrlTag::Normal, 2), (UrlTag::Saved, 1)]);
+
+    let hours = stats::hours(&mut conn, tz, Filter::default())
+        .await
+        .unwrap();
+    let got: Vec<_> = hours.iter().map(|h| (h.hour, h.visit_count)).collect();
+    assert_eq!(got, vec![(19, 6)]);
+
+    // a url is added once, later visits do not add it again
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(100_000), None)
+        .await
+        .unwrap();
+    let urls = stats::urls(&mut conn, Period::Day, tz, Filter::default())
+        .await
+        .unwrap();
+    assert_eq!(urls.len(), 1);
+    assert_eq!(urls[0].provider_name, mock_node().to_string());
+    assert_eq!(urls[0].time, visits[0].time);
+    assert_eq!(urls[0].url_count, 3);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn timeline() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, Browser::Firefox, mock_urls(10), None)
+        .await
+        .unwrap();
+    url::set_privacy(&mut conn, ["u3".to_owned()], UrlPrivacy::Private)
+        .await
+        .unwrap();
+    let urls = |page: &TimelinePage| -> Vec<(Browser, Vec<String>)> {
+        page.groups
+            .iter()
+            .map(|g| (g.browser, g.visits.iter().map(|v| v.url.clone()).collect()))
+            .collect()
+    };
+    let f = Filter {
+        limit: 3,
+        ..Default::default()
+    };
+
+    let first = timeline::timeline(&mut conn, f.clone(), None, None)
+        .await
+        .unwrap();
+    assert_eq!(
+        urls(&first),
+        vec![
+            (Browser::Firefox, vec!["u2".to_owned(), "u1".to_owned()]),
+            (mock_browser(), vec!["u2".to_owned()])
+        ]
+    );
+    assert_eq!(first.newer, None);
+
+    let older = first.older.clone().unwrap().parse().unwrap();
+    let second = timeline::timeline(&mut conn, f.clone(), Some(older), None)
+        .await
+        .unwrap();
+    assert_eq!(urls(&second), vec![(mock_browser(), vec!["u1".to_owned()])]);
+    assert_eq!(second.older, None);
+
+    let newer = second.newer.unwrap().parse().unwrap();
+    let back = timeline::timeline(&mut conn, f.clone(), None, Some(newer))
+        .await
+        .unwrap();
+    assert_eq!(urls(&back), urls(&first));
+    assert_eq!(back.newer, None);
+
+    let unlocked = Filter {
+        unlocked: true,
+        ..f
+    };
+    let first = timeline::timeline(&mut conn, unlocked, None, None)
+        .await
+        .unwrap();
+    assert_eq!(first.groups[0].visits[0].url, "u3");
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn sessions() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    let mut urls = mock_urls(0);
+    urls[2].last_visit_time = mock_time(3 * 3600);
+    urls.push(Url {
+        url: "u4".to_owned(),
+        title: "t4".to_owned(),
+        visit_count: 1,
+        last_visit_time: mock_time(3 * 3600 + 60),
+        privacy: UrlPrivacy::Private,
+    });
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), urls, None)
+        .await
+        .unwrap();
+
+    let n = session::rebuild(&mut conn, &SessionBuilder::default())
+        .await
+        .unwrap();
+    assert_eq!(n, 2);
+    // rebuilding replaces the old sessions
+    let n = session::rebuild(&mut conn, &SessionBuilder::default())
+        .await
+        .unwrap();
+    assert_eq!(n, 2);
+
+    let found = session::search(&mut conn, Filter::parse("t2".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    let urls: Vec<_> = found[0].urls.iter().map(|u| u.url.as_str()).collect();
+    assert_eq!(urls, vec!["u1", "u2"]);
+
+    // private pages are neither matched nor shown
+    let f = Filter::parse("t4".to_owned());
+    assert!(session::search(&mut conn, f.clone())
+        .await
+        .unwrap()
+        .is_empty());
+    let found = session::search(
+        &mut conn,
+        Filter {
+            unlocked: true,
+            ..f
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(found[0].urls.len(), 2);
+    assert_eq!(
+        session::urls(&mut conn, found[0].id, false).await.unwrap(),
+        vec!["u3".to_owned()]
+    );
+
+    // merged urls stay in their sessions
+    url::merge(&mut conn, 2, 1).await.unwrap();
+    let found = session::search(&mut conn, Filter::parse("t1".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(found[0].urls.len(), 1);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn navigations() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    let mut urls = mock_urls(0);
+    urls.push(Url {
+        url: "u4".to_owned(),
+        title: "t4".to_owned(),
+        visit_count: 1,
+        last_visit_time: mock_time(4),
+        privacy: UrlPrivacy::Normal,
+    });
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), urls, None)
+        .await
+        .unwrap();
+    let edge =
+        |from: &str, to: &str, t| NavigationEdge::new(from.to_owned(), to.to_owned(), mock_time(t));
+    let edges = vec![
+        edge("u1", "u2", 2),
+        edge("u2", "u3", 3),
+        edge("u3", "u1", 3),
+        edge("u3", "u4", 4),
+        edge("u3", "u4", 5),
+        edge("unknown", "u4", 5),
+    ];
+    let n = navigation::insert_navigations(&mut conn, &mock_node(), mock_browser(), edges)
+        .await
+        .unwrap();
+    assert_eq!(n, 4);
+
+    let nav = navigation::navigation(&mut conn, "u3", 10, false)
+        .await
+        .unwrap()
+        .unwrap();
+    let referrers: Vec<_> = nav.referrers.iter().map(|s| s.url.as_str()).collect();
+    assert_eq!(referrers, vec!["u1", "u2"]);
+    let opened: Vec<_> = nav
+        .opened
+        .iter()
+        .map(|s| (s.url.as_str(), s.visit_count))
+        .collect();
+    assert_eq!(opened, vec![("u4", 2), ("u1", 1)]);
+
+    url::set_privacy(&mut conn, ["u4".to_owned()], UrlPrivacy::Private)
+        .await
+        .unwrap();
+    let nav = navigation::navigation(&mut conn, "u3", 1, false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(nav.referrers.len(), 1);
+    assert_eq!(nav.opened.len(), 1);
+    assert!(navigation::navigation(&mut conn, "u4", 10, false)
+        .await
+        .unwrap()
+        .is_none());
+
+    // merged urls keep their navigations
+    url::merge(&mut conn, 1, 4).await.unwrap();
+    let nav = navigation::navigation(&mut conn, "u4", 10, true)
+        .await
+        .unwrap()
+        .unwrap();
+    let referrers: Vec<_> = nav.referrers.iter().map(|s| s.url.as_str()).collect();
+    assert_eq!(referrers, vec!["u2", "u3"]);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn search_queries() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    let mut urls = mock_urls(0);
+    urls[0].url = "https://www.google.com/search?q=postgres+locks".to_owned();
+    urls[1].url = "https://duckduckgo.com/?q=private+query".to_owned();
+    urls[1].privacy = UrlPrivacy::Private;
+    urls[2].url = "https://postgresql.org/docs/locks".to_owned();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), urls.clone(), None)
+        .await
+        .unwrap();
+    let edges = vec![NavigationEdge::new(
+        urls[0].url.clone(),
+        urls[2].url.clone(),
+        mock_time(3),
+    )];
+    navigation::insert_navigations(&mut conn, &mock_node(), mock_browser(), edges)
+        .await
+        .unwrap();
+
+    let n = search_query::backfill_search_queries(&mut conn, &SearchEngines::default())
+        .await
+        .unwrap();
+    assert_eq!(n, 2);
+    let terms = vec![SearchTerm {
+        url: urls[0].url.clone(),
+        term: "postgres row locks".to_owned(),
+    }];
+    let queries = SearchEngines::default().extract_all([], terms);
+    search_query::insert_search_queries(&mut conn, queries)
+        .await
+        .unwrap();
+
+    let found = search_query::search_queries(&mut conn, Filter::parse("locks".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].query, "postgres row locks");
+    assert_eq!(found[0].engine, "google");
+    assert_eq!(found[0].results[0].url, urls[2].url);
+    let private = || Filter::parse("query".to_owned());
+    assert!(search_query::search_queries(&mut conn, private())
+        .await
+        .unwrap()
+        .is_empty());
+    let f = Filter {
+        unlocked: true,
+        ..private()
+    };
+    assert_eq!(
+        search_query::search_queries(&mut conn, f)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let found = search_query::queries_of_url(&mut conn, &urls[2].url, 3, false)
+        .await
+        .unwrap();
+    let queries: Vec<_> = found.iter().map(|q| q.query.as_str()).collect();
+    assert_eq!(queries, vec!["postgres row locks"]);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn redirects() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+    let redirect =
+        |from: &str, to: &str| NavigationEdge::new(from.to_owned(), to.to_owned(), mock_time(3));
+    // the loop u1 -> u2 -> u1 is dropped
+    url::set_redirects(&mut conn, vec![redirect("u1", "u2"), redirect("u2", "u1")])
+        .await
+        .unwrap();
+    let res = joined::fuzzy_search(&mut conn, Filter::default())
+        .await
+        .unwrap();
+    assert_eq!(res.len(), 3);
+
+    // u1 -> u2 -> u3
+    let n = url::set_redirects(&mut conn, vec![redirect("u1", "u2")])
+        .await
+        .unwrap();
+    assert_eq!(n, 1);
+    url::set_redirects(&mut conn, vec![redirect("u2", "u3")])
+        .await
+        .unwrap();
+
+    let res = joined::fuzzy_search(&mut conn, Filter::default())
+        .await
+        .unwrap();
+    let urls: Vec<_> = res.iter().map(|u| u.url.as_str()).collect();
+    assert_eq!(urls, vec!["u3"]);
+    let f = Filter::bulk_urls(mock_node().to_string(), mock_browser(), mock_time(-1));
+    assert_eq!(joined::get_urls(&mut conn, f).await.unwrap().len(), 3);
+
+    let res = joined::fuzzy_search(&mut conn, Filter::parse(",t".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(res.len(), 3);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn durations() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+    let duration = |url: &str, visit_id, secs, estimated| UrlDuration {
+        url: url.to_owned(),
+        visit_id,
+        secs,
+        estimated,
+    };
+    let durations = vec![
+        duration("u1", 1, 30, false),
+        duration("u2", 2, 20, true),
+        duration("unknown", 3, 10, false),
+    ];
+    let n = visit::add_durations(&mut conn, &mock_node(), mock_browser(), durations.clone())
+        .await
+        .unwrap();
+    assert_eq!(n, 2);
+    // durations which are sent again are not counted twice
+    visit::add_durations(&mut conn, &mock_node(), mock_browser(), durations)
+        .await
+        .unwrap();
+    visit::add_durations(
+        &mut conn,
+        &mock_node(),
+        mock_browser(),
+        vec![duration("u1", 4, 15, false)],
+    )
+    .await
+    .unwrap();
+
+    let res = joined::fuzzy_search(&mut conn, Filter::default())
+        .await
+        .unwrap();
+    let times: Vec<_> = res
+        .iter()
+        .map(|u| (u.url.as_str(), u.time_spent, u.time_estimated))
+        .collect();
+    assert!(times.contains(&("u1", Some(45), false)));
+    assert!(times.contains(&("u2", Some(20), true)));
+    assert!(times.contains(&("u3", None, false)));
+
+    let res = stats::time_spent(&mut conn, false, Filter::default())
+        .await
+        .unwrap();
+    let keys: Vec<_> = res.iter().map(|t| (t.key.as_str(), t.secs)).collect();
+    assert_eq!(keys, vec![("u1", 45), ("u2", 20)]);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn favicons() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    let mut urls = mock_urls(0);
+    urls[0].url = "https://example.com/a".to_owned();
+    urls[1].url = "https://secret.com/b".to_owned();
+    urls[1].privacy = UrlPrivacy::Private;
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), urls, None)
+        .await
+        .unwrap();
+    let png = vec![0x89, b'P', b'N', b'G', 1];
+    let icon = |url: &str, width| Favicon::from_page(url, png.clone(), width).unwrap();
+    favicon::upsert_favicons(
+        &mut conn,
+        vec![
+            icon("https://example.com", 16),
+            icon("https://secret.com", 16),
+        ],
+    )
+    .await
+    .unwrap();
+    let got = favicon::favicon(&mut conn, "example.com", false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((got.mime.as_str(), got.width), ("image/png", 16));
+    assert!(favicon::favicon(&mut conn, "secret.com", false)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(favicon::favicon(&mut conn, "secret.com", true)
+        .await
+        .unwrap()
+        .is_some());
+
+    // a worse icon does not replace a better one
+    let n = favicon::upsert_favicons(&mut conn, vec![icon("https://example.com", 128)])
+        .await
+        .unwrap();
+    assert_eq!(n, 0);
+    favicon::upsert_favicons(&mut conn, vec![icon("https://example.com", 32)])
+        .await
+        .unwrap();
+    let got = favicon::favicon(&mut conn, "example.com", false)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(got.width, 32);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn downloads() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    let download = Download::from_path(
+        "u2".to_owned(),
+        "file:///home/weiss/my%20paper.pdf",
+        Some(1024),
+        None,
+        Some("u1".to_owned()),
+        mock_time(2),
+    )
+    .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+    // the url of a download which was never visited is stored without a visit
+    let unvisited = Download::from_path(
+        "u9".to_owned(),
+        "/tmp/setup.exe",
+        None,
+        None,
+        None,
+        mock_time(3),
+    )
+    .unwrap();
+    let rules = PrivacyRules::new(vec![PrivacyRule::Profile {
+        provider: mock_node().to_string(),
+        browser: None,
+    }]);
+    let n = download::insert_downloads(
+        &mut conn,
+        &mock_node(),
+        mock_browser(),
+        vec![download.clone(), download.clone(), unvisited.clone()],
+        &rules,
+    )
+    .await
+    .unwrap();
+    assert_eq!(n, 2);
+    let visits: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM visits INNER JOIN urls ON urls.id = url_id WHERE url = 'u9'",
+    )
+    .fetch_one(&mut conn)
+    .await
+    .unwrap();
+    assert_eq!(visits, 0);
+    let (title, privacy): (String, UrlPrivacy) =
+        sqlx::query_as("SELECT title, privacy FROM urls WHERE url = 'u9'")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+    assert_eq!(
+        (title.as_str(), privacy),
+        ("setup.exe", UrlPrivacy::Private)
+    );
+    // a stored url keeps its title and privacy
+    let (title, privacy): (String, UrlPrivacy) =
+        sqlx::query_as("SELECT title, privacy FROM urls WHERE url = 'u2'")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+    assert_eq!((title.as_str(), privacy), ("t2", UrlPrivacy::Normal));
+
+    let res = joined::fuzzy_search(&mut conn, Filter::parse("is:download".to_owned()))
+        .await
+        .unwrap();
+    let urls: Vec<_> = res.iter().map(|u| u.url.as_str()).collect();
+    assert_eq!(urls, vec!["u2"]);
+    let mut f = Filter::parse("is:download setup".to_owned());
+    f.unlocked = true;
+    let res = joined::fuzzy_search(&mut conn, f).await.unwrap();
+    assert_eq!(res.len(), 1);
+    assert_eq!(res[0].url, "u9");
+    // the file name is searched as well as the title
+    let res = joined::fuzzy_search(&mut conn, Filter::parse("is:download paper".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(res.len(), 1);
+
+    let res = download::downloads_of_url(&mut conn, "u2", false)
+        .await
+        .unwrap();
+    assert_eq!(res, vec![download]);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn change_log() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+
+    let all = sync::changes(&mut conn, 0, 100, &[]).await.unwrap();
+    assert_eq!((all.urls.len(), all.visits.len(), all.more), (3, 3, false));
+    let page = sync::changes(&mut conn, 0, 2, &[]).await.unwrap();
+    assert!(page.more);
+    assert!(page.last_seq < all.last_seq);
+    let rest = sync::changes(&mut conn, page.last_seq, 100, &[])
+        .await
+        .unwrap();
+    assert_eq!(page.visits.len() + rest.visits.len(), 3);
+
+    // only real changes are logged
+    url::set_tag(&mut conn, vec!["u1".to_owned()], UrlTag::Saved)
+        .await
+        .unwrap();
+    url::set_tag(&mut conn, vec!["u2".to_owned()], UrlTag::Normal)
+        .await
+        .unwrap();
+    let tagged = sync::changes(&mut conn, all.last_seq, 100, &[])
+        .await
+        .unwrap();
+    let urls: Vec<_> = tagged
+        .urls
+        .iter()
+        .map(|u| (u.url.as_str(), u.tag))
+        .collect();
+    assert_eq!(urls, vec![("u1", UrlTag::Saved)]);
+
+    // the changes of a peer
+    let mut peer = sync::changes(&mut conn, 0, 100, &[]).await.unwrap();
+    peer.node_id = "peer".to_owned();
+    peer.last_seq = 42;
+    peer.urls[0].title = "new title".to_owned();
+    peer.visits[0].visit_count = 100;
+    peer.visits[0].provider_name = "other".to_owned();
+    peer.providers.push(Provider {
+        name: "other".to_owned(),
+        last_import_time: mock_time(9),
+    });
+    let before = sync::changes(&mut conn, 0, 100, &[])
+        .await
+        .unwrap()
+        .last_seq;
+    sync::apply(&mut conn, peer.clone()).await.unwrap();
+    assert_eq!(sync::acked_seq(&mut conn, "peer").await.unwrap(), 42);
+    assert_eq!(sync::acked_seq(&mut conn, "unknown").await.unwrap(), 0);
+    let applied = sync::changes(&mut conn, before, 100, &[]).await.unwrap();
+    // the retitled url, and the url of the new visit
+    assert_eq!((applied.urls.len(), applied.visits.len()), (2, 1));
+    assert!(applied.urls.iter().any(|u| u.title == "new title"));
+    assert_eq!(applied.visits[0].provider_name, "other");
+
+    // applying the same changes again changes nothing
+    sync::apply(&mut conn, peer).await.unwrap();
+    let again = sync::changes(&mut conn, applied.last_seq, 100, &[])
+        .await
+        .unwrap();
+    assert!(again.is_empty());
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn change_log_commit_order() {
+    let mut conn = conn(pool.clone()).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+    let first = sync::changes(&mut conn, 0, 100, &[]).await.unwrap();
+
+    // a change which takes its number first but commits last is not skipped
+    let mut late = pool.begin().await.unwrap();
+    sqlx::query("INSERT INTO urls(url, title) VALUES ('late', 'late')")
+        .execute(&mut late)
+        .await
+        .unwrap();
+    sqlx::query("INSERT INTO urls(url, title) VALUES ('early', 'early')")
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    let since = first.last_seq;
+    let mut reader = tokio::spawn(async move {
+        let mut conn = pool.acquire().await.unwrap();
+        sync::changes(&mut conn, since, 100, &[]).await.unwrap()
+    });
+    let waiting = tokio::time::timeout(std::time::Duration::from_millis(200), &mut reader).await;
+    assert!(waiting.is_err());
+    late.commit().await.unwrap();
+    let changes = reader.await.unwrap();
+    let mut urls: Vec<_> = changes.urls.iter().map(|u| u.url.as_str()).collect();
+    urls.sort_unstable();
+    assert_eq!(urls, vec!["early", "late"]);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn change_log_deletions() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+    let all = sync::changes(&mut conn, 0, 100, &[]).await.unwrap();
+    let id = |url: &'static str| {
+        sqlx::query_scalar::<_, i32>("SELECT id FROM urls WHERE url = $1").bind(url)
+    };
+    let (u1, u2) = (
+        id("u1").fetch_one(&mut conn).await.unwrap(),
+        id("u2").fetch_one(&mut conn).await.unwrap(),
+    );
+    url::merge(&mut conn, u1, u2).await.unwrap();
+    let merged = sync::changes(&mut conn, all.last_seq, 100, &[])
+        .await
+        .unwrap();
+    assert_eq!(merged.deleted.len(), 1);
+    assert_eq!(
+        (
+            merged.deleted[0].url.as_str(),
+            merged.deleted[0].merged_into.as_str()
+        ),
+        ("u1", "u2")
+    );
+
+    // a peer which did not know the removal of u1 yet sends it again with a visit, and removed u3
+    let mut peer = all.clone();
+    peer.node_id = "peer".to_owned();
+    peer.last_seq = 7;
+    peer.urls.retain(|u| u.url == "u1");
+    peer.visits.retain(|v| v.url == "u1");
+    peer.visits[0].provider_name = "other".to_owned();
+    peer.providers.push(Provider {
+        name: "other".to_owned(),
+        last_import_time: mock_time(9),
+    });
+    peer.deleted = vec![UrlDeletion {
+        url: "u3".to_owned(),
+        merged_into: "u2".to_owned(),
+        seq: 5,
+        origin: "peer".to_owned(),
+        origin_seq: 5,
+    }];
+    sync::apply(&mut conn, peer).await.unwrap();
+    let urls: Vec<String> = sqlx::query_scalar("SELECT url FROM urls ORDER BY url")
+        .fetch_all(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(urls, vec!["u2"]);
+    let providers: Vec<String> = sqlx::query_scalar(
+        "SELECT provider_name FROM visits INNER JOIN providers ON providers.id = provider_id
+         ORDER BY 1",
+    )
+    .fetch_all(&mut conn)
+    .await
+    .unwrap();
+    assert_eq!(providers, vec!["other", "test"]);
+
+    // the removal of the peer is forwarded, but not sent back to it
+    let to_other = sync::changes(&mut conn, all.last_seq, 100, &[])
+        .await
+        .unwrap();
+    let mut deleted: Vec<_> = to_other.deleted.iter().map(|d| d.url.as_str()).collect();
+    deleted.sort_unstable();
+    assert_eq!(deleted, vec!["u1", "u3"]);
+    let peer_known = vec![OriginVersion {
+        node_id: "peer".to_owned(),
+        seq: 5,
+    }];
+    let to_peer = sync::changes(&mut conn, all.last_seq, 100, &peer_known)
+        .await
+        .unwrap();
+    assert!(to_peer.deleted.iter().all(|d| d.url != "u3"));
+
+    // a url inserted again is alive
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(5), None)
+        .await
+        .unwrap();
+    let tombstones: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM url_tombstones")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(tombstones, 0);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn change_log_privacy() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+    let privacy = |privacy, versions| ChangeSet {
+        node_id: "peer".to_owned(),
+        urls: vec![UrlChange {
+            url: "u1".to_owned(),
+            title: "t1".to_owned(),
+            tag: UrlTag::Normal,
+            privacy,
+            privacy_set_at: None,
+            seq: 1,
+            origin: "peer".to_owned(),
+            origin_seq: 1,
+        }],
+        versions,
+        ..Default::default()
+    };
+    async fn get(conn: &mut Pool) -> sqlx::Result<UrlPrivacy> {
+        sqlx::query_scalar("SELECT privacy FROM urls WHERE url = 'u1'")
+            .fetch_one(conn)
+            .await
+    }
+
+    // a peer which did not know the url here made it private
+    sync::apply(&mut conn, privacy(UrlPrivacy::Private, vec![]))
+        .await
+        .unwrap();
+    assert_eq!(get(&mut conn).await.unwrap(), UrlPrivacy::Private);
+    // a change which does not know that version does not lower the privacy
+    sync::apply(&mut conn, privacy(UrlPrivacy::Normal, vec![]))
+        .await
+        .unwrap();
+    assert_eq!(get(&mut conn).await.unwrap(), UrlPrivacy::Private);
+    // a change made after it does
+    let known = vec![OriginVersion {
+        node_id: "peer".to_owned(),
+        seq: 1,
+    }];
+    sync::apply(&mut conn, privacy(UrlPrivacy::Normal, known.clone()))
+        .await
+        .unwrap();
+    assert_eq!(get(&mut conn).await.unwrap(), UrlPrivacy::Normal);
+
+    // both nodes changed it, the privacy a user set here wins over the derived one of the peer
+    url::set_privacy(&mut conn, vec!["u1".to_owned()], UrlPrivacy::Private)
+        .await
+        .unwrap();
+    sync::apply(&mut conn, privacy(UrlPrivacy::Normal, known))
+        .await
+        .unwrap();
+    assert_eq!(get(&mut conn).await.unwrap(), UrlPrivacy::Private);
+    // and a user who set it on the peer later wins
+    let mut later = privacy(UrlPrivacy::Normal, vec![]);
+    later.urls[0].privacy_set_at = Some(Utc::now().naive_utc() + chrono::Duration::hours(1));
+    sync::apply(&mut conn, later).await.unwrap();
+    assert_eq!(get(&mut conn).await.unwrap(), UrlPrivacy::Normal);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn change_log_url_details() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+    let edges = vec![NavigationEdge::new(
+        "u1".to_owned(),
+        "u2".to_owned(),
+        mock_time(2),
+    )];
+    navigation::insert_navigations(&mut conn, &mock_node(), mock_browser(), edges)
+        .await
+        .unwrap();
+    let query = SearchQuery {
+        url: "u3".to_owned(),
+        query: "query".to_owned(),
+        engine: "engine".to_owned(),
+    };
+    search_query::insert_search_queries(&mut conn, vec![query])
+        .await
+        .unwrap();
+    let download = Download::from_path(
+        "u2".to_owned(),
+        "/tmp/paper.pdf",
+        Some(1024),
+        None,
+        Some("u1".to_owned()),
+        mock_time(2),
+    )
+    .unwrap();
+    download::insert_downloads(
+        &mut conn,
+        &mock_node(),
+        mock_browser(),
+        vec![download],
+        &PrivacyRules::new(vec![]),
+    )
+    .await
+    .unwrap();
+    let icon =
+        Favicon::from_page("https://example.com", vec![0x89, b'P', b'N', b'G', 1], 16).unwrap();
+    favicon::upsert_favicons(&mut conn, vec![icon])
+        .await
+        .unwrap();
+
+    let all = sync::changes(&mut conn, 0, 1000, &[]).await.unwrap();
+    let details = |c: &ChangeSet| {
+        (
+            c.navigations.len(),
+            c.search_queries.len(),
+            c.downloads.len(),
+            c.favicons.len(),
+        )
+    };
+    assert_eq!(details(&all), (1, 1, 1, 1));
+    assert_eq!(all.downloads[0].referrer.as_deref(), Some("u1"));
+
+    // the favicon changed last, and it counts as more rows than the others
+    let rows = (all.urls.len() + all.visits.len() + 3) as i64;
+    let page = sync::changes(&mut conn, 0, rows, &[]).await.unwrap();
+    assert_eq!((details(&page), page.more), ((1, 1, 1, 0), true));
+    let page = sync::changes(&mut conn, 0, rows + FAVICON_WEIGHT, &[])
+        .await
+        .unwrap();
+    assert_eq!(page.favicons.len(), 1);
+    let page = sync::changes(&mut conn, 0, rows + FAVICON_WEIGHT + 1, &[])
+        .await
+        .unwrap();
+    assert!(!page.more);
+
+    // the changes of a peer
+    let mut peer = all.clone();
+    peer.node_id = "peer".to_owned();
+    peer.last_seq = 42;
+    peer.navigations[0].visit_count = 10;
+    peer.search_queries[0].query = "new query".to_owned();
+    peer.downloads[0].size = Some(2048);
+    peer.favicons[0].width = 32;
+    peer.favicons[0].data = vec![0x89, b'P', b'N', b'G', 2];
+    peer.navigations[0].origin = "peer".to_owned();
+    peer.search_queries[0].origin = "peer".to_owned();
+    peer.downloads[0].origin = "peer".to_owned();
+    peer.favicons[0].origin = "peer".to_owned();
+    sync::apply(&mut conn, peer.clone()).await.unwrap();
+    let applied = sync::changes(&mut conn, all.last_seq, 1000, &[])
+        .await
+        .unwrap();
+    assert_eq!(details(&applied), (1, 1, 1, 1));
+    assert_eq!(applied.navigations[0].visit_count, 10);
+    assert_eq!(applied.search_queries[0].query, "new query");
+    assert_eq!(applied.downloads[0].size, Some(2048));
+    assert_eq!(applied.favicons[0].width, 32);
+    // the urls of the rows are included
+    assert!(applied.urls.iter().any(|u| u.url == "u3"));
+
+    // the rows equal the changes of the peer, they are not sent back to it
+    let peer_known = vec![OriginVersion {
+        node_id: "peer".to_owned(),
+        seq: peer
+            .navigations
+            .iter()
+            .map(|n| n.origin_seq)
+            .chain(peer.search_queries.iter().map(|q| q.origin_seq))
+            .chain(peer.downloads.iter().map(|d| d.origin_seq))
+            .chain(peer.favicons.iter().map(|f| f.origin_seq))
+            .max()
+            .unwrap(),
+    }];
+    let echo = sync::changes(&mut conn, all.last_seq, 1000, &peer_known)
+        .await
+        .unwrap();
+    assert!(echo.is_empty());
+
+    // applying the same changes again changes nothing
+    sync::apply(&mut conn, peer).await.unwrap();
+    let again = sync::changes(&mut conn, applied.last_seq, 1000, &[])
+        .await
+        .unwrap();
+    assert!(again.is_empty());
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn origin_versions() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+    let own = sync::versions(&mut conn).await.unwrap();
+    assert_eq!(own.len(), 1);
+
+    // a peer knows all changes of this node, nothing is echoed back
+    let echo = sync::changes(&mut conn, 0, 100, &own).await.unwrap();
+    assert!(echo.is_empty());
+    // the own version is the watermark the changes are paged up to
+    assert_eq!(own[0].seq, echo.last_seq);
+
+    // changes made on x and forwarded by a peer keep their origin
+    let mut forwarded = sync::changes(&mut conn, 0, 100, &[]).await.unwrap();
+    forwarded.node_id = "peer".to_owned();
+    forwarded.urls.retain(|u| u.url == "u1");
+    forwarded.visits.retain(|v| v.url == "u1");
+    forwarded.urls[0].title = "from x".to_owned();
+    forwarded.visits[0].visit_count = 50;
+    for (i, u) in forwarded.urls.iter_mut().enumerate() {
+        u.origin = "x".to_owned();
+        u.origin_seq = 10 + i as i64;
+    }
+    for v in forwarded.visits.iter_mut() {
+        v.origin = "x".to_owned();
+        v.origin_seq = 20;
+    }
+    sync::apply(&mut conn, forwarded).await.unwrap();
+    let all = sync::changes(&mut conn, 0, 100, &[]).await.unwrap();
+    let u1 = all.urls.iter().find(|u| u.url == "u1").unwrap();
+    assert_eq!((u1.origin.as_str(), u1.origin_seq), ("x", 10));
+
+    // a node which knows the changes of x does not get them again
+    let x_known = vec![OriginVersion {
+        node_id: "x".to_owned(),
+        seq: 20,
+    }];
+    let to_x = sync::changes(&mut conn, 0, 100, &x_known).await.unwrap();
+    assert!(to_x.visits.iter().all(|v| v.url != "u1"));
+    assert!(to_x.urls.iter().all(|u| u.url != "u1"));
+    assert_eq!(to_x.visits.len(), 2);
+
+    // a local change makes the row a change of this node again
+    url::set_tag(&mut conn, vec!["u1".to_owned()], UrlTag::Saved)
+        .await
+        .unwrap();
+    let to_x = sync::changes(&mut conn, 0, 100, &x_known).await.unwrap();
+    assert!(to_x.urls.iter().any(|u| u.url == "u1"));
+
+    sync::record_versions(&mut conn, x_known.clone())
+        .await
+        .unwrap();
+    sync::record_versions(&mut conn, own.clone()).await.unwrap();
+    let versions = sync::versions(&mut conn).await.unwrap();
+    assert_eq!(versions.len(), 2);
+    assert!(versions.contains(&x_known[0]));
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn hash_tree() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), mock_urls(0), None)
+        .await
+        .unwrap();
+
+    let before = sync::tree(&mut conn).await.unwrap();
+    assert_eq!(before.len(), 1);
+    assert_eq!(before[0].browser, mock_browser());
+    assert_eq!(before, sync::tree(&mut conn).await.unwrap());
+    let buckets_before = sync::buckets(&mut conn, &mock_provider(), mock_browser())
+        .await
+        .unwrap();
+    let all: Vec<i32> = buckets_before.iter().map(|b| b.bucket).collect();
+    let rows = sync::bucket_rows(&mut conn, &mock_provider(), mock_browser(), &all)
+        .await
+        .unwrap();
+    assert_eq!((rows.urls.len(), rows.visits.len()), (3, 3));
+
+    // a repair of one url only changes the hash of its bucket
+    let mut repaired = rows.clone();
+    repaired.visits.retain(|v| v.url == "u1");
+    repaired.visits[0].visit_count += 10;
+    let repaired = tree::reconcile(rows, repaired);
+    sync::repair(&mut conn, repaired.clone()).await.unwrap();
+    assert_ne!(before, sync::tree(&mut conn).await.unwrap());
+    let buckets_after = sync::buckets(&mut conn, &mock_provider(), mock_browser())
+        .await
+        .unwrap();
+    let key =
+        |b: Vec<BucketHash>| -> Vec<_> { b.into_iter().map(|b| (b.bucket, b.hash)).collect() };
+    let differing = tree::diff(&key(buckets_before), &key(buckets_after.clone()));
+    let u1 = repaired.visits.iter().find(|v| v.url == "u1").unwrap();
+    let u1_bucket = sync::bucket_rows(&mut conn, &mock_provider(), mock_browser(), &differing)
+        .await
+        .unwrap();
+    assert_eq!(u1_bucket.visits.len(), 1);
+    assert_eq!(u1_bucket.visits[0].visit_count, u1.visit_count);
+
+    // repairing again changes nothing
+    sync::repair(&mut conn, repaired).await.unwrap();
+    assert_eq!(
+        buckets_after,
+        sync::buckets(&mut conn, &mock_provider(), mock_browser())
+            .await
+            .unwrap()
+    );
+
+    // a url merged here is not inserted again by the rows of a node which still has it
+    let stale = sync::bucket_rows(&mut conn, &mock_provider(), mock_browser(), &all)
+        .await
+        .unwrap();
+    let id = |url: &'static str| {
+        sqlx::query_scalar::<_, i32>("SELECT id FROM urls WHERE url = $1").bind(url)
+    };
+    let (u1, u2) = (
+        id("u1").fetch_one(&mut conn).await.unwrap(),
+        id("u2").fetch_one(&mut conn).await.unwrap(),
+    );
+    url::merge(&mut conn, u1, u2).await.unwrap();
+    let rows = sync::bucket_rows(&mut conn, &mock_provider(), mock_browser(), &all)
+        .await
+        .unwrap();
+    assert_eq!(rows.deleted.len(), 1);
+    sync::repair(&mut conn, tree::reconcile(rows, stale))
+        .await
+        .unwrap();
+    let urls: Vec<String> = sqlx::query_scalar("SELECT url FROM urls ORDER BY url")
+        .fetch_all(&mut conn)
+        .await
+        .unwrap();
+    assert_eq!(urls, vec!["u2", "u3"]);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn url_pages() {
+    let mut conn = conn(pool).await;
+    let p_id = provider::find_or_insert(&mut conn, &mock_node())
+        .await
+        .unwrap();
+    let mut urls = mock_urls(0);
+    // the same time as u2, the order falls back to the url
+    urls.push(Url {
+        url: "u0".to_owned(),
+        ..urls[1].clone()
+    });
+    urls.push(Url {
+        url: "u4".to_owned(),
+        privacy: UrlPrivacy::Private,
+        ..urls[2].clone()
+    });
+    joined::insert_urls_by_id(&mut conn, p_id, mock_browser(), urls, None)
+        .await
+        .unwrap();
+
+    let pages = |unlocked| Filter {
+        limit: 2,
+        unlocked,
+        ..Filter::bulk_urls(mock_node().to_string(), mock_browser(), mock_time(-1))
+    };
+    let mut got = vec![];
+    let mut f = pages(false);
+    loop {
+        let page = joined::get_url_page(&mut conn, f.clone()).await.unwrap();
+        got.extend(page.urls.into_iter().map(|u| u.url));
+        match page.next {
+            Some(after) => f.after = Some(after),
+            None => break,
+        }
+    }
+    assert_eq!(got, vec!["u1", "u0", "u2", "u3"]);
+    // private urls are only paged if unlocked
+    let page = joined::get_url_page(
+        &mut conn,
+        Filter {
+            limit: 10,
+            ..pages(true)
+        },
+    )
+    .await
+    .unwrap();
+    assert_eq!(page.urls.len(), 5);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn upload_cursors() {
+    let mut conn = conn(pool).await;
+    assert_eq!(upload::upload_cursor(&mut conn, "a").await.unwrap(), 0);
+    upload::advance_upload(&mut conn, "a", 2).await.unwrap();
+    // a late retry of an earlier chunk does not move the cursor back
+    upload::advance_upload(&mut conn, "a", 1).await.unwrap();
+    assert_eq!(upload::upload_cursor(&mut conn, "a").await.unwrap(), 2);
+    assert_eq!(upload::upload_cursor(&mut conn, "b").await.unwrap(), 0);
+}
+
+#[sqlx_database_tester::test(pool(variable = "pool"))]
+async fn sync_runs() {
+    let mut conn = conn(pool).await;
+    let run = |peer: &str, finished: i32, error: Option<&str>| SyncRun {
+        peer: peer.to_owned(),
+        started_at: mock_time(finished - 1),
+        finished_at: mock_time(finished),
+        error: error.map(str::to_owned),
+        rows: vec![SyncRows {
+            provider_name: mock_provider(),
+            browser: mock_browser(),
+            pulled: finished.into(),
+            pushed: 0,
+        }],
+    };
+    sync::record_run(&mut conn, run("a", 1, None))
+        .await
+        .unwrap();
+    sync::record_run(&mut conn, run("a", 2, Some("timeout")))
+        .await
+        .unwrap();
+    sync::record_run(&mut conn, run("b", 3, None))
+        .await
+        .unwrap();
+
+    let history = sync::runs(&mut conn, Some("a"), 10).await.unwrap();
+    assert_eq!(
+        history,
+        vec![run("a", 2, Some("timeout")), run("a", 1, None)]
+    );
+    assert_eq!(sync::runs(&mut conn, None, 10).await.unwrap().len(), 3);
+
+    let last = sync::last_runs(&mut conn).await.unwrap();
+    assert_eq!(
+        last,
+        vec![
+            (run("a", 2, Some("timeout")), Some(mock_time(1))),
+            (run("b", 3, None), Some(mock_time(3)))
+        ]
+    );
+}
+
 #[sqlx_database_tester::test(pool(variable = "pool"))]
 async fn upsert_urls() {
     let mut conn = conn(pool).await;
-    let (urls, titles, _, _) = joined::unpack_urls(mock_urls(0)[..2].to_vec());
-    let url_ids = url::upsert_urls(&mut conn, urls, titles).await.unwrap();
+    let (urls, titles, _, _, privacies) = joined::unpack_urls(mock_urls(0)[..2].to_vec());
+    let url_ids = url::upsert_urls(&mut conn, urls, titles, privacies)
+        .await
+        .unwrap();
     assert_eq!(vec![1, 2], url_ids);
 
-    let (urls, titles, _, _) = joined::unpack_urls(mock_urls(0)[1..].to_vec());
-    let url_ids = url::upsert_urls(&mut conn, urls, titles).await.unwrap();
+    let (urls, titles, _, _, privacies) = joined::unpack_urls(mock_urls(0)[1..].to_vec());
+    let url_ids = url::upsert_urls(&mut conn, urls, titles, privacies)
+        .await
+        .unwrap();
     assert_eq!(vec![2, 4], url_ids);
 }
 
@@ -218,12 +1637,12 @@ async fn upsert_urls() {
 // #[sqlx_database_tester::test(pool(variable = "pool"))]
 // async fn upsert_safari_urls() {
 //     let mut conn = conn(pool).await;
-//     let (urls, titles, _, _) = joined::unpack_urls(mock_urls(0)[..2].to_vec());
-//     let url_ids = url::upsert_urls(&mut conn, urls, titles).await.unwrap();
+//     let (urls, titles, _, _, privacies) = joined::unpack_urls(mock_urls(0)[..2].to_vec());
+//     let url_ids = url::upsert_urls(&mut conn, urls, titles, privacies).await.unwrap();
 //     assert_eq!(vec![1, 2], url_ids);
 
-//     let (urls, titles, _, _) = joined::unpack_urls(mock_urls(0)[1..].to_vec());
-//     let url_ids = url::upsert_urls(&mut conn, urls, titles).await.unwrap();
+//     let (urls, titles, _, _, privacies) = joined::unpack_urls(mock_urls(0)[1..].to_vec());
+//     let url_ids = url::upsert_urls(&mut conn, urls, titles, privacies).await.unwrap();
 //     assert_eq!(vec![2, 4], url_ids);
 // }
 
@@ -239,11 +1658,14 @@ async fn upsert_bulk_urls() {
             title: format!("title {}", n),
             visit_count: 1 + n,
             last_visit_time: mock_time(1 + n),
+            privacy: UrlPrivacy::Normal,
         })
         .collect();
 
-    let (urls, titles, _, _) = joined::unpack_urls(bulk_urls);
-    let url_ids = url::upsert_urls(&mut conn, urls, titles).await.unwrap();
+    let (urls, titles, _, _, privacies) = joined::unpack_urls(bulk_urls);
+    let url_ids = url::upsert_urls(&mut conn, urls, titles, privacies)
+        .await
+        .unwrap();
     assert_eq!(range.clone().collect::<Vec<_>>(), url_ids);
 }
 
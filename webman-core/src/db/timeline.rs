@@ -0,0 +1,119 @@
+use super::Pool;
+use crate::{
+    browser::Browser,
+    error::WebmanError,
+    timeline::{Cursor, TimelinePage, TimelineVisit},
+    url::{Filter, UrlPrivacy, UrlTag},
+};
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+
+/// The visits in the time window of `f`, newest first. With `before` the page continues backward
+/// in time from the cursor, with `after` it continues forward, without both it starts from the
+/// newest visit.
+pub async fn timeline(
+    pool: &mut Pool,
+    mut f: Filter,
+    before: Option<Cursor>,
+    after: Option<Cursor>,
+) -> Result<TimelinePage> {
+    f.init();
+    f.check_pid(&mut *pool).await?;
+    if before.is_some() && after.is_some() {
+        return Err(WebmanError::InvalidInput(
+            "only one of before and after can be used".to_owned(),
+        )
+        .into());
+    }
+    let forward = after.is_some();
+    let cursor = before.as_ref().or(after.as_ref());
+
+    // one more row is fetched to know whether there are more visits
+    let mut rows = sqlx::query!(
+        r#"
+SELECT url_id, provider_id, provider_name, browser_type as "browser: Browser",
+  url, title, tag as "tag: UrlTag", privacy as "privacy: UrlPrivacy",
+  visit_count, last_visit_time
+FROM urls
+  INNER JOIN visits ON urls.id = visits.url_id
+  INNER JOIN providers ON providers.id = visits.provider_id
+WHERE visit_matches(urls, visits, $1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+  AND ($11::timestamp is null
+    OR ($15 AND (last_visit_time, url_id, provider_id, browser_type) > ($11, $12::integer, $13::smallint, $14::browser))
+    OR (NOT $15 AND (last_visit_time, url_id, provider_id, browser_type) < ($11, $12::integer, $13::smallint, $14::browser)))
+ORDER BY
+  CASE WHEN $15 THEN last_visit_time END,
+  CASE WHEN $15 THEN url_id END,
+  CASE WHEN $15 THEN provider_id END,
+  CASE WHEN $15 THEN browser_type END,
+  last_visit_time DESC, url_id DESC, provider_id DESC, browser_type DESC
+LIMIT $16
+"#,
+        f.p_id,
+        f.browser as Option<Browser>,
+        f.privacy as Option<UrlPrivacy>,
+        f.time_from,
+        f.time_to,
+        f.url_segs.as_slice(),
+        f.title_segs.as_slice(),
+        f.tag as Option<UrlTag>,
+        f.unlocked,
+        f.host,
+        cursor.map(|c| c.time) as Option<NaiveDateTime>,
+        cursor.map(|c| c.url_id),
+        cursor.map(|c| c.provider_id),
+        cursor.map(|c| c.browser) as Option<Browser>,
+        forward,
+        f.limit + 1,
+    )
+    .fetch_all(pool)
+    .await
+    .with_context(|| format!("could not get timeline by {:?}", f))?;
+
+    let more = rows.len() as i64 > f.limit;
+    rows.truncate(f.limit as usize);
+    if forward {
+        rows.reverse();
+    }
+    let rows: Vec<_> = rows
+        .into_iter()
+        .map(|r| {
+            let cursor = Cursor {
+                time: r.last_visit_time,
+                url_id: r.url_id,
+                provider_id: r.provider_id,
+                browser: r.browser,
+            };
+            let visit = TimelineVisit {
+                url: r.url,
+                title: r.title,
+                tag: r.tag,
+                privacy: r.privacy,
+                visit_count: r.visit_count,
+                last_visit_time: r.last_visit_time,
+            };
+            (cursor, (r.provider_name, r.browser, visit))
+        })
+        .collect();
+
+    // the visit at the cursor is on the other side of the page
+    let (has_newer, has_older) = if forward {
+        (more, true)
+    } else {
+        (before.is_some(), more)
+    };
+    let newer = rows
+        .first()
+        .filter(|_| has_newer)
+        .map(|(c, _)| c.to_string());
+    let older = rows
+        .last()
+        .filter(|_| has_older)
+        .map(|(c, _)| c.to_string());
+
+    Ok(TimelinePage {
+        groups: TimelinePage::group(rows.into_iter().map(|(_, v)| v)),
+        newer,
+        older,
+    })
+}
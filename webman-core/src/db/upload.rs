@@ -0,0 +1,67 @@
+use super::{Conn, Pool};
+use anyhow::{Context, Result};
+
+/// the index of the next chunk of the upload which is expected, 0 if the upload is unknown
+pub async fn upload_cursor(pool: &mut Pool, upload_id: &str) -> Result<i32> {
+    sqlx::query_scalar!(
+        r#"
+SELECT next_chunk FROM upload_cursors WHERE upload_id = $1
+"#,
+        upload_id
+    )
+    .fetch_optional(pool)
+    .await
+    .with_context(|| format!("could not get cursor of upload {}", upload_id))
+    .map(|n| n.unwrap_or(0))
+}
+
+/// The index of the next chunk of the upload which is expected, the cursor is locked until the
+/// transaction ends. A chunk which is sent twice at once is stored once, the second waits for the
+/// first and then finds it stored.
+pub async fn lock_upload(pool: &mut Conn, upload_id: &str) -> Result<i32> {
+    sqlx::query!(
+        r#"
+INSERT INTO upload_cursors(upload_id, next_chunk) VALUES ($1, 0)
+ON CONFLICT (upload_id) DO NOTHING
+"#,
+        upload_id
+    )
+    .execute(&mut *pool)
+    .await
+    .with_context(|| format!("could not create cursor of upload {}", upload_id))?;
+    sqlx::query_scalar!(
+        r#"
+SELECT next_chunk FROM upload_cursors WHERE upload_id = $1 FOR UPDATE
+"#,
+        upload_id
+    )
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("could not lock cursor of upload {}", upload_id))
+}
+
+/// remember that the chunks of the upload before `next_chunk` are stored, the cursors of uploads
+/// which were not continued for a week are dropped
+pub async fn advance_upload(pool: &mut Conn, upload_id: &str, next_chunk: i32) -> Result<()> {
+    sqlx::query!(
+        r#"
+INSERT INTO upload_cursors(upload_id, next_chunk) VALUES ($1, $2)
+ON CONFLICT (upload_id) DO UPDATE
+SET next_chunk = GREATEST(upload_cursors.next_chunk, EXCLUDED.next_chunk), updated_at = now()
+"#,
+        upload_id,
+        next_chunk,
+    )
+    .execute(&mut *pool)
+    .await
+    .with_context(|| format!("could not advance cursor of upload {}", upload_id))?;
+    sqlx::query!(
+        r#"
+DELETE FROM upload_cursors WHERE updated_at < now() - interval '7 days'
+"#
+    )
+    .execute(pool)
+    .await
+    .context("could not drop stale upload cursors")?;
+    Ok(())
+}
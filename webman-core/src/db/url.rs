@@ -1,21 +1,36 @@
-use super::Pool;
-use crate::url::{self, UrlTag};
+use super::{host, Conn, Pool};
+use crate::{
+    navigation::NavigationEdge,
+    url::{self, canonical, normalized_host, SanitizeRules, UrlPrivacy, UrlTag},
+};
 use anyhow::{Context, Result};
+use sqlx::Connection;
+
+/// the maximal length of a redirect chain
+const MAX_REDIRECTS: usize = 10;
 
 pub async fn upsert_urls<'a>(
-    pool: &mut Pool,
+    pool: &mut Conn,
     urls: Vec<String>,
     titles: Vec<String>,
+    privacies: Vec<UrlPrivacy>,
 ) -> Result<Vec<i32>> {
+    let canonicals: Vec<String> = urls.iter().map(|u| canonical(u)).collect();
+    let hosts: Vec<Option<String>> = urls.iter().map(|u| normalized_host(u)).collect();
+    host::upsert_hosts(&mut *pool, &hosts).await?;
     let data = sqlx::query!(
         r#"
-INSERT INTO urls(url,title)
-SELECT * FROM UNNEST($1::text[],$2::text[])
-ON CONFLICT (url) DO UPDATE SET title = EXCLUDED.title
+INSERT INTO urls(url,title,privacy,canonical,host_id)
+SELECT u, t, p, c, (SELECT id FROM hosts WHERE hosts.host = h)
+FROM UNNEST($1::text[],$2::text[],$3::privacy[],$4::text[],$5::text[]) AS data(u,t,p,c,h)
+ON CONFLICT (url) DO UPDATE SET title = EXCLUDED.title, privacy = CASE WHEN urls.privacy_set_at IS NULL THEN GREATEST(urls.privacy, EXCLUDED.privacy) ELSE urls.privacy END, canonical = EXCLUDED.canonical, host_id = EXCLUDED.host_id
 RETURNING id
 "#,
         &urls[..],
-        &titles[..]
+        &titles[..],
+        &privacies[..] as &[UrlPrivacy],
+        &canonicals[..],
+        &hosts[..] as &[Option<String>]
     )
     .fetch_all(pool)
     .await
@@ -24,6 +39,36 @@ RETURNING id
     Ok(data.into_iter().map(|r| r.id).collect())
 }
 
+/// insert the urls which are not stored yet without touching the stored ones, e.g. the urls
+/// files were downloaded from without a visit
+pub async fn insert_missing(
+    pool: &mut Conn,
+    urls: Vec<String>,
+    titles: Vec<String>,
+    privacies: Vec<UrlPrivacy>,
+) -> Result<u64> {
+    let canonicals: Vec<String> = urls.iter().map(|u| canonical(u)).collect();
+    let hosts: Vec<Option<String>> = urls.iter().map(|u| normalized_host(u)).collect();
+    host::upsert_hosts(&mut *pool, &hosts).await?;
+    sqlx::query!(
+        r#"
+INSERT INTO urls(url,title,privacy,canonical,host_id)
+SELECT u, t, p, c, (SELECT id FROM hosts WHERE hosts.host = h)
+FROM UNNEST($1::text[],$2::text[],$3::privacy[],$4::text[],$5::text[]) AS data(u,t,p,c,h)
+ON CONFLICT (url) DO NOTHING
+"#,
+        &urls[..],
+        &titles[..],
+        &privacies[..] as &[UrlPrivacy],
+        &canonicals[..],
+        &hosts[..] as &[Option<String>]
+    )
+    .execute(pool)
+    .await
+    .context("could not insert missing urls")
+    .map(|r| r.rows_affected())
+}
+
 pub async fn set_tag<S: AsRef<[String]>>(pool: &mut Pool, urls: S, tag: UrlTag) -> Result<u64> {
     sqlx::query!(
         r#"
@@ -38,14 +83,58 @@ UPDATE urls SET tag = $1 WHERE url = ANY ($2::text[])
     .map(|r| r.rows_affected())
 }
 
-pub async fn get_all_tags(pool: &mut Pool) -> Result<url::Tags> {
+/// set the privacy of the urls as chosen by a user, it is kept until a user changes it again and
+/// only a later choice on a peer replaces it
+pub async fn set_privacy<S: AsRef<[String]>>(
+    pool: &mut Pool,
+    urls: S,
+    privacy: UrlPrivacy,
+) -> Result<u64> {
+    sqlx::query!(
+        r#"
+UPDATE urls SET privacy = $1, privacy_set_at = timezone('utc', now())
+WHERE url = ANY ($2::text[])
+"#,
+        privacy as UrlPrivacy,
+        urls.as_ref()
+    )
+    .execute(pool)
+    .await
+    .with_context(|| format!("could not set privacy {:?}", privacy))
+    .map(|r| r.rows_affected())
+}
+
+/// make the urls at least as private as derived by the privacy rules, the urls whose privacy a
+/// user set are kept
+pub async fn raise_privacy<S: AsRef<[String]>>(
+    pool: &mut Pool,
+    urls: S,
+    privacy: UrlPrivacy,
+) -> Result<u64> {
+    sqlx::query!(
+        r#"
+UPDATE urls SET privacy = $1
+WHERE url = ANY ($2::text[]) AND privacy < $1 AND privacy_set_at IS NULL
+"#,
+        privacy as UrlPrivacy,
+        urls.as_ref()
+    )
+    .execute(pool)
+    .await
+    .with_context(|| format!("could not raise privacy to {:?}", privacy))
+    .map(|r| r.rows_affected())
+}
+
+/// the tagged urls by tag, the private urls are hidden unless `unlocked`
+pub async fn get_all_tags(pool: &mut Pool, unlocked: bool) -> Result<url::Tags> {
     Ok(sqlx::query!(
         r#"
 SELECT tag as "tag!:UrlTag", array_agg(url)
 FROM urls
-WHERE tag != 'normal'
+WHERE tag != 'normal' AND ($1 OR privacy = 'normal')
 GROUP BY tag
 "#,
+        unlocked,
     )
     .fetch_all(pool)
     .await
@@ -54,3 +143,230 @@ GROUP BY tag
     .map(|r| (r.tag, r.array_agg.unwrap_or_default()))
     .collect())
 }
+
+pub async fn try_find(pool: &mut Conn, url: &str) -> Result<Option<i32>> {
+    let res = sqlx::query!(
+        r#"
+SELECT id
+FROM urls
+WHERE url = $1
+"#,
+        url
+    )
+    .fetch_optional(pool)
+    .await
+    .context("could not find url")?;
+    Ok(res.map(|r| r.id))
+}
+
+/// mark the sources of the redirects as transient pages of the pages they finally redirected to,
+/// returns the number of marked urls
+pub async fn set_redirects(pool: &mut Conn, redirects: Vec<NavigationEdge>) -> Result<u64> {
+    if redirects.is_empty() {
+        return Ok(0);
+    }
+    let (sources, targets): (Vec<String>, Vec<String>) = redirects
+        .into_iter()
+        .map(|r| (r.from_url, r.to_url))
+        .unzip();
+    let n = sqlx::query!(
+        r#"
+UPDATE urls SET redirect_to = targets.id
+FROM UNNEST($1::text[], $2::text[]) AS data(source, target)
+  INNER JOIN urls AS targets ON targets.url = data.target
+WHERE urls.url = data.source AND urls.id != targets.id
+  AND targets.redirect_to IS DISTINCT FROM urls.id
+"#,
+        &sources,
+        &targets,
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not set redirects")?
+    .rows_affected();
+
+    // pages redirecting in a loop have no final page, so they are all kept
+    sqlx::query!(
+        r#"
+WITH RECURSIVE chains(start_id, id, depth) AS (
+  SELECT id, redirect_to, 1 FROM urls WHERE redirect_to IS NOT NULL
+  UNION ALL
+  SELECT start_id, urls.redirect_to, depth + 1
+  FROM chains INNER JOIN urls ON urls.id = chains.id
+  WHERE urls.redirect_to IS NOT NULL AND chains.id != start_id AND depth <= $1
+)
+UPDATE urls SET redirect_to = NULL
+WHERE id IN (SELECT start_id FROM chains WHERE id = start_id)
+"#,
+        MAX_REDIRECTS as i32
+    )
+    .execute(&mut *pool)
+    .await
+    .context("could not remove redirect loops")?;
+
+    // collapse the chains, so that every transient page points to the final page
+    for _ in 0..MAX_REDIRECTS {
+        let collapsed = sqlx::query!(
+            r#"
+UPDATE urls SET redirect_to = targets.redirect_to
+FROM urls AS targets
+WHERE urls.redirect_to = targets.id AND targets.redirect_to IS NOT NULL
+  AND targets.redirect_to != urls.id
+"#
+        )
+        .execute(&mut *pool)
+        .await
+        .context("could not collapse redirect chains")?
+        .rows_affected();
+        if collapsed == 0 {
+            break;
+        }
+    }
+    Ok(n)
+}
+
+/// move the visits of url `from_id` to url `to_id` and delete `from_id`, the stronger tag and
+/// privacy of both are kept.
+pub async fn merge(pool: &mut Conn, from_id: i32, to_id: i32) -> Result<()> {
+    sqlx::query!(
+        r#"
+SELECT merge_url($1, $2) as id
+"#,
+        from_id,
+        to_id
+    )
+    .fetch_one(pool)
+    .await
+    .with_context(|| format!("could not merge url {} into {}", from_id, to_id))?;
+    Ok(())
+}
+
+/// sanitize all stored urls in one transaction, returns the number of changed urls.
+pub async fn sanitize_all(pool: &mut Pool, rules: &SanitizeRules) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+    let rows = sqlx::query!(
+        r#"
+SELECT id, url
+FROM urls
+"#
+    )
+    .fetch_all(&mut tx)
+    .await
+    .context("could not get all urls")?;
+
+    let mut n = 0;
+    for r in rows {
+        let new_url = rules.sanitize(&r.url);
+        if new_url == r.url {
+            continue;
+        }
+        match try_find(&mut tx, &new_url).await? {
+            Some(to_id) => merge(&mut tx, r.id, to_id).await?,
+            None => {
+                sqlx::query!(
+                    r#"
+UPDATE urls SET url = $1, canonical = $2 WHERE id = $3
+"#,
+                    new_url.as_ref(),
+                    canonical(&new_url),
+                    r.id
+                )
+                .execute(&mut tx)
+                .await
+                .with_context(|| format!("could not update url {}", r.id))?;
+            }
+        }
+        n += 1;
+    }
+    tx.commit()
+        .await
+        .context("could not commit sanitized urls")?;
+    Ok(n)
+}
+
+/// compute the canonical keys of the urls which have none, e.g. the urls stored before the keys
+/// were introduced, returns the number of updated urls.
+pub async fn backfill_canonical(pool: &mut Pool) -> Result<u64> {
+    let rows = sqlx::query!(
+        r#"
+SELECT id, url
+FROM urls
+WHERE canonical IS NULL
+"#
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not get urls without canonical url")?;
+    set_canonicals(pool, rows.into_iter().map(|r| (r.id, r.url))).await
+}
+
+async fn set_canonicals(pool: &mut Conn, urls: impl Iterator<Item = (i32, String)>) -> Result<u64> {
+    let (ids, canonicals): (Vec<i32>, Vec<String>) =
+        urls.map(|(id, url)| (id, canonical(&url))).unzip();
+    sqlx::query!(
+        r#"
+UPDATE urls SET canonical = data.canonical
+FROM UNNEST($1::integer[], $2::text[]) AS data(id, canonical)
+WHERE urls.id = data.id AND urls.canonical IS DISTINCT FROM data.canonical
+"#,
+        &ids[..],
+        &canonicals[..]
+    )
+    .execute(pool)
+    .await
+    .context("could not update canonical urls")
+    .map(|r| r.rows_affected())
+}
+
+/// Recompute the canonical keys of all urls and merge the urls with the same key in one
+/// transaction, returns the number of merged urls. The url which is kept is the one visited most
+/// recently among those without tracking parameters or secrets, so a clean url is never replaced
+/// by a tracked variant of it.
+pub async fn merge_duplicates(pool: &mut Pool) -> Result<u64> {
+    let mut tx = pool.begin().await?;
+    let rows = sqlx::query!(
+        r#"
+SELECT id, url
+FROM urls
+"#
+    )
+    .fetch_all(&mut tx)
+    .await
+    .context("could not get all urls")?;
+    set_canonicals(&mut tx, rows.into_iter().map(|r| (r.id, r.url))).await?;
+
+    let groups = sqlx::query!(
+        r#"
+SELECT array_agg(id ORDER BY last_visit_time DESC NULLS LAST, id) as "ids!",
+  array_agg(url ORDER BY last_visit_time DESC NULLS LAST, id) as "urls!"
+FROM urls LEFT JOIN (
+  SELECT url_id, MAX(last_visit_time) as last_visit_time
+  FROM visits
+  GROUP BY url_id
+) v ON urls.id = v.url_id
+WHERE canonical IS NOT NULL
+GROUP BY canonical
+HAVING COUNT(*) > 1
+"#
+    )
+    .fetch_all(&mut tx)
+    .await
+    .context("could not find duplicate urls")?;
+
+    let clean = SanitizeRules::default();
+    let mut n = 0;
+    for g in groups {
+        let kept = g
+            .urls
+            .iter()
+            .position(|u| clean.sanitize(u) == u.as_str())
+            .unwrap_or_default();
+        let to_id = g.ids[kept];
+        for from_id in g.ids.into_iter().filter(|id| *id != to_id) {
+            merge(&mut tx, from_id, to_id).await?;
+            n += 1;
+        }
+    }
+    tx.commit().await.context("could not commit merged urls")?;
+    Ok(n)
+}
@@ -1,5 +1,5 @@
-use super::{provider, Pool};
-use crate::browser::Browser;
+use super::{provider, Conn, Pool};
+use crate::{browser::Browser, url::UrlDuration};
 use anyhow::{Context, Result};
 use chrono::NaiveDateTime;
 
@@ -23,7 +23,7 @@ pub async fn all_browsers(pool: &mut Pool, name: &str) -> Result<Vec<Browser>> {
     all_browsers_by_p_id(pool, p_id).await
 }
 
-pub async fn last_visit_time(pool: &mut Pool, p_id: i16) -> Result<NaiveDateTime> {
+pub async fn last_visit_time(pool: &mut Conn, p_id: i16) -> Result<NaiveDateTime> {
     sqlx::query!(
         r#"
 SELECT MAX(last_visit_time) as time
@@ -42,7 +42,7 @@ WHERE provider_id = $1
 }
 
 pub async fn upsert_visits(
-    pool: &mut Pool,
+    pool: &mut Conn,
     p_id: i16,
     browser: Browser,
     url_ids: Vec<i32>,
@@ -64,3 +64,75 @@ SELECT upsert_visits($1,$2::browser, $3,$4,$5) as number;
     .context("could not upsert visits")
     .map(|r| r.number.unwrap_or(0))
 }
+
+/// Store the time spent on the visits of the provider and browser, durations of unknown urls are
+/// dropped. A duration of a visit which is already stored replaces it, so storing the same
+/// durations twice does not change the time spent on a url.
+pub async fn add_durations(
+    pool: &mut Conn,
+    name: &str,
+    browser: Browser,
+    durations: Vec<UrlDuration>,
+) -> Result<u64> {
+    if durations.is_empty() {
+        return Ok(0);
+    }
+    let p_id = provider::find_or_insert(&mut *pool, name).await?;
+    let n = durations.len();
+    let (mut urls, mut visit_ids, mut secs, mut estimated) = (
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+        Vec::with_capacity(n),
+    );
+    for d in durations {
+        urls.push(d.url);
+        visit_ids.push(d.visit_id);
+        secs.push(d.secs);
+        estimated.push(d.estimated);
+    }
+    let url_ids = sqlx::query_scalar!(
+        r#"
+INSERT INTO visit_durations(url_id, provider_id, browser_type, visit_id, secs, estimated)
+SELECT urls.id, $1, $2, data.visit_id, data.secs, data.estimated
+FROM UNNEST($3::text[], $4::bigint[], $5::bigint[], $6::bool[])
+    AS data(url, visit_id, secs, estimated)
+  INNER JOIN urls ON urls.url = data.url
+  INNER JOIN visits ON visits.url_id = urls.id
+    AND visits.provider_id = $1 AND visits.browser_type = $2
+ON CONFLICT ON CONSTRAINT visit_durations_pkey DO
+UPDATE SET secs = EXCLUDED.secs, estimated = EXCLUDED.estimated
+RETURNING url_id
+"#,
+        p_id,
+        browser as Browser,
+        &urls,
+        &visit_ids,
+        &secs,
+        &estimated,
+    )
+    .fetch_all(&mut *pool)
+    .await
+    .context("could not store visit durations")?;
+
+    sqlx::query!(
+        r#"
+UPDATE visits
+SET duration_secs = d.secs, duration_estimated = d.estimated
+FROM (
+  SELECT url_id, SUM(secs)::bigint as secs, bool_or(estimated) as estimated
+  FROM visit_durations
+  WHERE provider_id = $1 AND browser_type = $2 AND url_id = ANY($3::integer[])
+  GROUP BY url_id
+) d
+WHERE visits.url_id = d.url_id AND visits.provider_id = $1 AND visits.browser_type = $2
+"#,
+        p_id,
+        browser as Browser,
+        &url_ids,
+    )
+    .execute(pool)
+    .await
+    .context("could not sum up visit durations")
+    .map(|r| r.rows_affected())
+}
@@ -0,0 +1,511 @@
+//! Nodes in the same LAN can find each other without being configured by address. A node which
+//! looks for peers broadcasts a probe with a random nonce over udp, and every server with
+//! discovery enabled answers with its name, port and a proof, the HMAC of the answer and the
+//! nonce keyed by the api key. The proof only shows that some node with the api key answered,
+//! since anyone in the LAN can send a probe to a node and replay its answer from another address.
+//! So a peer whose proof matches is challenged at the address it answered from before it is
+//! trusted: it has to return the HMAC of a fresh nonce and of the address it was challenged at,
+//! which it only gives for an address it listens on, see [`listens_at`]. So a node which merely
+//! relays the challenge cannot get the proof for its own address. The api key is never sent to the
+//! peer during either step. The trusted peers are [`register`]ed and can then be used
+//! by their name like the configured nodes.
+use crate::{
+    config,
+    node::{Endpoint, Host, Node},
+};
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::net::UdpSocket;
+
+pub const DEFAULT_PORT: u16 = 7778;
+const DEFAULT_WAIT_MS: u64 = 1500;
+/// the largest datagram which is read, an answer is far smaller
+const MAX_DATAGRAM: usize = 1024;
+pub const MAX_NONCE_LEN: usize = 64;
+
+/// the nodes found in the LAN by their name
+static DISCOVERED: Lazy<Mutex<HashMap<String, Arc<Node>>>> = Lazy::new(Default::default);
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct DiscoveryConfig {
+    /// the udp port on which the servers answer probes
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// how long to wait for answers after a probe, and for the answer to a challenge
+    #[serde(default = "default_wait_ms")]
+    pub wait_ms: u64,
+}
+
+fn default_port() -> u16 {
+    DEFAULT_PORT
+}
+
+fn default_wait_ms() -> u64 {
+    DEFAULT_WAIT_MS
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Message {
+    Probe { nonce: String },
+    Answer(Answer),
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+struct Answer {
+    name: String,
+    port: Option<u16>,
+    tls: bool,
+    nonce: String,
+    /// the HMAC-SHA256 of the other fields in hex
+    proof: String,
+}
+
+/// the HMAC of the fields, the first one tells what is proven so that one proof is never
+/// accepted as another
+fn mac(api_key: &str, fields: &[&str]) -> Hmac<Sha256> {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(api_key.as_bytes()).expect("hmac accepts keys of any size");
+    for field in fields {
+        mac.update(field.as_bytes());
+        mac.update(&[0]);
+    }
+    mac
+}
+
+fn answer_mac(
+    api_key: &str,
+    name: &str,
+    port: Option<u16>,
+    tls: bool,
+    nonce: &str,
+) -> Hmac<Sha256> {
+    let port = port.map(|p| p.to_string()).unwrap_or_default();
+    mac(
+        api_key,
+        &["answer", name, &port, if tls { "tls" } else { "" }, nonce],
+    )
+}
+
+fn challenge_mac(api_key: &str, name: &str, addr: &SocketAddr, nonce: &str) -> Hmac<Sha256> {
+    mac(api_key, &["challenge", name, &addr.to_string(), nonce])
+}
+
+/// Whether a server bound to `bind` is reached at `addr`. A server bound to the unspecified
+/// address is reached at every address of its host, which are the ones a socket can be bound to.
+pub fn listens_at(bind: &SocketAddr, addr: &SocketAddr) -> bool {
+    if bind.port() != addr.port() || addr.ip().is_unspecified() {
+        false
+    } else if bind.ip().is_unspecified() {
+        std::net::UdpSocket::bind((addr.ip(), 0)).is_ok()
+    } else {
+        bind.ip() == addr.ip()
+    }
+}
+
+/// whether the proof in hex is the one of the mac
+fn verify(mac: Hmac<Sha256>, proof: &str) -> bool {
+    match hex::decode(proof) {
+        Ok(p) => mac.verify_slice(&p).is_ok(),
+        Err(_) => false,
+    }
+}
+
+impl Answer {
+    fn new(name: &str, port: Option<u16>, tls: bool, nonce: &str, api_key: &str) -> Answer {
+        let proof = hex::encode(
+            answer_mac(api_key, name, port, tls, nonce)
+                .finalize()
+                .into_bytes(),
+        );
+        Answer {
+            name: name.to_owned(),
+            port,
+            tls,
+            nonce: nonce.to_owned(),
+            proof,
+        }
+    }
+
+    /// whether the answer is to the probe with the nonce, from a node with the same api key
+    fn verify(&self, nonce: &str, api_key: &str) -> bool {
+        self.nonce == nonce
+            && verify(
+                answer_mac(api_key, &self.name, self.port, self.tls, nonce),
+                &self.proof,
+            )
+    }
+}
+
+/// The answer of the node `name`, which listens on `addr`, to a challenge with the nonce, see
+/// [`challenge`].
+pub fn challenge_proof(api_key: &str, name: &str, addr: &SocketAddr, nonce: &str) -> String {
+    hex::encode(
+        challenge_mac(api_key, name, addr, nonce)
+            .finalize()
+            .into_bytes(),
+    )
+}
+
+/// A node which answered a probe.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    pub name: String,
+    pub endpoint: Endpoint,
+    /// whether its answer is signed with the same api key and it answered the challenge at its
+    /// endpoint, only verified peers are used
+    pub verified: bool,
+}
+
+/// Answer the probes of other nodes as `name`, whose api is served at `port`.
+pub async fn serve(
+    config: &DiscoveryConfig,
+    name: &str,
+    port: Option<u16>,
+    tls: bool,
+    api_key: &str,
+) -> Result<()> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, config.port))
+        .await
+        .with_context(|| format!("could not bind udp port {} for discovery", config.port))?;
+    log::info!("answering discovery probes on udp port {}", config.port);
+    let mut buf = [0; MAX_DATAGRAM];
+    loop {
+        let (n, from) = socket.recv_from(&mut buf).await?;
+        match serde_json::from_slice(&buf[..n]) {
+            Ok(Message::Probe { nonce }) if nonce.len() <= MAX_NONCE_LEN => {
+                let answer = Message::Answer(Answer::new(name, port, tls, &nonce, api_key));
+                if let Err(e) = socket.send_to(&serde_json::to_vec(&answer)?, from).await {
+                    log::warn!("could not answer the discovery probe of {}: {}", from, e);
+                }
+            }
+            _ => log::debug!("ignored a datagram of {} on the discovery port", from),
+        }
+    }
+}
+
+/// Broadcast a probe in the LAN and collect the answers which arrive within the wait. The peers
+/// whose answer is signed with the api key are challenged at their endpoint, see [`challenge`].
+pub async fn discover(config: &DiscoveryConfig, api_key: &str) -> Result<Vec<Peer>> {
+    let to = SocketAddr::from((Ipv4Addr::BROADCAST, config.port));
+    let wait = Duration::from_millis(config.wait_ms);
+    let mut peers = discover_at(to, wait, api_key).await?;
+    let challenges = peers.iter().map(|p| async move {
+        p.verified && {
+            let passed = challenge(&p.endpoint, &p.name, api_key, wait).await;
+            if !passed {
+                log::warn!("{} at {} failed the challenge", p.name, p.endpoint);
+            }
+            passed
+        }
+    });
+    let passed = futures::future::join_all(challenges).await;
+    for (p, passed) in peers.iter_mut().zip(passed) {
+        p.verified = passed;
+    }
+    Ok(peers)
+}
+
+/// the peers which answered, verified by their answer alone
+async fn discover_at(to: SocketAddr, wait: Duration, api_key: &str) -> Result<Vec<Peer>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+    let nonce = hex::encode(rand::random::<[u8; 16]>());
+    let probe = serde_json::to_vec(&Message::Probe {
+        nonce: nonce.clone(),
+    })?;
+    socket
+        .send_to(&probe, to)
+        .await
+        .with_context(|| format!("could not send discovery probe to {}", to))?;
+    let deadline = tokio::time::Instant::now() + wait;
+    let mut peers: Vec<Peer> = vec![];
+    let mut buf = [0; MAX_DATAGRAM];
+    while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let (n, from) = received?;
+        let answer = match serde_json::from_slice(&buf[..n]) {
+            Ok(Message::Answer(a)) => a,
+            _ => continue,
+        };
+        let ip = match from.ip() {
+            IpAddr::V4(ip) => ip,
+            IpAddr::V6(_) => continue,
+        };
+        let peer = Peer {
+            verified: answer.verify(&nonce, api_key),
+            endpoint: Endpoint {
+                host: Host::Ipv4(ip),
+                tls: answer.tls,
+                port: answer.port,
+                timeout_ms: None,
+            },
+            name: answer.name,
+        };
+        if !peer.verified {
+            log::warn!(
+                "{} at {} answered with a wrong proof",
+                peer.name,
+                peer.endpoint
+            );
+        }
+        if !peers.contains(&peer) {
+            peers.push(peer);
+        }
+    }
+    Ok(peers)
+}
+
+/// Whether the node at the endpoint proves that it is `name` with the api key and listens there.
+/// It is asked for the proof of a fresh nonce and of the address it is connected at, see
+/// [`challenge_proof`], the api key is not sent.
+pub async fn challenge(endpoint: &Endpoint, name: &str, api_key: &str, wait: Duration) -> bool {
+    let addr = match endpoint.host {
+        Host::Ipv4(ip) => {
+            SocketAddr::from((ip, endpoint.port.unwrap_or(default_http_port(endpoint))))
+        }
+        _ => return false,
+    };
+    let nonce = hex::encode(rand::random::<[u8; 16]>());
+    let res = reqwest::Client::new()
+        .get(format!("{}/auth/challenge", endpoint.base_url()))
+        .query(&[("nonce", &nonce), ("addr", &addr.to_string())])
+        .timeout(wait)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status());
+    let proof: String = match res {
+        Ok(r) => match r.json().await {
+            Ok(p) => p,
+            Err(_) => return false,
+        },
+        Err(e) => {
+            log::debug!("could not challenge {} at {}: {}", name, endpoint, e);
+            return false;
+        }
+    };
+    verify(challenge_mac(api_key, name, &addr, &nonce), &proof)
+}
+
+fn default_http_port(endpoint: &Endpoint) -> u16 {
+    if endpoint.tls {
+        443
+    } else {
+        80
+    }
+}
+
+/// The configured endpoints first, then the discovered one, unless it is configured already or
+/// would give up the tls of the configured node. A discovered address is only a fallback, it must
+/// not take the place of one the user chose.
+fn merge(peer: &Peer, configured: Option<&Node>) -> Node {
+    let configured = match configured {
+        Some(node) => node,
+        None => {
+            return Node {
+                endpoints: vec![peer.endpoint.clone()],
+            }
+        }
+    };
+    let mut endpoints = configured.endpoints.clone();
+    let known = endpoints
+        .iter()
+        .any(|e| e.host == peer.endpoint.host && e.port == peer.endpoint.port);
+    let downgrade = !peer.endpoint.tls && endpoints.iter().any(|e| e.tls);
+    if !known && !downgrade {
+        endpoints.push(peer.endpoint.clone());
+    }
+    Node { endpoints }
+}
+
+/// Make the verified peers usable by their name.
+pub fn register(peers: &[Peer]) {
+    let mut discovered = DISCOVERED.lock().unwrap();
+    for peer in peers.iter().filter(|p| p.verified) {
+        let node = merge(peer, config().nodes.configured(&peer.name));
+        if discovered.get(&peer.name).is_none_or(|n| **n != node) {
+            log::info!("discovered {} at {}", peer.name, peer.endpoint);
+            discovered.insert(peer.name.clone(), Arc::new(node));
+        }
+    }
+}
+
+/// the discovered node of the name, see [`register`]
+pub fn lookup(name: &str) -> Option<Arc<Node>> {
+    DISCOVERED.lock().unwrap().get(name).cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    #[test]
+    fn proof() {
+        let a = Answer::new("RaspberryPi", Some(7777), false, "abc", "key");
+        assert!(a.verify("abc", "key"));
+        assert!(!a.verify("abc", "other key"));
+        assert!(!a.verify("replayed", "key"));
+        let forged = Answer {
+            port: Some(8888),
+            ..a.clone()
+        };
+        assert!(!forged.verify("abc", "key"));
+        let msg = serde_json::to_string(&Message::Answer(a.clone())).unwrap();
+        assert!(msg.starts_with(r#"{"kind":"answer","name":"RaspberryPi""#));
+        assert_eq!(
+            serde_json::from_str::<Message>(&msg).unwrap(),
+            Message::Answer(a)
+        );
+    }
+
+    fn endpoint(ip: [u8; 4], tls: bool, port: Option<u16>) -> Endpoint {
+        Endpoint {
+            host: Host::Ipv4(ip.into()),
+            tls,
+            port,
+            timeout_ms: None,
+        }
+    }
+
+    #[test]
+    fn merge_endpoints() {
+        let peer = Peer {
+            name: "RaspberryPi".to_owned(),
+            endpoint: endpoint([192, 168, 8, 40], false, Some(7777)),
+            verified: true,
+        };
+        assert_eq!(merge(&peer, None).endpoints, vec![peer.endpoint.clone()]);
+
+        // a discovered address goes after the configured ones
+        let configured = Node {
+            endpoints: vec![endpoint([192, 168, 8, 31], false, Some(7777))],
+        };
+        assert_eq!(
+            merge(&peer, Some(&configured)).endpoints,
+            vec![configured.endpoints[0].clone(), peer.endpoint.clone()]
+        );
+
+        // a configured address keeps its settings
+        let configured = Node {
+            endpoints: vec![Endpoint {
+                timeout_ms: Some(500),
+                ..peer.endpoint.clone()
+            }],
+        };
+        assert_eq!(merge(&peer, Some(&configured)), configured);
+
+        // a node reached by tls is not reached without it
+        let configured = Node {
+            endpoints: vec![endpoint([10, 0, 0, 2], true, Some(443))],
+        };
+        assert_eq!(merge(&peer, Some(&configured)), configured);
+    }
+
+    /// a node which answers the challenges as `name` bound to `bind` like the server, it listens
+    /// on all addresses of the host and is connected at localhost
+    async fn challenged(name: &'static str, bind: Option<SocketAddr>) -> Endpoint {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, 0)).await.unwrap();
+        let local = listener.local_addr().unwrap();
+        let bind = bind.unwrap_or(local);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = vec![0; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or_default();
+                let head = String::from_utf8_lossy(&buf[..n]);
+                let param = |key: &str| {
+                    head.split(&format!("{}=", key))
+                        .nth(1)
+                        .and_then(|s| s.split([' ', '&']).next())
+                        .unwrap_or_default()
+                        .replace("%3A", ":")
+                };
+                let nonce = param("nonce");
+                let (status, body) = match param("addr").parse() {
+                    Ok(addr) if listens_at(&bind, &addr) => (
+                        "200 OK",
+                        format!("\"{}\"", challenge_proof("key", name, &addr, &nonce)),
+                    ),
+                    _ => (
+                        "400 Bad Request",
+                        "\"not an address of this node\"".to_owned(),
+                    ),
+                };
+                let resp = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(resp.as_bytes()).await.ok();
+            }
+        });
+        endpoint([127, 0, 0, 1], false, Some(local.port()))
+    }
+
+    #[tokio::test]
+    async fn challenge_at_endpoint() {
+        let wait = Duration::from_millis(500);
+        let e = challenged("RaspberryPi", None).await;
+        assert!(challenge(&e, "RaspberryPi", "key", wait).await);
+        assert!(!challenge(&e, "RaspberryPi", "other key", wait).await);
+        assert!(!challenge(&e, "Laptop", "key", wait).await);
+
+        // a node which relays the challenge gets no proof from the real node for its address
+        let real = SocketAddr::from((Ipv4Addr::new(192, 168, 8, 40), 7777));
+        let relay = challenged("RaspberryPi", Some(real)).await;
+        assert!(!challenge(&relay, "RaspberryPi", "key", wait).await);
+    }
+
+    #[test]
+    fn listens_at_bound_address() {
+        let any = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 7777));
+        let local = SocketAddr::from((Ipv4Addr::LOCALHOST, 7777));
+        assert!(listens_at(&any, &local));
+        assert!(!listens_at(&any, &any));
+        assert!(!listens_at(
+            &any,
+            &SocketAddr::from((Ipv4Addr::LOCALHOST, 8888))
+        ));
+        // not an address of this host
+        let other = SocketAddr::from((Ipv4Addr::new(203, 0, 113, 7), 7777));
+        assert!(!listens_at(&any, &other));
+        assert!(listens_at(&local, &local));
+        assert!(!listens_at(&other, &local));
+    }
+
+    #[tokio::test]
+    async fn probe_and_answer() {
+        let config = DiscoveryConfig {
+            port: 47781,
+            wait_ms: 500,
+        };
+        tokio::spawn(async move { serve(&config, "RaspberryPi", Some(7777), false, "key").await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let to = SocketAddr::from((Ipv4Addr::LOCALHOST, 47781));
+        let peers = discover_at(to, Duration::from_millis(500), "key")
+            .await
+            .unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].name, "RaspberryPi");
+        assert!(peers[0].verified);
+        assert_eq!(peers[0].endpoint.api_url(""), "http://127.0.0.1:7777/api");
+
+        let peers = discover_at(to, Duration::from_millis(500), "other key")
+            .await
+            .unwrap();
+        assert!(!peers[0].verified);
+    }
+}
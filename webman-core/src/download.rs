@@ -0,0 +1,148 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// A file downloaded by a browser.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct Download {
+    /// the url the download started from
+    pub url: String,
+    pub file_name: String,
+    /// the size in bytes, None if unknown
+    pub size: Option<i64>,
+    pub mime: Option<String>,
+    /// the page the download was started on
+    pub referrer: Option<String>,
+    pub start_time: NaiveDateTime,
+}
+
+impl Download {
+    /// the download saved at `path`, which is a file path or a `file://` uri. None if the path has
+    /// no file name. Unknown sizes are <= 0, and the mime type is guessed by the file extension
+    /// if it is unknown.
+    pub fn from_path(
+        url: String,
+        path: &str,
+        size: Option<i64>,
+        mime: Option<String>,
+        referrer: Option<String>,
+        start_time: NaiveDateTime,
+    ) -> Option<Download> {
+        let file_name = file_name(path)?;
+        let mime = mime
+            .filter(|m| !m.is_empty())
+            .or_else(|| guess_mime(&file_name).map(str::to_owned));
+        Some(Download {
+            url,
+            file_name,
+            size: size.filter(|&s| s > 0),
+            mime,
+            referrer: referrer.filter(|r| !r.is_empty()),
+            start_time,
+        })
+    }
+}
+
+/// the last segment of a path or a percent encoded `file://` uri
+pub fn file_name(path: &str) -> Option<String> {
+    let name = path.rsplit(['/', '\\']).next()?;
+    if name.is_empty() {
+        return None;
+    }
+    if path.starts_with("file://") {
+        Some(percent_decode(name))
+    } else {
+        Some(name.to_owned())
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut res = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                res.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                res.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&res).into_owned()
+}
+
+/// the mime type of the common file extensions
+pub fn guess_mime(file_name: &str) -> Option<&'static str> {
+    let (_, ext) = file_name.rsplit_once('.')?;
+    let mime = match ext.to_ascii_lowercase().as_str() {
+        "pdf" => "application/pdf",
+        "epub" => "application/epub+zip",
+        "zip" => "application/zip",
+        "gz" | "tgz" => "application/gzip",
+        "tar" => "application/x-tar",
+        "7z" => "application/x-7z-compressed",
+        "dmg" => "application/x-apple-diskimage",
+        "iso" => "application/x-iso9660-image",
+        "exe" | "msi" => "application/x-msdownload",
+        "deb" => "application/vnd.debian.binary-package",
+        "json" => "application/json",
+        "doc" => "application/msword",
+        "docx" => "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "xls" => "application/vnd.ms-excel",
+        "xlsx" => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "ppt" => "application/vnd.ms-powerpoint",
+        "pptx" => "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "txt" | "md" => "text/plain",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn download_file_name() {
+        assert_eq!(
+            file_name("file:///home/weiss/Downloads/my%20paper.pdf"),
+            Some("my paper.pdf".to_owned())
+        );
+        assert_eq!(
+            file_name(r"C:\Users\weiss\Downloads\50%.txt"),
+            Some("50%.txt".to_owned())
+        );
+        assert_eq!(file_name("/home/weiss/"), None);
+
+        let d = Download::from_path(
+            "https://example.com/a".to_owned(),
+            "/tmp/Report.PDF",
+            Some(0),
+            Some("".to_owned()),
+            Some("".to_owned()),
+            NaiveDateTime::from_timestamp(0, 0),
+        )
+        .unwrap();
+        assert_eq!(d.file_name, "Report.PDF");
+        assert_eq!(d.mime.as_deref(), Some("application/pdf"));
+        assert_eq!((d.size, d.referrer), (None, None));
+    }
+}
@@ -0,0 +1,221 @@
+//! The kinds of errors a user of webman can act on. The functions of webman still return
+//! [`anyhow::Result`] with a context for the logs, [`WebmanError::of`] finds the kind of such an
+//! error. The server replies it as json with the matching status, and the client decodes the
+//! reply back into the same [`WebmanError`].
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum WebmanError {
+    /// the provider, url, session or page does not exist
+    NotFound(String),
+    /// the api key or the unlock key is missing or wrong
+    Unauthorized(String),
+    /// the request could not be parsed or contains an invalid value
+    InvalidInput(String),
+    /// the request does not fit the state of the node, e.g. a chunk of an upload is out of order
+    Conflict(String),
+    /// the database or the node could not be reached, the request may succeed later
+    Unavailable(String),
+    /// the database of a browser could not be read
+    Miner(String),
+    Internal(String),
+}
+
+impl WebmanError {
+    pub fn message(&self) -> &str {
+        use WebmanError::*;
+        match self {
+            NotFound(m) | Unauthorized(m) | InvalidInput(m) | Conflict(m) | Unavailable(m)
+            | Miner(m) | Internal(m) => m,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        use WebmanError::*;
+        match self {
+            NotFound(_) => "not found",
+            Unauthorized(_) => "unauthorized",
+            InvalidInput(_) => "invalid input",
+            Conflict(_) => "conflict",
+            Unavailable(_) => "unavailable",
+            Miner(_) => "miner error",
+            Internal(_) => "internal error",
+        }
+    }
+
+    /// the same kind of error with a message which reveals nothing of the server
+    pub fn redacted(&self) -> WebmanError {
+        use WebmanError::*;
+        let message = "see the log of the server for details".to_owned();
+        match self {
+            NotFound(_) => NotFound(message),
+            Unauthorized(_) => Unauthorized(message),
+            InvalidInput(_) => InvalidInput(message),
+            Conflict(_) => Conflict(message),
+            Unavailable(_) => Unavailable(message),
+            Miner(_) => Miner(message),
+            Internal(_) => Internal(message),
+        }
+    }
+
+    /// the http status the server replies with
+    pub fn status(&self) -> u16 {
+        use WebmanError::*;
+        match self {
+            NotFound(_) => 404,
+            Unauthorized(_) => 401,
+            InvalidInput(_) => 400,
+            Conflict(_) => 409,
+            Unavailable(_) => 503,
+            Miner(_) | Internal(_) => 500,
+        }
+    }
+
+    /// the error of a reply without a json body, e.g. from a proxy in front of the server
+    pub fn from_status(status: u16, message: String) -> WebmanError {
+        use WebmanError::*;
+        match status {
+            404 => NotFound(message),
+            401 | 403 => Unauthorized(message),
+            400 | 422 => InvalidInput(message),
+            409 => Conflict(message),
+            408 | 429 | 502 | 503 | 504 => Unavailable(message),
+            _ => Internal(message),
+        }
+    }
+
+    /// The kind of the first error in the chain which has one, [`WebmanError::Internal`] if
+    /// none has. The message is the whole chain, unless the error already is a [`WebmanError`].
+    pub fn of(e: &anyhow::Error) -> WebmanError {
+        let message = || format!("{:#}", e);
+        for cause in e.chain() {
+            if let Some(e) = cause.downcast_ref::<WebmanError>() {
+                return e.clone();
+            }
+            #[cfg(feature = "server")]
+            if let Some(e) = cause.downcast_ref::<sqlx::Error>() {
+                return from_sqlx(e, message());
+            }
+            #[cfg(feature = "browser")]
+            if cause.downcast_ref::<rusqlite::Error>().is_some() {
+                return WebmanError::Miner(message());
+            }
+            if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+                if e.is_connect() || e.is_timeout() {
+                    return WebmanError::Unavailable(message());
+                }
+            }
+        }
+        WebmanError::Internal(message())
+    }
+}
+
+/// classified by the SQLSTATE of postgres, see
+/// <https://www.postgresql.org/docs/current/errcodes-appendix.html>
+#[cfg(feature = "server")]
+fn from_sqlx(e: &sqlx::Error, message: String) -> WebmanError {
+    use sqlx::Error::*;
+    match e {
+        RowNotFound => WebmanError::NotFound(message),
+        PoolTimedOut | PoolClosed | Io(_) | Tls(_) | WorkerCrashed => {
+            WebmanError::Unavailable(message)
+        }
+        Database(db) => match db.code().as_deref().and_then(|c| c.get(..2)) {
+            Some("23") => WebmanError::Conflict(message),
+            Some("22") => WebmanError::InvalidInput(message),
+            Some("08" | "53" | "57") => WebmanError::Unavailable(message),
+            _ => WebmanError::Internal(message),
+        },
+        _ => WebmanError::Internal(message),
+    }
+}
+
+impl Display for WebmanError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for WebmanError {}
+
+impl From<anyhow::Error> for WebmanError {
+    fn from(e: anyhow::Error) -> Self {
+        WebmanError::of(&e)
+    }
+}
+
+/// The error as json body with its status. The errors of the server itself are logged and
+/// replied with a generic message, since their message may reveal the internals of the server.
+#[cfg(feature = "server")]
+impl<'r> rocket::response::Responder<'r, 'static> for WebmanError {
+    fn respond_to(self, req: &'r rocket::Request<'_>) -> rocket::response::Result<'static> {
+        let status = rocket::http::Status::new(self.status());
+        let body = if status.code >= 500 {
+            log::error!("{} {}: {}", req.method(), req.uri(), self);
+            self.redacted()
+        } else {
+            self
+        };
+        rocket::Response::build_from(rocket::serde::json::Json(body).respond_to(req)?)
+            .status(status)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn json_body() {
+        let e = WebmanError::Conflict("chunk 3 is out of order".to_owned());
+        let body = serde_json::to_string(&e).unwrap();
+        assert_eq!(
+            body,
+            r#"{"kind":"conflict","message":"chunk 3 is out of order"}"#
+        );
+        assert_eq!(serde_json::from_str::<WebmanError>(&body).unwrap(), e);
+        assert_eq!(e.status(), 409);
+        assert_eq!(
+            WebmanError::from_status(e.status(), "".to_owned()).kind(),
+            e.kind()
+        );
+    }
+
+    #[test]
+    fn kind_of_chain() {
+        let e = Err::<(), _>(WebmanError::NotFound("no such session".to_owned()))
+            .context("could not tag session")
+            .unwrap_err();
+        assert_eq!(
+            WebmanError::of(&e),
+            WebmanError::NotFound("no such session".to_owned())
+        );
+
+        let e = anyhow::anyhow!("something broke").context("could not sync");
+        assert_eq!(
+            WebmanError::of(&e),
+            WebmanError::Internal("could not sync: something broke".to_owned())
+        );
+    }
+
+    #[test]
+    fn redacted() {
+        let e = WebmanError::of(&anyhow::anyhow!("relation \"urls\" does not exist"));
+        let r = e.redacted();
+        assert_eq!((r.status(), r.kind()), (500, "internal error"));
+        assert!(!r.message().contains("urls"));
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn kind_of_sqlx_error() {
+        let e = anyhow::Error::new(sqlx::Error::RowNotFound).context("could not find url");
+        assert!(matches!(WebmanError::of(&e), WebmanError::NotFound(_)));
+        let e = anyhow::Error::new(sqlx::Error::PoolTimedOut);
+        assert_eq!(WebmanError::of(&e).status(), 503);
+    }
+}
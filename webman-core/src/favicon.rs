@@ -0,0 +1,127 @@
+use crate::url::normalized_host;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// icons larger than this are dropped, they are most likely not favicons
+pub const MAX_FAVICON_BYTES: usize = 64 * 1024;
+/// the width the web ui renders the icons with, the closest one of a host is kept
+pub const PREFERRED_WIDTH: i32 = 32;
+
+/// The icon of a host, mined from the favicon database of a browser.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct Favicon {
+    /// the normalized host, see [`normalized_host`]
+    pub host: String,
+    pub mime: String,
+    /// the width in pixels, 0 if unknown
+    pub width: i32,
+    pub data: Vec<u8>,
+}
+
+impl Favicon {
+    /// the icon of the host of `page_url`, None if the url has no host, the data is too large or
+    /// its format is not known
+    pub fn from_page(page_url: &str, data: Vec<u8>, width: i32) -> Option<Favicon> {
+        if data.len() > MAX_FAVICON_BYTES {
+            return None;
+        }
+        Some(Favicon {
+            host: normalized_host(page_url)?,
+            mime: sniff_mime(&data)?.to_owned(),
+            width,
+            data,
+        })
+    }
+
+    /// whether the icon fits the web ui better than `other`: the width closer to
+    /// [`PREFERRED_WIDTH`] wins, and the larger one on ties
+    pub fn is_better_than(&self, other: &Favicon) -> bool {
+        let dist = |w: i32| (w - PREFERRED_WIDTH).abs();
+        (dist(self.width), -self.width) < (dist(other.width), -other.width)
+    }
+
+    /// the ETag of the data, which does not change between releases of webman
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", hex::encode(&Sha256::digest(&self.data)[..16]))
+    }
+
+    /// keep the best icon of every host, sorted by host
+    pub fn dedup(icons: impl IntoIterator<Item = Favicon>) -> Vec<Favicon> {
+        let mut best = HashMap::<String, Favicon>::new();
+        for icon in icons {
+            match best.get(&icon.host) {
+                Some(old) if !icon.is_better_than(old) => {}
+                _ => {
+                    best.insert(icon.host.clone(), icon);
+                }
+            }
+        }
+        let mut res: Vec<_> = best.into_values().collect();
+        res.sort_by(|a, b| a.host.cmp(&b.host));
+        res
+    }
+}
+
+/// the mime type of an image detected by its magic bytes
+pub fn sniff_mime(data: &[u8]) -> Option<&'static str> {
+    let mime = match data {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0x00, 0x00, 0x01, 0x00, ..] => "image/x-icon",
+        [b'G', b'I', b'F', b'8', ..] => "image/gif",
+        [0xFF, 0xD8, 0xFF, ..] => "image/jpeg",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+        _ => {
+            let head = String::from_utf8_lossy(&data[..data.len().min(256)]);
+            let head = head.trim_start();
+            if head.starts_with("<svg") || (head.starts_with("<?xml") && head.contains("<svg")) {
+                "image/svg+xml"
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(mime)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+    #[test]
+    fn sniff() {
+        assert_eq!(sniff_mime(PNG), Some("image/png"));
+        assert_eq!(sniff_mime(&[0, 0, 1, 0, 1, 0]), Some("image/x-icon"));
+        assert_eq!(
+            sniff_mime(br#"<?xml version="1.0"?><svg xmlns="http://www.w3.org/2000/svg"/>"#),
+            Some("image/svg+xml")
+        );
+        assert_eq!(sniff_mime(b"<html>"), None);
+        assert_eq!(sniff_mime(&[]), None);
+    }
+
+    #[test]
+    fn dedup_by_host() {
+        let icon = |url: &str, width| Favicon::from_page(url, PNG.to_vec(), width).unwrap();
+        let icons = Favicon::dedup([
+            icon("https://www.example.com/a", 16),
+            icon("https://example.com/b", 64),
+            icon("https://rust-lang.org", 16),
+            icon("https://example.com/c", 32),
+            icon("https://rust-lang.org/learn", 0),
+        ]);
+        let got: Vec<_> = icons.iter().map(|i| (i.host.as_str(), i.width)).collect();
+        assert_eq!(got, vec![("example.com", 32), ("rust-lang.org", 16)]);
+        assert!(Favicon::from_page("no host", PNG.to_vec(), 16).is_none());
+        assert!(Favicon::from_page("https://a.com", b"text".to_vec(), 16).is_none());
+    }
+
+    #[test]
+    fn etag() {
+        let icon = Favicon::from_page("https://example.com", PNG.to_vec(), 16).unwrap();
+        assert_eq!(icon.etag(), "\"4c4b6a3be1314ab86138bef4314dde02\"");
+    }
+}
@@ -3,12 +3,22 @@ extern crate sqlx;
 
 pub mod browser;
 mod config;
+pub mod discovery;
+pub mod download;
+pub mod error;
+pub mod favicon;
+pub mod navigation;
 pub mod node;
+pub mod session;
+pub mod stats;
+pub mod sync;
+pub mod timeline;
 pub mod url;
 mod web;
 
 pub use self::{
     config::{config, init_fig},
+    error::WebmanError,
     web::{client::Client, resp},
 };
 
@@ -0,0 +1,86 @@
+use crate::url::SanitizeRules;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// A link followed in the browser, from the page `from_url` to the page `to_url`
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct NavigationEdge {
+    pub from_url: String,
+    pub to_url: String,
+    pub visit_time: NaiveDateTime,
+}
+
+impl NavigationEdge {
+    pub fn new(from_url: String, to_url: String, visit_time: NaiveDateTime) -> Self {
+        Self {
+            from_url,
+            to_url,
+            visit_time,
+        }
+    }
+}
+
+/// sanitize both ends of the edges like the urls, edges which become loops are dropped
+pub fn sanitize_edges(rules: &SanitizeRules, edges: Vec<NavigationEdge>) -> Vec<NavigationEdge> {
+    edges
+        .into_iter()
+        .map(|e| NavigationEdge {
+            from_url: rules.sanitize(&e.from_url).into_owned(),
+            to_url: rules.sanitize(&e.to_url).into_owned(),
+            visit_time: e.visit_time,
+        })
+        .filter(|e| e.from_url != e.to_url)
+        .collect()
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct NavigationStep {
+    pub url: String,
+    pub title: String,
+    /// the last time the link was followed
+    pub visit_time: NaiveDateTime,
+    /// how often the link was followed
+    pub visit_count: i32,
+}
+
+/// How a page was reached and which pages were opened from it.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct Navigation {
+    pub url: String,
+    /// the chain of pages which led to `url`, the first one is the origin. Every page is followed by
+    /// the page most recently opened from it.
+    pub referrers: Vec<NavigationStep>,
+    /// the pages opened from `url`, the latest first
+    pub opened: Vec<NavigationStep>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize() {
+        let t = NaiveDateTime::from_timestamp(0, 0);
+        let edges = vec![
+            NavigationEdge::new(
+                "https://a.com/?utm_source=x".to_owned(),
+                "https://a.com/".to_owned(),
+                t,
+            ),
+            NavigationEdge::new(
+                "https://a.com/".to_owned(),
+                "https://b.com/?fbclid=1".to_owned(),
+                t,
+            ),
+        ];
+        assert_eq!(
+            sanitize_edges(&SanitizeRules::default(), edges),
+            vec![NavigationEdge::new(
+                "https://a.com/".to_owned(),
+                "https://b.com/".to_owned(),
+                t
+            )]
+        );
+    }
+}
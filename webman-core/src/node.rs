@@ -5,9 +5,10 @@ use std::{
     fmt::Display,
     net::{Ipv4Addr, Ipv6Addr},
     ops::Deref,
+    sync::Arc,
 };
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, Clone)]
 pub enum Host {
     #[serde(alias = "domain")]
     Domain(String),
@@ -59,8 +60,9 @@ impl Deref for Name {
     }
 }
 
-impl AsRef<Node> for Name {
-    fn as_ref(&self) -> &Node {
+impl Name {
+    /// the node of the name, see [`Nodes::get`]
+    pub fn node(&self) -> Arc<Node> {
         config().nodes.get(self.0.as_str())
     }
 }
@@ -73,28 +75,86 @@ pub struct Provider {
     pub last_import_time: chrono::NaiveDateTime,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
-pub struct Node {
+/// how long an endpoint may take to answer a probe if its timeout is not set
+pub const DEFAULT_PROBE_TIMEOUT_MS: u64 = 3000;
+
+/// One address of a node.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, Clone)]
+pub struct Endpoint {
     pub host: Host,
     pub tls: bool,
     pub port: Option<u16>,
+    /// how long the endpoint may take to answer a probe before the next one is tried
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
 }
 
-impl Node {
-    pub fn api_url(&self, end_point: &str) -> String {
+impl Endpoint {
+    /// the scheme, host and port without a trailing slash
+    pub fn base_url(&self) -> String {
+        let host = match &self.host {
+            Host::Ipv6(i) => format!("[{}]", i),
+            h => h.to_string(),
+        };
         format!(
-            "{}://{}{}{}/api{}",
+            "{}://{}{}",
             if self.tls { "https" } else { "http" },
-            self.host,
-            if self.port.is_some() { ":" } else { "" },
-            if let Some(p) = self.port {
-                p.to_string()
-            } else {
-                String::new()
-            },
-            end_point
+            host,
+            self.port.map(|p| format!(":{}", p)).unwrap_or_default()
         )
     }
+
+    pub fn api_url(&self, end_point: &str) -> String {
+        format!("{}/api{}", self.base_url(), end_point)
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_millis(self.timeout_ms.unwrap_or(DEFAULT_PROBE_TIMEOUT_MS))
+    }
+}
+
+impl Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.base_url().fmt(f)
+    }
+}
+
+/// A node is reached by the first of its endpoints which answers, e.g. by its address in the LAN
+/// at home and by its domain elsewhere. A node with one endpoint may be configured by `host`,
+/// `tls` and `port` directly.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Hash, Clone)]
+#[serde(try_from = "NodeConfig")]
+pub struct Node {
+    pub endpoints: Vec<Endpoint>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NodeConfig {
+    Endpoints { endpoints: Vec<Endpoint> },
+    Endpoint(Endpoint),
+}
+
+impl TryFrom<NodeConfig> for Node {
+    type Error = &'static str;
+
+    fn try_from(c: NodeConfig) -> Result<Self, Self::Error> {
+        let endpoints = match c {
+            NodeConfig::Endpoints { endpoints } => endpoints,
+            NodeConfig::Endpoint(e) => vec![e],
+        };
+        if endpoints.is_empty() {
+            return Err("a node needs at least one endpoint");
+        }
+        Ok(Node { endpoints })
+    }
+}
+
+impl Node {
+    /// the url of the end point at the first endpoint
+    pub fn api_url(&self, end_point: &str) -> String {
+        self.endpoints[0].api_url(end_point)
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
@@ -102,8 +162,10 @@ impl Node {
 pub struct Nodes(HashMap<String, Node>);
 
 impl Nodes {
-    pub fn get(&self, name: &str) -> &Node {
-        let c = self.0.get(name);
+    /// the discovered node of the name, which has the configured endpoints and the current
+    /// address in the LAN, otherwise the configured one, see [`crate::discovery`]
+    pub fn get(&self, name: &str) -> Arc<Node> {
+        let c = self.find(name);
         assert!(
             c.is_some(),
             "could not find node by name {}, possibile nodes are {:?}",
@@ -112,25 +174,92 @@ impl Nodes {
         );
         c.unwrap()
     }
+
+    pub fn find(&self, name: &str) -> Option<Arc<Node>> {
+        crate::discovery::lookup(name).or_else(|| self.configured(name).cloned().map(Arc::new))
+    }
+
+    /// the node as it is written in config
+    pub fn configured(&self, name: &str) -> Option<&Node> {
+        self.0.get(name)
+    }
+
+    /// the configured nodes ordered by name
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Node)> {
+        let mut nodes: Vec<_> = self.0.iter().collect();
+        nodes.sort_by_key(|(name, _)| name.as_str());
+        nodes.into_iter()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    fn endpoint(host: Host, tls: bool, port: Option<u16>) -> Endpoint {
+        Endpoint {
+            host,
+            tls,
+            port,
+            timeout_ms: None,
+        }
+    }
+
     #[test]
     fn api_url() {
         let node1 = Node {
-            host: Host::Ipv4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
-            tls: false,
-            port: Some(7777),
+            endpoints: vec![endpoint(
+                Host::Ipv4(std::net::Ipv4Addr::new(127, 0, 0, 1)),
+                false,
+                Some(7777),
+            )],
         };
         assert_eq!(node1.api_url(""), "http://127.0.0.1:7777/api");
 
         let node2 = Node {
-            host: Host::Domain("www.myserver.com".to_owned()),
-            tls: true,
-            port: None,
+            endpoints: vec![endpoint(
+                Host::Domain("www.myserver.com".to_owned()),
+                true,
+                None,
+            )],
         };
         assert_eq!(node2.api_url(""), "https://www.myserver.com/api");
+
+        let e = endpoint(Host::Ipv6(Ipv6Addr::LOCALHOST), false, Some(7777));
+        assert_eq!(e.api_url("/sync/id"), "http://[::1]:7777/api/sync/id");
+    }
+
+    #[test]
+    fn node_config() {
+        let single: Node = serde_json::from_str(
+            r#"{"host": {"Ipv4": "192.168.8.31"}, "tls": false, "port": 7777}"#,
+        )
+        .unwrap();
+        assert_eq!(single.endpoints.len(), 1);
+
+        let multi: Node = serde_json::from_str(
+            r#"{"endpoints": [
+                {"host": {"Ipv4": "192.168.8.31"}, "tls": false, "port": 7777, "timeout_ms": 500},
+                {"host": {"Domain": "webman.remote.com"}, "tls": true, "port": null}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            multi.endpoints[0],
+            Endpoint {
+                timeout_ms: Some(500),
+                ..single.endpoints[0].clone()
+            }
+        );
+        assert_eq!(
+            multi.endpoints[1].timeout(),
+            std::time::Duration::from_secs(3)
+        );
+        assert_eq!(
+            multi.endpoints[1].api_url(""),
+            "https://webman.remote.com/api"
+        );
+
+        assert!(serde_json::from_str::<Node>(r#"{"endpoints": []}"#).is_err());
     }
 }
@@ -0,0 +1,120 @@
+use crate::{browser::Browser, url::UrlResult};
+use chrono::{Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+/// A trail of pages visited in one provider and browser without a long break. Browsers only keep
+/// the last visit time of a url, so a page revisited later moves to a later session when the
+/// sessions are rebuilt.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct BrowsingSession {
+    pub id: i32,
+    pub provider_name: String,
+    pub browser: Browser,
+    pub start_time: NaiveDateTime,
+    pub end_time: NaiveDateTime,
+    pub urls: Vec<UrlResult>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SessionVisit {
+    pub url_id: i32,
+    pub host_id: Option<i32>,
+    pub time: NaiveDateTime,
+}
+
+/// Clusters visits into sessions. A visit belongs to the current session if the gap to the
+/// previous visit is at most `idle`, or at most `host_idle` if the session already contains a page
+/// of the same host.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SessionBuilder {
+    pub idle: Duration,
+    pub host_idle: Duration,
+    /// sessions with fewer urls are dropped
+    pub min_urls: usize,
+}
+
+impl Default for SessionBuilder {
+    fn default() -> Self {
+        Self {
+            idle: Duration::minutes(30),
+            host_idle: Duration::hours(2),
+            min_urls: 2,
+        }
+    }
+}
+
+impl SessionBuilder {
+    pub fn new(idle_minutes: i64) -> Self {
+        Self {
+            idle: Duration::minutes(idle_minutes),
+            host_idle: Duration::minutes(idle_minutes * 4),
+            ..Default::default()
+        }
+    }
+
+    fn joins(&self, session: &[SessionVisit], v: &SessionVisit) -> bool {
+        session.last().is_some_and(|last| {
+            let gap = v.time - last.time;
+            gap <= self.idle
+                || (gap <= self.host_idle
+                    && v.host_id.is_some()
+                    && session.iter().any(|o| o.host_id == v.host_id))
+        })
+    }
+
+    /// Add the next visit to the `open` session. If the visit starts a new session, the closed
+    /// one is returned unless it has too few urls. The visits must be pushed sorted by time.
+    pub fn push(&self, open: &mut Vec<SessionVisit>, v: SessionVisit) -> Option<Vec<SessionVisit>> {
+        if open.is_empty() || self.joins(open, &v) {
+            open.push(v);
+            return None;
+        }
+        self.finish(std::mem::replace(open, vec![v]))
+    }
+
+    /// the open session after the last visit, unless it has too few urls
+    pub fn finish(&self, open: Vec<SessionVisit>) -> Option<Vec<SessionVisit>> {
+        (open.len() >= self.min_urls).then_some(open)
+    }
+
+    /// `visits` must be sorted by time and come from the same provider and browser
+    pub fn build(&self, visits: Vec<SessionVisit>) -> Vec<Vec<SessionVisit>> {
+        let mut open = vec![];
+        let mut sessions: Vec<Vec<SessionVisit>> = visits
+            .into_iter()
+            .filter_map(|v| self.push(&mut open, v))
+            .collect();
+        sessions.extend(self.finish(open));
+        sessions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build() {
+        let visit = |url_id, host_id, minute: i64| SessionVisit {
+            url_id,
+            host_id,
+            time: NaiveDateTime::from_timestamp(minute * 60, 0),
+        };
+        let visits = vec![
+            visit(1, Some(1), 0),
+            visit(2, Some(2), 20),
+            // the same host after a long break
+            visit(3, Some(1), 80),
+            // another host after a long break
+            visit(4, Some(3), 150),
+            visit(5, None, 300),
+            visit(6, None, 310),
+        ];
+        let sessions = SessionBuilder::default().build(visits);
+        let ids: Vec<Vec<i32>> = sessions
+            .iter()
+            .map(|s| s.iter().map(|v| v.url_id).collect())
+            .collect();
+        assert_eq!(ids, vec![vec![1, 2, 3], vec![5, 6]]);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{browser::Browser, url::UrlTag};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumString};
+
+/// the length of the time buckets of a trend
+#[derive(
+    Debug, PartialEq, Eq, Clone, Copy, Default, Deserialize, Serialize, EnumString, Display,
+)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Day,
+    #[default]
+    Week,
+    Month,
+}
+
+/// The visits of a host. Browsers only keep the last visit time of a url, so `first_visit_time` is
+/// the earliest last visit time of the urls of the host.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct HostSummary {
+    pub host: String,
+    pub visit_count: i64,
+    pub url_count: i64,
+    pub first_visit_time: NaiveDateTime,
+    pub last_visit_time: NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct BrowserUsage {
+    pub provider_name: String,
+    pub browser: Browser,
+    pub visit_count: i64,
+    pub url_count: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct TrendPoint {
+    /// the start of the time bucket
+    pub time: NaiveDateTime,
+    pub visit_count: i64,
+    pub url_count: i64,
+}
+
+/// the urls added to the history of a provider and browser in a time bucket
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct UrlsAdded {
+    pub provider_name: String,
+    pub browser: Browser,
+    /// the start of the time bucket
+    pub time: NaiveDateTime,
+    pub url_count: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct HostStats {
+    pub summary: HostSummary,
+    pub browsers: Vec<BrowserUsage>,
+    /// visits grouped by the last visit time of urls
+    pub trend: Vec<TrendPoint>,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct TagCount {
+    pub tag: UrlTag,
+    pub visit_count: i64,
+    pub url_count: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct HourCount {
+    /// the hour of the day in the requested time zone, from 0 to 23
+    pub hour: i32,
+    pub visit_count: i64,
+    pub url_count: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct TimeSpent {
+    /// the url or the host
+    pub key: String,
+    pub secs: i64,
+    /// whether a part of `secs` is estimated from the gaps between visits
+    pub estimated: bool,
+}
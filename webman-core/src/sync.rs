@@ -0,0 +1,370 @@
+//! Incremental replication between nodes. Every node numbers the changes of its urls, visits,
+//! navigations, search queries, downloads and favicons with a monotonic sequence, a peer pulls
+//! the changes since the last sequence number it acknowledged. Sequence numbers are only
+//! comparable within a node, so they are scoped by the random `node_id` of the node, which
+//! changes if its database is recreated. A page of changes never goes beyond the numbers of
+//! transactions which may still commit, and the urls merged into other urls leave tombstones, so
+//! that peers merge them too.
+//!
+//! Changes travel through any topology of peers: every row keeps the node which made its latest
+//! change and the sequence number there. A node knows up to which sequence number it has all
+//! changes of every origin, see [`OriginVersion`], and a peer only sends the changes the node
+//! does not know yet, so changes are forwarded once and never sent back to their origin.
+pub mod tree;
+
+use crate::{
+    browser::Browser,
+    node::Provider,
+    url::{UrlPrivacy, UrlTag},
+};
+use chrono::{Duration, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+/// the maximal number of changed rows in one [`ChangeSet`]
+pub const PAGE_SIZE: i64 = 5000;
+/// a changed favicon counts as this many rows towards the size of a page, since icons are much
+/// larger than the other rows
+pub const FAVICON_WEIGHT: i64 = 50;
+/// the sync interval assumed for peers which are not synced periodically
+pub const DEFAULT_INTERVAL_SECS: i64 = 24 * 60 * 60;
+/// a peer is stale if it was not synced successfully for this many intervals
+pub const STALE_INTERVALS: i32 = 3;
+
+/// The latest state of a changed url.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct UrlChange {
+    pub url: String,
+    pub title: String,
+    pub tag: UrlTag,
+    pub privacy: UrlPrivacy,
+    /// when a user set the privacy, None if it was derived from the privacy rules
+    #[serde(default)]
+    pub privacy_set_at: Option<NaiveDateTime>,
+    pub seq: i64,
+    /// the node which made the change, empty for changes which are not replicated as they are
+    #[serde(default)]
+    pub origin: String,
+    #[serde(default)]
+    pub origin_seq: i64,
+}
+
+impl UrlChange {
+    /// The privacy of a url whose rows differ, and when a user set it. The privacy a user set last
+    /// wins, a privacy a user set wins over one derived from the privacy rules, and of two
+    /// derived ones or two set at the same time the stricter one wins.
+    pub fn winning_privacy(&self, other: &UrlChange) -> (UrlPrivacy, Option<NaiveDateTime>) {
+        match (self.privacy_set_at, other.privacy_set_at) {
+            (Some(a), Some(b)) if a > b => (self.privacy, Some(a)),
+            (Some(a), Some(b)) if a < b => (other.privacy, Some(b)),
+            (Some(a), None) => (self.privacy, Some(a)),
+            (None, Some(b)) => (other.privacy, Some(b)),
+            (a, _) => (self.privacy.max(other.privacy), a),
+        }
+    }
+}
+
+/// The latest state of the visits of a url by a provider and browser.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct VisitChange {
+    pub url: String,
+    pub provider_name: String,
+    pub browser: Browser,
+    pub visit_count: i32,
+    pub last_visit_time: NaiveDateTime,
+    pub duration_secs: i64,
+    pub duration_estimated: bool,
+    pub seq: i64,
+    /// the node which made the change, empty for changes which are not replicated as they are
+    #[serde(default)]
+    pub origin: String,
+    #[serde(default)]
+    pub origin_seq: i64,
+}
+
+/// The latest state of the navigations from a url to another by a provider and browser.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct NavigationChange {
+    pub from_url: String,
+    pub to_url: String,
+    pub provider_name: String,
+    pub browser: Browser,
+    pub visit_count: i32,
+    pub last_visit_time: NaiveDateTime,
+    pub seq: i64,
+    /// the node which counted the navigations last, see [`UrlChange::origin`]
+    #[serde(default)]
+    pub origin: String,
+    #[serde(default)]
+    pub origin_seq: i64,
+}
+
+/// The latest search query of a result page.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct SearchQueryChange {
+    pub url: String,
+    pub query: String,
+    pub engine: String,
+    pub seq: i64,
+    /// the node which extracted the query, see [`UrlChange::origin`]
+    #[serde(default)]
+    pub origin: String,
+    #[serde(default)]
+    pub origin_seq: i64,
+}
+
+/// The latest state of a download by a provider and browser.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct DownloadChange {
+    pub url: String,
+    pub provider_name: String,
+    pub browser: Browser,
+    pub file_name: String,
+    pub size: Option<i64>,
+    pub mime: Option<String>,
+    pub referrer: Option<String>,
+    pub start_time: NaiveDateTime,
+    pub seq: i64,
+    /// the node which stored the download, see [`UrlChange::origin`]
+    #[serde(default)]
+    pub origin: String,
+    #[serde(default)]
+    pub origin_seq: i64,
+}
+
+/// The latest icon of a host.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct FaviconChange {
+    pub host: String,
+    pub mime: String,
+    pub width: i32,
+    pub data: Vec<u8>,
+    pub seq: i64,
+    /// the node which mined the icon, see [`UrlChange::origin`]
+    #[serde(default)]
+    pub origin: String,
+    #[serde(default)]
+    pub origin_seq: i64,
+}
+
+/// A url which was merged into another url and removed.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct UrlDeletion {
+    pub url: String,
+    pub merged_into: String,
+    pub seq: i64,
+    /// the node which removed the url, empty for removals which are not replicated as they are
+    #[serde(default)]
+    pub origin: String,
+    #[serde(default)]
+    pub origin_seq: i64,
+}
+
+/// A node has all changes of the origin node up to `seq`.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct OriginVersion {
+    pub node_id: String,
+    pub seq: i64,
+}
+
+/// The changes of a node with sequence numbers in `(since, last_seq]`. The urls of all visits,
+/// navigations, search queries and downloads in the set are included, so that a set can be
+/// applied on its own.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Default)]
+pub struct ChangeSet {
+    pub node_id: String,
+    pub since: i64,
+    pub last_seq: i64,
+    /// whether there may be changes after `last_seq`
+    pub more: bool,
+    pub providers: Vec<Provider>,
+    pub urls: Vec<UrlChange>,
+    pub visits: Vec<VisitChange>,
+    #[serde(default)]
+    pub deleted: Vec<UrlDeletion>,
+    #[serde(default)]
+    pub navigations: Vec<NavigationChange>,
+    #[serde(default)]
+    pub search_queries: Vec<SearchQueryChange>,
+    #[serde(default)]
+    pub downloads: Vec<DownloadChange>,
+    #[serde(default)]
+    pub favicons: Vec<FaviconChange>,
+    /// the versions of the node when the set was made, a change wins over a row whose version
+    /// the node already knew
+    #[serde(default)]
+    pub versions: Vec<OriginVersion>,
+}
+
+impl ChangeSet {
+    pub fn is_empty(&self) -> bool {
+        self.urls.is_empty()
+            && self.visits.is_empty()
+            && self.deleted.is_empty()
+            && self.navigations.is_empty()
+            && self.search_queries.is_empty()
+            && self.downloads.is_empty()
+            && self.favicons.is_empty()
+    }
+}
+
+/// The visits a sync run moved for a provider and browser.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct SyncRows {
+    pub provider_name: String,
+    pub browser: Browser,
+    /// the rows moved from the peer to the host
+    pub pulled: i64,
+    /// the rows moved from the host to the peer
+    pub pushed: i64,
+}
+
+/// A sync run between the host and a peer.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct SyncRun {
+    pub peer: String,
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    /// the error the run failed with, None if it succeeded
+    pub error: Option<String>,
+    #[serde(default)]
+    pub rows: Vec<SyncRows>,
+}
+
+impl SyncRun {
+    pub fn start(peer: String) -> SyncRun {
+        let now = chrono::Utc::now().naive_utc();
+        SyncRun {
+            peer,
+            started_at: now,
+            finished_at: now,
+            error: None,
+            rows: vec![],
+        }
+    }
+
+    /// count the visits of a change set as moved rows
+    pub fn count(&mut self, visits: &[VisitChange], pulled: bool) {
+        for v in visits {
+            let i = match self
+                .rows
+                .iter()
+                .position(|r| r.provider_name == v.provider_name && r.browser == v.browser)
+            {
+                Some(i) => i,
+                None => {
+                    self.rows.push(SyncRows {
+                        provider_name: v.provider_name.clone(),
+                        browser: v.browser,
+                        pulled: 0,
+                        pushed: 0,
+                    });
+                    self.rows.len() - 1
+                }
+            };
+            if pulled {
+                self.rows[i].pulled += 1;
+            } else {
+                self.rows[i].pushed += 1;
+            }
+        }
+    }
+
+    pub fn finish(&mut self, error: Option<&anyhow::Error>) {
+        self.finished_at = chrono::Utc::now().naive_utc();
+        self.error = error.map(|e| format!("{:#}", e));
+    }
+}
+
+/// The sync state of a peer of the host.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct PeerStatus {
+    pub peer: String,
+    pub last_run: Option<SyncRun>,
+    /// when the last successful run finished
+    pub last_success: Option<NaiveDateTime>,
+    /// whether the peer was not synced successfully for [`STALE_INTERVALS`] intervals
+    pub stale: bool,
+}
+
+impl PeerStatus {
+    /// the status of a peer which is synced every `interval`, or daily if it is None
+    pub fn new(
+        peer: String,
+        last_run: Option<SyncRun>,
+        last_success: Option<NaiveDateTime>,
+        interval: Option<Duration>,
+        now: NaiveDateTime,
+    ) -> PeerStatus {
+        let interval = interval.unwrap_or_else(|| Duration::seconds(DEFAULT_INTERVAL_SECS));
+        let stale = match last_success {
+            Some(t) => now - t > interval * STALE_INTERVALS,
+            None => true,
+        };
+        PeerStatus {
+            peer,
+            last_run,
+            last_success,
+            stale,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn winning_privacy() {
+        let time = |s: i64| Some(NaiveDateTime::from_timestamp(s, 0));
+        let url = |privacy, privacy_set_at| UrlChange {
+            url: "u".to_owned(),
+            title: "t".to_owned(),
+            tag: UrlTag::Normal,
+            privacy,
+            privacy_set_at,
+            seq: 0,
+            origin: String::new(),
+            origin_seq: 0,
+        };
+        let (normal, private) = (UrlPrivacy::Normal, UrlPrivacy::Private);
+        let cases = [
+            (url(normal, None), url(private, None), (private, None)),
+            (url(normal, time(1)), url(private, None), (normal, time(1))),
+            (
+                url(normal, time(2)),
+                url(private, time(1)),
+                (normal, time(2)),
+            ),
+            (
+                url(normal, time(1)),
+                url(private, time(1)),
+                (private, time(1)),
+            ),
+        ];
+        for (a, b, want) in cases {
+            assert_eq!(a.winning_privacy(&b), want);
+            assert_eq!(b.winning_privacy(&a), want);
+        }
+    }
+
+    #[test]
+    fn peer_staleness() {
+        let time = |h: i64| NaiveDateTime::from_timestamp(h * 3600, 0);
+        let status = |success, interval| {
+            PeerStatus::new("p".to_owned(), None, success, interval, time(100)).stale
+        };
+        assert!(status(None, None));
+        assert!(!status(Some(time(50)), None));
+        assert!(!status(Some(time(98)), Some(Duration::hours(1))));
+        assert!(status(Some(time(96)), Some(Duration::hours(1))));
+    }
+}
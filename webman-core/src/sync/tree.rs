@@ -0,0 +1,310 @@
+//! Anti-entropy between nodes. The visits of every node are hashed in a tree of two levels: one
+//! hash for every provider and browser, and below it one hash for every bucket of urls. Two
+//! nodes compare the upper level first and only fetch the buckets below differing hashes, the
+//! rows of differing buckets are reconciled and written back to both nodes. The hashes of the
+//! buckets are kept by the nodes and only computed again for the buckets whose visits changed.
+use super::{ChangeSet, UrlChange, UrlDeletion, VisitChange};
+use crate::{browser::Browser, node::Provider};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+};
+
+/// the number of url buckets of every provider and browser, a url falls into the bucket of the
+/// first byte of its md5 hash
+pub const BUCKETS: i32 = 256;
+
+/// The hash of all visits of a provider and browser.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct TreeNode {
+    pub provider_name: String,
+    pub browser: Browser,
+    pub hash: String,
+}
+
+/// The hash of the visits of the urls in a bucket.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "server", derive(sqlx::FromRow))]
+pub struct BucketHash {
+    pub bucket: i32,
+    pub hash: String,
+}
+
+/// The buckets of a provider and browser which differ between two nodes.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct Divergence {
+    pub provider_name: String,
+    pub browser: Browser,
+    pub buckets: Vec<i32>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: buckets {}",
+            self.provider_name,
+            self.browser,
+            ranges(&self.buckets)
+        )
+    }
+}
+
+/// the sorted buckets as ranges, e.g. `0-3, 7`
+pub fn ranges(buckets: &[i32]) -> String {
+    let mut res: Vec<String> = vec![];
+    let mut iter = buckets.iter().copied().peekable();
+    while let Some(start) = iter.next() {
+        let mut end = start;
+        while iter.peek() == Some(&(end + 1)) {
+            end = iter.next().unwrap();
+        }
+        res.push(if start == end {
+            start.to_string()
+        } else {
+            format!("{}-{}", start, end)
+        });
+    }
+    res.join(", ")
+}
+
+/// the keys whose hashes differ, including the keys which only one side has, in sorted order
+pub fn diff<K: Ord + Clone>(ours: &[(K, String)], theirs: &[(K, String)]) -> Vec<K> {
+    let mut hashes = BTreeMap::<&K, (Option<&str>, Option<&str>)>::new();
+    for (k, h) in ours {
+        hashes.entry(k).or_default().0 = Some(h);
+    }
+    for (k, h) in theirs {
+        hashes.entry(k).or_default().1 = Some(h);
+    }
+    hashes
+        .into_iter()
+        .filter(|(_, (a, b))| a != b)
+        .map(|(k, _)| k.clone())
+        .collect()
+}
+
+/// Merge the rows of the same buckets of two nodes, the result is the same whichever side is
+/// `ours`. Visits keep the larger values, privacy follows [`UrlChange::winning_privacy`], and the
+/// title and the tag of a url are taken from the side which visited it last. A url which one side
+/// merged into another url is merged on both sides, its visits are applied to the url it was
+/// merged into.
+pub fn reconcile(ours: ChangeSet, theirs: ChangeSet) -> ChangeSet {
+    let mut providers = HashMap::<String, NaiveDateTime>::new();
+    for p in ours.providers.into_iter().chain(theirs.providers) {
+        let t = providers.entry(p.name).or_insert(p.last_import_time);
+        *t = (*t).max(p.last_import_time);
+    }
+
+    let mut visits = BTreeMap::<(String, String, String), VisitChange>::new();
+    let mut last_visits = [HashMap::<String, NaiveDateTime>::new(), HashMap::new()];
+    for (side, rows) in [ours.visits, theirs.visits].into_iter().enumerate() {
+        for v in rows {
+            let last = last_visits[side]
+                .entry(v.url.clone())
+                .or_insert(v.last_visit_time);
+            *last = (*last).max(v.last_visit_time);
+            let key = (
+                v.url.clone(),
+                v.provider_name.clone(),
+                v.browser.to_string(),
+            );
+            match visits.get_mut(&key) {
+                Some(old) => {
+                    old.visit_count = old.visit_count.max(v.visit_count);
+                    old.last_visit_time = old.last_visit_time.max(v.last_visit_time);
+                    if v.duration_secs > old.duration_secs {
+                        old.duration_estimated = v.duration_estimated;
+                    } else if v.duration_secs == old.duration_secs {
+                        old.duration_estimated |= v.duration_estimated;
+                    }
+                    old.duration_secs = old.duration_secs.max(v.duration_secs);
+                }
+                None => {
+                    visits.insert(
+                        key,
+                        VisitChange {
+                            seq: 0,
+                            origin: String::new(),
+                            origin_seq: 0,
+                            ..v
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    let mut urls = BTreeMap::<String, UrlChange>::new();
+    for (side, rows) in [ours.urls, theirs.urls].into_iter().enumerate() {
+        for u in rows {
+            let newer = |u: &UrlChange, side: usize| {
+                (
+                    last_visits[side].get(&u.url).copied(),
+                    u.title.clone(),
+                    format!("{:?}", u.tag),
+                )
+            };
+            match urls.get_mut(&u.url) {
+                Some(old) => {
+                    let (privacy, privacy_set_at) = old.winning_privacy(&u);
+                    if newer(&u, side) > newer(old, 1 - side) {
+                        *old = local(u);
+                    }
+                    old.privacy = privacy;
+                    old.privacy_set_at = privacy_set_at;
+                }
+                None => {
+                    urls.insert(u.url.clone(), local(u));
+                }
+            }
+        }
+    }
+
+    let mut deleted = BTreeMap::<String, UrlDeletion>::new();
+    for d in ours.deleted.into_iter().chain(theirs.deleted) {
+        match deleted.get_mut(&d.url) {
+            Some(old) => {
+                if d.merged_into < old.merged_into {
+                    old.merged_into = d.merged_into;
+                }
+            }
+            None => {
+                deleted.insert(
+                    d.url.clone(),
+                    UrlDeletion {
+                        seq: 0,
+                        origin: String::new(),
+                        origin_seq: 0,
+                        ..d
+                    },
+                );
+            }
+        }
+    }
+    urls.retain(|url, _| !deleted.contains_key(url));
+
+    ChangeSet {
+        providers: providers
+            .into_iter()
+            .map(|(name, last_import_time)| Provider {
+                name,
+                last_import_time,
+            })
+            .collect(),
+        urls: urls.into_values().collect(),
+        visits: visits.into_values().collect(),
+        deleted: deleted.into_values().collect(),
+        ..Default::default()
+    }
+}
+
+/// the reconciled rows are new changes of the nodes they are written to
+fn local(u: UrlChange) -> UrlChange {
+    UrlChange {
+        seq: 0,
+        origin: String::new(),
+        origin_seq: 0,
+        ..u
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::url::{UrlPrivacy, UrlTag};
+
+    fn time(secs: i64) -> NaiveDateTime {
+        NaiveDateTime::from_timestamp(secs, 0)
+    }
+
+    fn side(title: &str, tag: UrlTag, privacy: UrlPrivacy, count: i32, secs: i64) -> ChangeSet {
+        ChangeSet {
+            urls: vec![UrlChange {
+                url: "u".to_owned(),
+                title: title.to_owned(),
+                tag,
+                privacy,
+                privacy_set_at: None,
+                seq: 1,
+                origin: "n".to_owned(),
+                origin_seq: 1,
+            }],
+            visits: vec![VisitChange {
+                url: "u".to_owned(),
+                provider_name: "p".to_owned(),
+                browser: Browser::Firefox,
+                visit_count: count,
+                last_visit_time: time(secs),
+                duration_secs: 0,
+                duration_estimated: false,
+                seq: 1,
+                origin: "n".to_owned(),
+                origin_seq: 1,
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn reconcile_symmetric() {
+        let a = side("old", UrlTag::Saved, UrlPrivacy::Private, 5, 10);
+        let b = side("new", UrlTag::Normal, UrlPrivacy::Normal, 3, 20);
+        let merged = reconcile(a.clone(), b.clone());
+        assert_eq!(merged, reconcile(b, a));
+        let u = &merged.urls[0];
+        assert_eq!(
+            (u.title.as_str(), u.tag, u.privacy),
+            ("new", UrlTag::Normal, UrlPrivacy::Private)
+        );
+        let v = &merged.visits[0];
+        assert_eq!((v.visit_count, v.last_visit_time), (5, time(20)));
+
+        // a privacy a user set wins over a stricter derived one
+        let a = side("old", UrlTag::Saved, UrlPrivacy::Private, 5, 10);
+        let mut b = side("new", UrlTag::Normal, UrlPrivacy::Normal, 3, 20);
+        b.urls[0].privacy_set_at = Some(time(30));
+        let merged = reconcile(a.clone(), b.clone());
+        assert_eq!(merged, reconcile(b, a));
+        assert_eq!(merged.urls[0].privacy, UrlPrivacy::Normal);
+    }
+
+    #[test]
+    fn reconcile_deleted() {
+        let a = side("a", UrlTag::Normal, UrlPrivacy::Normal, 5, 10);
+        let mut b = side("b", UrlTag::Normal, UrlPrivacy::Normal, 3, 20);
+        b.urls.clear();
+        b.visits.clear();
+        b.deleted.push(UrlDeletion {
+            url: "u".to_owned(),
+            merged_into: "v".to_owned(),
+            seq: 3,
+            origin: "n".to_owned(),
+            origin_seq: 3,
+        });
+        let merged = reconcile(a.clone(), b.clone());
+        assert_eq!(merged, reconcile(b, a));
+        assert!(merged.urls.is_empty());
+        assert_eq!(merged.visits.len(), 1);
+        assert_eq!(
+            (
+                merged.deleted[0].merged_into.as_str(),
+                merged.deleted[0].seq
+            ),
+            ("v", 0)
+        );
+    }
+
+    #[test]
+    fn diff_and_ranges() {
+        let h = |k: i32, h: &str| (k, h.to_owned());
+        let ours = vec![h(0, "a"), h(1, "b"), h(2, "c"), h(5, "x")];
+        let theirs = vec![h(0, "a"), h(1, "B"), h(2, "C"), h(3, "d")];
+        let keys = diff(&ours, &theirs);
+        assert_eq!(keys, vec![1, 2, 3, 5]);
+        assert_eq!(ranges(&keys), "1-3, 5");
+    }
+}
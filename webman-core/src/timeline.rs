@@ -0,0 +1,148 @@
+use crate::{
+    browser::Browser,
+    url::{UrlPrivacy, UrlTag},
+};
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+const TIME_FORMAT: &str = "%Y%m%dT%H%M%S%.6f";
+
+/// The position of a visit in the timeline. Visits are ordered by their last visit time, visits
+/// with the same time are ordered by the rest of the primary key of `visits`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Cursor {
+    pub time: NaiveDateTime,
+    pub url_id: i32,
+    pub provider_id: i16,
+    pub browser: Browser,
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}_{}_{}_{}",
+            self.time.format(TIME_FORMAT),
+            self.url_id,
+            self.provider_id,
+            self.browser
+        )
+    }
+}
+
+impl FromStr for Cursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.split('_');
+        let mut next = || {
+            parts
+                .next()
+                .with_context(|| format!("incomplete timeline cursor {}", s))
+        };
+        Ok(Self {
+            time: NaiveDateTime::parse_from_str(next()?, TIME_FORMAT)
+                .with_context(|| format!("invalid time in timeline cursor {}", s))?,
+            url_id: next()?.parse()?,
+            provider_id: next()?.parse()?,
+            browser: next()?.parse()?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct TimelineVisit {
+    pub url: String,
+    pub title: String,
+    pub tag: UrlTag,
+    pub privacy: UrlPrivacy,
+    pub visit_count: i32,
+    pub last_visit_time: NaiveDateTime,
+}
+
+/// consecutive visits from the same provider and browser
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct TimelineGroup {
+    pub provider_name: String,
+    pub browser: Browser,
+    pub visits: Vec<TimelineVisit>,
+}
+
+/// A page of the timeline, newest visits first. Pass `older` as `before` or `newer` as `after` to
+/// get the neighbouring pages, they are None if there are no more visits in that direction.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Default)]
+pub struct TimelinePage {
+    pub groups: Vec<TimelineGroup>,
+    pub newer: Option<String>,
+    pub older: Option<String>,
+}
+
+impl TimelinePage {
+    /// group the time ordered visits by runs of the same provider and browser
+    pub fn group(
+        visits: impl IntoIterator<Item = (String, Browser, TimelineVisit)>,
+    ) -> Vec<TimelineGroup> {
+        let mut groups: Vec<TimelineGroup> = Vec::new();
+        for (provider_name, browser, visit) in visits {
+            match groups.last_mut() {
+                Some(g) if g.provider_name == provider_name && g.browser == browser => {
+                    g.visits.push(visit)
+                }
+                _ => groups.push(TimelineGroup {
+                    provider_name,
+                    browser,
+                    visits: vec![visit],
+                }),
+            }
+        }
+        groups
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor() {
+        let c = Cursor {
+            time: NaiveDateTime::from_timestamp(1666000000, 123_456_000),
+            url_id: 42,
+            provider_id: 3,
+            browser: Browser::Firefox,
+        };
+        let s = c.to_string();
+        assert_eq!(s, "20221017T094640.123456_42_3_Firefox");
+        assert_eq!(s.parse::<Cursor>().unwrap(), c);
+        assert!("20221017T094640_42".parse::<Cursor>().is_err());
+    }
+
+    #[test]
+    fn group() {
+        let visit = |url: &str| TimelineVisit {
+            url: url.to_owned(),
+            title: String::new(),
+            tag: UrlTag::Normal,
+            privacy: UrlPrivacy::Normal,
+            visit_count: 1,
+            last_visit_time: NaiveDateTime::from_timestamp(0, 0),
+        };
+        let groups = TimelinePage::group([
+            ("a".to_owned(), Browser::Firefox, visit("1")),
+            ("a".to_owned(), Browser::Firefox, visit("2")),
+            ("a".to_owned(), Browser::Chrome, visit("3")),
+            ("a".to_owned(), Browser::Firefox, visit("4")),
+        ]);
+        let got: Vec<_> = groups.iter().map(|g| (g.browser, g.visits.len())).collect();
+        assert_eq!(
+            got,
+            vec![
+                (Browser::Firefox, 2),
+                (Browser::Chrome, 1),
+                (Browser::Firefox, 1)
+            ]
+        );
+    }
+}
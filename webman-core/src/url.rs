@@ -1,3 +1,9 @@
+pub mod canonical;
+pub mod duration;
+pub mod matcher;
+pub mod privacy;
+pub mod sanitize;
+pub mod search_engine;
 pub mod tag;
 
 use crate::browser::Browser;
@@ -6,9 +12,19 @@ use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use strum::EnumString;
 
-pub use self::tag::{Tags, UrlTag};
+pub use self::{
+    canonical::canonical,
+    duration::UrlDuration,
+    matcher::UrlMatcher,
+    privacy::{PrivacyRule, PrivacyRules},
+    sanitize::SanitizeRules,
+    search_engine::{QueryLogEntry, SearchEngines, SearchQuery, SearchTerm},
+    tag::{Tags, UrlTag},
+};
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Deserialize, Serialize, Hash, EnumString)]
+#[derive(
+    Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Deserialize, Serialize, Hash, EnumString,
+)]
 #[strum(serialize_all = "lowercase")]
 #[serde(rename_all = "lowercase")]
 #[cfg_attr(feature = "server", derive(sqlx::Type))]
@@ -28,6 +44,13 @@ impl Default for UrlPrivacy {
     }
 }
 
+#[cfg(feature = "server")]
+impl sqlx::postgres::PgHasArrayType for UrlPrivacy {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_privacy")
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Default)]
 #[cfg_attr(feature = "server", derive(sqlx::FromRow))]
 pub struct Url {
@@ -35,9 +58,11 @@ pub struct Url {
     pub title: String,
     pub visit_count: i32,
     pub last_visit_time: NaiveDateTime,
+    #[serde(default)]
+    pub privacy: UrlPrivacy,
 }
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Default)]
 #[serde(rename_all = "lowercase")]
 #[cfg_attr(feature = "server", derive(sqlx::FromRow))]
 pub struct UrlResult {
@@ -45,15 +70,24 @@ pub struct UrlResult {
     pub title: String,
     pub tag: UrlTag,
     pub privacy: UrlPrivacy,
+    /// the seconds spent on the url, None if unknown
+    #[serde(default)]
+    pub time_spent: Option<i64>,
+    /// whether `time_spent` is estimated from the gaps between visits
+    #[serde(default)]
+    pub time_estimated: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Default, PartialEq, Eq, Clone)]
 pub struct Filter {
     pub p_id: Option<i16>,
     pub provider_name: Option<String>,
     pub privacy: Option<UrlPrivacy>,
     pub tag: Option<UrlTag>,
     pub browser: Option<Browser>,
+    /// the normalized host, see [`normalized_host`]
+    #[serde(default)]
+    pub host: Option<String>,
     pub time_from: Option<NaiveDateTime>,
     pub time_to: Option<NaiveDateTime>,
     pub url_segs: Vec<String>,
@@ -63,16 +97,98 @@ pub struct Filter {
     /// title_segs without %% symbol
     pub title_segs_raw: Vec<String>,
     pub limit: i64,
+    /// private urls are only searched if the session is unlocked
+    #[serde(default)]
+    pub unlocked: bool,
+    /// search transient pages which redirected to another page, otherwise they are hidden and
+    /// their visits count for the page they redirected to
+    #[serde(default)]
+    pub transient: bool,
+    /// only search the urls files were downloaded from
+    #[serde(default)]
+    pub download: bool,
+    /// only return the urls after this position, see [`UrlPage`]
+    #[serde(default)]
+    pub after: Option<UrlCursor>,
     /// filter can only be used if ready is true, to make sure fields are initialized
     pub ready: bool,
 }
 
+/// The position after a url in the order of last visit time and url.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct UrlCursor {
+    pub last_visit_time: NaiveDateTime,
+    pub url: String,
+}
+
+/// A page of filtered urls, the next page starts after `next`, which is None on the last page.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Default)]
+pub struct UrlPage {
+    pub urls: Vec<Url>,
+    pub next: Option<UrlCursor>,
+}
+
+/// the host part of the url without userinfo and port, e.g. `docs.rs` for `https://docs.rs/regex`
+pub fn host(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, a)| a);
+    let host = if authority.starts_with('[') {
+        authority.split_inclusive(']').next()?
+    } else {
+        authority.split(':').next()?
+    };
+    if host.is_empty() {
+        None
+    } else {
+        Some(host)
+    }
+}
+
+/// the lowercased host without `www.`, which is used to group urls by site
+pub fn normalized_host(url: &str) -> Option<String> {
+    host(url).map(|h| {
+        let h = h.to_ascii_lowercase();
+        match h.strip_prefix("www.") {
+            Some(stripped) => stripped.to_owned(),
+            None => h,
+        }
+    })
+}
+
+/// merge urls with the same url string, the visit counts are summed up and the title of the latest
+/// visit is kept. The order of first occurrence is preserved.
+pub fn dedup_urls(urls: Vec<Url>) -> Vec<Url> {
+    let mut index = std::collections::HashMap::<String, usize>::with_capacity(urls.len());
+    let mut res: Vec<Url> = Vec::with_capacity(urls.len());
+    for u in urls {
+        match index.get(&u.url) {
+            Some(&i) => {
+                let old = &mut res[i];
+                old.visit_count += u.visit_count;
+                old.privacy = old.privacy.max(u.privacy);
+                if u.last_visit_time > old.last_visit_time {
+                    old.last_visit_time = u.last_visit_time;
+                    old.title = u.title;
+                }
+            }
+            None => {
+                index.insert(u.url.clone(), res.len());
+                res.push(u);
+            }
+        }
+    }
+    res
+}
+
 fn to_like(segs: &[String]) -> Vec<String> {
     segs.iter().map(|seg| format!("%{}%", seg)).collect()
 }
 
 impl Filter {
-    /// parse a query to filter, which will be split by space, the segment start with / will be recognised as url_segs.
+    /// parse a query to filter, which will be split by space, the segment start with / will be recognised as url_segs,
+    /// the segment start with @ will be recognised as host, `is:download` only keeps the urls of
+    /// downloads.
     pub fn parse(mut query: String) -> Self {
         let mut res = Self::default();
 
@@ -86,6 +202,10 @@ impl Filter {
         for arg in query.split(' ') {
             if let Some(url_seg) = arg.strip_prefix('/') {
                 res.url_segs.push(format!("%{}%", &url_seg));
+            } else if arg == "is:download" {
+                res.download = true;
+            } else if let Some(host) = arg.strip_prefix('@') {
+                res.host = normalized_host(&format!("http://{}", host));
             } else if let Some(pattern) = arg.strip_prefix(',') {
                 match pattern {
                     "p" | "privacy" => res.privacy = Some(UrlPrivacy::Private),
@@ -93,6 +213,7 @@ impl Filter {
                     "s" | "saved" => res.tag = Some(UrlTag::Saved),
                     "f" | "favorite" => res.tag = Some(UrlTag::Favorite),
                     "r" | "readlater" => res.tag = Some(UrlTag::ReadLater),
+                    "t" | "transient" => res.transient = true,
                     _ => (),
                 }
             } else {
@@ -152,4 +273,31 @@ mod tests {
             }
         )
     }
+
+    #[test]
+    fn filter_parse_host() {
+        let f = Filter::parse("@www.Docs.rs regex".to_string());
+        assert_eq!(f.host, Some("docs.rs".to_owned()));
+        assert_eq!(f.title_segs, vec!["%regex%".to_owned()]);
+    }
+
+    #[test]
+    fn filter_parse_download() {
+        let f = Filter::parse("is:download paper".to_string());
+        assert!(f.download);
+        assert_eq!(f.title_segs, vec!["%paper%".to_owned()]);
+    }
+
+    #[test]
+    fn url_host() {
+        assert_eq!(host("https://docs.rs/regex"), Some("docs.rs"));
+        assert_eq!(host("http://user:pw@localhost:3000?a=b"), Some("localhost"));
+        assert_eq!(host("http://[::1]:8080/"), Some("[::1]"));
+        assert_eq!(host("file:///home/weiss/a.pdf"), None);
+        assert_eq!(host("about:blank"), None);
+        assert_eq!(
+            normalized_host("https://www.Docs.rs/regex"),
+            Some("docs.rs".to_owned())
+        );
+    }
 }
@@ -0,0 +1,87 @@
+use super::sanitize::is_tracking_param;
+
+/// The key to find duplicates of a url: `http` and `https` are treated the same, the scheme, `www.`,
+/// default ports, fragment, trailing slash and tracking parameters are dropped, the host is
+/// lowercased and the query parameters are sorted. e.g. `http://www.X.com/a/?b=1&a=2#frag` becomes
+/// `x.com/a?a=2&b=1`. Urls without authority like `about:blank` are returned unchanged.
+pub fn canonical(url: &str) -> String {
+    let url = url.split('#').next().unwrap_or_default();
+    let (scheme, rest) = match url.split_once("://") {
+        Some(r) => r,
+        None => return url.to_owned(),
+    };
+    let scheme = scheme.to_ascii_lowercase();
+    let is_http = scheme == "http" || scheme == "https";
+
+    let (rest, query) = match rest.split_once('?') {
+        Some((r, q)) => (r, Some(q)),
+        None => (rest, None),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+    let authority = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, a)| a)
+        .to_ascii_lowercase();
+    let authority = if is_http {
+        let a = authority.strip_prefix("www.").unwrap_or(&authority);
+        a.strip_suffix(":80")
+            .or_else(|| a.strip_suffix(":443"))
+            .unwrap_or(a)
+            .to_owned()
+    } else {
+        authority
+    };
+
+    let mut res = if is_http {
+        authority
+    } else {
+        format!("{}://{}", scheme, authority)
+    };
+    res.push_str(path.trim_end_matches('/'));
+
+    if let Some(q) = query {
+        let mut params: Vec<&str> = q
+            .split('&')
+            .filter(|p| {
+                !p.is_empty() && !is_tracking_param(p.split('=').next().unwrap_or_default())
+            })
+            .collect();
+        if !params.is_empty() {
+            params.sort_unstable();
+            res.push('?');
+            res.push_str(&params.join("&"));
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_url() {
+        for url in [
+            "http://x.com",
+            "https://x.com/",
+            "https://www.x.com/#frag",
+            "https://X.com:443/?utm_source=hn",
+            "http://user@www.x.com:80",
+        ] {
+            assert_eq!(canonical(url), "x.com", "url: {}", url);
+        }
+        assert_eq!(
+            canonical("https://docs.rs/regex/?b=1&a=2&utm_medium=x#impl"),
+            "docs.rs/regex?a=2&b=1"
+        );
+        assert_eq!(canonical("https://x.com:8080/a"), "x.com:8080/a");
+        assert_eq!(
+            canonical("file:///home/weiss/a.pdf"),
+            "file:///home/weiss/a.pdf"
+        );
+        assert_eq!(canonical("about:blank"), "about:blank");
+    }
+}
@@ -0,0 +1,112 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// the longest gap between two visits which is counted as time spent on the first page, longer
+/// gaps are most likely breaks
+pub const MAX_ESTIMATED_SECS: i64 = 600;
+
+/// The time spent on a url during one visit. The visit is identified by its id in the browser, so
+/// that a duration which is sent again replaces the stored one instead of being added to it.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct UrlDuration {
+    pub url: String,
+    pub visit_id: i64,
+    pub secs: i64,
+    /// true if the browser does not record durations and `secs` is estimated from the gaps between
+    /// visits
+    pub estimated: bool,
+}
+
+impl UrlDuration {
+    /// keep the last duration of every visit of a url, the order of first occurrence is preserved
+    pub fn dedup(durations: Vec<UrlDuration>) -> Vec<UrlDuration> {
+        let mut index = HashMap::<(String, i64), usize>::with_capacity(durations.len());
+        let mut res: Vec<UrlDuration> = Vec::with_capacity(durations.len());
+        for d in durations {
+            match index.get(&(d.url.clone(), d.visit_id)) {
+                Some(&i) => res[i] = d,
+                None => {
+                    index.insert((d.url.clone(), d.visit_id), res.len());
+                    res.push(d);
+                }
+            }
+        }
+        res
+    }
+}
+
+/// Estimate the durations from visits sorted by time, every visit lasts until the next visit but
+/// at most [`MAX_ESTIMATED_SECS`]. The last visit is not counted since its end is unknown.
+pub fn estimate(
+    visits: impl IntoIterator<Item = (i64, String, NaiveDateTime)>,
+) -> Vec<UrlDuration> {
+    let mut visits = visits.into_iter().peekable();
+    let mut durations = vec![];
+    while let Some((visit_id, url, time)) = visits.next() {
+        if let Some((_, _, next)) = visits.peek() {
+            let secs = (*next - time).num_seconds().min(MAX_ESTIMATED_SECS);
+            if secs > 0 {
+                durations.push(UrlDuration {
+                    url,
+                    visit_id,
+                    secs,
+                    estimated: true,
+                });
+            }
+        }
+    }
+    durations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_durations() {
+        let visit =
+            |id, url: &str, secs| (id, url.to_owned(), NaiveDateTime::from_timestamp(secs, 0));
+        let durations = estimate([
+            visit(1, "a", 0),
+            visit(2, "b", 30),
+            visit(3, "a", 3600),
+            visit(4, "c", 3660),
+            visit(5, "a", 3660),
+            visit(6, "d", 3700),
+        ]);
+        let got: Vec<_> = durations
+            .iter()
+            .map(|d| (d.visit_id, d.url.as_str(), d.secs))
+            .collect();
+        assert_eq!(
+            got,
+            vec![(1, "a", 30), (2, "b", 600), (3, "a", 60), (5, "a", 40)]
+        );
+        assert!(durations.iter().all(|d| d.estimated));
+    }
+
+    #[test]
+    fn dedup_durations() {
+        let duration = |url: &str, visit_id, secs| UrlDuration {
+            url: url.to_owned(),
+            visit_id,
+            secs,
+            estimated: false,
+        };
+        let got = UrlDuration::dedup(vec![
+            duration("a", 1, 10),
+            duration("b", 1, 20),
+            duration("a", 2, 30),
+            duration("a", 1, 40),
+        ]);
+        assert_eq!(
+            got,
+            vec![
+                duration("a", 1, 40),
+                duration("b", 1, 20),
+                duration("a", 2, 30)
+            ]
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use super::host;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Matches urls by scheme, domain or regex.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum UrlMatcher {
+    /// matches the scheme of the url, e.g. `file`
+    Scheme { scheme: String },
+    /// matches the domain itself and all its subdomains
+    Domain { domain: String },
+    /// matches if the regex matches anywhere in the url
+    Regex {
+        #[serde(
+            deserialize_with = "deserialize_regex",
+            serialize_with = "serialize_regex"
+        )]
+        regex: Regex,
+    },
+}
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let buf = String::deserialize(deserializer)?;
+    Regex::new(&buf).map_err(serde::de::Error::custom)
+}
+
+fn serialize_regex<S>(regex: &Regex, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(regex.as_str())
+}
+
+impl UrlMatcher {
+    pub fn is_match(&self, url: &str) -> bool {
+        match self {
+            UrlMatcher::Scheme { scheme } => url
+                .split_once(':')
+                .is_some_and(|(s, _)| s.eq_ignore_ascii_case(scheme)),
+            UrlMatcher::Domain { domain } => host(url).is_some_and(|h| {
+                let (h, d) = (h.to_ascii_lowercase(), domain.to_ascii_lowercase());
+                h == d || h.ends_with(&format!(".{}", d))
+            }),
+            UrlMatcher::Regex { regex } => regex.is_match(url),
+        }
+    }
+}
+
+impl PartialEq for UrlMatcher {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (UrlMatcher::Scheme { scheme: a }, UrlMatcher::Scheme { scheme: b }) => a == b,
+            (UrlMatcher::Domain { domain: a }, UrlMatcher::Domain { domain: b }) => a == b,
+            (UrlMatcher::Regex { regex: a }, UrlMatcher::Regex { regex: b }) => {
+                a.as_str() == b.as_str()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for UrlMatcher {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(json: &str) -> UrlMatcher {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn domain_matcher() {
+        let m = matcher(r#"{"domain": "bank.com"}"#);
+        for url in [
+            "https://bank.com",
+            "https://online.bank.com/login?x=1",
+            "http://user@BANK.com:8080/",
+        ] {
+            assert!(m.is_match(url), "url: {}", url);
+        }
+        for url in ["https://notbank.com", "https://bank.com.evil.org/"] {
+            assert!(!m.is_match(url), "url: {}", url);
+        }
+    }
+
+    #[test]
+    fn scheme_and_regex_matcher() {
+        let m = matcher(r#"{"scheme": "file"}"#);
+        assert!(m.is_match("file:///home/weiss/a.pdf"));
+        assert!(!m.is_match("https://docs.rs/file"));
+
+        let m = matcher(r#"{"regex": "^https?://localhost(:\\d+)?/"}"#);
+        assert!(m.is_match("http://localhost:3000/"));
+        assert!(!m.is_match("https://docs.rs/?q=http://localhost/"));
+    }
+}
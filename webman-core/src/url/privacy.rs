@@ -0,0 +1,99 @@
+use super::{Url, UrlMatcher, UrlPrivacy};
+use crate::browser::Browser;
+use serde::{Deserialize, Serialize};
+
+/// A rule which marks matching urls as [`UrlPrivacy::Private`].
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum PrivacyRule {
+    Url(UrlMatcher),
+    /// matches all urls provided by the provider, optionally only from one browser
+    Profile {
+        provider: String,
+        browser: Option<Browser>,
+    },
+}
+
+impl PrivacyRule {
+    pub fn is_match(&self, url: &str, provider: &str, browser: Browser) -> bool {
+        match self {
+            PrivacyRule::Url(m) => m.is_match(url),
+            PrivacyRule::Profile {
+                provider: p,
+                browser: b,
+            } => p == provider && (b.is_none() || *b == Some(browser)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct PrivacyRules(Vec<PrivacyRule>);
+
+impl PrivacyRules {
+    pub fn new(rules: Vec<PrivacyRule>) -> Self {
+        Self(rules)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn classify(&self, url: &str, provider: &str, browser: Browser) -> UrlPrivacy {
+        if self.0.iter().any(|r| r.is_match(url, provider, browser)) {
+            UrlPrivacy::Private
+        } else {
+            UrlPrivacy::Normal
+        }
+    }
+
+    /// mark the matching urls as private, urls which are already private are kept untouched.
+    pub fn apply(&self, provider: &str, browser: Browser, urls: &mut [Url]) {
+        for u in urls.iter_mut() {
+            if u.privacy == UrlPrivacy::Normal {
+                u.privacy = self.classify(&u.url, provider, browser);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify() {
+        let r: PrivacyRules = serde_json::from_str(
+            r#"[{"domain": "bank.com"}, {"provider": "Work", "browser": "Firefox"}]"#,
+        )
+        .unwrap();
+        for (url, provider, browser, privacy) in [
+            (
+                "https://online.bank.com",
+                "Desktop",
+                Browser::Chromium,
+                UrlPrivacy::Private,
+            ),
+            (
+                "https://docs.rs",
+                "Work",
+                Browser::Firefox,
+                UrlPrivacy::Private,
+            ),
+            (
+                "https://docs.rs",
+                "Work",
+                Browser::Chromium,
+                UrlPrivacy::Normal,
+            ),
+            (
+                "https://docs.rs",
+                "Desktop",
+                Browser::Firefox,
+                UrlPrivacy::Normal,
+            ),
+        ] {
+            assert_eq!(r.classify(url, provider, browser), privacy, "url: {}", url);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use super::{dedup_urls, Url};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/// tracking parameters which are removed from urls
+const TRACKING_PARAMS: &[&str] = &[
+    "utm_*", "fbclid", "gclid", "gclsrc", "dclid", "msclkid", "yclid", "mc_cid", "mc_eid", "_ga",
+    "_gl", "igshid", "ref_src",
+];
+
+/// parameters carrying secrets, whose values are replaced by [`REDACTED`]
+const SECRET_PARAMS: &[&str] = &[
+    "code",
+    "token",
+    "access_token",
+    "id_token",
+    "refresh_token",
+    "client_secret",
+    "secret",
+    "password",
+    "passwd",
+    "api_key",
+    "apikey",
+    "session",
+    "sessionid",
+    "session_id",
+    "sid",
+    "jsessionid",
+    "phpsessid",
+    "ticket",
+    "otp",
+    "signature",
+    "x-amz-signature",
+    "x-amz-credential",
+    "x-amz-security-token",
+];
+
+pub const REDACTED: &str = "redacted";
+
+/// Removes tracking parameters and redacts secrets in the query and fragment of urls. The built-in
+/// rules can be extended by `remove` and `redact`, and disabled for single parameters by `keep`.
+/// A trailing `*` in a parameter name matches any suffix. Names are case insensitive.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Default)]
+pub struct SanitizeRules {
+    #[serde(default)]
+    pub remove: Vec<String>,
+    #[serde(default)]
+    pub redact: Vec<String>,
+    #[serde(default)]
+    pub keep: Vec<String>,
+}
+
+enum Action {
+    Keep,
+    Remove,
+    Redact,
+}
+
+fn matches<S: AsRef<str>>(patterns: &[S], key: &str) -> bool {
+    patterns.iter().any(|p| {
+        let p = p.as_ref();
+        match p.strip_suffix('*') {
+            Some(prefix) => {
+                key.len() >= prefix.len() && key[..prefix.len()].eq_ignore_ascii_case(prefix)
+            }
+            None => key.eq_ignore_ascii_case(p),
+        }
+    })
+}
+
+/// whether the parameter is removed by the built-in rules
+pub fn is_tracking_param(key: &str) -> bool {
+    matches(TRACKING_PARAMS, key)
+}
+
+impl SanitizeRules {
+    fn action(&self, key: &str) -> Action {
+        if matches(&self.keep, key) {
+            Action::Keep
+        } else if is_tracking_param(key) || matches(&self.remove, key) {
+            Action::Remove
+        } else if matches(SECRET_PARAMS, key) || matches(&self.redact, key) {
+            Action::Redact
+        } else {
+            Action::Keep
+        }
+    }
+
+    /// sanitize `a=1&b=2`, returns None if nothing is changed
+    fn sanitize_params(&self, params: &str) -> Option<String> {
+        let mut changed = false;
+        let kept: Vec<Cow<str>> = params
+            .split('&')
+            .filter_map(|param| {
+                let key = param.split('=').next().unwrap_or_default();
+                match self.action(key) {
+                    Action::Keep => Some(param.into()),
+                    Action::Remove => {
+                        changed = true;
+                        None
+                    }
+                    Action::Redact => {
+                        let redacted = format!("{}={}", key, REDACTED);
+                        changed |= redacted != param;
+                        Some(redacted.into())
+                    }
+                }
+            })
+            .collect();
+        changed.then(|| kept.join("&"))
+    }
+
+    pub fn sanitize<'a>(&self, url: &'a str) -> Cow<'a, str> {
+        let (rest, fragment) = match url.split_once('#') {
+            Some((r, f)) => (r, Some(f)),
+            None => (url, None),
+        };
+        let (base, query) = match rest.split_once('?') {
+            Some((b, q)) => (b, Some(q)),
+            None => (rest, None),
+        };
+        let new_query = query.and_then(|q| self.sanitize_params(q));
+        // fragments like `#access_token=...` are used by oauth implicit flows
+        let new_fragment = fragment
+            .filter(|f| f.contains('='))
+            .and_then(|f| self.sanitize_params(f));
+        if new_query.is_none() && new_fragment.is_none() {
+            return url.into();
+        }
+
+        let mut res = base.to_owned();
+        if let Some(q) = new_query.as_deref().or(query) {
+            if !q.is_empty() {
+                res.push('?');
+                res.push_str(q);
+            }
+        }
+        if let Some(f) = new_fragment.as_deref().or(fragment) {
+            if !f.is_empty() {
+                res.push('#');
+                res.push_str(f);
+            }
+        }
+        res.into()
+    }
+
+    /// sanitize all urls, urls which become identical are merged
+    pub fn apply(&self, urls: Vec<Url>) -> Vec<Url> {
+        let urls = urls
+            .into_iter()
+            .map(|mut u| {
+                if let Cow::Owned(s) = self.sanitize(&u.url) {
+                    u.url = s;
+                }
+                u
+            })
+            .collect();
+        dedup_urls(urls)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize() {
+        let rules = SanitizeRules {
+            remove: vec!["ref".to_owned()],
+            keep: vec!["sid".to_owned()],
+            ..Default::default()
+        };
+        for (url, want) in [
+            ("https://docs.rs/regex", "https://docs.rs/regex"),
+            (
+                "https://a.com/p?utm_source=x&id=3&UTM_medium=y#top",
+                "https://a.com/p?id=3#top",
+            ),
+            ("https://a.com/?fbclid=1&ref=hn", "https://a.com/"),
+            (
+                "https://a.com/cb?code=abc&state=s",
+                "https://a.com/cb?code=redacted&state=s",
+            ),
+            (
+                "https://a.com/cb#access_token=abc&expires_in=3600",
+                "https://a.com/cb#access_token=redacted&expires_in=3600",
+            ),
+            ("https://a.com/?sid=1", "https://a.com/?sid=1"),
+        ] {
+            assert_eq!(rules.sanitize(url), want, "url: {}", url);
+        }
+    }
+
+    #[test]
+    fn apply_merges_urls() {
+        let url = |u: &str, count| Url {
+            url: u.to_owned(),
+            visit_count: count,
+            ..Default::default()
+        };
+        let urls = SanitizeRules::default().apply(vec![
+            url("https://a.com/?utm_source=x", 1),
+            url("https://b.com/", 1),
+            url("https://a.com/?utm_source=y", 2),
+        ]);
+        assert_eq!(
+            urls,
+            vec![url("https://a.com/", 3), url("https://b.com/", 1)]
+        );
+    }
+}
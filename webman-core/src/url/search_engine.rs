@@ -0,0 +1,197 @@
+use super::{normalized_host, UrlMatcher, UrlResult};
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// (name, domain, path prefix, query parameter) of the built-in search engines
+const DEFAULT_ENGINES: &[(&str, &str, &str, &str)] = &[
+    ("google", "google.com", "/search", "q"),
+    ("bing", "bing.com", "/search", "q"),
+    ("duckduckgo", "duckduckgo.com", "/", "q"),
+    ("github", "github.com", "/search", "q"),
+    ("stackoverflow", "stackoverflow.com", "/search", "q"),
+    ("youtube", "youtube.com", "/results", "search_query"),
+    ("wikipedia", "wikipedia.org", "/w/index.php", "search"),
+    ("docs.rs", "docs.rs", "/releases/search", "query"),
+    ("crates.io", "crates.io", "/search", "q"),
+];
+
+fn default_param() -> String {
+    "q".to_owned()
+}
+
+/// A search engine whose result pages match `matcher` and `path`, and carry the query in `param`.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct SearchEngine {
+    pub name: String,
+    #[serde(flatten)]
+    pub matcher: UrlMatcher,
+    /// the prefix of the path of result pages
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default = "default_param")]
+    pub param: String,
+}
+
+impl SearchEngine {
+    pub fn extract(&self, url: &str) -> Option<String> {
+        if !self.matcher.is_match(url) {
+            return None;
+        }
+        let (_, rest) = url.split_once("://")?;
+        let rest = rest.split('#').next()?;
+        let (path_and_host, query) = rest.split_once('?')?;
+        let path = path_and_host.find('/').map_or("/", |i| &path_and_host[i..]);
+        if let Some(p) = &self.path {
+            if !path.starts_with(p.as_str()) {
+                return None;
+            }
+        }
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(k, _)| k == self.param.as_str())
+            .map(|(_, v)| v.trim().to_owned())
+            .filter(|v| !v.is_empty())
+    }
+}
+
+/// The configured search engines, the built-in ones are used if none is configured.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+#[serde(transparent)]
+pub struct SearchEngines(Vec<SearchEngine>);
+
+impl Default for SearchEngines {
+    fn default() -> Self {
+        Self(
+            DEFAULT_ENGINES
+                .iter()
+                .map(|(name, domain, path, param)| SearchEngine {
+                    name: name.to_string(),
+                    matcher: UrlMatcher::Domain {
+                        domain: domain.to_string(),
+                    },
+                    path: Some(path.to_string()),
+                    param: param.to_string(),
+                })
+                .collect(),
+        )
+    }
+}
+
+impl SearchEngines {
+    /// the query of a result page of a search engine
+    pub fn extract(&self, url: &str) -> Option<SearchQuery> {
+        self.0.iter().find_map(|e| {
+            e.extract(url).map(|query| SearchQuery {
+                url: url.to_owned(),
+                query,
+                engine: e.name.clone(),
+            })
+        })
+    }
+
+    /// the queries of `urls` and the `terms` recorded by the browser, terms take precedence
+    pub fn extract_all<'a>(
+        &self,
+        urls: impl IntoIterator<Item = &'a str>,
+        terms: Vec<SearchTerm>,
+    ) -> Vec<SearchQuery> {
+        let mut queries: Vec<SearchQuery> = terms
+            .into_iter()
+            .map(|t| SearchQuery {
+                engine: self
+                    .extract(&t.url)
+                    .map(|q| q.engine)
+                    .or_else(|| normalized_host(&t.url))
+                    .unwrap_or_default(),
+                url: t.url,
+                query: t.term,
+            })
+            .collect();
+        for url in urls {
+            if !queries.iter().any(|q| q.url == url) {
+                queries.extend(self.extract(url));
+            }
+        }
+        queries
+    }
+}
+
+/// A search term which the browser recorded for a result page, e.g. `keyword_search_terms` of
+/// Chromium
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct SearchTerm {
+    pub url: String,
+    pub term: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct SearchQuery {
+    /// the result page of the search engine
+    pub url: String,
+    pub query: String,
+    pub engine: String,
+}
+
+/// A stored search query and the pages opened from its result page
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct QueryLogEntry {
+    pub query: String,
+    pub engine: String,
+    pub url: String,
+    pub last_visit_time: NaiveDateTime,
+    pub results: Vec<UrlResult>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract() {
+        let engines = SearchEngines::default();
+        for (url, want) in [
+            (
+                "https://www.google.com/search?q=postgres+row+lock&oq=x",
+                Some(("google", "postgres row lock")),
+            ),
+            (
+                "https://duckduckgo.com/?q=rust%20sqlx&t=h_",
+                Some(("duckduckgo", "rust sqlx")),
+            ),
+            (
+                "https://github.com/search?q=webman&type=repositories",
+                Some(("github", "webman")),
+            ),
+            ("https://github.com/rust-lang/rust?q=x", None),
+            ("https://www.google.com/maps?q=berlin", None),
+            ("https://www.google.com/search?q=", None),
+            ("https://example.com/search?q=x", None),
+        ] {
+            let got = engines.extract(url);
+            assert_eq!(
+                got.as_ref().map(|q| (q.engine.as_str(), q.query.as_str())),
+                want,
+                "url: {}",
+                url
+            );
+        }
+    }
+
+    #[test]
+    fn extract_all() {
+        let engines: SearchEngines =
+            serde_json::from_str(r#"[{"name": "intranet", "domain": "corp.lan", "param": "s"}]"#)
+                .unwrap();
+        let queries = engines.extract_all(
+            ["https://corp.lan/find?s=vpn", "https://a.com/?q=1"],
+            vec![SearchTerm {
+                url: "https://a.com/?q=1".to_owned(),
+                term: "one".to_owned(),
+            }],
+        );
+        let got: Vec<_> = queries
+            .iter()
+            .map(|q| (q.engine.as_str(), q.query.as_str()))
+            .collect();
+        assert_eq!(got, vec![("a.com", "one"), ("intranet", "vpn")]);
+    }
+}
@@ -25,6 +25,13 @@ impl Default for UrlTag {
     }
 }
 
+#[cfg(feature = "server")]
+impl sqlx::postgres::PgHasArrayType for UrlTag {
+    fn array_type_info() -> sqlx::postgres::PgTypeInfo {
+        sqlx::postgres::PgTypeInfo::with_name("_tag")
+    }
+}
+
 pub type Tags = std::collections::HashMap<UrlTag, Vec<String>>;
 
 #[derive(Clone, Copy, Default, Debug, Serialize, Deserialize)]
@@ -1,5 +1,5 @@
-use super::{client_trait::ClientTrait, Client};
-use crate::node::Node;
+use super::{client_trait::ClientTrait, request::Failover, Client};
+use crate::node::{Endpoint, Node};
 
 use async_trait::async_trait;
 
@@ -8,6 +8,7 @@ use reqwest::RequestBuilder;
 pub struct ApiKeyClient {
     c: reqwest::Client,
     api_key: String,
+    failover: Failover,
 }
 
 impl Client {
@@ -15,6 +16,7 @@ impl Client {
         Client(Box::new(ApiKeyClient {
             c: reqwest::Client::new(),
             api_key: api_key.to_owned(),
+            failover: Failover::default(),
         }))
     }
 }
@@ -31,4 +33,15 @@ impl ClientTrait for ApiKeyClient {
             .get(node.api_url(end_point))
             .header("x-api-key", &self.api_key)
     }
+    fn ping(&self, endpoint: &Endpoint) -> RequestBuilder {
+        self.c
+            .get(format!("{}/auth/ping", endpoint.base_url()))
+            .header("x-api-key", &self.api_key)
+    }
+    fn http(&self) -> &reqwest::Client {
+        &self.c
+    }
+    fn failover(&self) -> &Failover {
+        &self.failover
+    }
 }
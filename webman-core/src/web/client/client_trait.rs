@@ -1,10 +1,19 @@
+use super::request::{self, Call, Failover};
 use crate::{
     browser::Browser,
-    node::{Node, Provider},
-    url::{self, tag, Url},
-    web::resp::{UrlInsert, UrlTagSetter},
+    error::WebmanError,
+    navigation::Navigation,
+    node::{Endpoint, Node},
+    session::BrowsingSession,
+    stats::{HostStats, HostSummary, Period},
+    sync::{
+        tree::{self, BucketHash, Divergence, TreeNode},
+        ChangeSet, OriginVersion, PeerStatus, SyncRun,
+    },
+    url::{self, tag},
+    web::resp::{UrlInsert, UrlPrivacySetter, UrlTagSetter},
 };
-use anyhow::{Context, Result};
+use anyhow::Result;
 use async_trait::async_trait;
 use chrono::NaiveDateTime;
 use log::info;
@@ -22,29 +31,55 @@ where
     for<'de> T: Deserialize<'de>,
 {
     let req = c.get(node, end_point);
-    if let Some(p) = params {
+    let req = if let Some(p) = params {
         req.query(&p)
     } else {
         req
+    };
+    request::json(c, node, req, end_point, Call::Read).await
+}
+
+/// None if the node replied 404 Not Found
+fn found<T>(res: Result<T>) -> Result<Option<T>> {
+    match res {
+        Ok(t) => Ok(Some(t)),
+        Err(e) if matches!(WebmanError::of(&e), WebmanError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e),
     }
-    .send()
-    .await
-    .with_context(|| format!("error getting to {}", end_point))?
-    .json()
-    .await
-    .with_context(|| {
-        format!(
-            "error converting body of type {} via endpoint {}",
-            std::any::type_name::<T>(),
-            end_point
-        )
-    })
 }
 
 #[async_trait]
 pub trait ClientTrait: 'static + Send + Sync {
     fn post(&self, node: &Node, end_point: &str) -> RequestBuilder;
     fn get(&self, node: &Node, end_point: &str) -> RequestBuilder;
+    /// the request to check whether the endpoint answers and accepts the credentials
+    fn ping(&self, endpoint: &Endpoint) -> RequestBuilder;
+    fn http(&self) -> &reqwest::Client;
+    fn failover(&self) -> &Failover;
+
+    /// try the endpoints of the node in order, each within its own timeout, and remember the
+    /// first one which answers
+    async fn probe(&self, node: &Node) -> Result<usize> {
+        for (i, endpoint) in node.endpoints.iter().enumerate() {
+            match self.ping(endpoint).timeout(endpoint.timeout()).send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    if self.failover().get(node) != Some(i) {
+                        info!("using endpoint {}", endpoint);
+                    }
+                    self.failover().set(node, i);
+                    return Ok(i);
+                }
+                Ok(resp) => log::debug!("endpoint {} replied {}", endpoint, resp.status()),
+                Err(e) => log::debug!("endpoint {} is unreachable: {}", endpoint, e),
+            }
+        }
+        let endpoints: Vec<_> = node.endpoints.iter().map(|e| e.to_string()).collect();
+        Err(WebmanError::Unavailable(format!(
+            "none of the endpoints {} answered",
+            endpoints.join(", ")
+        ))
+        .into())
+    }
 
     async fn last_import_time(&self, node: &Node, name: &str) -> Result<NaiveDateTime> {
         get_json(
@@ -56,70 +91,240 @@ pub trait ClientTrait: 'static + Send + Sync {
         .await
     }
 
-    async fn get_urls(&self, node: &Node, filter: url::Filter) -> Result<Vec<Url>> {
-        let body = self
-            .post(node, "/urls/filter")
-            .json(&filter)
-            .send()
-            .await
-            .context("error posting to /urls/filter")?
-            .bytes()
-            .await?;
-
-        rmp_serde::from_slice(&body).context("could not convert bytes to Vec<Url>")
-    }
-
-    async fn insert_urls(&self, node: &Node, url: UrlInsert) -> Result<()> {
+    /// Send the insert in chunks, see [`UrlInsert::into_chunks`]. The upload starts at the chunk
+    /// the node expects next, so sending the insert again with the same `upload_id` skips the
+    /// chunks the node already stored. A failed chunk is retried by [`request::send`] alone.
+    async fn insert_urls(&self, node: &Node, url: UrlInsert, upload_id: &str) -> Result<()> {
         log::debug!(
             "trying to send urls with length {} to {:?}",
             url.urls.len(),
             node
         );
-        self.post(node, "/urls/insert")
-            .body(rmp_serde::to_vec(&url)?)
-            .send()
-            .await
-            .context("error posting to /urls/insert")?;
+        let chunks = url.into_chunks(upload_id);
+        let next = self.upload_cursor(node, upload_id).await? as usize;
+        for chunk in chunks.iter().skip(next) {
+            self.insert_chunk(node, chunk).await?;
+        }
         Ok(())
     }
 
-    async fn all_browsers(&self, node: &Node, name: &str) -> Result<Vec<Browser>> {
-        get_json(self, node, "/provider/browsers", Some(&[("name", name)])).await
+    /// The node checks the cursor, stores the chunk and advances the cursor in one transaction,
+    /// and skips a chunk which was already stored, so sending it again is safe.
+    async fn insert_chunk(&self, node: &Node, chunk: &UrlInsert) -> Result<()> {
+        request::send(
+            self,
+            node,
+            self.post(node, "/urls/insert")
+                .body(rmp_serde::to_vec(chunk)?),
+            "/urls/insert",
+            Call::Idempotent,
+        )
+        .await?;
+        Ok(())
     }
 
-    async fn provider_info(&self, node: &Node) -> Result<Vec<Provider>> {
-        get_json(self, node, "/provider/info", None).await
+    async fn upload_cursor(&self, node: &Node, upload_id: &str) -> Result<i32> {
+        get_json(
+            self,
+            node,
+            "/urls/insert/cursor",
+            Some(&[("upload_id", upload_id)]),
+        )
+        .await
     }
 
     async fn set_tag(&self, node: &Node, tag_setter: &UrlTagSetter) -> Result<()> {
-        self.post(node, "/urls/tag")
-            .json(&tag_setter)
-            .send()
-            .await
-            .context("error posting to /urls/tag")?;
+        request::send(
+            self,
+            node,
+            self.post(node, "/urls/tag").json(&tag_setter),
+            "/urls/tag",
+            Call::Idempotent,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn set_privacy(&self, node: &Node, privacy_setter: &UrlPrivacySetter) -> Result<()> {
+        request::send(
+            self,
+            node,
+            self.post(node, "/urls/privacy").json(&privacy_setter),
+            "/urls/privacy",
+            Call::Idempotent,
+        )
+        .await?;
         Ok(())
     }
 
+    async fn backfill_privacy(&self, node: &Node) -> Result<u64> {
+        request::json(
+            self,
+            node,
+            self.post(node, "/urls/privacy/backfill"),
+            "/urls/privacy/backfill",
+            Call::Job,
+        )
+        .await
+    }
+
+    async fn sanitize_urls(&self, node: &Node) -> Result<u64> {
+        request::json(
+            self,
+            node,
+            self.post(node, "/urls/sanitize"),
+            "/urls/sanitize",
+            Call::Job,
+        )
+        .await
+    }
+
+    async fn merge_duplicate_urls(&self, node: &Node) -> Result<u64> {
+        request::json(
+            self,
+            node,
+            self.post(node, "/urls/merge_duplicates"),
+            "/urls/merge_duplicates",
+            Call::Job,
+        )
+        .await
+    }
+
+    async fn top_hosts(&self, node: &Node, filter: &url::Filter) -> Result<Vec<HostSummary>> {
+        request::json(
+            self,
+            node,
+            self.post(node, "/hosts/top").json(filter),
+            "/hosts/top",
+            Call::Read,
+        )
+        .await
+    }
+
+    async fn host_stats(
+        &self,
+        node: &Node,
+        host: &str,
+        period: Period,
+        filter: &url::Filter,
+    ) -> Result<Option<HostStats>> {
+        found(
+            request::json(
+                self,
+                node,
+                self.post(node, "/hosts/stats")
+                    .query(&[("host", host), ("period", &period.to_string())])
+                    .json(filter),
+                "/hosts/stats",
+                Call::Read,
+            )
+            .await,
+        )
+    }
+
+    async fn rebuild_sessions(&self, node: &Node, idle: Option<i64>) -> Result<usize> {
+        let req = self.post(node, "/sessions/rebuild");
+        let req = if let Some(idle) = idle {
+            req.query(&[("idle", idle)])
+        } else {
+            req
+        };
+        request::json(self, node, req, "/sessions/rebuild", Call::Job).await
+    }
+
+    async fn search_sessions(
+        &self,
+        node: &Node,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<BrowsingSession>> {
+        get_json(
+            self,
+            node,
+            "/sessions/search",
+            Some(&[("query", query), ("limit", &limit.to_string())]),
+        )
+        .await
+    }
+
+    async fn session_urls(&self, node: &Node, id: i32) -> Result<Vec<String>> {
+        get_json(self, node, &format!("/sessions/{}/urls", id), None).await
+    }
+
+    async fn tag_session(&self, node: &Node, id: i32, tag: url::UrlTag) -> Result<u64> {
+        let end_point = format!("/sessions/{}/tag", id);
+        request::json(
+            self,
+            node,
+            self.post(node, &end_point).json(&tag),
+            &end_point,
+            Call::Idempotent,
+        )
+        .await
+    }
+
+    async fn navigation(&self, node: &Node, url: &str, depth: i32) -> Result<Option<Navigation>> {
+        found(
+            request::json(
+                self,
+                node,
+                self.get(node, "/urls/navigation")
+                    .query(&[("url", url), ("depth", &depth.to_string())]),
+                "/urls/navigation",
+                Call::Read,
+            )
+            .await,
+        )
+    }
+
+    async fn backfill_search_queries(&self, node: &Node) -> Result<u64> {
+        request::json(
+            self,
+            node,
+            self.post(node, "/queries/backfill"),
+            "/queries/backfill",
+            Call::Job,
+        )
+        .await
+    }
+
+    async fn search_queries(
+        &self,
+        node: &Node,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<url::QueryLogEntry>> {
+        get_json(
+            self,
+            node,
+            "/queries/search",
+            Some(&[("query", query), ("limit", &limit.to_string())]),
+        )
+        .await
+    }
+
+    async fn queries_of_url(&self, node: &Node, url: &str) -> Result<Vec<url::QueryLogEntry>> {
+        get_json(self, node, "/urls/queries", Some(&[("url", url)])).await
+    }
+
     async fn get_all_tags(&self, node: &Node) -> Result<url::Tags> {
-        self.get(node, "/urls/tags")
-            .send()
-            .await
-            .context("error getting to /urls/tags")?
-            .json::<url::Tags>()
-            .await
-            .context("error converting body to url::Tags")
+        get_json(self, node, "/urls/tags", None).await
     }
 
     async fn get_tag_log(&self, node: &Node) -> Result<tag::History> {
         get_json(self, node, "/memory/tag_log", None).await
     }
 
+    /// the node merges the log into its own, so sending it again is safe
     async fn update_tag_log(&self, node: &Node, log: tag::History) -> Result<()> {
-        self.post(node, "/memory/tag_log")
-            .json(&log)
-            .send()
-            .await
-            .context("error posting to /memory/tag_log")?;
+        request::send(
+            self,
+            node,
+            self.post(node, "/memory/tag_log").json(&log),
+            "/memory/tag_log",
+            Call::Idempotent,
+        )
+        .await?;
         Ok(())
     }
 
@@ -139,64 +344,237 @@ pub trait ClientTrait: 'static + Send + Sync {
         Ok(())
     }
 
-    async fn sync_urls(&self, host: &Node, remote: &Node) -> Result<()> {
-        info!("start to sync urls between {:?} and {:?}", host, remote);
-        use std::cmp::Ordering;
-        let unix = NaiveDateTime::from_timestamp(0, 0);
-        // (host import time, remote import time)
-        let mut hm = std::collections::HashMap::<String, (NaiveDateTime, NaiveDateTime)>::new();
-
-        log::debug!("getting provider info of host {:?}", host);
-        // insert host to hashmap
-        for Provider {
-            name,
-            last_import_time,
-        } in self.provider_info(host).await?
-        {
-            hm.insert(name, (last_import_time, unix));
+    async fn sync_node_id(&self, node: &Node) -> Result<String> {
+        get_json(self, node, "/sync/id", None).await
+    }
+
+    async fn sync_acked(&self, node: &Node, node_id: &str) -> Result<i64> {
+        get_json(self, node, "/sync/acked", Some(&[("node_id", node_id)])).await
+    }
+
+    async fn sync_versions(&self, node: &Node) -> Result<Vec<OriginVersion>> {
+        get_json(self, node, "/sync/versions", None).await
+    }
+
+    async fn record_sync_versions(&self, node: &Node, versions: &[OriginVersion]) -> Result<()> {
+        request::send(
+            self,
+            node,
+            self.post(node, "/sync/versions").json(versions),
+            "/sync/versions",
+            Call::Idempotent,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn sync_changes(
+        &self,
+        node: &Node,
+        since: i64,
+        known: &[OriginVersion],
+    ) -> Result<ChangeSet> {
+        request::msgpack(
+            self,
+            node,
+            self.post(node, "/sync/changes")
+                .query(&[("since", since)])
+                .json(known),
+            "/sync/changes",
+            Call::Read,
+        )
+        .await
+    }
+
+    /// the changes are merged by their versions, so applying them again is safe
+    async fn sync_apply(&self, node: &Node, changes: &ChangeSet) -> Result<u64> {
+        request::json(
+            self,
+            node,
+            self.post(node, "/sync/apply")
+                .body(rmp_serde::to_vec(changes)?),
+            "/sync/apply",
+            Call::Idempotent,
+        )
+        .await
+    }
+
+    /// Apply the changes of `from` which `to` has neither acknowledged nor knows from another
+    /// node, page by page, the moved visits are counted in `run`. Once all pages are applied,
+    /// `to` has every change `from` had at the start.
+    async fn pull_changes(
+        &self,
+        from: &Node,
+        to: &Node,
+        run: &mut SyncRun,
+        pulled: bool,
+    ) -> Result<u64> {
+        let node_id = self.sync_node_id(from).await?;
+        let mut since = self.sync_acked(to, &node_id).await?;
+        let (versions, known) =
+            futures::try_join!(self.sync_versions(from), self.sync_versions(to))?;
+        let mut applied = 0;
+        loop {
+            let changes = self.sync_changes(from, since, &known).await?;
+            if changes.node_id != node_id {
+                anyhow::bail!("node id of {:?} changed during sync", from);
+            }
+            if !changes.is_empty() || changes.last_seq > since {
+                applied += self.sync_apply(to, &changes).await?;
+                run.count(&changes.visits, pulled);
+            }
+            if !changes.more || changes.last_seq <= since {
+                break;
+            }
+            since = changes.last_seq;
         }
+        self.record_sync_versions(to, &versions).await?;
+        Ok(applied)
+    }
+
+    async fn record_sync_run(&self, node: &Node, run: &SyncRun) -> Result<()> {
+        request::send(
+            self,
+            node,
+            self.post(node, "/sync/runs").json(run),
+            "/sync/runs",
+            Call::Write,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn sync_status(&self, node: &Node) -> Result<Vec<PeerStatus>> {
+        get_json(self, node, "/sync/status", None).await
+    }
 
-        log::debug!("getting provider info of remote {:?}", remote);
-        // update remote import time to hashmap
-        for Provider {
-            name,
-            last_import_time,
-        } in self.provider_info(remote).await?
-        {
-            let ent = hm.entry(name).or_insert((unix, unix));
-            ent.1 = last_import_time;
+    async fn sync_history(
+        &self,
+        node: &Node,
+        peer: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<SyncRun>> {
+        let limit = limit.to_string();
+        let mut params = vec![("limit", limit.as_str())];
+        if let Some(peer) = peer {
+            params.push(("peer", peer));
         }
+        get_json(self, node, "/sync/history", Some(&params)).await
+    }
+
+    async fn sync_tree(&self, node: &Node) -> Result<Vec<TreeNode>> {
+        get_json(self, node, "/sync/tree", None).await
+    }
+
+    async fn sync_buckets(
+        &self,
+        node: &Node,
+        provider: &str,
+        browser: Browser,
+    ) -> Result<Vec<BucketHash>> {
+        let browser = browser.to_string();
+        get_json(
+            self,
+            node,
+            "/sync/tree/buckets",
+            Some(&[("provider", provider), ("browser", &browser)]),
+        )
+        .await
+    }
 
-        for (name, (host_time, remote_time)) in hm.into_iter() {
-            let (since, older, newer) = match host_time.cmp(&remote_time) {
-                Ordering::Less => (host_time, host, remote),
-                Ordering::Equal => continue,
-                Ordering::Greater => (remote_time, remote, host),
+    async fn sync_bucket_rows(&self, node: &Node, d: &Divergence) -> Result<ChangeSet> {
+        let mut params = vec![
+            ("provider", d.provider_name.clone()),
+            ("browser", d.browser.to_string()),
+        ];
+        params.extend(d.buckets.iter().map(|b| ("buckets", b.to_string())));
+        request::msgpack(
+            self,
+            node,
+            self.get(node, "/sync/tree/rows").query(&params),
+            "/sync/tree/rows",
+            Call::Read,
+        )
+        .await
+    }
+
+    async fn sync_repair(&self, node: &Node, rows: &ChangeSet) -> Result<u64> {
+        request::json(
+            self,
+            node,
+            self.post(node, "/sync/repair")
+                .body(rmp_serde::to_vec(rows)?),
+            "/sync/repair",
+            Call::Idempotent,
+        )
+        .await
+    }
+
+    /// compare the hash trees of both nodes, only the buckets below differing hashes are fetched
+    async fn check_divergence(&self, host: &Node, remote: &Node) -> Result<Vec<Divergence>> {
+        let (ours, theirs) = futures::try_join!(self.sync_tree(host), self.sync_tree(remote))?;
+        let key = |nodes: Vec<TreeNode>| -> Vec<_> {
+            nodes
+                .into_iter()
+                .map(|n| ((n.provider_name, n.browser.to_string()), n.hash))
+                .collect()
+        };
+        let mut res = vec![];
+        for (provider, browser) in tree::diff(&key(ours), &key(theirs)) {
+            let browser: Browser = browser.parse()?;
+            let (ours, theirs) = futures::try_join!(
+                self.sync_buckets(host, &provider, browser),
+                self.sync_buckets(remote, &provider, browser)
+            )?;
+            let key = |buckets: Vec<BucketHash>| -> Vec<_> {
+                buckets.into_iter().map(|b| (b.bucket, b.hash)).collect()
             };
-            log::info!(
-                "trying to sync urls since {} from {:?} to {:?}",
-                since,
-                newer,
-                older
-            );
-            let browsers = self.all_browsers(newer, &name).await?;
-            for browser in browsers {
-                let urls = self
-                    .get_urls(
-                        newer,
-                        url::Filter::bulk_urls(name.to_owned(), browser, since),
-                    )
-                    .await?;
-                let insert = UrlInsert {
-                    name: name.to_owned(),
-                    browser,
-                    urls,
-                    last_import_time: None,
-                };
-                self.insert_urls(older, insert).await?;
-            }
+            res.push(Divergence {
+                provider_name: provider,
+                browser,
+                buckets: tree::diff(&key(ours), &key(theirs)),
+            });
+        }
+        Ok(res)
+    }
+
+    /// reconcile the rows of the differing buckets and write them back to both nodes, the urls
+    /// removed on one side are merged on the other instead of being inserted again
+    async fn repair_divergence(
+        &self,
+        host: &Node,
+        remote: &Node,
+        divergences: &[Divergence],
+    ) -> Result<u64> {
+        let mut repaired = 0;
+        for d in divergences {
+            let (ours, theirs) = futures::try_join!(
+                self.sync_bucket_rows(host, d),
+                self.sync_bucket_rows(remote, d)
+            )?;
+            let rows = tree::reconcile(ours, theirs);
+            let (a, b) = futures::try_join!(
+                self.sync_repair(host, &rows),
+                self.sync_repair(remote, &rows)
+            )?;
+            repaired += a.max(b);
         }
+        Ok(repaired)
+    }
 
+    async fn sync_urls(&self, host: &Node, remote: &Node, run: &mut SyncRun) -> Result<()> {
+        info!("start to sync urls between {:?} and {:?}", host, remote);
+        let pulled = self.pull_changes(remote, host, run, true).await?;
+        let pushed = self.pull_changes(host, remote, run, false).await?;
+        info!("pulled {} and pushed {} changes", pulled, pushed);
+        let divergences = self.check_divergence(host, remote).await?;
+        if !divergences.is_empty() {
+            for d in &divergences {
+                log::warn!("{:?} and {:?} differ in {}", host, remote, d);
+            }
+            let n = self.repair_divergence(host, remote, &divergences).await?;
+            info!("repaired {} rows", n);
+        }
         Ok(())
     }
 }
@@ -1,6 +1,13 @@
 mod apikey_client;
 mod client_trait;
-use crate::{node::Node, url::UrlTag};
+mod request;
+use crate::{
+    navigation::Navigation,
+    session::BrowsingSession,
+    stats::{HostStats, HostSummary, Period},
+    sync::{tree::Divergence, PeerStatus, SyncRun},
+    url::{Filter, QueryLogEntry, UrlPrivacy, UrlTag},
+};
 
 use chrono::NaiveDateTime;
 
@@ -9,27 +16,77 @@ use anyhow::Result;
 
 use client_trait::ClientTrait;
 
-use super::resp::{UrlInsert, UrlTagSetter};
+use super::resp::{UrlInsert, UrlPrivacySetter, UrlTagSetter};
 
 pub struct Client(Box<dyn ClientTrait>);
 
 impl Client {
-    pub async fn insert_urls(&self, name: &node::Name, url: UrlInsert) -> Result<()> {
-        self.0.insert_urls(name.as_ref(), url).await
+    pub async fn insert_urls(
+        &self,
+        name: &node::Name,
+        url: UrlInsert,
+        upload_id: &str,
+    ) -> Result<()> {
+        self.0.insert_urls(&name.node(), url, upload_id).await
+    }
+
+    /// the endpoint of the node which answers first, see [`node::Node::endpoints`]
+    pub async fn probe(&self, name: &node::Name) -> Result<node::Endpoint> {
+        let node = name.node();
+        let i = self.0.probe(&node).await?;
+        Ok(node.endpoints[i].clone())
+    }
+
+    pub async fn sync_tags(&self, host: &node::Name, remote: &node::Name) -> Result<()> {
+        self.0.sync_tag_logs(&host.node(), &remote.node()).await
+    }
+
+    /// the buckets which differ between both nodes
+    pub async fn check_sync(
+        &self,
+        host: &node::Name,
+        remote: &node::Name,
+    ) -> Result<Vec<Divergence>> {
+        self.0.check_divergence(&host.node(), &remote.node()).await
     }
 
-    pub async fn sync_tags<N: AsRef<Node>>(&self, host: &N, remote: &N) -> Result<()> {
-        self.0.sync_tag_logs(host.as_ref(), remote.as_ref()).await
+    /// reconcile the differing buckets, returns the number of repaired rows
+    pub async fn repair_sync(
+        &self,
+        host: &node::Name,
+        remote: &node::Name,
+        divergences: &[Divergence],
+    ) -> Result<u64> {
+        self.0
+            .repair_divergence(&host.node(), &remote.node(), divergences)
+            .await
     }
 
-    pub async fn sync_urls<N: AsRef<Node>>(&self, host: &N, remote: &N) -> Result<()> {
-        self.0.sync_urls(host.as_ref(), remote.as_ref()).await
+    /// sync the urls of both nodes, the run is recorded in the sync history of the host
+    pub async fn sync_all(&self, host: &node::Name, remote: &node::Name) -> Result<SyncRun> {
+        let mut run = SyncRun::start(remote.to_string());
+        let res = self
+            .0
+            .sync_urls(&host.node(), &remote.node(), &mut run)
+            .await;
+        run.finish(res.as_ref().err());
+        if let Err(e) = self.0.record_sync_run(&host.node(), &run).await {
+            log::warn!("could not record the sync run with {}: {:?}", remote, e);
+        }
+        res.map(|_| run)
     }
 
-    pub async fn sync_all<N: AsRef<Node>>(&self, host: &N, remote: &N) -> Result<()> {
-        self.sync_urls(host, remote).await?;
-        // futures::try_join!(self.sync_urls(host, remote), self.sync_tags(host, remote))?;
-        Ok(())
+    pub async fn sync_status(&self, node: &node::Name) -> Result<Vec<PeerStatus>> {
+        self.0.sync_status(&node.node()).await
+    }
+
+    pub async fn sync_history(
+        &self,
+        node: &node::Name,
+        peer: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<SyncRun>> {
+        self.0.sync_history(&node.node(), peer, limit).await
     }
 
     pub async fn last_import_time(
@@ -37,16 +94,100 @@ impl Client {
         node: &node::Name,
         provider_name: &str,
     ) -> Result<NaiveDateTime> {
-        self.0.last_import_time(node.as_ref(), provider_name).await
+        self.0.last_import_time(&node.node(), provider_name).await
     }
 
     pub async fn set_tag(&self, node: &node::Name, tag: UrlTag, urls: Vec<String>) -> Result<()> {
         self.0
-            .set_tag(node.as_ref(), &UrlTagSetter::new(tag, urls))
+            .set_tag(&node.node(), &UrlTagSetter::new(tag, urls))
+            .await
+    }
+
+    pub async fn set_privacy(
+        &self,
+        node: &node::Name,
+        privacy: UrlPrivacy,
+        urls: Vec<String>,
+    ) -> Result<()> {
+        self.0
+            .set_privacy(&node.node(), &UrlPrivacySetter::new(privacy, urls))
             .await
     }
 
+    pub async fn backfill_privacy(&self, node: &node::Name) -> Result<u64> {
+        self.0.backfill_privacy(&node.node()).await
+    }
+
+    pub async fn sanitize_urls(&self, node: &node::Name) -> Result<u64> {
+        self.0.sanitize_urls(&node.node()).await
+    }
+
+    pub async fn merge_duplicate_urls(&self, node: &node::Name) -> Result<u64> {
+        self.0.merge_duplicate_urls(&node.node()).await
+    }
+
+    pub async fn top_hosts(&self, node: &node::Name, filter: &Filter) -> Result<Vec<HostSummary>> {
+        self.0.top_hosts(&node.node(), filter).await
+    }
+
+    pub async fn host_stats(
+        &self,
+        node: &node::Name,
+        host: &str,
+        period: Period,
+        filter: &Filter,
+    ) -> Result<Option<HostStats>> {
+        self.0.host_stats(&node.node(), host, period, filter).await
+    }
+
+    pub async fn rebuild_sessions(&self, node: &node::Name, idle: Option<i64>) -> Result<usize> {
+        self.0.rebuild_sessions(&node.node(), idle).await
+    }
+
+    pub async fn search_sessions(
+        &self,
+        node: &node::Name,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<BrowsingSession>> {
+        self.0.search_sessions(&node.node(), query, limit).await
+    }
+
+    pub async fn session_urls(&self, node: &node::Name, id: i32) -> Result<Vec<String>> {
+        self.0.session_urls(&node.node(), id).await
+    }
+
+    pub async fn tag_session(&self, node: &node::Name, id: i32, tag: UrlTag) -> Result<u64> {
+        self.0.tag_session(&node.node(), id, tag).await
+    }
+
+    pub async fn navigation(
+        &self,
+        node: &node::Name,
+        url: &str,
+        depth: i32,
+    ) -> Result<Option<Navigation>> {
+        self.0.navigation(&node.node(), url, depth).await
+    }
+
+    pub async fn backfill_search_queries(&self, node: &node::Name) -> Result<u64> {
+        self.0.backfill_search_queries(&node.node()).await
+    }
+
+    pub async fn search_queries(
+        &self,
+        node: &node::Name,
+        query: &str,
+        limit: i64,
+    ) -> Result<Vec<QueryLogEntry>> {
+        self.0.search_queries(&node.node(), query, limit).await
+    }
+
+    pub async fn queries_of_url(&self, node: &node::Name, url: &str) -> Result<Vec<QueryLogEntry>> {
+        self.0.queries_of_url(&node.node(), url).await
+    }
+
     pub async fn get_all_tags(&self, node: &node::Name) -> Result<url::Tags> {
-        self.0.get_all_tags(node.as_ref()).await
+        self.0.get_all_tags(&node.node()).await
     }
 }
@@ -0,0 +1,403 @@
+//! Every request of [`ClientTrait`] is sent through [`send`], which sends it to the endpoint of
+//! the node which answered last, gives it a timeout according to its [`Call`] unless it has one
+//! already, retries it with a jittered backoff if it is safe to repeat, and decodes a non-2xx
+//! response into a [`WebmanError`].
+use super::client_trait::ClientTrait;
+use crate::{
+    error::WebmanError,
+    node::{Endpoint, Node},
+};
+use anyhow::{Context, Result};
+use rand::Rng;
+use reqwest::{RequestBuilder, Response, StatusCode, Url};
+use serde::Deserialize;
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+/// how often a request is sent at most
+const MAX_ATTEMPTS: u32 = 4;
+const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+/// the longest body kept as message if it is not a [`WebmanError`]
+const MAX_MESSAGE_LEN: usize = 500;
+
+/// How a request may be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Call {
+    /// reads nothing but data, it is retried
+    Read,
+    /// writes data, but sending it twice has the same effect as sending it once, it is retried
+    Idempotent,
+    /// writes data and must not be repeated
+    Write,
+    /// starts a job on the node which goes over all its urls, it may take minutes
+    Job,
+}
+
+impl Call {
+    pub fn timeout(self) -> Duration {
+        match self {
+            Call::Read | Call::Write => Duration::from_secs(30),
+            Call::Idempotent => Duration::from_secs(2 * 60),
+            Call::Job => Duration::from_secs(15 * 60),
+        }
+    }
+
+    fn retries(self) -> bool {
+        matches!(self, Call::Read | Call::Idempotent)
+    }
+}
+
+/// The index of the endpoint of every node which answered the last probe.
+#[derive(Debug, Default)]
+pub struct Failover(Mutex<HashMap<Node, usize>>);
+
+impl Failover {
+    /// None if the node was not probed yet
+    pub fn get(&self, node: &Node) -> Option<usize> {
+        self.0.lock().unwrap().get(node).copied()
+    }
+
+    pub fn set(&self, node: &Node, endpoint: usize) {
+        self.0.lock().unwrap().insert(node.clone(), endpoint);
+    }
+}
+
+/// the url with the scheme, host and port of the endpoint
+fn rebase(url: &Url, endpoint: &Endpoint) -> Result<Url> {
+    let mut res = format!("{}{}", endpoint.base_url(), url.path());
+    if let Some(q) = url.query() {
+        res.push('?');
+        res.push_str(q);
+    }
+    Url::parse(&res).with_context(|| format!("invalid url {}", res))
+}
+
+/// whether the node may answer differently if the request is sent again
+fn is_transient(status: StatusCode) -> bool {
+    status.is_server_error() && status != StatusCode::NOT_IMPLEMENTED
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+/// a random delay before the next attempt, up to twice as long as before the previous one
+fn backoff(attempt: u32) -> Duration {
+    let max = FIRST_BACKOFF
+        .saturating_mul(1 << attempt.min(16))
+        .min(MAX_BACKOFF);
+    rand::thread_rng().gen_range(max / 2..=max)
+}
+
+/// the error in the json body, or one derived from the status if the body is something else
+fn decode(status: StatusCode, body: &str) -> WebmanError {
+    serde_json::from_str(body).unwrap_or_else(|_| {
+        let mut message = body.trim();
+        if message.len() > MAX_MESSAGE_LEN {
+            let mut end = MAX_MESSAGE_LEN;
+            while !message.is_char_boundary(end) {
+                end -= 1;
+            }
+            message = &message[..end];
+        }
+        let message = if message.is_empty() {
+            status.to_string()
+        } else {
+            message.to_owned()
+        };
+        WebmanError::from_status(status.as_u16(), message)
+    })
+}
+
+async fn into_error(end_point: &str, resp: Response) -> anyhow::Error {
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    anyhow::Error::new(decode(status, &body)).context(format!("{} replied {}", end_point, status))
+}
+
+/// Send the request, and retry it on connection errors, timeouts and transient statuses if the
+/// call allows it. If the node has several endpoints, they are probed before the first request
+/// and whenever the chosen one cannot be reached. The error of a non-2xx response is a
+/// [`WebmanError`], see [`WebmanError::of`].
+pub async fn send<C>(
+    c: &C,
+    node: &Node,
+    req: RequestBuilder,
+    end_point: &str,
+    call: Call,
+) -> Result<Response>
+where
+    C: ClientTrait + ?Sized,
+{
+    let multiple = node.endpoints.len() > 1;
+    if multiple && c.failover().get(node).is_none() {
+        // the first endpoint is used if none answers, so the request fails with its error
+        c.probe(node).await.ok();
+    }
+    let mut req = req.build()?;
+    req.timeout_mut().get_or_insert(call.timeout());
+    let (mut attempt, mut failovers) = (0, 0);
+    loop {
+        attempt += 1;
+        let next = req.try_clone();
+        let endpoint = &node.endpoints[c.failover().get(node).unwrap_or_default()];
+        *req.url_mut() = rebase(req.url(), endpoint)?;
+        let err: anyhow::Error = match c.http().execute(req).await {
+            Ok(resp) if resp.status().is_success() => return Ok(resp),
+            Ok(resp) => {
+                let transient = is_transient(resp.status());
+                let e = into_error(end_point, resp).await;
+                if !transient {
+                    return Err(e);
+                }
+                e
+            }
+            Err(e) => {
+                let (connect, timeout) = (e.is_connect(), e.is_timeout());
+                let err = anyhow::Error::new(e).context(format!(
+                    "error sending request to {} at {}",
+                    end_point, endpoint
+                ));
+                if (connect || timeout) && multiple && failovers < node.endpoints.len() {
+                    failovers += 1;
+                    let before = c.failover().get(node);
+                    let switched = c.probe(node).await.is_ok() && c.failover().get(node) != before;
+                    // a request which could not connect never reached the node, so it is safe to
+                    // send it to another endpoint, a timed out one is only sent again if retried
+                    if switched && connect {
+                        if let Some(next) = next {
+                            log::warn!("{:?}, failing over", err);
+                            req = next;
+                            continue;
+                        }
+                    }
+                }
+                err
+            }
+        };
+        match next {
+            Some(next) if call.retries() && attempt < MAX_ATTEMPTS => {
+                let delay = backoff(attempt);
+                log::warn!(
+                    "attempt {} of {} failed, retrying in {:?}: {:?}",
+                    attempt,
+                    end_point,
+                    delay,
+                    err
+                );
+                tokio::time::sleep(delay).await;
+                req = next;
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+/// send the request and convert the json body of the response
+pub async fn json<C, T>(
+    c: &C,
+    node: &Node,
+    req: RequestBuilder,
+    end_point: &str,
+    call: Call,
+) -> Result<T>
+where
+    C: ClientTrait + ?Sized,
+    for<'de> T: Deserialize<'de>,
+{
+    send(c, node, req, end_point, call)
+        .await?
+        .json()
+        .await
+        .with_context(|| {
+            format!(
+                "error converting body of type {} via endpoint {}",
+                std::any::type_name::<T>(),
+                end_point
+            )
+        })
+}
+
+/// send the request and convert the msgpack body of the response
+pub async fn msgpack<C, T>(
+    c: &C,
+    node: &Node,
+    req: RequestBuilder,
+    end_point: &str,
+    call: Call,
+) -> Result<T>
+where
+    C: ClientTrait + ?Sized,
+    for<'de> T: Deserialize<'de>,
+{
+    let body = send(c, node, req, end_point, call).await?.bytes().await?;
+    rmp_serde::from_slice(&body).with_context(|| {
+        format!(
+            "could not convert bytes to {} via endpoint {}",
+            std::any::type_name::<T>(),
+            end_point
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{node::Host, web::client::Client};
+    use std::{net::Ipv4Addr, sync::Arc};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// the requests an endpoint received as method and path
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    /// An endpoint on localhost which answers every request with 200, or never if it `hangs`.
+    async fn endpoint(hangs: bool) -> (Endpoint, Log) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let log = Log::default();
+        let received = log.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let received = received.clone();
+                tokio::spawn(async move {
+                    let mut buf = vec![0; 4096];
+                    let n = stream.read(&mut buf).await.unwrap_or_default();
+                    let head = String::from_utf8_lossy(&buf[..n]);
+                    let line = head.split(" HTTP/").next().unwrap_or_default().to_owned();
+                    received.lock().unwrap().push(line);
+                    if hangs {
+                        tokio::time::sleep(Duration::from_secs(60)).await;
+                    }
+                    let resp =
+                        "HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok";
+                    stream.write_all(resp.as_bytes()).await.ok();
+                });
+            }
+        });
+        (local(port), log)
+    }
+
+    /// an endpoint on localhost which refuses connections
+    fn dead() -> Endpoint {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        local(listener.local_addr().unwrap().port())
+    }
+
+    fn local(port: u16) -> Endpoint {
+        Endpoint {
+            host: Host::Ipv4(Ipv4Addr::LOCALHOST),
+            tls: false,
+            port: Some(port),
+            timeout_ms: Some(200),
+        }
+    }
+
+    fn requests(log: &Log, path: &str) -> usize {
+        log.lock()
+            .unwrap()
+            .iter()
+            .filter(|l| l.ends_with(path))
+            .count()
+    }
+
+    #[tokio::test]
+    async fn fails_over_on_connect_error() {
+        let (live, log) = endpoint(false).await;
+        let node = Node {
+            endpoints: vec![dead(), live],
+        };
+        let c = Client::with_apikey("key");
+        // the dead endpoint answered the last probe
+        c.0.failover().set(&node, 0);
+        let req = c.0.post(&node, "/urls/tag").body("tag");
+        send(&*c.0, &node, req, "/urls/tag", Call::Write)
+            .await
+            .unwrap();
+        assert_eq!(c.0.failover().get(&node), Some(1));
+        assert_eq!(requests(&log, "/api/urls/tag"), 1);
+
+        // the next request goes to the remembered endpoint without a probe
+        let pings = requests(&log, "/auth/ping");
+        let req = c.0.post(&node, "/urls/tag").body("tag");
+        send(&*c.0, &node, req, "/urls/tag", Call::Write)
+            .await
+            .unwrap();
+        assert_eq!(requests(&log, "/api/urls/tag"), 2);
+        assert_eq!(requests(&log, "/auth/ping"), pings);
+    }
+
+    #[tokio::test]
+    async fn timed_out_write_is_not_replayed() {
+        let (slow, slow_log) = endpoint(true).await;
+        let (live, log) = endpoint(false).await;
+        let node = Node {
+            endpoints: vec![slow, live],
+        };
+        let c = Client::with_apikey("key");
+        c.0.failover().set(&node, 0);
+        let req =
+            c.0.post(&node, "/urls/tag")
+                .body("tag")
+                .timeout(Duration::from_millis(200));
+        let err = send(&*c.0, &node, req, "/urls/tag", Call::Write)
+            .await
+            .unwrap_err();
+        assert_eq!(WebmanError::of(&err).status(), 503);
+        // the write may have reached the slow endpoint, so it is not sent again
+        assert_eq!(requests(&slow_log, "/api/urls/tag"), 1);
+        assert_eq!(requests(&log, "/api/urls/tag"), 0);
+        // but the next request goes to the endpoint which answered the probe
+        assert_eq!(c.0.failover().get(&node), Some(1));
+    }
+
+    #[test]
+    fn backoff_is_jittered_and_capped() {
+        let ms = Duration::from_millis;
+        for _ in 0..100 {
+            let d = backoff(1);
+            assert!(d >= ms(500) && d <= ms(1000), "{:?} for attempt 1", d);
+            let d = backoff(2);
+            assert!(d >= ms(1000) && d <= ms(2000), "{:?} for attempt 2", d);
+            for attempt in [5, 16, 30] {
+                let d = backoff(attempt);
+                assert!(
+                    d >= ms(5000) && d <= ms(10_000),
+                    "{:?} for attempt {}",
+                    d,
+                    attempt
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn transient_statuses() {
+        assert!(is_transient(StatusCode::BAD_GATEWAY));
+        assert!(is_transient(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(is_transient(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!is_transient(StatusCode::NOT_IMPLEMENTED));
+        assert!(!is_transient(StatusCode::BAD_REQUEST));
+        assert!(!is_transient(StatusCode::CONFLICT));
+    }
+
+    #[test]
+    fn decode_body() {
+        let e = WebmanError::NotFound("no such session".to_owned());
+        let body = serde_json::to_string(&e).unwrap();
+        assert_eq!(decode(StatusCode::NOT_FOUND, &body), e);
+        assert_eq!(
+            decode(StatusCode::BAD_GATEWAY, "<html>bad gateway</html>"),
+            WebmanError::Unavailable("<html>bad gateway</html>".to_owned())
+        );
+        assert_eq!(
+            decode(StatusCode::UNAUTHORIZED, ""),
+            WebmanError::Unauthorized("401 Unauthorized".to_owned())
+        );
+        assert_eq!(
+            decode(StatusCode::OK, &"x".repeat(1000)).message().len(),
+            500
+        );
+    }
+}
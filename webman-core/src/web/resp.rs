@@ -1,9 +1,13 @@
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 use crate::{
     browser::Browser,
-    url::{Url, UrlTag},
+    download::Download,
+    favicon::Favicon,
+    navigation::NavigationEdge,
+    url::{SearchTerm, Url, UrlDuration, UrlPrivacy, UrlTag},
 };
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
@@ -11,12 +15,169 @@ pub struct Auth {
     pub api_key: String,
 }
 
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct Unlock {
+    pub unlock_key: String,
+}
+
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct UrlInsert {
     pub name: String,
     pub browser: Browser,
     pub urls: Vec<Url>,
     pub last_import_time: Option<NaiveDateTime>,
+    /// the links followed between the urls
+    #[serde(default)]
+    pub edges: Vec<NavigationEdge>,
+    /// the search terms recorded by the browser
+    #[serde(default)]
+    pub search_terms: Vec<SearchTerm>,
+    /// the redirects from transient pages to the pages they redirected to
+    #[serde(default)]
+    pub redirects: Vec<NavigationEdge>,
+    /// the time spent on the urls since the last import
+    #[serde(default)]
+    pub durations: Vec<UrlDuration>,
+    /// the best favicon of every host of `urls`
+    #[serde(default)]
+    pub favicons: Vec<Favicon>,
+    /// the downloads since the last import, their source urls are in `urls`
+    #[serde(default)]
+    pub downloads: Vec<Download>,
+    /// the position of this insert in a chunked upload, None if it is sent at once
+    #[serde(default)]
+    pub chunk: Option<Chunk>,
+}
+
+/// the maximal number of rows in one chunk of a [`UrlInsert`]
+pub const CHUNK_ROWS: usize = 5000;
+/// the maximal number of favicons in one chunk, which are up to 64 KiB each
+pub const CHUNK_FAVICONS: usize = 64;
+
+/// A chunk of an upload, the server stores the chunks of an upload in order and only once.
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct Chunk {
+    pub upload_id: String,
+    pub index: i32,
+    pub last: bool,
+}
+
+impl UrlInsert {
+    /// Split the insert into chunks of bounded size. The urls are sent first, each chunk with the
+    /// search terms of its urls, followed by the rest which refers to them. Only the last chunk
+    /// carries the `last_import_time`, so an interrupted upload is mined again on the next import.
+    pub fn into_chunks(self, upload_id: &str) -> Vec<UrlInsert> {
+        let UrlInsert {
+            name,
+            browser,
+            urls,
+            last_import_time,
+            edges,
+            search_terms,
+            redirects,
+            durations,
+            favicons,
+            downloads,
+            chunk: _,
+        } = self;
+        let empty = UrlInsert {
+            name,
+            browser,
+            urls: vec![],
+            last_import_time: None,
+            edges: vec![],
+            search_terms: vec![],
+            redirects: vec![],
+            durations: vec![],
+            favicons: vec![],
+            downloads: vec![],
+            chunk: None,
+        };
+        let mut chunks: Vec<_> = split(urls, CHUNK_ROWS)
+            .into_iter()
+            .map(|urls| UrlInsert {
+                urls,
+                ..empty.clone()
+            })
+            .collect();
+        // a search term goes into the chunk of its url, so that the url exists when it is stored
+        let index: HashMap<String, usize> = chunks
+            .iter()
+            .enumerate()
+            .flat_map(|(i, c)| c.urls.iter().map(move |u| (u.url.clone(), i)))
+            .collect();
+        let search_terms: Vec<_> = search_terms
+            .into_iter()
+            .filter_map(|t| match index.get(&t.url) {
+                Some(&i) => {
+                    chunks[i].search_terms.push(t);
+                    None
+                }
+                None => Some(t),
+            })
+            .collect();
+        for search_terms in split(search_terms, CHUNK_ROWS) {
+            chunks.push(UrlInsert {
+                search_terms,
+                ..empty.clone()
+            });
+        }
+        for edges in split(edges, CHUNK_ROWS) {
+            chunks.push(UrlInsert {
+                edges,
+                ..empty.clone()
+            });
+        }
+        for redirects in split(redirects, CHUNK_ROWS) {
+            chunks.push(UrlInsert {
+                redirects,
+                ..empty.clone()
+            });
+        }
+        for durations in split(durations, CHUNK_ROWS) {
+            chunks.push(UrlInsert {
+                durations,
+                ..empty.clone()
+            });
+        }
+        for favicons in split(favicons, CHUNK_FAVICONS) {
+            chunks.push(UrlInsert {
+                favicons,
+                ..empty.clone()
+            });
+        }
+        for downloads in split(downloads, CHUNK_ROWS) {
+            chunks.push(UrlInsert {
+                downloads,
+                ..empty.clone()
+            });
+        }
+        if chunks.is_empty() {
+            chunks.push(empty);
+        }
+        let n = chunks.len();
+        for (i, c) in chunks.iter_mut().enumerate() {
+            c.chunk = Some(Chunk {
+                upload_id: upload_id.to_owned(),
+                index: i as i32,
+                last: i + 1 == n,
+            });
+        }
+        chunks[n - 1].last_import_time = last_import_time;
+        chunks
+    }
+}
+
+fn split<T>(mut v: Vec<T>, size: usize) -> Vec<Vec<T>> {
+    let mut res = vec![];
+    while v.len() > size {
+        let rest = v.split_off(size);
+        res.push(std::mem::replace(&mut v, rest));
+    }
+    if !v.is_empty() {
+        res.push(v);
+    }
+    res
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
@@ -31,6 +192,18 @@ impl UrlTagSetter {
     }
 }
 
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct UrlPrivacySetter {
+    pub privacy: UrlPrivacy,
+    pub urls: Vec<String>,
+}
+
+impl UrlPrivacySetter {
+    pub fn new(privacy: UrlPrivacy, urls: Vec<String>) -> Self {
+        Self { privacy, urls }
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct FakeUrl {
     pub url: String,
@@ -48,6 +221,55 @@ impl From<FakeUrl> for UrlInsert {
             browser: fu.browser,
             urls: vec![url],
             last_import_time: None,
+            edges: vec![],
+            search_terms: vec![],
+            redirects: vec![],
+            durations: vec![],
+            favicons: vec![],
+            downloads: vec![],
+            chunk: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks() {
+        let url = |i: usize| Url {
+            url: format!("u{}", i),
+            ..Default::default()
+        };
+        let insert = UrlInsert {
+            name: "p".to_owned(),
+            browser: Browser::Firefox,
+            urls: (0..CHUNK_ROWS + 1).map(url).collect(),
+            last_import_time: Some(NaiveDateTime::from_timestamp(1, 0)),
+            edges: vec![],
+            search_terms: vec![SearchTerm {
+                url: format!("u{}", CHUNK_ROWS),
+                term: "t".to_owned(),
+            }],
+            redirects: vec![],
+            durations: vec![],
+            favicons: vec![],
+            downloads: vec![],
+            chunk: None,
+        };
+        let chunks = insert.into_chunks("id");
+        let sizes: Vec<_> = chunks
+            .iter()
+            .map(|c| (c.urls.len(), c.search_terms.len()))
+            .collect();
+        assert_eq!(sizes, vec![(CHUNK_ROWS, 0), (1, 1)]);
+        assert_eq!(chunks[0].last_import_time, None);
+        assert!(chunks[1].last_import_time.is_some());
+        let last: Vec<_> = chunks
+            .iter()
+            .map(|c| c.chunk.as_ref().map(|c| (c.index, c.last)))
+            .collect();
+        assert_eq!(last, vec![Some((0, false)), Some((1, true))]);
+    }
+}
@@ -3,10 +3,16 @@ use figment::Figment;
 use once_cell::sync::OnceCell;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::time::Duration;
-use webman_core::{config, init_fig, node};
+use webman_core::{
+    config, init_fig, node,
+    url::{PrivacyRules, SearchEngines},
+};
 
 pub static HOST: OnceCell<node::Name> = OnceCell::new();
 pub static SYNC_NODES: OnceCell<Vec<SyncNode>> = OnceCell::new();
+pub static PRIVACY_RULES: OnceCell<PrivacyRules> = OnceCell::new();
+pub static SEARCH_ENGINES: OnceCell<SearchEngines> = OnceCell::new();
+pub static UNLOCK: OnceCell<Option<UnlockConfig>> = OnceCell::new();
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct SyncNode {
@@ -15,6 +21,14 @@ pub struct SyncNode {
     pub interval: Duration,
 }
 
+/// the secret to unlock private urls, and how long an unlocked session lasts
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct UnlockConfig {
+    pub key: String,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub duration: Duration,
+}
+
 fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
 where
     D: Deserializer<'de>,
@@ -46,19 +60,61 @@ pub fn rocket_figment() -> Figment {
         })
         .unwrap();
 
+    PRIVACY_RULES
+        .set(match rocket_fig.find_value("privacy") {
+            // an invalid rule should never be silently ignored, otherwise private urls would leak
+            Ok(_) => rocket_fig
+                .extract_inner("privacy")
+                .expect("could not parse privacy rules in [server]"),
+            Err(_) => PrivacyRules::default(),
+        })
+        .unwrap();
+
+    SEARCH_ENGINES
+        .set(match rocket_fig.find_value("search_engines") {
+            Ok(_) => rocket_fig
+                .extract_inner("search_engines")
+                .expect("could not parse search_engines in [server]"),
+            Err(_) => SearchEngines::default(),
+        })
+        .unwrap();
+
+    let unlock: Option<UnlockConfig> = match rocket_fig.find_value("unlock") {
+        Ok(_) => Some(
+            rocket_fig
+                .extract_inner("unlock")
+                .expect("could not parse unlock in [server]"),
+        ),
+        Err(_) => {
+            log::warn!("unlock is not set, private urls can not be searched");
+            None
+        }
+    };
+    if let Some(u) = &unlock {
+        assert_ne!(
+            u.key,
+            config().api_key,
+            "unlock key must be different from api key"
+        );
+    }
+    UNLOCK.set(unlock).unwrap();
+
     HOST.set(node::Name::new(&host_name))
         .expect("could not set HOST");
     let host_node = config().nodes.get(&host_name);
 
-    if let node::Host::Ipv4(ip) = host_node.host {
-        Figment::from(rocket::Config::default())
+    // the server listens on the first ipv4 endpoint, the others may be e.g. a proxy in front of it
+    match host_node.endpoints.iter().find_map(|e| match e.host {
+        node::Host::Ipv4(ip) => Some((ip, e.port)),
+        _ => None,
+    }) {
+        Some((ip, port)) => Figment::from(rocket::Config::default())
             .merge(rocket_fig)
             .merge(("address", ip))
-            .merge(("port", host_node.port))
-    } else {
-        panic!(
-            "host node [{}] must have ipv4 address, but it was: {:?}",
-            host_name, host_node.host
-        )
+            .merge(("port", port)),
+        None => panic!(
+            "host node [{}] must have an endpoint with ipv4 address, but it has: {:?}",
+            host_name, host_node.endpoints
+        ),
     }
 }
@@ -5,26 +5,85 @@ mod config;
 mod server;
 
 use config::{HOST, SYNC_NODES};
-use tokio::{task, time};
-use webman_core::{config, Client, ToOk};
+use std::{sync::Arc, time::Duration};
+use tokio::{sync::Notify, task, time};
+use webman_core::{config, discovery, node, Client, ToOk};
+
+/// how often the LAN is searched for peers if discovery is enabled
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(5 * 60);
 
 #[rocket::main]
 async fn main() -> Result<(), rocket::Error> {
     let fig = config::rocket_figment();
     // webman_core::db::migrate(&db_url).await.expect("");
-    for n in SYNC_NODES.get().unwrap() {
+    if let Some(d) = &config().discovery {
+        let host = HOST.get().unwrap();
+        // announce the endpoint the server listens on, see config::rocket_figment
+        let (port, tls) = config()
+            .nodes
+            .configured(host)
+            .and_then(|n| {
+                n.endpoints
+                    .iter()
+                    .find(|e| matches!(e.host, node::Host::Ipv4(_)))
+            })
+            .map(|e| (e.port, e.tls))
+            .unwrap_or_default();
+        task::spawn(async move {
+            discovery::serve(d, host, port, tls, &config().api_key)
+                .await
+                .to_ok()
+        });
+        task::spawn(async move {
+            let mut interval = time::interval(DISCOVERY_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Some(peers) = discovery::discover(d, &config().api_key).await.to_ok() {
+                    discovery::register(&peers);
+                }
+            }
+        });
+    }
+
+    let nodes = SYNC_NODES.get().unwrap();
+    // the changes pulled from one peer are forwarded to the others right away, instead of on
+    // their next interval
+    let forward: Arc<Vec<Notify>> = Arc::new(nodes.iter().map(|_| Notify::new()).collect());
+    for (i, n) in nodes.iter().enumerate() {
+        let forward = forward.clone();
         task::spawn(async move {
             let host = HOST.get().unwrap();
             let client = Client::with_apikey(&config().api_key);
             let mut interval = time::interval(n.interval);
             loop {
-                interval.tick().await;
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = forward[i].notified() => {}
+                }
+                // a peer which is only found by discovery may not be found yet
+                if config().nodes.find(&n.name).is_none() {
+                    log::warn!(
+                        "{} is neither configured nor discovered, skip syncing",
+                        n.name
+                    );
+                    continue;
+                }
                 log::debug!(
                     "Syncing nodes between host {} and {}",
                     host.as_str(),
                     &n.name
                 );
-                client.sync_all(host, &n.name).await.to_ok();
+                let pulled: i64 = match client.sync_all(host, &n.name).await.to_ok() {
+                    Some(run) => run.rows.iter().map(|r| r.pulled).sum(),
+                    None => 0,
+                };
+                if pulled > 0 {
+                    for (j, other) in forward.iter().enumerate() {
+                        if j != i {
+                            other.notify_one();
+                        }
+                    }
+                }
             }
         });
     }
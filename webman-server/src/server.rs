@@ -1,28 +1,45 @@
-use super::config::{HOST, SYNC_NODES};
+use super::config::{HOST, PRIVACY_RULES, SEARCH_ENGINES, SYNC_NODES, UNLOCK};
+use anyhow::Context;
 use chrono::NaiveDateTime;
 use cookie::time::{Duration, OffsetDateTime};
 use once_cell::sync::Lazy;
 use rocket::{
     fairing::{self, AdHoc},
     fs::FileServer,
-    http::{Cookie, CookieJar, Status},
+    http::{ContentType, Cookie, CookieJar, Header, Status},
     request::{FromRequest, Outcome, Request},
     response::status,
     serde::{json::Json, msgpack::MsgPack},
     Build, Rocket,
 };
-use rocket_db_pools::{sqlx, Connection, Database};
-use std::sync::Mutex;
+use rocket_db_pools::{
+    sqlx::{self, Connection as _},
+    Connection, Database,
+};
+use std::{net::SocketAddr, sync::Mutex};
 use webman_core::{
     browser::Browser,
     config,
     db::prelude as db,
+    discovery,
+    download::Download,
+    favicon::{sniff_mime, Favicon},
+    navigation::{self, Navigation},
     node::{self, Provider},
     resp::*,
-    url::{self, tag, Filter, Url, UrlResult},
-    Client,
+    session::{BrowsingSession, SessionBuilder},
+    stats::{
+        HostStats, HostSummary, HourCount, Period, TagCount, TimeSpent, TrendPoint, UrlsAdded,
+    },
+    sync::{
+        tree::{BucketHash, TreeNode},
+        ChangeSet, OriginVersion, PeerStatus, SyncRun, PAGE_SIZE,
+    },
+    timeline::TimelinePage,
+    url::{self, tag, Filter, QueryLogEntry, SearchTerm, Url, UrlDuration, UrlPage, UrlResult},
+    Client, WebmanError,
 };
-type Result<T> = std::result::Result<T, rocket::response::Debug<anyhow::Error>>;
+type Result<T> = std::result::Result<T, WebmanError>;
 
 static TAG_LOG: Lazy<Mutex<tag::History>> = Lazy::new(|| Mutex::new(tag::History::default()));
 
@@ -31,8 +48,11 @@ static TAG_LOG: Lazy<Mutex<tag::History>> = Lazy::new(|| Mutex::new(tag::History
 
 pub struct Pool(pub sqlx::PgPool);
 
-/// there are two ways to pass the ApiKey request guard. The first way is to add api key to header "x-api-key", the second way is add the api key in the cookie "logged"
-pub struct ApiKey<'r>(&'r str);
+/// there are two ways to pass the ApiKey request guard. The first way is to add api key to header "x-api-key", the second way is add the api key in the cookie "logged".
+/// Private urls are only accessible if the request is unlocked, either by the unlock key in header "x-unlock-key" or by the unexpired cookie "unlocked".
+pub struct ApiKey {
+    unlocked: bool,
+}
 
 #[derive(Debug)]
 pub enum ApiKeyError {
@@ -40,30 +60,81 @@ pub enum ApiKeyError {
     Invalid,
 }
 
+fn unlocked(req: &Request<'_>) -> std::result::Result<bool, ApiKeyError> {
+    let unlock = match UNLOCK.get().unwrap() {
+        Some(u) => u,
+        None => return Ok(false),
+    };
+    match req.headers().get_one("x-unlock-key") {
+        Some(key) if key == unlock.key => Ok(true),
+        Some(_) => Err(ApiKeyError::Invalid),
+        // the value of the cookie is the unix timestamp when the session expires
+        None => Ok(req
+            .cookies()
+            .get_private("unlocked")
+            .and_then(|c| c.value().parse::<i64>().ok())
+            .is_some_and(|exp| exp > OffsetDateTime::now_utc().unix_timestamp())),
+    }
+}
+
 #[rocket::async_trait]
-impl<'r> FromRequest<'r> for ApiKey<'r> {
+impl<'r> FromRequest<'r> for ApiKey {
     type Error = ApiKeyError;
 
     async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
         // we first check if api key is set in the header, if not, cookie will be checked
         match req.headers().get_one("x-api-key") {
-            Some(key) if key == config().api_key => Outcome::Success(ApiKey(key)),
-            Some(_) => Outcome::Failure((Status::BadRequest, ApiKeyError::Invalid)),
+            Some(key) if key == config().api_key => {}
+            Some(_) => return Outcome::Failure((Status::Unauthorized, ApiKeyError::Invalid)),
             None => match req.cookies().get_private("logged") {
-                Some(c) if c.value() == config().api_key => Outcome::Success(ApiKey("logged")),
-                Some(_) => Outcome::Failure((Status::BadRequest, ApiKeyError::Invalid)),
-                None => Outcome::Failure((Status::BadRequest, ApiKeyError::Missing)),
+                Some(c) if c.value() == config().api_key => {}
+                Some(_) => return Outcome::Failure((Status::Unauthorized, ApiKeyError::Invalid)),
+                None => return Outcome::Failure((Status::Unauthorized, ApiKeyError::Missing)),
             },
         }
+        match unlocked(req) {
+            Ok(unlocked) => Outcome::Success(ApiKey { unlocked }),
+            Err(e) => Outcome::Failure((Status::Unauthorized, e)),
+        }
     }
 }
 
 /// to check whether the request guard ApiKey can be passed.
 #[get("/ping")]
-async fn auth_ping(_key: ApiKey<'_>) -> status::Accepted<&'static str> {
+async fn auth_ping(_key: ApiKey) -> status::Accepted<&'static str> {
     status::Accepted(Some("login succeed"))
 }
 
+/// The proof that this node has the api key and listens at `addr`, the address a node which
+/// discovered it in the LAN connected to, see [`discovery::challenge`]. The key is not needed to
+/// ask for it.
+#[get("/challenge?<nonce>&<addr>")]
+async fn auth_challenge(
+    nonce: &str,
+    addr: SocketAddr,
+    rocket_config: &rocket::Config,
+) -> Result<Json<String>> {
+    if nonce.len() > discovery::MAX_NONCE_LEN {
+        return Err(WebmanError::InvalidInput(
+            "the nonce is too long".to_owned(),
+        ));
+    }
+    let bind = SocketAddr::from((rocket_config.address, rocket_config.port));
+    if !discovery::listens_at(&bind, &addr) {
+        return Err(WebmanError::InvalidInput(format!(
+            "{} is not an address of this node",
+            addr
+        )));
+    }
+    let name = HOST.get().unwrap();
+    Ok(Json(discovery::challenge_proof(
+        &config().api_key,
+        name,
+        &addr,
+        nonce,
+    )))
+}
+
 #[post("/login", data = "<auth>")]
 async fn login(auth: Json<Auth>, cookies: &CookieJar<'_>) -> Result<&'static str> {
     if auth.api_key == config().api_key {
@@ -75,16 +146,53 @@ async fn login(auth: Json<Auth>, cookies: &CookieJar<'_>) -> Result<&'static str
 
         cookies.add_private(c);
     } else {
-        Err(anyhow::anyhow!("the api key is incorrect!"))?;
+        return Err(WebmanError::Unauthorized(
+            "the api key is incorrect!".to_owned(),
+        ));
     }
     Ok("login succeed")
 }
 
+/// unlock private urls for the duration set in config
+#[post("/unlock", data = "<unlock>")]
+async fn unlock(
+    unlock: Json<Unlock>,
+    cookies: &CookieJar<'_>,
+    _key: ApiKey,
+) -> Result<&'static str> {
+    match UNLOCK.get().unwrap() {
+        Some(u) if u.key == unlock.unlock_key => {
+            let mut exp = OffsetDateTime::now_utc();
+            exp += u.duration;
+            let mut c = Cookie::new("unlocked", exp.unix_timestamp().to_string());
+            c.set_expires(exp);
+            cookies.add_private(c);
+        }
+        Some(_) => {
+            return Err(WebmanError::Unauthorized(
+                "the unlock key is incorrect!".to_owned(),
+            ))
+        }
+        None => {
+            return Err(WebmanError::InvalidInput(
+                "unlock is not set in config!".to_owned(),
+            ))
+        }
+    }
+    Ok("unlock succeed")
+}
+
+#[post("/lock")]
+async fn lock(cookies: &CookieJar<'_>) -> &'static str {
+    cookies.remove_private(Cookie::named("unlocked"));
+    "lock succeed"
+}
+
 #[get("/provider/last_import_time?<name>")]
 async fn last_import_time(
     mut pool: Connection<Pool>,
     name: &str,
-    _key: ApiKey<'_>,
+    _key: ApiKey,
 ) -> Result<Json<NaiveDateTime>> {
     let time = db::last_import_time(&mut pool, name).await?;
     Ok(Json(time))
@@ -100,10 +208,11 @@ async fn search_by_query(
     mut pool: Connection<Pool>,
     query: String,
     limit: i64,
-    _key: ApiKey<'_>,
+    key: ApiKey,
 ) -> Result<Json<Vec<UrlResult>>> {
     let f = Filter {
         limit,
+        unlocked: key.unlocked,
         ..Filter::parse(query)
     };
     let res = db::fuzzy_search(&mut pool, f).await?;
@@ -114,45 +223,166 @@ async fn search_by_query(
 async fn get_urls(
     mut pool: Connection<Pool>,
     filter: Json<url::Filter>,
-    _key: ApiKey<'_>,
-) -> Result<MsgPack<Vec<Url>>> {
+    key: ApiKey,
+) -> Result<MsgPack<UrlPage>> {
     let mut filter = filter.into_inner();
     filter.init();
-    let urls = db::get_urls(&mut pool, filter).await?;
-    Ok(MsgPack(urls))
+    filter.unlocked = key.unlocked;
+    let page = db::get_url_page(&mut pool, filter).await?;
+    Ok(MsgPack(page))
 }
 
-#[post("/urls/insert", data = "<insert>")]
-async fn insert_urls(
-    mut pool: Connection<Pool>,
-    insert: MsgPack<UrlInsert>,
-    _key: ApiKey<'_>,
-) -> Result<Status> {
+/// sanitize the urls and apply privacy rules before they are inserted
+fn preprocess(name: &str, browser: Browser, urls: Vec<Url>) -> Vec<Url> {
+    let mut urls = config().sanitize.apply(urls);
+    PRIVACY_RULES.get().unwrap().apply(name, browser, &mut urls);
+    urls
+}
+
+/// store the urls and everything mined along with them
+async fn store(pool: &mut sqlx::PgConnection, insert: UrlInsert) -> anyhow::Result<()> {
     let UrlInsert {
         name,
         browser,
         urls,
         last_import_time,
-    } = insert.into_inner();
-    info!("start to insert urls with length {}", urls.len());
-    db::insert_urls(&mut pool, &name, browser, urls, last_import_time).await?;
-    info!("urls successfull inserted!");
+        edges,
+        search_terms,
+        redirects,
+        durations,
+        favicons,
+        downloads,
+        chunk,
+    } = insert;
+    // only the last chunk of an upload moves the last import time
+    let last_import_time = match chunk {
+        Some(Chunk { last: false, .. }) => Some(db::last_import_time(&mut *pool, &name).await?),
+        _ => last_import_time,
+    };
+    let urls = preprocess(&name, browser, urls);
+    let search_terms = search_terms
+        .into_iter()
+        .map(|t| SearchTerm {
+            url: config().sanitize.sanitize(&t.url).into_owned(),
+            ..t
+        })
+        .collect();
+    let queries = SEARCH_ENGINES
+        .get()
+        .unwrap()
+        .extract_all(urls.iter().map(|u| u.url.as_str()), search_terms);
+    db::insert_urls(&mut *pool, &name, browser, urls, last_import_time).await?;
+    let edges = navigation::sanitize_edges(&config().sanitize, edges);
+    db::insert_navigations(&mut *pool, &name, browser, edges).await?;
+    db::insert_search_queries(&mut *pool, queries).await?;
+    let redirects = navigation::sanitize_edges(&config().sanitize, redirects);
+    db::set_redirects(&mut *pool, redirects).await?;
+    let durations = durations
+        .into_iter()
+        .map(|d| UrlDuration {
+            url: config().sanitize.sanitize(&d.url).into_owned(),
+            ..d
+        })
+        .collect();
+    db::add_durations(&mut *pool, &name, browser, UrlDuration::dedup(durations)).await?;
+    db::upsert_favicons(&mut *pool, Favicon::dedup(favicons)).await?;
+    let downloads = downloads
+        .into_iter()
+        .map(|d| Download {
+            url: config().sanitize.sanitize(&d.url).into_owned(),
+            referrer: d
+                .referrer
+                .map(|r| config().sanitize.sanitize(&r).into_owned()),
+            ..d
+        })
+        .collect();
+    db::insert_downloads(
+        &mut *pool,
+        &name,
+        browser,
+        downloads,
+        PRIVACY_RULES.get().unwrap(),
+    )
+    .await?;
+    Ok(())
+}
+
+#[post("/urls/insert", data = "<insert>")]
+async fn insert_urls(
+    mut pool: Connection<Pool>,
+    insert: MsgPack<UrlInsert>,
+    _key: ApiKey,
+) -> Result<Status> {
+    let insert = insert.into_inner();
+    let chunk = match insert.chunk.clone() {
+        Some(chunk) => chunk,
+        None => {
+            info!("start to insert urls with length {}", insert.urls.len());
+            store(&mut pool, insert).await?;
+            info!("urls successfull inserted!");
+            return Ok(Status::Ok);
+        }
+    };
+    // the cursor is checked, the chunk stored and the cursor advanced in one transaction
+    let mut tx = pool
+        .begin()
+        .await
+        .context("could not start transaction for chunk")?;
+    let next = db::lock_upload(&mut tx, &chunk.upload_id).await?;
+    if chunk.index < next {
+        info!(
+            "chunk {} of {} is already stored",
+            chunk.index, chunk.upload_id
+        );
+        return Ok(Status::Ok);
+    }
+    if chunk.index > next {
+        let message = format!(
+            "chunk {} of {} is out of order, expected {}",
+            chunk.index, chunk.upload_id, next
+        );
+        warn!("{}", message);
+        return Err(WebmanError::Conflict(message));
+    }
+    info!(
+        "start to insert chunk {} of {} with {} urls",
+        chunk.index,
+        chunk.upload_id,
+        insert.urls.len()
+    );
+    store(&mut tx, insert).await?;
+    db::advance_upload(&mut tx, &chunk.upload_id, chunk.index + 1).await?;
+    tx.commit()
+        .await
+        .with_context(|| format!("could not commit chunk {}", chunk.index))?;
     Ok(Status::Ok)
 }
 
+/// the index of the next chunk of an upload the server expects
+#[get("/urls/insert/cursor?<upload_id>")]
+async fn upload_cursor(
+    mut pool: Connection<Pool>,
+    upload_id: &str,
+    _key: ApiKey,
+) -> Result<Json<i32>> {
+    Ok(Json(db::upload_cursor(&mut pool, upload_id).await?))
+}
+
 #[post("/urls/insert_fake", data = "<insert>")]
 async fn insert_fake_url(
     mut pool: Connection<Pool>,
     insert: Json<FakeUrl>,
-    _key: ApiKey<'_>,
+    _key: ApiKey,
 ) -> Result<Status> {
     let UrlInsert {
         name,
         browser,
         urls,
         last_import_time,
+        ..
     } = insert.into_inner().into();
     info!("start to insert fake url: {}", &urls[0].url);
+    let urls = preprocess(&name, browser, urls);
     db::insert_urls(&mut pool, &name, browser, urls, last_import_time).await?;
     info!("fake url successfully inserted!");
     Ok(Status::Ok)
@@ -162,16 +392,11 @@ async fn insert_fake_url(
 async fn insert_urls_json(
     mut pool: Connection<Pool>,
     insert: Json<UrlInsert>,
-    _key: ApiKey<'_>,
+    _key: ApiKey,
 ) -> Result<Status> {
-    let UrlInsert {
-        name,
-        browser,
-        urls,
-        last_import_time,
-    } = insert.into_inner();
-    info!("start to insert urls with length {}", urls.len());
-    db::insert_urls(&mut pool, &name, browser, urls, last_import_time).await?;
+    let insert = insert.into_inner();
+    info!("start to insert urls with length {}", insert.urls.len());
+    store(&mut pool, insert).await?;
     info!("urls successfully inserted!");
     Ok(Status::Ok)
 }
@@ -180,7 +405,7 @@ async fn insert_urls_json(
 async fn set_tag(
     mut pool: Connection<Pool>,
     tag_setter: Json<UrlTagSetter>,
-    _key: ApiKey<'_>,
+    _key: ApiKey,
 ) -> Result<Status> {
     info!("start to set tag as {:?}", &tag_setter);
     let UrlTagSetter { tag, urls } = tag_setter.into_inner();
@@ -189,6 +414,377 @@ async fn set_tag(
     Ok(Status::Ok)
 }
 
+#[post("/urls/privacy", data = "<privacy_setter>")]
+async fn set_privacy(
+    mut pool: Connection<Pool>,
+    privacy_setter: Json<UrlPrivacySetter>,
+    _key: ApiKey,
+) -> Result<Status> {
+    info!("start to set privacy as {:?}", &privacy_setter);
+    let UrlPrivacySetter { privacy, urls } = privacy_setter.into_inner();
+    db::set_privacy(&mut pool, urls, privacy).await?;
+    Ok(Status::Ok)
+}
+
+/// apply the privacy rules in config to the urls which are already stored
+#[post("/urls/privacy/backfill")]
+async fn backfill_privacy(mut pool: Connection<Pool>, _key: ApiKey) -> Result<Json<u64>> {
+    let n = db::backfill_privacy(&mut pool, PRIVACY_RULES.get().unwrap()).await?;
+    info!("{} urls are marked as private by privacy rules", n);
+    Ok(Json(n))
+}
+
+/// sanitize the urls which are already stored
+#[post("/urls/sanitize")]
+async fn sanitize_urls(mut pool: Connection<Pool>, _key: ApiKey) -> Result<Json<u64>> {
+    let n = db::sanitize_urls(&mut pool, &config().sanitize).await?;
+    info!("{} urls are sanitized", n);
+    Ok(Json(n))
+}
+
+/// merge the stored urls with the same canonical url
+#[post("/urls/merge_duplicates")]
+async fn merge_duplicate_urls(mut pool: Connection<Pool>, _key: ApiKey) -> Result<Json<u64>> {
+    let n = db::merge_duplicate_urls(&mut pool).await?;
+    info!("{} duplicate urls are merged", n);
+    Ok(Json(n))
+}
+
+#[post("/hosts/top", data = "<filter>")]
+async fn top_hosts(
+    mut pool: Connection<Pool>,
+    filter: Json<Filter>,
+    key: ApiKey,
+) -> Result<Json<Vec<HostSummary>>> {
+    let f = Filter {
+        unlocked: key.unlocked,
+        ..filter.into_inner()
+    };
+    let hosts = db::top_hosts(&mut pool, f).await?;
+    Ok(Json(hosts))
+}
+
+#[post("/hosts/stats?<host>&<period>", data = "<filter>")]
+async fn host_stats(
+    mut pool: Connection<Pool>,
+    host: &str,
+    period: Option<&str>,
+    filter: Json<Filter>,
+    key: ApiKey,
+) -> Result<Option<Json<HostStats>>> {
+    let period = parse_period(period)?;
+    let f = Filter {
+        unlocked: key.unlocked,
+        ..filter.into_inner()
+    };
+    let stats = db::host_stats(&mut pool, host, period, f).await?;
+    Ok(stats.map(Json))
+}
+
+/// `before` and `after` are the cursors `older` and `newer` of a previous page
+#[post("/timeline?<before>&<after>", data = "<filter>")]
+async fn timeline(
+    mut pool: Connection<Pool>,
+    before: Option<&str>,
+    after: Option<&str>,
+    filter: Json<Filter>,
+    key: ApiKey,
+) -> Result<Json<TimelinePage>> {
+    let f = Filter {
+        unlocked: key.unlocked,
+        ..filter.into_inner()
+    };
+    let cursor = |c: &str| {
+        c.parse()
+            .map_err(|e| WebmanError::InvalidInput(format!("invalid cursor {}: {:#}", c, e)))
+    };
+    let before = before.map(cursor).transpose()?;
+    let after = after.map(cursor).transpose()?;
+    let page = db::timeline(&mut pool, f, before, after).await?;
+    Ok(Json(page))
+}
+
+/// rebuild the browsing sessions from the stored visits, a session ends after `idle` minutes
+/// without visits
+#[post("/sessions/rebuild?<idle>")]
+async fn rebuild_sessions(
+    mut pool: Connection<Pool>,
+    idle: Option<i64>,
+    _key: ApiKey,
+) -> Result<Json<usize>> {
+    let builder = idle.map(SessionBuilder::new).unwrap_or_default();
+    let n = db::session::rebuild(&mut pool, &builder).await?;
+    info!("{} browsing sessions are built", n);
+    Ok(Json(n))
+}
+
+/// the sessions containing a page which matches the query, see [`search_by_query`]
+#[get("/sessions/search?<query>&<limit>")]
+async fn search_sessions(
+    mut pool: Connection<Pool>,
+    query: String,
+    limit: i64,
+    key: ApiKey,
+) -> Result<Json<Vec<BrowsingSession>>> {
+    let f = Filter {
+        limit,
+        unlocked: key.unlocked,
+        ..Filter::parse(query)
+    };
+    let res = db::session::search(&mut pool, f).await?;
+    Ok(Json(res))
+}
+
+#[get("/sessions/<id>/urls")]
+async fn session_urls(
+    mut pool: Connection<Pool>,
+    id: i32,
+    key: ApiKey,
+) -> Result<Json<Vec<String>>> {
+    let urls = db::session::urls(&mut pool, id, key.unlocked).await?;
+    Ok(Json(urls))
+}
+
+/// set the tag of all urls of the session
+#[post("/sessions/<id>/tag", data = "<tag>")]
+async fn tag_session(
+    mut pool: Connection<Pool>,
+    id: i32,
+    tag: Json<tag::UrlTag>,
+    key: ApiKey,
+) -> Result<Json<u64>> {
+    let tag = tag.into_inner();
+    let urls = db::session::urls(&mut pool, id, key.unlocked).await?;
+    info!(
+        "start to set tag of {} urls in session {} as {:?}",
+        urls.len(),
+        id,
+        tag
+    );
+    TAG_LOG.lock().unwrap().batch_insert(urls.clone(), tag);
+    let n = db::set_tag(&mut pool, urls, tag).await?;
+    Ok(Json(n))
+}
+
+/// the chain of pages which led to the url, at most `depth` (10 by default) pages back, and the
+/// pages opened from it
+#[get("/urls/navigation?<url>&<depth>")]
+async fn get_navigation(
+    mut pool: Connection<Pool>,
+    url: &str,
+    depth: Option<i32>,
+    key: ApiKey,
+) -> Result<Option<Json<Navigation>>> {
+    let res = db::navigation(&mut pool, url, depth.unwrap_or(10), key.unlocked).await?;
+    Ok(res.map(Json))
+}
+
+/// extract the search queries of the stored urls by the configured search engines
+#[post("/queries/backfill")]
+async fn backfill_search_queries(mut pool: Connection<Pool>, _key: ApiKey) -> Result<Json<u64>> {
+    let n = db::backfill_search_queries(&mut pool, SEARCH_ENGINES.get().unwrap()).await?;
+    info!("{} search queries are extracted", n);
+    Ok(Json(n))
+}
+
+/// the search queries matching the query, with the pages opened from their results
+#[get("/queries/search?<query>&<limit>")]
+async fn search_queries(
+    mut pool: Connection<Pool>,
+    query: String,
+    limit: i64,
+    key: ApiKey,
+) -> Result<Json<Vec<QueryLogEntry>>> {
+    let f = Filter {
+        limit,
+        unlocked: key.unlocked,
+        ..Filter::parse(query)
+    };
+    let res = db::search_queries(&mut pool, f).await?;
+    Ok(Json(res))
+}
+
+/// what was searched for before the url was found, over at most `depth` (3 by default) pages
+#[get("/urls/queries?<url>&<depth>")]
+async fn queries_of_url(
+    mut pool: Connection<Pool>,
+    url: &str,
+    depth: Option<i32>,
+    key: ApiKey,
+) -> Result<Json<Vec<QueryLogEntry>>> {
+    let res = db::queries_of_url(&mut pool, url, depth.unwrap_or(3), key.unlocked).await?;
+    Ok(Json(res))
+}
+
+/// the files downloaded from the url
+#[get("/urls/downloads?<url>")]
+async fn downloads_of_url(
+    mut pool: Connection<Pool>,
+    url: &str,
+    key: ApiKey,
+) -> Result<Json<Vec<Download>>> {
+    let res = db::downloads_of_url(&mut pool, url, key.unlocked).await?;
+    Ok(Json(res))
+}
+
+fn parse_period(period: Option<&str>) -> Result<Period> {
+    match period {
+        Some(p) => p
+            .parse::<Period>()
+            .map_err(|e| WebmanError::InvalidInput(format!("invalid period {}: {}", p, e))),
+        None => Ok(Period::default()),
+    }
+}
+
+/// `tz` is the time zone of the time buckets, UTC by default
+#[post("/stats/visits?<period>&<tz>", data = "<filter>")]
+async fn stats_visits(
+    mut pool: Connection<Pool>,
+    period: Option<&str>,
+    tz: Option<&str>,
+    filter: Json<Filter>,
+    key: ApiKey,
+) -> Result<Json<Vec<TrendPoint>>> {
+    let period = parse_period(period)?;
+    let f = Filter {
+        unlocked: key.unlocked,
+        ..filter.into_inner()
+    };
+    let res = db::stats::visits(&mut pool, period, tz.unwrap_or("UTC"), f).await?;
+    Ok(Json(res))
+}
+
+/// `tz` is the time zone of the time buckets, UTC by default
+#[post("/stats/urls?<period>&<tz>", data = "<filter>")]
+async fn stats_urls(
+    mut pool: Connection<Pool>,
+    period: Option<&str>,
+    tz: Option<&str>,
+    filter: Json<Filter>,
+    key: ApiKey,
+) -> Result<Json<Vec<UrlsAdded>>> {
+    let period = parse_period(period)?;
+    let f = Filter {
+        unlocked: key.unlocked,
+        ..filter.into_inner()
+    };
+    let res = db::stats::urls(&mut pool, period, tz.unwrap_or("UTC"), f).await?;
+    Ok(Json(res))
+}
+
+#[post("/stats/tags", data = "<filter>")]
+async fn stats_tags(
+    mut pool: Connection<Pool>,
+    filter: Json<Filter>,
+    key: ApiKey,
+) -> Result<Json<Vec<TagCount>>> {
+    let f = Filter {
+        unlocked: key.unlocked,
+        ..filter.into_inner()
+    };
+    let res = db::stats::tags(&mut pool, f).await?;
+    Ok(Json(res))
+}
+
+#[post("/stats/hours?<tz>", data = "<filter>")]
+async fn stats_hours(
+    mut pool: Connection<Pool>,
+    tz: Option<&str>,
+    filter: Json<Filter>,
+    key: ApiKey,
+) -> Result<Json<Vec<HourCount>>> {
+    let f = Filter {
+        unlocked: key.unlocked,
+        ..filter.into_inner()
+    };
+    let res = db::stats::hours(&mut pool, tz.unwrap_or("UTC"), f).await?;
+    Ok(Json(res))
+}
+
+#[post("/stats/time/urls", data = "<filter>")]
+async fn stats_time_urls(
+    mut pool: Connection<Pool>,
+    filter: Json<Filter>,
+    key: ApiKey,
+) -> Result<Json<Vec<TimeSpent>>> {
+    let f = Filter {
+        unlocked: key.unlocked,
+        ..filter.into_inner()
+    };
+    let res = db::stats::time_spent(&mut pool, false, f).await?;
+    Ok(Json(res))
+}
+
+#[post("/stats/time/hosts", data = "<filter>")]
+async fn stats_time_hosts(
+    mut pool: Connection<Pool>,
+    filter: Json<Filter>,
+    key: ApiKey,
+) -> Result<Json<Vec<TimeSpent>>> {
+    let f = Filter {
+        unlocked: key.unlocked,
+        ..filter.into_inner()
+    };
+    let res = db::stats::time_spent(&mut pool, true, f).await?;
+    Ok(Json(res))
+}
+
+/// the value of the header "If-None-Match"
+pub struct IfNoneMatch<'r>(Option<&'r str>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for IfNoneMatch<'r> {
+    type Error = ();
+
+    async fn from_request(req: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(IfNoneMatch(req.headers().get_one("If-None-Match")))
+    }
+}
+
+#[derive(Responder)]
+struct FaviconResponse {
+    inner: (Status, Vec<u8>),
+    content_type: ContentType,
+    cache_control: Header<'static>,
+    etag: Header<'static>,
+    csp: Header<'static>,
+    nosniff: Header<'static>,
+}
+
+/// The icons rarely change, so browsers may cache them for a day and revalidate them by the ETag.
+/// The icons are uploaded by the clients and served from the origin of the login cookie, so the
+/// type is sniffed again, and scripts in svg icons are blocked by the Content-Security-Policy.
+#[get("/favicon/<host>")]
+async fn favicon(
+    mut pool: Connection<Pool>,
+    host: &str,
+    if_none_match: IfNoneMatch<'_>,
+    key: ApiKey,
+) -> Result<Option<FaviconResponse>> {
+    let host = url::normalized_host(&format!("http://{}", host)).unwrap_or_default();
+    let icon = match db::favicon(&mut pool, &host, key.unlocked).await? {
+        Some(icon) => icon,
+        None => return Ok(None),
+    };
+    let etag = icon.etag();
+    let content_type = sniff_mime(&icon.data)
+        .and_then(ContentType::parse_flexible)
+        .unwrap_or(ContentType::Binary);
+    let inner = if if_none_match.0 == Some(etag.as_str()) {
+        (Status::NotModified, vec![])
+    } else {
+        (Status::Ok, icon.data)
+    };
+    Ok(Some(FaviconResponse {
+        inner,
+        content_type,
+        cache_control: Header::new("Cache-Control", "private, max-age=86400"),
+        etag: Header::new("ETag", etag),
+        csp: Header::new("Content-Security-Policy", "default-src 'none'"),
+        nosniff: Header::new("X-Content-Type-Options", "nosniff"),
+    }))
+}
+
 #[get("/memory/tag_log")]
 async fn get_tag_log<'a>() -> Json<tag::History> {
     let log: &tag::History = &TAG_LOG.lock().unwrap();
@@ -211,13 +807,13 @@ async fn update_tag_log(mut pool: Connection<Pool>, log: Json<tag::History>) ->
 }
 
 #[get("/urls/tags")]
-async fn get_all_tags(mut pool: Connection<Pool>, _key: ApiKey<'_>) -> Result<Json<url::Tags>> {
-    let tags = db::get_all_tags(&mut pool).await?;
+async fn get_all_tags(mut pool: Connection<Pool>, key: ApiKey) -> Result<Json<url::Tags>> {
+    let tags = db::get_all_tags(&mut pool, key.unlocked).await?;
     Ok(Json(tags))
 }
 
 #[get("/provider/info")]
-async fn providers(mut pool: Connection<Pool>, _key: ApiKey<'_>) -> Result<Json<Vec<Provider>>> {
+async fn providers(mut pool: Connection<Pool>, _key: ApiKey) -> Result<Json<Vec<Provider>>> {
     let p = db::all_providers(&mut pool).await?;
     Ok(Json(p))
 }
@@ -226,14 +822,188 @@ async fn providers(mut pool: Connection<Pool>, _key: ApiKey<'_>) -> Result<Json<
 async fn browsers(
     mut pool: Connection<Pool>,
     name: &str,
-    _key: ApiKey<'_>,
+    _key: ApiKey,
 ) -> Result<Json<Vec<Browser>>> {
     let bs: Vec<Browser> = db::all_browsers(&mut pool, name).await?;
     Ok(Json(bs))
 }
 
+/// the random id of this node, which scopes its sequence numbers
+#[get("/sync/id")]
+async fn sync_node_id(mut pool: Connection<Pool>, _key: ApiKey) -> Result<Json<String>> {
+    Ok(Json(db::sync::node_id(&mut pool).await?))
+}
+
+/// the last sequence number of the peer whose changes are applied here
+#[get("/sync/acked?<node_id>")]
+async fn sync_acked(mut pool: Connection<Pool>, node_id: &str, _key: ApiKey) -> Result<Json<i64>> {
+    Ok(Json(db::sync::acked_seq(&mut pool, node_id).await?))
+}
+
+/// the changes after `since` which are not known by the versions of the peer
+#[post("/sync/changes?<since>&<limit>", data = "<known>")]
+async fn sync_changes(
+    mut pool: Connection<Pool>,
+    since: i64,
+    limit: Option<i64>,
+    known: Json<Vec<OriginVersion>>,
+    _key: ApiKey,
+) -> Result<MsgPack<ChangeSet>> {
+    let limit = limit.unwrap_or(PAGE_SIZE).min(PAGE_SIZE);
+    let changes = db::sync::changes(&mut pool, since, limit, &known).await?;
+    Ok(MsgPack(changes))
+}
+
+/// the changes after `since`, for the peers which do not send their versions yet
+#[get("/sync/changes?<since>&<limit>")]
+async fn sync_changes_unversioned(
+    mut pool: Connection<Pool>,
+    since: i64,
+    limit: Option<i64>,
+    _key: ApiKey,
+) -> Result<MsgPack<ChangeSet>> {
+    let limit = limit.unwrap_or(PAGE_SIZE).min(PAGE_SIZE);
+    let changes = db::sync::changes(&mut pool, since, limit, &[]).await?;
+    Ok(MsgPack(changes))
+}
+
+/// up to which sequence number this node has all changes of every origin
+#[get("/sync/versions")]
+async fn sync_versions(
+    mut pool: Connection<Pool>,
+    _key: ApiKey,
+) -> Result<Json<Vec<OriginVersion>>> {
+    Ok(Json(db::sync::versions(&mut pool).await?))
+}
+
+/// the versions of a peer whose changes are all applied here
+#[post("/sync/versions", data = "<versions>")]
+async fn record_sync_versions(
+    mut pool: Connection<Pool>,
+    versions: Json<Vec<OriginVersion>>,
+    _key: ApiKey,
+) -> Result<Status> {
+    db::sync::record_versions(&mut pool, versions.into_inner()).await?;
+    Ok(Status::Ok)
+}
+
+#[post("/sync/apply", data = "<changes>")]
+async fn sync_apply(
+    mut pool: Connection<Pool>,
+    changes: MsgPack<ChangeSet>,
+    _key: ApiKey,
+) -> Result<Json<u64>> {
+    let changes = changes.into_inner();
+    info!(
+        "applying changes ({}, {}] of {}",
+        changes.since, changes.last_seq, changes.node_id
+    );
+    Ok(Json(db::sync::apply(&mut pool, changes).await?))
+}
+
+/// the hash of every provider and browser, see [`webman_core::sync::tree`]
+#[get("/sync/tree")]
+async fn sync_tree(mut pool: Connection<Pool>, _key: ApiKey) -> Result<Json<Vec<TreeNode>>> {
+    Ok(Json(db::sync::tree(&mut pool).await?))
+}
+
+#[get("/sync/tree/buckets?<provider>&<browser>")]
+async fn sync_buckets(
+    mut pool: Connection<Pool>,
+    provider: &str,
+    browser: &str,
+    _key: ApiKey,
+) -> Result<Json<Vec<BucketHash>>> {
+    let browser = parse_browser(browser)?;
+    Ok(Json(db::sync::buckets(&mut pool, provider, browser).await?))
+}
+
+#[get("/sync/tree/rows?<provider>&<browser>&<buckets>")]
+async fn sync_bucket_rows(
+    mut pool: Connection<Pool>,
+    provider: &str,
+    browser: &str,
+    buckets: Vec<i32>,
+    _key: ApiKey,
+) -> Result<MsgPack<ChangeSet>> {
+    let browser = parse_browser(browser)?;
+    let rows = db::sync::bucket_rows(&mut pool, provider, browser, &buckets).await?;
+    Ok(MsgPack(rows))
+}
+
+/// merge the reconciled rows of differing buckets
+#[post("/sync/repair", data = "<rows>")]
+async fn sync_repair(
+    mut pool: Connection<Pool>,
+    rows: MsgPack<ChangeSet>,
+    _key: ApiKey,
+) -> Result<Json<u64>> {
+    Ok(Json(db::sync::repair(&mut pool, rows.into_inner()).await?))
+}
+
+fn parse_browser(browser: &str) -> Result<Browser> {
+    browser
+        .parse::<Browser>()
+        .map_err(|e| WebmanError::InvalidInput(format!("invalid browser {}: {}", browser, e)))
+}
+
+#[post("/sync/runs", data = "<run>")]
+async fn record_sync_run(
+    mut pool: Connection<Pool>,
+    run: Json<SyncRun>,
+    _key: ApiKey,
+) -> Result<Status> {
+    db::sync::record_run(&mut pool, run.into_inner()).await?;
+    Ok(Status::Ok)
+}
+
+/// the last run of every peer which was synced or is configured to be synced
+#[get("/sync/status")]
+async fn sync_status(mut pool: Connection<Pool>, _key: ApiKey) -> Result<Json<Vec<PeerStatus>>> {
+    let now = chrono::Utc::now().naive_utc();
+    let interval = |peer: &str| {
+        SYNC_NODES
+            .get()
+            .unwrap()
+            .iter()
+            .find(|n| n.name.as_str() == peer)
+            .and_then(|n| chrono::Duration::from_std(n.interval).ok())
+    };
+    let mut status: Vec<PeerStatus> = db::sync::last_runs(&mut pool)
+        .await?
+        .into_iter()
+        .map(|(run, last_success)| {
+            let peer = run.peer.clone();
+            PeerStatus::new(peer.clone(), Some(run), last_success, interval(&peer), now)
+        })
+        .collect();
+    for n in SYNC_NODES.get().unwrap() {
+        if !status.iter().any(|s| s.peer == n.name.as_str()) {
+            status.push(PeerStatus::new(
+                n.name.to_string(),
+                None,
+                None,
+                interval(&n.name),
+                now,
+            ));
+        }
+    }
+    Ok(Json(status))
+}
+
+#[get("/sync/history?<peer>&<limit>")]
+async fn sync_history(
+    mut pool: Connection<Pool>,
+    peer: Option<&str>,
+    limit: Option<i64>,
+    _key: ApiKey,
+) -> Result<Json<Vec<SyncRun>>> {
+    let limit = limit.unwrap_or(20);
+    Ok(Json(db::sync::runs(&mut pool, peer, limit).await?))
+}
+
 #[get("/sync?<remote>")]
-async fn sync(remote: String, _key: ApiKey<'_>) -> Result<Status> {
+async fn sync(remote: String, _key: ApiKey) -> Result<Status> {
     let c = Client::with_apikey(&config().api_key);
     let remote = node::Name::new(remote);
     c.sync_all(HOST.get().unwrap(), &remote).await?;
@@ -241,7 +1011,7 @@ async fn sync(remote: String, _key: ApiKey<'_>) -> Result<Status> {
 }
 
 #[get("/sync")]
-async fn sync_all_nodes(_key: ApiKey<'_>) -> Result<Status> {
+async fn sync_all_nodes(_key: ApiKey) -> Result<Status> {
     let c = Client::with_apikey(&config().api_key);
     for node in SYNC_NODES.get().unwrap() {
         c.sync_all(HOST.get().unwrap(), &node.name).await?;
@@ -249,14 +1019,32 @@ async fn sync_all_nodes(_key: ApiKey<'_>) -> Result<Status> {
     Ok(Status::Ok)
 }
 
+/// the reply to a request whose data could not be parsed, the reason is only in the log
+const DATA_GUARD_FAILED: &str = "Error Data guard happened, please check the server log files";
+
 #[catch(422)]
 fn error422() -> &'static str {
-    "Error Data guard happened, please check the server log files"
+    DATA_GUARD_FAILED
+}
+
+/// the errors of the api which are not replied by a route, e.g. a failed request guard, as json
+#[catch(default)]
+fn api_error(status: Status, req: &Request<'_>) -> (Status, WebmanError) {
+    let message = match status.code {
+        422 => DATA_GUARD_FAILED.to_owned(),
+        _ => format!("{} {} {}", status, req.method(), req.uri()),
+    };
+    (status, WebmanError::from_status(status.code, message))
 }
 
 async fn run_migrations(rocket: Rocket<Build>) -> fairing::Result {
     if let Some(pool) = Pool::fetch(&rocket) {
         db::migrate(pool).await.unwrap();
+        let mut conn = pool.acquire().await.unwrap();
+        let n = db::backfill_canonical(&mut conn).await.unwrap();
+        if n > 0 {
+            info!("computed the canonical urls of {} urls", n);
+        }
         Ok(rocket)
     } else {
         Err(rocket)
@@ -277,20 +1065,63 @@ pub fn launch(fig: figment::Figment) -> Rocket<Build> {
                 last_import_time,
                 get_urls,
                 insert_urls,
+                upload_cursor,
                 insert_urls_json,
                 insert_fake_url,
                 providers,
                 set_tag,
+                set_privacy,
+                backfill_privacy,
+                sanitize_urls,
+                merge_duplicate_urls,
+                top_hosts,
+                host_stats,
+                stats_visits,
+                stats_urls,
+                stats_tags,
+                stats_hours,
+                stats_time_urls,
+                stats_time_hosts,
+                favicon,
+                timeline,
+                rebuild_sessions,
+                search_sessions,
+                session_urls,
+                tag_session,
+                get_navigation,
+                backfill_search_queries,
+                search_queries,
+                queries_of_url,
+                downloads_of_url,
                 get_all_tags,
                 get_tag_log,
                 update_tag_log,
                 search_by_query,
                 sync,
                 sync_all_nodes,
+                sync_node_id,
+                sync_acked,
+                sync_changes,
+                sync_changes_unversioned,
+                sync_versions,
+                record_sync_versions,
+                sync_apply,
+                sync_tree,
+                sync_buckets,
+                sync_bucket_rows,
+                sync_repair,
+                record_sync_run,
+                sync_status,
+                sync_history,
                 search_by_query_without_key
             ],
         )
-        .mount("/auth", routes![auth_ping, login,])
+        .mount(
+            "/auth",
+            routes![auth_ping, auth_challenge, login, unlock, lock],
+        )
         .mount("/", FileServer::from(react_location))
         .register("/", catchers![error422])
+        .register("/api", catchers![api_error])
+        .register("/auth", catchers![api_error])
 }